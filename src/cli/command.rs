@@ -0,0 +1,1480 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{Address, OutPoint, Script, Transaction, TxOut, Txid};
+use qrcode_generator::QrCodeEcc;
+use rgb::{Consignment, ContractId, ToBech32};
+use serde_crate::Serialize;
+use strict_encoding::StrictEncode;
+use wallet::descriptors;
+
+use bitcoin_pro::controller::broadcast::{self, TxBroadcast};
+use bitcoin_pro::controller::coin_select::{self, Selection};
+use bitcoin_pro::controller::fee_bump::{self, FeeBump};
+use bitcoin_pro::controller::history_lookup::HistoryLookup;
+use bitcoin_pro::controller::psbt_compose::{self, Composition, PsbtCompose};
+use bitcoin_pro::controller::psbt_finalize;
+use bitcoin_pro::controller::utxo_lookup::{self, UtxoLookup};
+use bitcoin_pro::controller::utxo_refresh::{SpentUtxo, UtxoRefresh};
+use bitcoin_pro::model::bip380;
+use bitcoin_pro::model::export::Format;
+use bitcoin_pro::model::{
+    self, addresses_csv, AddressRow, DescriptorAccount, Document, HistoryRow,
+    LogFormat, PsbtRow, ResolverError, ScriptMatch, ScriptOwner,
+    TrackingAccount, UtxoEntry,
+};
+use bitcoin_pro::util::psbt::{self, PsbtParseError};
+use bitcoin_pro::util::resolver_mode::ResolverModeType;
+
+use crate::opts::{
+    AddressCommand, AssetCommand, Command, DescriptorCommand, DocCommand,
+    KeyCommand, Opts, Payment, PsbtCommand, TxCommand, UtxoCommand,
+};
+
+/// Environment variable with the document passphrase
+const PASSPHRASE_ENV: &str = "BPRO_PASSPHRASE";
+
+/// Environment variable with the new passphrase for `doc passphrase`
+const NEW_PASSPHRASE_ENV: &str = "BPRO_NEW_PASSPHRASE";
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+/// Errors happening during command-line operations with the document
+pub enum Error {
+    /// Document error: {0}
+    #[from]
+    Document(model::Error),
+
+    /// Resolver configuration error: {0}
+    #[from]
+    Resolver(ResolverError),
+
+    /// UTXO lookup error: {0}
+    #[from]
+    UtxoLookup(utxo_lookup::Error),
+
+    /// RGB-20 asset issue error: {0}
+    #[from]
+    Rgb20(rgb20::Error),
+
+    /// {0}
+    #[from]
+    Psbt(PsbtParseError),
+
+    /// Unable to compose PSBT: {0}
+    #[from]
+    Compose(psbt_compose::Error),
+
+    /// Unable to finalize PSBT: {0}
+    #[from]
+    Finalize(psbt_finalize::Error),
+
+    /// Transaction can't be broadcasted: {0}
+    #[from]
+    Broadcast(broadcast::Error),
+
+    /// File does not contain a valid transaction
+    InvalidTransaction,
+
+    /// Unable to bump transaction fee: {0}
+    #[from]
+    FeeBump(fee_bump::Error),
+
+    /// Transaction {0} is not found in the transaction cache
+    UnknownTransaction(Txid),
+
+    /// Transaction {0} is already confirmed
+    ConfirmedTransaction(Txid),
+
+    /// Coin selection error: {0}
+    #[from]
+    CoinSelect(coin_select::Error),
+
+    /// Output {0} is not found among the cached unspent outputs
+    UnknownUtxo(OutPoint),
+
+    /// Address {0} does not belong to the document network
+    WrongNetwork(Address),
+
+    /// Invalid public key data: {0}
+    InvalidKey(String),
+
+    /// Invalid descriptor generator string `{0}`
+    InvalidGenerator(String),
+
+    /// No descriptor account exists at position {0}
+    UnknownDescriptor(usize),
+
+    /// Output category `{0}` is not enabled for the descriptor account
+    UnknownCategory(String),
+
+    /// Address #{0} is not in the address book
+    UnknownAddress(u32),
+
+    /// `{0}` is neither an address nor a hex-encoded script
+    InvalidScript(String),
+
+    /// Output descriptor error: {0}
+    #[from]
+    OutputDescriptor(bip380::Error),
+
+    /// Asset with contract id {0} is not known to the document
+    UnknownAsset(ContractId),
+
+    /// New passphrase must be given with `--passphrase-file` or
+    /// `BPRO_NEW_PASSPHRASE` environment variable; use `--remove` to remove
+    /// the document encryption
+    NoPassphrase,
+
+    /// Unable to write the output: {0}
+    Output(String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Output(err.to_string())
+    }
+}
+
+impl From<strict_encoding::Error> for Error {
+    fn from(err: strict_encoding::Error) -> Self {
+        Error::Output(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Output(err.to_string())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct DocInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub chain: String,
+    pub resolver: String,
+    pub tracking_accounts: usize,
+    pub descriptors: usize,
+    pub utxos: usize,
+    pub assets: usize,
+    pub encrypted: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct KeyInfo {
+    pub index: usize,
+    pub name: String,
+    pub key: String,
+    pub count: u32,
+}
+
+impl KeyInfo {
+    pub fn with(index: usize, account: &TrackingAccount) -> Self {
+        KeyInfo {
+            index,
+            name: account.name.clone(),
+            key: account.details(),
+            count: account.count(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct DescriptorInfo {
+    pub index: usize,
+    pub name: String,
+    pub type_name: String,
+    pub generator: String,
+    pub last_used_indexes: BTreeMap<String, u32>,
+}
+
+impl DescriptorInfo {
+    pub fn with(index: usize, account: &DescriptorAccount) -> Self {
+        DescriptorInfo {
+            index,
+            name: account.name(),
+            type_name: account.type_name(),
+            generator: account.descriptor(),
+            last_used_indexes: last_used_indexes(&account.last_used_indexes),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct OutputDescriptorInfo {
+    pub category: String,
+    pub descriptor: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct AddressInfo {
+    pub index: u32,
+    pub category: String,
+    pub address: String,
+    pub label: String,
+    pub note: String,
+    pub issued: Option<String>,
+    pub used: bool,
+}
+
+impl From<&AddressRow> for AddressInfo {
+    fn from(row: &AddressRow) -> Self {
+        AddressInfo {
+            index: row.index,
+            category: row.category.to_string(),
+            address: row.address_string(),
+            label: row.label.clone(),
+            note: row.note.clone(),
+            issued: row.issued.clone(),
+            used: row.used,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct ScriptMatchInfo {
+    pub account: String,
+    pub position: usize,
+    pub name: String,
+    pub category: String,
+    pub derivation_index: u32,
+}
+
+impl From<ScriptMatch> for ScriptMatchInfo {
+    fn from(found: ScriptMatch) -> Self {
+        let (account, position, name) = match found.owner {
+            ScriptOwner::Descriptor { position, name } => {
+                (s!("descriptor"), position, name)
+            }
+            ScriptOwner::Tracking { position, name } => {
+                (s!("key"), position, name)
+            }
+        };
+        ScriptMatchInfo {
+            account,
+            position,
+            name,
+            category: found.category.to_string(),
+            derivation_index: found.derivation_index,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct UtxoInfo {
+    pub txid: String,
+    pub vout: u32,
+    pub amount: u64,
+    pub height: u32,
+    pub category: String,
+    pub derivation_index: u32,
+}
+
+impl From<&UtxoEntry> for UtxoInfo {
+    fn from(utxo: &UtxoEntry) -> Self {
+        UtxoInfo {
+            txid: utxo.outpoint.txid.to_string(),
+            vout: utxo.outpoint.vout,
+            amount: utxo.amount,
+            height: utxo.height,
+            category: utxo.descriptor_category.to_string(),
+            derivation_index: utxo.derivation_index,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct HistoryInfo {
+    pub txid: String,
+    pub height: u32,
+    pub received: u64,
+    pub spent: u64,
+    pub net: i64,
+}
+
+impl From<&HistoryRow> for HistoryInfo {
+    fn from(row: &HistoryRow) -> Self {
+        HistoryInfo {
+            txid: row.txid.to_string(),
+            height: row.height,
+            received: row.received,
+            spent: row.spent,
+            net: row.net(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct LookupReport {
+    pub descriptor: String,
+    pub found: usize,
+    pub new: usize,
+    pub last_used_indexes: BTreeMap<String, u32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct SpentInfo {
+    pub txid: String,
+    pub vout: u32,
+    pub amount: u64,
+    pub spending_txid: Option<String>,
+}
+
+impl From<&SpentUtxo> for SpentInfo {
+    fn from(spent: &SpentUtxo) -> Self {
+        SpentInfo {
+            txid: spent.utxo.outpoint.txid.to_string(),
+            vout: spent.utxo.outpoint.vout,
+            amount: spent.utxo.amount,
+            spending_txid: spent.spending_txid.as_ref().map(|t| t.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct RefreshReport {
+    pub unspent: usize,
+    pub moved: Vec<UtxoInfo>,
+    pub spent: Vec<SpentInfo>,
+    pub unverified: Vec<UtxoInfo>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct SelectionInfo {
+    pub mode: String,
+    pub inputs: Vec<UtxoInfo>,
+    pub amount: u64,
+    pub fee: u64,
+    pub change: Option<u64>,
+    pub waste: i64,
+}
+
+impl From<&Selection> for SelectionInfo {
+    fn from(selection: &Selection) -> Self {
+        SelectionInfo {
+            mode: selection.mode.to_string(),
+            inputs: selection.utxos.iter().map(UtxoInfo::from).collect(),
+            amount: selection.amount(),
+            fee: selection.fee,
+            change: selection.change,
+            waste: selection.waste,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct PsbtInfo {
+    pub index: usize,
+    pub txid: String,
+    pub inputs: usize,
+    pub outputs: usize,
+    pub signed: usize,
+}
+
+impl PsbtInfo {
+    pub fn with(index: usize, row: &PsbtRow) -> Self {
+        PsbtInfo {
+            index,
+            txid: row.txid.to_string(),
+            inputs: row.inputs,
+            outputs: row.outputs,
+            signed: row.signed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct PsbtExport {
+    pub txid: String,
+    pub base64: String,
+    pub file: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct PsbtComposition {
+    pub index: usize,
+    pub txid: String,
+    pub fee: u64,
+    pub change: Option<u64>,
+    pub base64: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct PsbtFinalization {
+    pub index: usize,
+    pub txid: String,
+    pub finalized: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct TxExtract {
+    pub txid: String,
+    pub hex: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct BroadcastInfo {
+    pub txid: String,
+    pub fee: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct FeeEstimate {
+    pub blocks: u16,
+    pub fee_rate: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct AssetInfo {
+    pub contract_id: String,
+    pub ticker: String,
+    pub name: String,
+    pub known_circulating: Option<u64>,
+    pub decimals: u8,
+    pub inflatable: bool,
+}
+
+impl AssetInfo {
+    pub fn with(contract_id: ContractId, asset: &rgb20::Asset) -> Self {
+        AssetInfo {
+            contract_id: contract_id.to_bech32_string(),
+            ticker: asset.ticker().clone(),
+            name: asset.name().clone(),
+            known_circulating: asset
+                .precise_supply(rgb20::SupplyMeasure::KnownCirculating),
+            decimals: *asset.decimal_precision(),
+            inflatable: !asset.known_inflation().is_empty(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct AssetExport {
+    pub contract_id: String,
+    pub genesis: String,
+    pub file: Option<String>,
+}
+
+struct Lookup;
+
+impl UtxoLookup for Lookup {}
+impl HistoryLookup for Lookup {}
+impl UtxoRefresh for Lookup {}
+impl PsbtCompose for Lookup {}
+impl TxBroadcast for Lookup {}
+impl FeeBump for Lookup {}
+
+/// Reads transaction from the data in binary or hex form
+fn read_transaction(data: &[u8]) -> Result<Transaction, Error> {
+    let hex = String::from_utf8_lossy(data);
+    Vec::<u8>::from_hex(hex.trim())
+        .ok()
+        .and_then(|data| deserialize(&data).ok())
+        .or_else(|| deserialize(data).ok())
+        .ok_or(Error::InvalidTransaction)
+}
+
+/// Broadcasts the transaction and records it into the document history
+fn broadcast_tx(
+    doc: &mut Document,
+    tx: Transaction,
+    force: bool,
+) -> Result<BroadcastInfo, Error> {
+    let txid = Lookup.broadcast_tx(&*doc.resolver()?, &tx, !force, |txid| {
+        doc.transaction(txid).cloned()
+    })?;
+    let fee = doc.tx_fee(&tx);
+    doc.record_broadcast(tx)?;
+    Ok(BroadcastInfo {
+        txid: txid.to_string(),
+        fee,
+    })
+}
+
+/// Adds composed PSBT to the document, reporting it
+fn add_composition(
+    doc: &mut Document,
+    composition: Composition,
+) -> Result<PsbtComposition, Error> {
+    let report = PsbtComposition {
+        index: doc.psbts().len(),
+        txid: composition.psbt.unsigned_tx.txid().to_string(),
+        fee: composition.fee,
+        change: composition.change,
+        base64: psbt::psbt_base64(&composition.psbt),
+    };
+    doc.add_composed_psbt(composition.psbt, composition.change_link.as_ref())?;
+    Ok(report)
+}
+
+/// Converts payments into transaction outputs, checking that addresses
+/// match the document network
+fn payment_outputs(
+    doc: &Document,
+    payments: Vec<Payment>,
+) -> Result<Vec<TxOut>, Error> {
+    let network = doc.network();
+    payments
+        .into_iter()
+        .map(|payment| match network {
+            Some(network) if !payment.address.is_valid_for_network(network) => {
+                Err(Error::WrongNetwork(payment.address))
+            }
+            _ => Ok(TxOut {
+                value: payment.amount,
+                script_pubkey: payment.address.script_pubkey(),
+            }),
+        })
+        .collect()
+}
+
+/// Descriptor account receiving the change, together with the derivation
+/// index of the next unallocated address used for it
+fn change_account(
+    doc: &Document,
+    change: Option<usize>,
+) -> Result<Option<(DescriptorAccount, u32)>, Error> {
+    change
+        .map(|index| {
+            let account = doc
+                .descriptor_at(index)
+                .ok_or(Error::UnknownDescriptor(index))?;
+            let index = doc.next_address_index(&account)?;
+            Ok((account, index))
+        })
+        .transpose()
+}
+
+/// Enabled output category of the descriptor account matching the given name,
+/// defaulting to the first of the enabled categories
+fn account_category(
+    account: &DescriptorAccount,
+    category: Option<String>,
+) -> Result<descriptors::Category, Error> {
+    let categories = account.categories();
+    let name = category.unwrap_or_else(|| {
+        categories
+            .first()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    });
+    categories
+        .into_iter()
+        .find(|category| category.to_string().eq_ignore_ascii_case(&name))
+        .ok_or(Error::UnknownCategory(name))
+}
+
+/// Address of the descriptor account with its address book data
+fn address_info(
+    doc: &Document,
+    account: &DescriptorAccount,
+    category: descriptors::Category,
+    index: u32,
+) -> Option<AddressInfo> {
+    doc.address_rows(account, category, index, 1)
+        .first()
+        .map(AddressInfo::from)
+}
+
+/// Last used derivation indexes keyed by the output category names
+fn last_used_indexes(
+    indexes: &BTreeMap<descriptors::Category, u32>,
+) -> BTreeMap<String, u32> {
+    indexes
+        .iter()
+        .map(|(category, index)| (category.to_string(), *index))
+        .collect()
+}
+
+/// Passphrase from the first line of the file or, if no file is given, from
+/// the environment variable; an empty passphrase counts as a missing one
+fn read_passphrase(
+    file: Option<&Path>,
+    var: &str,
+) -> Result<Option<String>, Error> {
+    let passphrase = match file {
+        Some(path) => fs::read_to_string(path)?
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_owned(),
+        None => env::var(var).unwrap_or_default(),
+    };
+    Ok(Some(passphrase).filter(|passphrase| !passphrase.is_empty()))
+}
+
+/// New document passphrase for `doc passphrase`
+fn new_passphrase(file: Option<&Path>) -> Result<String, Error> {
+    read_passphrase(file, NEW_PASSPHRASE_ENV)?.ok_or(Error::NoPassphrase)
+}
+
+fn print<T>(value: &T, compact: bool) -> Result<(), Error>
+where
+    T: Serialize,
+{
+    let output = if compact {
+        serde_json::to_string(value)?
+    } else {
+        serde_json::to_string_pretty(value)?
+    };
+    println!("{}", output);
+    Ok(())
+}
+
+impl Opts {
+    pub fn exec(self) -> Result<(), Error> {
+        let passphrase =
+            read_passphrase(self.passphrase_file.as_deref(), PASSPHRASE_ENV)?;
+        if let Command::Doc {
+            subcommand:
+                DocCommand::New {
+                    chain,
+                    electrum,
+                    bitcoin_core,
+                },
+        } = &self.command
+        {
+            let mut doc = Document::new();
+            doc.set_chain(&chain.to_string())?;
+            if let Some(addr) = electrum {
+                doc.set_electrum(*addr)?;
+            }
+            if let Some(connection) = bitcoin_core {
+                doc.set_bitcoin_core(connection.clone())?;
+            }
+            doc.set_passphrase(passphrase.as_deref())?;
+            doc.save_as(self.file.clone())?;
+            return print(&doc_info(&doc), self.compact);
+        }
+        if let Command::Doc {
+            subcommand: DocCommand::Recover,
+        } = &self.command
+        {
+            let mut doc =
+                Document::recover(self.file.clone(), passphrase.as_deref())?;
+            doc.save()?;
+            return print(&doc_info(&doc), self.compact);
+        }
+        if let Command::Doc {
+            subcommand: DocCommand::Import { source, format },
+        } = &self.command
+        {
+            let format = format
+                .or_else(|| Format::with_path(source))
+                .unwrap_or(Format::Json);
+            let data =
+                fs::read_to_string(source).map_err(model::Error::from)?;
+            let mut doc = Document::import(&data, format)?;
+            doc.set_passphrase(passphrase.as_deref())?;
+            doc.save_as(self.file.clone())?;
+            return print(&doc_info(&doc), self.compact);
+        }
+
+        let mut doc = Document::load_with_passphrase(
+            self.file.clone(),
+            passphrase.as_deref(),
+        )?;
+        match self.command {
+            Command::Doc { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+            Command::Key { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+            Command::Descriptor { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+            Command::Address { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+            Command::Utxo { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+            Command::Psbt { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+            Command::Tx { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+            Command::Asset { subcommand } => {
+                subcommand.exec(&mut doc, self.compact)
+            }
+        }
+    }
+}
+
+fn doc_info(doc: &Document) -> DocInfo {
+    DocInfo {
+        name: doc.name(),
+        description: doc.description().map(str::to_owned),
+        chain: doc.chain().to_string(),
+        resolver: doc.resolver_config().to_string(),
+        tracking_accounts: doc.tracking_accounts().len(),
+        descriptors: doc.descriptors().len(),
+        utxos: doc.utxos(None).len(),
+        assets: doc.asset_ids().len(),
+        encrypted: doc.is_encrypted(),
+    }
+}
+
+impl DocCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            DocCommand::New { .. }
+            | DocCommand::Recover
+            | DocCommand::Import { .. } => {
+                unreachable!("command is processed before document loading")
+            }
+            DocCommand::Info => print(&doc_info(doc), compact),
+            DocCommand::Passphrase {
+                passphrase_file,
+                remove,
+            } => {
+                let new = match remove {
+                    true => None,
+                    false => Some(new_passphrase(passphrase_file.as_deref())?),
+                };
+                doc.set_passphrase(new.as_deref())?;
+                print(&doc_info(doc), compact)
+            }
+            DocCommand::Export { format, output } => {
+                let format = format
+                    .or_else(|| output.as_deref().and_then(Format::with_path))
+                    .unwrap_or(Format::Json);
+                let data = doc.export(format)?;
+                match output {
+                    Some(path) => fs::write(path, data)?,
+                    None => println!("{}", data.trim_end()),
+                }
+                Ok(())
+            }
+            DocCommand::Log { format, output } => {
+                let format = format
+                    .or_else(|| {
+                        output.as_deref().and_then(LogFormat::with_path)
+                    })
+                    .unwrap_or(LogFormat::Json);
+                let data = doc.export_log(format)?;
+                match output {
+                    Some(path) => fs::write(path, data)?,
+                    None => println!("{}", data.trim_end()),
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl KeyCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            KeyCommand::Add { name, key } => {
+                let key = descriptors::SingleSig::from_str(&key)
+                    .map_err(|err| Error::InvalidKey(err.to_string()))?;
+                let account = TrackingAccount { name, key };
+                let index = doc.tracking_accounts().len();
+                doc.add_tracking_account(account.clone())?;
+                print(&KeyInfo::with(index, &account), compact)
+            }
+            KeyCommand::List => print(
+                &doc.tracking_accounts()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, account)| KeyInfo::with(index, account))
+                    .collect::<Vec<_>>(),
+                compact,
+            ),
+            KeyCommand::Remove { index } => {
+                let account = doc
+                    .tracking_account_at(index)
+                    .ok_or(model::Error::WrongPosition(index))?;
+                doc.remove_tracking_account_at(index)?;
+                print(&KeyInfo::with(index, &account), compact)
+            }
+        }
+    }
+}
+
+impl DescriptorCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            DescriptorCommand::Add { name, generator } => {
+                let generator = descriptors::Generator::from_str(&generator)
+                    .map_err(|_| Error::InvalidGenerator(generator))?;
+                let account = DescriptorAccount {
+                    name,
+                    generator,
+                    last_used_indexes: empty!(),
+                    key_origins: empty!(),
+                    tap_leaves: empty!(),
+                };
+                let index = doc.descriptors().len();
+                doc.add_descriptor(account.clone())?;
+                print(&DescriptorInfo::with(index, &account), compact)
+            }
+            DescriptorCommand::Import { name, descriptor } => {
+                let accounts =
+                    DescriptorAccount::from_bip380(&name, &descriptor)?;
+                let mut infos = vec![];
+                for account in accounts {
+                    let index = doc.descriptors().len();
+                    doc.add_descriptor(account.clone())?;
+                    infos.push(DescriptorInfo::with(index, &account));
+                }
+                print(&infos, compact)
+            }
+            DescriptorCommand::Export { descriptor } => {
+                let account = doc
+                    .descriptor_at(descriptor)
+                    .ok_or(Error::UnknownDescriptor(descriptor))?;
+                print(
+                    &account
+                        .to_bip380()?
+                        .into_iter()
+                        .map(|(category, descriptor)| OutputDescriptorInfo {
+                            category: category.to_string(),
+                            descriptor,
+                        })
+                        .collect::<Vec<_>>(),
+                    compact,
+                )
+            }
+            DescriptorCommand::List => print(
+                &doc.descriptors()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, account)| {
+                        DescriptorInfo::with(index, account)
+                    })
+                    .collect::<Vec<_>>(),
+                compact,
+            ),
+            DescriptorCommand::History {
+                descriptor,
+                refresh,
+                mode,
+            } => {
+                let account = doc
+                    .descriptor_at(descriptor)
+                    .ok_or(Error::UnknownDescriptor(descriptor))?;
+                if refresh {
+                    let update = Lookup.history_lookup(
+                        &*doc.resolver()?,
+                        mode,
+                        &account,
+                        |txid| doc.is_transaction_known(txid),
+                    )?;
+                    doc.update_tx_history(update.transactions, update.entries)?;
+                    doc.update_last_used_indexes(
+                        &account,
+                        &update.last_used_indexes,
+                    )?;
+                }
+                print(
+                    &doc.history_rows(&account)
+                        .iter()
+                        .map(HistoryInfo::from)
+                        .collect::<Vec<_>>(),
+                    compact,
+                )
+            }
+        }
+    }
+}
+
+impl AddressCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            AddressCommand::List {
+                descriptor,
+                category,
+                from,
+                count,
+                csv,
+                output,
+            } => {
+                let account = doc
+                    .descriptor_at(descriptor)
+                    .ok_or(Error::UnknownDescriptor(descriptor))?;
+                let category = account_category(&account, category)?;
+                let rows = doc.address_rows(&account, category, from, count);
+                let infos =
+                    rows.iter().map(AddressInfo::from).collect::<Vec<_>>();
+                let data = match (csv, &output) {
+                    (true, _) => addresses_csv(&rows),
+                    (false, Some(_)) => serde_json::to_string_pretty(&infos)?,
+                    (false, None) => return print(&infos, compact),
+                };
+                match output {
+                    Some(path) => fs::write(path, data)?,
+                    None => println!("{}", data.trim_end()),
+                }
+                Ok(())
+            }
+            AddressCommand::Issue {
+                descriptor,
+                category,
+                index,
+                label,
+                note,
+            } => {
+                let account = doc
+                    .descriptor_at(descriptor)
+                    .ok_or(Error::UnknownDescriptor(descriptor))?;
+                let category = account_category(&account, category)?;
+                let index = match index {
+                    Some(index) => index,
+                    None => doc.next_address_index(&account)?,
+                };
+                let entry =
+                    doc.address_entry(&account, category, index).cloned();
+                let label = label
+                    .or_else(|| entry.as_ref().map(|entry| entry.label.clone()))
+                    .unwrap_or_default();
+                let note = note
+                    .or_else(|| entry.map(|entry| entry.note))
+                    .unwrap_or_default();
+                doc.issue_address(&account, category, index, label, note)?;
+                print(&address_info(doc, &account, category, index), compact)
+            }
+            AddressCommand::Remove {
+                descriptor,
+                index,
+                category,
+            } => {
+                let account = doc
+                    .descriptor_at(descriptor)
+                    .ok_or(Error::UnknownDescriptor(descriptor))?;
+                let category = account_category(&account, category)?;
+                if doc.address_entry(&account, category, index).is_none() {
+                    return Err(Error::UnknownAddress(index));
+                }
+                doc.remove_address(&account, category, index)?;
+                print(&address_info(doc, &account, category, index), compact)
+            }
+            AddressCommand::Find { script, window } => {
+                let script_pubkey = match Address::from_str(&script) {
+                    Ok(address) => address.script_pubkey(),
+                    Err(_) => Vec::<u8>::from_hex(&script)
+                        .map(Script::from)
+                        .map_err(|_| Error::InvalidScript(script))?,
+                };
+                print(
+                    &doc.find_script(&script_pubkey, window)
+                        .into_iter()
+                        .map(ScriptMatchInfo::from)
+                        .collect::<Vec<_>>(),
+                    compact,
+                )
+            }
+            AddressCommand::Qr {
+                descriptor,
+                index,
+                output,
+                category,
+            } => {
+                let account = doc
+                    .descriptor_at(descriptor)
+                    .ok_or(Error::UnknownDescriptor(descriptor))?;
+                let category = account_category(&account, category)?;
+                let info = address_info(doc, &account, category, index);
+                if let Some(ref info) = info {
+                    let png = qrcode_generator::to_png_to_vec(
+                        format!("bitcoin:{}", info.address),
+                        QrCodeEcc::Low,
+                        1024,
+                    )
+                    .map_err(|err| Error::Output(err.to_string()))?;
+                    fs::write(output, png)?;
+                }
+                print(&info, compact)
+            }
+        }
+    }
+}
+
+impl UtxoCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            UtxoCommand::Lookup { descriptor, mode } => {
+                let account = doc
+                    .descriptor_at(descriptor)
+                    .ok_or(Error::UnknownDescriptor(descriptor))?;
+                let resolver = doc.resolver()?;
+                let mode = mode.unwrap_or_else(|| {
+                    ResolverModeType::default_for(
+                        resolver.provides_script_history(),
+                    )
+                });
+                let utxo_set = Rc::new(RefCell::new(HashSet::new()));
+                let result = Lookup.utxo_lookup(
+                    &*resolver,
+                    mode,
+                    account.clone(),
+                    utxo_set.clone(),
+                    |_| {},
+                )?;
+                let update = utxo_set.borrow().clone();
+                let new = update
+                    .iter()
+                    .filter(|utxo| !doc.is_outpoint_known(utxo.outpoint))
+                    .count();
+                doc.update_utxo_set(update)?;
+                doc.update_last_used_indexes(
+                    &account,
+                    &result.last_used_indexes,
+                )?;
+                print(
+                    &LookupReport {
+                        descriptor: account.descriptor(),
+                        found: result.found,
+                        new,
+                        last_used_indexes: last_used_indexes(
+                            &result.last_used_indexes,
+                        ),
+                    },
+                    compact,
+                )
+            }
+            UtxoCommand::Refresh => {
+                let reconciliation = Lookup.utxo_refresh(
+                    &*doc.resolver()?,
+                    doc.descriptors(),
+                    doc.utxos(None),
+                    |txid| doc.transaction(txid).cloned(),
+                )?;
+                let report = RefreshReport {
+                    unspent: reconciliation.unspent,
+                    moved: reconciliation
+                        .moved
+                        .iter()
+                        .map(UtxoInfo::from)
+                        .collect(),
+                    spent: reconciliation
+                        .spent
+                        .iter()
+                        .map(SpentInfo::from)
+                        .collect(),
+                    unverified: reconciliation
+                        .unverified
+                        .iter()
+                        .map(UtxoInfo::from)
+                        .collect(),
+                };
+                doc.reconcile_utxos(
+                    reconciliation.moved,
+                    reconciliation
+                        .spent
+                        .into_iter()
+                        .map(|spent| spent.utxo)
+                        .collect(),
+                )?;
+                doc.update_tx_history(
+                    reconciliation.transactions,
+                    reconciliation.entries,
+                )?;
+                print(&report, compact)
+            }
+            UtxoCommand::Select {
+                payments,
+                fee_rate,
+                change,
+                same_descriptor,
+            } => {
+                let outputs = payment_outputs(doc, payments)?;
+                let change = change_account(doc, change)?;
+                let params = psbt_compose::selection_params(
+                    &outputs,
+                    fee_rate,
+                    change.as_ref().map(|(account, index)| (account, *index)),
+                )?;
+                print(
+                    &coin_select::select_all(
+                        &doc.utxos(None),
+                        &params,
+                        same_descriptor,
+                    )
+                    .iter()
+                    .map(SelectionInfo::from)
+                    .collect::<Vec<_>>(),
+                    compact,
+                )
+            }
+            UtxoCommand::List { descriptor } => {
+                let filter = descriptor
+                    .map(|pos| {
+                        doc.descriptor_at(pos)
+                            .ok_or(Error::UnknownDescriptor(pos))
+                    })
+                    .transpose()?;
+                print(
+                    &doc.utxos(filter.as_ref())
+                        .iter()
+                        .map(UtxoInfo::from)
+                        .collect::<Vec<_>>(),
+                    compact,
+                )
+            }
+        }
+    }
+}
+
+impl PsbtCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            PsbtCommand::Import { source } => {
+                let psbt = psbt::parse_psbt(&fs::read(source)?)?;
+                let txid = psbt.unsigned_tx.txid();
+                let index = match doc
+                    .psbts()
+                    .iter()
+                    .position(|stored| stored.unsigned_tx.txid() == txid)
+                {
+                    Some(index) => {
+                        let mut merged = doc.psbts()[index].clone();
+                        psbt_finalize::merge_psbt(&mut merged, psbt)?;
+                        doc.update_psbt_at(index, merged)?;
+                        index
+                    }
+                    None => {
+                        doc.add_psbt(psbt)?;
+                        doc.psbts().len() - 1
+                    }
+                };
+                print(&PsbtInfo::with(index, &doc.psbt_rows()[index]), compact)
+            }
+            PsbtCommand::List => print(
+                &doc.psbt_rows()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, row)| PsbtInfo::with(index, row))
+                    .collect::<Vec<_>>(),
+                compact,
+            ),
+            PsbtCommand::Export { index, output } => {
+                let psbt = doc
+                    .psbt_at(index)
+                    .ok_or(model::Error::WrongPosition(index))?;
+                if let Some(ref path) = output {
+                    fs::write(path, psbt::psbt_binary(psbt))?;
+                }
+                print(
+                    &PsbtExport {
+                        txid: psbt.unsigned_tx.txid().to_string(),
+                        base64: psbt::psbt_base64(psbt),
+                        file: output.map(|path| path.display().to_string()),
+                    },
+                    compact,
+                )
+            }
+            PsbtCommand::Remove { index } => {
+                let row = doc
+                    .psbt_rows()
+                    .get(index)
+                    .cloned()
+                    .ok_or(model::Error::WrongPosition(index))?;
+                doc.remove_psbt_at(index)?;
+                print(&PsbtInfo::with(index, &row), compact)
+            }
+            PsbtCommand::Compose {
+                payments,
+                inputs,
+                select,
+                same_descriptor,
+                fee_rate,
+                change,
+            } => {
+                let outputs = payment_outputs(doc, payments)?;
+                let mut change = change_account(doc, change)?;
+                let utxos = match select {
+                    Some(mode) => {
+                        let params = psbt_compose::selection_params(
+                            &outputs,
+                            fee_rate,
+                            change
+                                .as_ref()
+                                .map(|(account, index)| (account, *index)),
+                        )?;
+                        let selection = coin_select::select(
+                            mode,
+                            &doc.utxos(None),
+                            &params,
+                            same_descriptor,
+                        )?;
+                        // Changeless selection leaves the excess to the fee
+                        if selection.change.is_none() {
+                            change = None;
+                        }
+                        selection.utxos
+                    }
+                    None => inputs
+                        .into_iter()
+                        .map(|outpoint| {
+                            doc.utxo_by_outpoint(outpoint)
+                                .ok_or(Error::UnknownUtxo(outpoint))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                };
+                let composition = Lookup.compose_psbt(
+                    &*doc.resolver()?,
+                    doc.descriptors(),
+                    utxos,
+                    outputs,
+                    fee_rate,
+                    change.as_ref().map(|(account, index)| (account, *index)),
+                    |txid| doc.transaction(txid).cloned(),
+                )?;
+                let report = add_composition(doc, composition)?;
+                print(&report, compact)
+            }
+            PsbtCommand::Finalize { index } => {
+                let mut psbt = doc
+                    .psbt_at(index)
+                    .cloned()
+                    .ok_or(model::Error::WrongPosition(index))?;
+                let finalized = psbt_finalize::finalize_psbt(
+                    &mut psbt,
+                    doc.descriptors(),
+                    &doc.utxos(None),
+                )?;
+                let txid = psbt.unsigned_tx.txid().to_string();
+                doc.update_psbt_at(index, psbt)?;
+                print(
+                    &PsbtFinalization {
+                        index,
+                        txid,
+                        finalized,
+                    },
+                    compact,
+                )
+            }
+            PsbtCommand::Extract { index, remove } => {
+                let psbt = doc
+                    .psbt_at(index)
+                    .ok_or(model::Error::WrongPosition(index))?;
+                let tx = psbt_finalize::extract_tx(psbt)?;
+                let report = TxExtract {
+                    txid: tx.txid().to_string(),
+                    hex: serialize_hex(&tx),
+                };
+                doc.update_tx_history(vec![tx], none!())?;
+                if remove {
+                    doc.remove_psbt_at(index)?;
+                }
+                print(&report, compact)
+            }
+            PsbtCommand::Broadcast {
+                index,
+                force,
+                remove,
+            } => {
+                let psbt = doc
+                    .psbt_at(index)
+                    .ok_or(model::Error::WrongPosition(index))?;
+                let tx = psbt_finalize::extract_tx(psbt)?;
+                let report = broadcast_tx(doc, tx, force)?;
+                if remove {
+                    doc.remove_psbt_at(index)?;
+                }
+                print(&report, compact)
+            }
+        }
+    }
+}
+
+impl TxCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            TxCommand::Broadcast { source, force } => {
+                let tx = read_transaction(&fs::read(source)?)?;
+                let report = broadcast_tx(doc, tx, force)?;
+                print(&report, compact)
+            }
+            TxCommand::EstimateFee { blocks } => {
+                let fee_rate = doc.resolver()?.estimate_fee(blocks)?;
+                print(&FeeEstimate { blocks, fee_rate }, compact)
+            }
+            TxCommand::Rbf {
+                txid,
+                fee_rate,
+                blocks,
+            } => {
+                let tx = doc
+                    .transaction(txid)
+                    .cloned()
+                    .ok_or(Error::UnknownTransaction(txid))?;
+                let resolver = doc.resolver()?;
+                let fee_rate = match fee_rate {
+                    Some(fee_rate) => fee_rate,
+                    None => resolver.estimate_fee(blocks)?,
+                };
+                let composition = Lookup.rbf_psbt(
+                    &*resolver,
+                    doc.descriptors(),
+                    doc.tx_links(txid),
+                    |account| doc.next_address_index(account),
+                    &tx,
+                    fee_rate,
+                    |txid| doc.transaction(txid).cloned(),
+                )?;
+                let report = add_composition(doc, composition)?;
+                print(&report, compact)
+            }
+            TxCommand::Cpfp {
+                txid,
+                fee_rate,
+                blocks,
+            } => {
+                let tx = doc
+                    .transaction(txid)
+                    .cloned()
+                    .ok_or(Error::UnknownTransaction(txid))?;
+                if doc.transaction_height(txid).unwrap_or_default() > 0 {
+                    return Err(Error::ConfirmedTransaction(txid));
+                }
+                let resolver = doc.resolver()?;
+                let fee_rate = match fee_rate {
+                    Some(fee_rate) => fee_rate,
+                    None => resolver.estimate_fee(blocks)?,
+                };
+                let composition = Lookup.cpfp_psbt(
+                    &*resolver,
+                    doc.descriptors(),
+                    doc.tx_links(txid),
+                    |account| doc.next_address_index(account),
+                    &tx,
+                    fee_rate,
+                    |txid| doc.transaction(txid).cloned(),
+                )?;
+                let report = add_composition(doc, composition)?;
+                print(&report, compact)
+            }
+        }
+    }
+}
+
+impl AssetCommand {
+    pub fn exec(self, doc: &mut Document, compact: bool) -> Result<(), Error> {
+        match self {
+            AssetCommand::Issue {
+                ticker,
+                name,
+                description,
+                precision,
+                allocations,
+                inflation,
+                renomination,
+                epoch,
+            } => {
+                let (asset, genesis) = rgb20::issue(
+                    doc.chain().clone(),
+                    ticker.to_uppercase(),
+                    name,
+                    description,
+                    precision,
+                    allocations
+                        .into_iter()
+                        .map(|alloc| (alloc.outpoint, alloc.coins))
+                        .collect(),
+                    inflation
+                        .into_iter()
+                        .map(|alloc| (alloc.outpoint, alloc.coins))
+                        .collect(),
+                    renomination,
+                    epoch,
+                )?;
+                let contract_id = genesis.contract_id();
+                let consignment =
+                    Consignment::with(genesis, none!(), none!(), none!());
+                doc.add_asset(consignment)?;
+                print(&AssetInfo::with(contract_id, &asset), compact)
+            }
+            AssetCommand::List => print(
+                &doc.asset_ids()
+                    .into_iter()
+                    .filter_map(|contract_id| {
+                        doc.asset_by_id(contract_id).map(|(asset, _)| {
+                            AssetInfo::with(contract_id, &asset)
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+                compact,
+            ),
+            AssetCommand::Export {
+                contract_id,
+                output,
+            } => {
+                let consignment = doc
+                    .asset_consignment(contract_id)
+                    .ok_or(Error::UnknownAsset(contract_id))?;
+                if let Some(ref path) = output {
+                    export_consignment(consignment, path)?;
+                }
+                print(
+                    &AssetExport {
+                        contract_id: contract_id.to_bech32_string(),
+                        genesis: consignment.genesis.to_bech32_string(),
+                        file: output.map(|path| path.display().to_string()),
+                    },
+                    compact,
+                )
+            }
+        }
+    }
+}
+
+fn export_consignment(
+    consignment: &Consignment,
+    path: &Path,
+) -> Result<(), Error> {
+    let file = File::create(path)?;
+    consignment.strict_encode(file)?;
+    Ok(())
+}
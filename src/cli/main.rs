@@ -0,0 +1,38 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Command-line interface to Bitcoin Pro documents, providing access to the
+//! same document model as the GUI application, with JSON output suitable for
+//! scripting and CI use.
+
+#[macro_use]
+extern crate amplify;
+
+mod command;
+mod opts;
+
+use clap::Parser;
+
+use crate::opts::Opts;
+
+fn main() {
+    let opts = Opts::parse();
+    if let Err(err) = opts.exec() {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
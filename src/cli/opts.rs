@@ -0,0 +1,664 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bitcoin::{Address, OutPoint, Txid};
+use clap::{Parser, Subcommand};
+use lnpbp::chain::Chain;
+use rgb::{AtomicValue, ContractId};
+
+use bitcoin_pro::controller::coin_select::SelectionMode;
+use bitcoin_pro::model::export::Format;
+use bitcoin_pro::model::{CoreConnection, LogFormat};
+use bitcoin_pro::util::resolver_mode::ResolverModeType;
+
+/// Command-line tool for working with Bitcoin Pro documents without GUI
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    name = "bpro",
+    bin_name = "bpro",
+    author,
+    version,
+    about = "Headless access to Bitcoin Pro (.bpro) documents"
+)]
+pub struct Opts {
+    /// Path to the Bitcoin Pro document
+    #[clap(short, long, env = "BPRO_FILE", value_name = "FILE")]
+    pub file: PathBuf,
+
+    /// File with the passphrase for the encrypted document on its first
+    /// line. Without it the passphrase is read from `BPRO_PASSPHRASE`
+    /// environment variable, so it does not appear in the process list or
+    /// shell history. New documents are encrypted when a passphrase is given.
+    #[clap(long, value_name = "FILE")]
+    pub passphrase_file: Option<PathBuf>,
+
+    /// Print compact single-line JSON instead of the pretty-printed one
+    #[clap(short, long)]
+    pub compact: bool,
+
+    /// Command to execute
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Document-level operations
+    Doc {
+        #[clap(subcommand)]
+        subcommand: DocCommand,
+    },
+
+    /// Extended public key tracking accounts
+    Key {
+        #[clap(subcommand)]
+        subcommand: KeyCommand,
+    },
+
+    /// Output descriptor accounts
+    Descriptor {
+        #[clap(subcommand)]
+        subcommand: DescriptorCommand,
+    },
+
+    /// Receive addresses of the descriptor accounts and their address book
+    Address {
+        #[clap(subcommand)]
+        subcommand: AddressCommand,
+    },
+
+    /// UTXO cache and lookup
+    Utxo {
+        #[clap(subcommand)]
+        subcommand: UtxoCommand,
+    },
+
+    /// Partially signed bitcoin transactions (PSBTs)
+    Psbt {
+        #[clap(subcommand)]
+        subcommand: PsbtCommand,
+    },
+
+    /// Signed transactions
+    Tx {
+        #[clap(subcommand)]
+        subcommand: TxCommand,
+    },
+
+    /// RGB-20 fungible assets
+    Asset {
+        #[clap(subcommand)]
+        subcommand: AssetCommand,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum DocCommand {
+    /// Creates a new empty document at the path given by `--file`
+    New {
+        /// Bitcoin network used by the document
+        #[clap(long, default_value = "testnet")]
+        chain: Chain,
+
+        /// Electrum server used for UTXO lookups
+        #[clap(long, conflicts_with = "bitcoin_core")]
+        electrum: Option<std::net::SocketAddr>,
+
+        /// Bitcoin Core node used for UTXO lookups, given as
+        /// `[user:password@]host:port`; without user name and password the
+        /// authentication cookie from the default data directory is used
+        #[clap(long)]
+        bitcoin_core: Option<CoreConnection>,
+    },
+
+    /// Prints document summary
+    Info,
+
+    /// Changes document encryption passphrase. The new passphrase is read
+    /// from the file given with `--passphrase-file` or from
+    /// `BPRO_NEW_PASSPHRASE` environment variable, so it does not appear
+    /// in the process list or shell history.
+    Passphrase {
+        /// File with the new passphrase on its first line
+        #[clap(long, value_name = "FILE")]
+        passphrase_file: Option<PathBuf>,
+
+        /// Removes the document encryption
+        #[clap(long, conflicts_with = "passphrase_file")]
+        remove: bool,
+    },
+
+    /// Restores a damaged document from its most recent readable backup
+    Recover,
+
+    /// Exports the whole document in a human-readable format
+    Export {
+        /// Export format; defaults to the output file extension or JSON
+        #[clap(long)]
+        format: Option<Format>,
+
+        /// Writes the export into the file instead of printing it
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Exports the log of the document operations
+    Log {
+        /// Export format, `csv` or `json`; defaults to the output file
+        /// extension or JSON
+        #[clap(long)]
+        format: Option<LogFormat>,
+
+        /// Writes the log into the file instead of printing it
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Creates the document at the path given by `--file` from the
+    /// previously exported one
+    Import {
+        /// File with exported document
+        source: PathBuf,
+
+        /// Import format; defaults to the source file extension or JSON
+        #[clap(long)]
+        format: Option<Format>,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum KeyCommand {
+    /// Adds new public key tracking account
+    Add {
+        /// Name for the tracking account
+        name: String,
+
+        /// Public key or extended public key with derivation information
+        key: String,
+    },
+
+    /// Lists all public key tracking accounts
+    List,
+
+    /// Removes tracking account at the given position
+    Remove {
+        /// Position of the account, as reported by `key list`
+        index: usize,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum DescriptorCommand {
+    /// Adds new descriptor account
+    Add {
+        /// Name for the descriptor account
+        name: String,
+
+        /// Descriptor generator in form of `variants<template>`
+        generator: String,
+    },
+
+    /// Imports descriptor accounts from a standard (BIP380) output
+    /// descriptor; a multipath descriptor adds an account per path
+    Import {
+        /// Name for the descriptor account
+        name: String,
+
+        /// Output descriptor, optionally with a checksum, like
+        /// `wpkh([d34db33f/84h/0h/0h]xpub.../<0;1>/*)#checksum`
+        descriptor: String,
+    },
+
+    /// Exports a descriptor account as standard (BIP380) output
+    /// descriptors, one per enabled output category
+    Export {
+        /// Position of the descriptor account, as reported by
+        /// `descriptor list`
+        descriptor: usize,
+    },
+
+    /// Lists all descriptor accounts
+    List,
+
+    /// Shows transaction history of a descriptor account
+    History {
+        /// Position of the descriptor account, as reported by
+        /// `descriptor list`
+        descriptor: usize,
+
+        /// Looks up the transaction history using the resolver configured
+        /// in the document before showing it. Not available with Bitcoin
+        /// Core resolver, which does not index transactions by scripts.
+        #[clap(short, long)]
+        refresh: bool,
+
+        /// Lookup mode used with `--refresh`: `gapN`, `while`, `firstN` or
+        /// `randomN`
+        #[clap(short, long, default_value = "gap20")]
+        mode: ResolverModeType,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum AddressCommand {
+    /// Lists receive addresses of a descriptor account, together with
+    /// their address book data and usage
+    List {
+        /// Position of the descriptor account, as reported by
+        /// `descriptor list`
+        descriptor: usize,
+
+        /// Output category of the addresses, like `segwit`; defaults to the
+        /// first category enabled for the account
+        #[clap(short, long)]
+        category: Option<String>,
+
+        /// Derivation index of the first address
+        #[clap(long, default_value = "0")]
+        from: u32,
+
+        /// Number of addresses to list
+        #[clap(short = 'n', long, default_value = "20")]
+        count: u32,
+
+        /// Lists addresses as CSV instead of JSON
+        #[clap(long)]
+        csv: bool,
+
+        /// Writes the list into the file instead of printing it
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Hands out a receive address, adding it to the address book; for an
+    /// address which was already handed out updates its label and note
+    Issue {
+        /// Position of the descriptor account, as reported by
+        /// `descriptor list`
+        descriptor: usize,
+
+        /// Output category of the address, like `segwit`; defaults to the
+        /// first category enabled for the account
+        #[clap(short, long)]
+        category: Option<String>,
+
+        /// Derivation index of the address; defaults to the first address
+        /// which was neither handed out nor used
+        #[clap(short, long)]
+        index: Option<u32>,
+
+        /// Short label, like the name of the payer
+        #[clap(short, long)]
+        label: Option<String>,
+
+        /// Notes about the payment expected to the address
+        #[clap(long)]
+        note: Option<String>,
+    },
+
+    /// Removes address from the address book
+    Remove {
+        /// Position of the descriptor account, as reported by
+        /// `descriptor list`
+        descriptor: usize,
+
+        /// Derivation index of the address
+        index: u32,
+
+        /// Output category of the address, like `segwit`; defaults to the
+        /// first category enabled for the account
+        #[clap(short, long)]
+        category: Option<String>,
+    },
+
+    /// Finds descriptor and public key tracking accounts deriving the
+    /// address or script, reporting the category and derivation index
+    Find {
+        /// Address or hex-encoded script pubkey
+        script: String,
+
+        /// Number of derivation indexes, starting from zero, which are
+        /// checked for each account
+        #[clap(short, long, default_value = "1000")]
+        window: u32,
+    },
+
+    /// Writes QR code with the `bitcoin:` URI of a receive address as PNG
+    /// image
+    Qr {
+        /// Position of the descriptor account, as reported by
+        /// `descriptor list`
+        descriptor: usize,
+
+        /// Derivation index of the address
+        index: u32,
+
+        /// File for the PNG image
+        output: PathBuf,
+
+        /// Output category of the address, like `segwit`; defaults to the
+        /// first category enabled for the account
+        #[clap(short, long)]
+        category: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum UtxoCommand {
+    /// Looks up unspent outputs for a descriptor account using the
+    /// resolver configured in the document
+    Lookup {
+        /// Position of the descriptor account, as reported by
+        /// `descriptor list`
+        descriptor: usize,
+
+        /// Lookup mode: `gapN` (scan until N consecutive unused keys, as
+        /// defined in BIP-44), `while`, `firstN` or `randomN`. Defaults to
+        /// `gap20`, or to `first20` with Bitcoin Core resolver, which does
+        /// not support gap-limit lookups.
+        #[clap(short, long)]
+        mode: Option<ResolverModeType>,
+    },
+
+    /// Re-verifies cached unspent outputs with the resolver configured in
+    /// the document, dropping the spent ones and updating heights of the
+    /// others
+    Refresh,
+
+    /// Reports outputs which would be selected by each of the coin
+    /// selection modes for paying to the given addresses, together with
+    /// their waste metrics
+    Select {
+        /// Payments, in form of `<sats>@<address>`
+        #[clap(required = true)]
+        payments: Vec<Payment>,
+
+        /// Fee rate, in sats per virtual byte
+        #[clap(long, default_value = "1")]
+        fee_rate: f32,
+
+        /// Position of the descriptor account receiving the change, as
+        /// reported by `descriptor list`
+        #[clap(long)]
+        change: Option<usize>,
+
+        /// Selects only outputs belonging to a single descriptor account
+        #[clap(long)]
+        same_descriptor: bool,
+    },
+
+    /// Lists cached unspent outputs
+    List {
+        /// Lists only outputs belonging to the descriptor account at the
+        /// given position
+        #[clap(short, long)]
+        descriptor: Option<usize>,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum PsbtCommand {
+    /// Adds PSBT from the file containing it in binary, base64 or hex form.
+    /// If the document already has a PSBT for the same transaction, the
+    /// signatures from the file are merged into it.
+    Import {
+        /// File with the PSBT
+        source: PathBuf,
+    },
+
+    /// Lists PSBTs stored in the document
+    List,
+
+    /// Exports PSBT, printing it in base64 encoding
+    Export {
+        /// Position of the PSBT, as reported by `psbt list`
+        index: usize,
+
+        /// Writes binary PSBT into the file
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Removes PSBT at the given position
+    Remove {
+        /// Position of the PSBT, as reported by `psbt list`
+        index: usize,
+    },
+
+    /// Composes PSBT spending cached unspent outputs and adds it to the
+    /// document
+    Compose {
+        /// Payments, in form of `<sats>@<address>`
+        payments: Vec<Payment>,
+
+        /// Cached unspent outputs to spend
+        #[clap(short, long = "input", required_unless_present = "select")]
+        inputs: Vec<OutPoint>,
+
+        /// Selects outputs to spend from the UTXO cache with the given
+        /// coin selection mode: `bnb`, `largest` or `oldest`
+        #[clap(short, long, conflicts_with = "inputs")]
+        select: Option<SelectionMode>,
+
+        /// Selects only outputs belonging to a single descriptor account
+        #[clap(long, requires = "select")]
+        same_descriptor: bool,
+
+        /// Fee rate, in sats per virtual byte
+        #[clap(long, default_value = "1")]
+        fee_rate: f32,
+
+        /// Position of the descriptor account receiving the change, as
+        /// reported by `descriptor list`
+        #[clap(long)]
+        change: Option<usize>,
+    },
+
+    /// Finalizes signed PSBT inputs using descriptors of the spent outputs
+    Finalize {
+        /// Position of the PSBT, as reported by `psbt list`
+        index: usize,
+    },
+
+    /// Extracts signed transaction from the finalized PSBT and adds it to
+    /// the transaction cache
+    Extract {
+        /// Position of the PSBT, as reported by `psbt list`
+        index: usize,
+
+        /// Removes the PSBT from the document after the extraction
+        #[clap(short, long)]
+        remove: bool,
+    },
+
+    /// Extracts signed transaction from the finalized PSBT and broadcasts
+    /// it with the document resolver
+    Broadcast {
+        /// Position of the PSBT, as reported by `psbt list`
+        index: usize,
+
+        /// Skips pre-flight checks of the transaction standardness, fee and
+        /// spent outputs
+        #[clap(long)]
+        force: bool,
+
+        /// Removes the PSBT from the document after the broadcast
+        #[clap(short, long)]
+        remove: bool,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum TxCommand {
+    /// Broadcasts signed transaction with the document resolver
+    Broadcast {
+        /// File with the transaction in binary or hex form
+        source: PathBuf,
+
+        /// Skips pre-flight checks of the transaction standardness, fee and
+        /// spent outputs
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Estimates fee rate, in sats per virtual byte, with the document
+    /// resolver
+    EstimateFee {
+        /// Number of blocks within which the transaction should be mined
+        #[clap(short, long, default_value = "6")]
+        blocks: u16,
+    },
+
+    /// Composes PSBT replacing the cached transaction with the one paying
+    /// higher fee (BIP125) and adds it to the document
+    Rbf {
+        /// Id of the cached transaction to replace
+        txid: Txid,
+
+        /// Fee rate of the replacement, in sats per virtual byte; estimated
+        /// with the document resolver if not given
+        #[clap(long)]
+        fee_rate: Option<f32>,
+
+        /// Number of blocks used for the fee rate estimation
+        #[clap(short, long, default_value = "6")]
+        blocks: u16,
+    },
+
+    /// Composes child PSBT spending output of the unconfirmed cached
+    /// transaction, which pays the fee for both of them (CPFP), and adds it
+    /// to the document
+    Cpfp {
+        /// Id of the unconfirmed cached transaction
+        txid: Txid,
+
+        /// Fee rate of the transaction together with its child, in sats per
+        /// virtual byte; estimated with the document resolver if not given
+        #[clap(long)]
+        fee_rate: Option<f32>,
+
+        /// Number of blocks used for the fee rate estimation
+        #[clap(short, long, default_value = "6")]
+        blocks: u16,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum AssetCommand {
+    /// Issues new RGB-20 asset
+    Issue {
+        /// Asset ticker
+        ticker: String,
+
+        /// Asset name
+        name: String,
+
+        /// Asset description
+        #[clap(short, long)]
+        description: Option<String>,
+
+        /// Number of decimal digits after the point
+        #[clap(short, long, default_value = "8")]
+        precision: u8,
+
+        /// Asset allocations, in form of `<atomic_value>@<txid>:<vout>`
+        #[clap(short, long = "allocate", required = true)]
+        allocations: Vec<OutpointCoins>,
+
+        /// Secondary issuance rights, in form of
+        /// `<atomic_value>@<txid>:<vout>`
+        #[clap(short, long = "inflation")]
+        inflation: Vec<OutpointCoins>,
+
+        /// Outpoint controlling asset renomination
+        #[clap(long)]
+        renomination: Option<OutPoint>,
+
+        /// Outpoint controlling burn & replacement epochs
+        #[clap(long)]
+        epoch: Option<OutPoint>,
+    },
+
+    /// Lists all known assets
+    List,
+
+    /// Exports asset consignment
+    Export {
+        /// Asset contract id
+        contract_id: ContractId,
+
+        /// Writes strict-encoded consignment into the file instead of
+        /// printing genesis in Bech32 encoding
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Amount of atomic asset units assigned to a specific bitcoin transaction
+/// output
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Display)]
+#[display("{coins}@{outpoint}")]
+pub struct OutpointCoins {
+    pub coins: AtomicValue,
+    pub outpoint: OutPoint,
+}
+
+/// Error parsing asset allocation: expected `<atomic_value>@<txid>:<vout>`
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct OutpointCoinsParseError;
+
+impl FromStr for OutpointCoins {
+    type Err = OutpointCoinsParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (coins, outpoint) =
+            s.split_once('@').ok_or(OutpointCoinsParseError)?;
+        Ok(OutpointCoins {
+            coins: coins.parse().map_err(|_| OutpointCoinsParseError)?,
+            outpoint: outpoint.parse().map_err(|_| OutpointCoinsParseError)?,
+        })
+    }
+}
+
+/// Amount of sats paid to a bitcoin address
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+#[display("{amount}@{address}")]
+pub struct Payment {
+    pub amount: u64,
+    pub address: Address,
+}
+
+/// Error parsing payment: expected `<sats>@<address>`
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct PaymentParseError;
+
+impl FromStr for Payment {
+    type Err = PaymentParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (amount, address) = s.split_once('@').ok_or(PaymentParseError)?;
+        Ok(Payment {
+            amount: amount.parse().map_err(|_| PaymentParseError)?,
+            address: address.parse().map_err(|_| PaymentParseError)?,
+        })
+    }
+}
@@ -0,0 +1,510 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16,
+};
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::consensus::encode::serialize;
+use bitcoin::{OutPoint, Script, Transaction, TxOut, Txid};
+
+use crate::model::ResolverError;
+use crate::resolver::Resolver;
+
+/// Maximal weight of a transaction relayed by the nodes
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Maximal size of the `OP_RETURN` output script relayed by the nodes
+pub const MAX_OP_RETURN_SIZE: usize = 83;
+
+/// Maximal number of keys in the bare multi-sig output relayed by the nodes
+pub const MAX_BARE_MULTISIG_KEYS: usize = 3;
+
+/// Minimal fee rate, in sats per virtual byte, required for the transaction
+/// relay
+pub const MIN_RELAY_FEE_RATE: f32 = 1.0;
+
+/// Fee rate, in sats per virtual byte, above which the fee is considered to
+/// be a mistake
+pub const MAX_FEE_RATE: f32 = 1000.0;
+
+#[derive(Clone, PartialEq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Blockchain information provider error
+    #[display("{0}")]
+    #[from]
+    Resolver(ResolverError),
+
+    /// Transaction has no inputs or outputs
+    Empty,
+
+    /// Transaction version {0} is not standard
+    Version(i32),
+
+    /// Transaction weight of {0} WU exceeds the standard limit
+    Weight(usize),
+
+    /// Input #{0} has a non-standard script sig containing non-push
+    /// operations
+    ScriptSig(usize),
+
+    /// Output #{0} has a non-standard script
+    NonStandardOutput(usize),
+
+    /// Transaction has more than a single `OP_RETURN` output
+    MultipleOpReturn,
+
+    /// Output #{0} of {1} sats is below the dust limit of {2} sats
+    Dust(usize, u64, u64),
+
+    /// Output {0} spent by the transaction does not exist
+    UnknownOutput(OutPoint),
+
+    /// Output {0} spent by the transaction is already spent
+    AlreadySpent(OutPoint),
+
+    /// Transaction pays {1} sats, which is more than {0} sats it spends
+    NegativeFee(u64, u64),
+
+    /// Transaction fee rate of {0} sat/vbyte is below the minimal relay fee
+    /// rate
+    LowFee(f32),
+
+    /// Transaction fee of {0} sats ({1} sat/vbyte) is absurdly high
+    AbsurdFee(u64, f32),
+}
+
+/// Results of the checks done before broadcasting a transaction
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Preflight {
+    pub fee: u64,
+    pub vsize: usize,
+}
+
+impl Preflight {
+    /// Fee rate in sats per virtual byte
+    pub fn fee_rate(&self) -> f32 {
+        self.fee as f32 / self.vsize as f32
+    }
+}
+
+/// Minimal value of the output which is relayed by the nodes, computed in
+/// the same way as Bitcoin Core does with the default dust relay fee rate
+/// of 3 sat/vbyte
+pub fn dust_limit(script_pubkey: &Script) -> u64 {
+    if script_pubkey.is_op_return() {
+        return 0;
+    }
+    let output_size = serialize(&TxOut {
+        value: 0,
+        script_pubkey: script_pubkey.clone(),
+    })
+    .len();
+    // Size of the input spending the output; witness data are discounted
+    let input_size = if script_pubkey.is_witness_program() {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    (output_size + input_size) as u64 * 3
+}
+
+fn is_standard_script(script_pubkey: &Script) -> bool {
+    script_pubkey.is_p2pk()
+        || script_pubkey.is_p2pkh()
+        || script_pubkey.is_p2sh()
+        || script_pubkey.is_witness_program()
+        || (script_pubkey.is_op_return()
+            && script_pubkey.len() <= MAX_OP_RETURN_SIZE)
+        || is_bare_multisig(script_pubkey)
+}
+
+/// Number pushed by `OP_1`..`OP_16` opcode
+fn pushnum(opcode: opcodes::All) -> Option<usize> {
+    let code = opcode.into_u8();
+    (OP_PUSHNUM_1.into_u8()..=OP_PUSHNUM_16.into_u8())
+        .contains(&code)
+        .then(|| (code - OP_PUSHNUM_1.into_u8() + 1) as usize)
+}
+
+/// Whether the data have the size and the prefix of a serialized public key
+fn is_pubkey(data: &[u8]) -> bool {
+    match data.first() {
+        Some(2 | 3) => data.len() == 33,
+        Some(4 | 6 | 7) => data.len() == 65,
+        _ => false,
+    }
+}
+
+/// Whether the script is the standard bare multi-sig
+/// `OP_m <pubkey>... OP_n OP_CHECKMULTISIG` with `1 <= m <= n <= 3`
+fn is_bare_multisig(script_pubkey: &Script) -> bool {
+    use Instruction::{Op, PushBytes};
+    let instructions = match script_pubkey
+        .instructions_minimal()
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(instructions) => instructions,
+        Err(_) => return false,
+    };
+    let (m, keys, n) = match instructions[..] {
+        [Op(m), ref keys @ .., Op(n), Op(last)] if last == OP_CHECKMULTISIG => {
+            (pushnum(m), keys, pushnum(n))
+        }
+        _ => return false,
+    };
+    let all_keys = keys.iter().all(|key| match key {
+        PushBytes(data) => is_pubkey(data),
+        Op(_) => false,
+    });
+    match (m, n) {
+        (Some(m), Some(n)) => {
+            all_keys && n == keys.len() && m <= n && n <= MAX_BARE_MULTISIG_KEYS
+        }
+        _ => false,
+    }
+}
+
+fn is_push_only(script_sig: &Script) -> bool {
+    script_sig
+        .instructions()
+        .all(|instruction| match instruction {
+            Ok(Instruction::PushBytes(_)) => true,
+            Ok(Instruction::Op(op)) => op.into_u8() <= OP_PUSHNUM_16.into_u8(),
+            Err(_) => false,
+        })
+}
+
+/// Checks that the transaction is standard and will be relayed by the nodes
+/// using the default policy
+pub fn check_standard(tx: &Transaction) -> Result<(), Error> {
+    if tx.input.is_empty() || tx.output.is_empty() {
+        return Err(Error::Empty);
+    }
+    if !(1..=2).contains(&tx.version) {
+        return Err(Error::Version(tx.version));
+    }
+    let weight = tx.weight();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        return Err(Error::Weight(weight));
+    }
+    if let Some(vin) = tx
+        .input
+        .iter()
+        .position(|txin| !is_push_only(&txin.script_sig))
+    {
+        return Err(Error::ScriptSig(vin));
+    }
+    let mut op_returns = 0;
+    for (vout, txout) in tx.output.iter().enumerate() {
+        if !is_standard_script(&txout.script_pubkey) {
+            return Err(Error::NonStandardOutput(vout));
+        }
+        if txout.script_pubkey.is_op_return() {
+            op_returns += 1;
+        }
+        let dust = dust_limit(&txout.script_pubkey);
+        if txout.value < dust {
+            return Err(Error::Dust(vout, txout.value, dust));
+        }
+    }
+    if op_returns > 1 {
+        return Err(Error::MultipleOpReturn);
+    }
+    Ok(())
+}
+
+/// Checks that the transaction fee is neither below the minimal relay fee
+/// nor absurdly high. `spent` must contain outputs spent by each of the
+/// transaction inputs.
+pub fn check_fee(
+    tx: &Transaction,
+    spent: &[TxOut],
+) -> Result<Preflight, Error> {
+    let inputs = spent.iter().map(|txout| txout.value).sum::<u64>();
+    let outputs = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+    let fee = inputs
+        .checked_sub(outputs)
+        .ok_or(Error::NegativeFee(inputs, outputs))?;
+    let preflight = Preflight {
+        fee,
+        vsize: (tx.weight() + 3) / 4,
+    };
+    let fee_rate = preflight.fee_rate();
+    if fee_rate < MIN_RELAY_FEE_RATE {
+        return Err(Error::LowFee(fee_rate));
+    }
+    if fee_rate > MAX_FEE_RATE {
+        return Err(Error::AbsurdFee(fee, fee_rate));
+    }
+    Ok(preflight)
+}
+
+/// Outputs spent by each of the transaction inputs. Transactions containing
+/// them are fetched from the resolver only if `cached` returns `None` for
+/// them.
+pub fn spent_outputs(
+    resolver: &dyn Resolver,
+    tx: &Transaction,
+    cached: impl Fn(Txid) -> Option<Transaction>,
+) -> Result<Vec<TxOut>, Error> {
+    let mut transactions = HashMap::<Txid, Transaction>::new();
+    let mut spent = Vec::with_capacity(tx.input.len());
+    for txin in &tx.input {
+        let outpoint = txin.previous_output;
+        if !transactions.contains_key(&outpoint.txid) {
+            let prev_tx = match cached(outpoint.txid) {
+                Some(prev_tx) => prev_tx,
+                None => resolver.transaction(outpoint.txid)?,
+            };
+            transactions.insert(outpoint.txid, prev_tx);
+        }
+        let txout = transactions[&outpoint.txid]
+            .output
+            .get(outpoint.vout as usize)
+            .ok_or(Error::UnknownOutput(outpoint))?;
+        spent.push(txout.clone());
+    }
+    Ok(spent)
+}
+
+pub trait TxBroadcast {
+    /// Checks the transaction before broadcasting it: its standardness, fee
+    /// sanity and that all of the spent outputs are still unspent.
+    /// Transactions containing spent outputs are fetched from the resolver
+    /// only if `cached` returns `None` for them.
+    fn preflight(
+        &self,
+        resolver: &dyn Resolver,
+        tx: &Transaction,
+        cached: impl Fn(Txid) -> Option<Transaction>,
+    ) -> Result<Preflight, Error> {
+        check_standard(tx)?;
+
+        let spent = spent_outputs(resolver, tx, cached)?;
+        for (txin, txout) in tx.input.iter().zip(&spent) {
+            let outpoint = txin.previous_output;
+            if !resolver.is_unspent(outpoint, &txout.script_pubkey)? {
+                return Err(Error::AlreadySpent(outpoint));
+            }
+        }
+
+        check_fee(tx, &spent)
+    }
+
+    /// Broadcasts the transaction with the resolver, running pre-flight
+    /// checks first if `check` is set
+    fn broadcast_tx(
+        &self,
+        resolver: &dyn Resolver,
+        tx: &Transaction,
+        check: bool,
+        cached: impl Fn(Txid) -> Option<Transaction>,
+    ) -> Result<Txid, Error> {
+        if check {
+            self.preflight(resolver, tx, cached)?;
+        }
+        Ok(resolver.broadcast(tx)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{PubkeyHash, TxIn, WPubkeyHash};
+
+    use super::*;
+    use crate::resolver::{MockResolver, Unspent};
+
+    struct Broadcast;
+    impl TxBroadcast for Broadcast {}
+
+    fn wpkh(seed: u8) -> Script {
+        Script::new_v0_p2wpkh(&WPubkeyHash::from_inner([seed; 20]))
+    }
+
+    fn tx(input: Vec<OutPoint>, output: Vec<(u64, Script)>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: input
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: output
+                .into_iter()
+                .map(|(value, script_pubkey)| TxOut {
+                    value,
+                    script_pubkey,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn standardness() {
+        let input = vec![OutPoint::default()];
+        assert_eq!(
+            check_standard(&tx(input.clone(), vec![])),
+            Err(Error::Empty)
+        );
+        assert_eq!(dust_limit(&wpkh(1)), 294);
+        assert_eq!(
+            dust_limit(&Script::new_p2pkh(&PubkeyHash::from_inner([1; 20]))),
+            546
+        );
+        assert_eq!(
+            check_standard(&tx(input.clone(), vec![(293, wpkh(1))])),
+            Err(Error::Dust(0, 293, 294))
+        );
+        let op_return = Script::new_op_return(&[1, 2, 3]);
+        assert_eq!(
+            check_standard(&tx(
+                input.clone(),
+                vec![(0, op_return.clone()), (0, op_return.clone())]
+            )),
+            Err(Error::MultipleOpReturn)
+        );
+        assert_eq!(
+            check_standard(&tx(input.clone(), vec![(0, Script::new())])),
+            Err(Error::NonStandardOutput(0))
+        );
+        let mut nonstandard = tx(input.clone(), vec![(0, op_return)]);
+        nonstandard.version = 3;
+        assert_eq!(check_standard(&nonstandard), Err(Error::Version(3)));
+        nonstandard.version = 1;
+        assert_eq!(check_standard(&nonstandard), Ok(()));
+    }
+
+    #[test]
+    fn bare_multisig_standardness() {
+        let multisig = |m: i64, keys: &[&[u8]], n: i64| {
+            keys.iter()
+                .fold(Builder::new().push_int(m), |builder, key| {
+                    builder.push_slice(key)
+                })
+                .push_int(n)
+                .push_opcode(OP_CHECKMULTISIG)
+                .into_script()
+        };
+        let key: &[u8] = &[2; 33];
+        let full: &[u8] = &[4; 65];
+        assert!(is_standard_script(&multisig(1, &[key], 1)));
+        assert!(is_standard_script(&multisig(2, &[key, full, key], 3)));
+        // Too many keys, wrong threshold or key count, and non-key data
+        assert!(!is_standard_script(&multisig(1, &[key; 4], 4)));
+        assert!(!is_standard_script(&multisig(0, &[key; 2], 2)));
+        assert!(!is_standard_script(&multisig(3, &[key; 2], 2)));
+        assert!(!is_standard_script(&multisig(1, &[key; 2], 3)));
+        assert!(!is_standard_script(&multisig(1, &[&[2; 32]], 1)));
+        assert!(!is_standard_script(
+            &Builder::new()
+                .push_slice(&[1, 2, 3])
+                .push_opcode(OP_CHECKMULTISIG)
+                .into_script()
+        ));
+    }
+
+    #[test]
+    fn preflight_and_broadcast() {
+        let funding =
+            tx(vec![OutPoint::default()], vec![(1_000_000, wpkh(1)); 2]);
+        let outpoint = OutPoint::new(funding.txid(), 1);
+        let mut resolver = MockResolver::new();
+        resolver.add_transaction(funding.clone(), 100);
+
+        // Spent output is not reported as unspent by the resolver
+        let spending = tx(vec![outpoint], vec![(999_500, wpkh(2))]);
+        assert_eq!(
+            Broadcast.broadcast_tx(&resolver, &spending, true, |_| None),
+            Err(Error::AlreadySpent(outpoint))
+        );
+        assert!(resolver.broadcasted().is_empty());
+
+        resolver.add_unspent(
+            wpkh(1),
+            Unspent {
+                outpoint,
+                height: 100,
+                amount: 1_000_000,
+            },
+        );
+        let preflight =
+            Broadcast.preflight(&resolver, &spending, |_| None).unwrap();
+        assert_eq!(preflight.fee, 500);
+        // Non-segwit transaction with a single input and a single output
+        assert_eq!(preflight.vsize, 82);
+
+        let cheap = tx(vec![outpoint], vec![(999_990, wpkh(2))]);
+        assert!(matches!(
+            Broadcast.preflight(&resolver, &cheap, |_| None),
+            Err(Error::LowFee(_))
+        ));
+        let absurd = tx(vec![outpoint], vec![(300, wpkh(2))]);
+        assert!(matches!(
+            Broadcast.preflight(&resolver, &absurd, |_| None),
+            Err(Error::AbsurdFee(999_700, _))
+        ));
+        let overspending = tx(vec![outpoint], vec![(1_000_001, wpkh(2))]);
+        assert_eq!(
+            Broadcast
+                .preflight(&resolver, &overspending, |_| Some(funding.clone())),
+            Err(Error::NegativeFee(1_000_000, 1_000_001))
+        );
+
+        assert_eq!(
+            Broadcast.broadcast_tx(&resolver, &spending, true, |_| None),
+            Ok(spending.txid())
+        );
+        // Checks may be skipped
+        assert_eq!(
+            Broadcast.broadcast_tx(&resolver, &absurd, false, |_| None),
+            Ok(absurd.txid())
+        );
+        assert_eq!(resolver.broadcasted(), vec![spending, absurd]);
+    }
+
+    #[test]
+    fn preflight_with_mempool_parent() {
+        let funding = tx(vec![OutPoint::default()], vec![(1_000_000, wpkh(1))]);
+        let outpoint = OutPoint::new(funding.txid(), 0);
+        let mut resolver = MockResolver::new();
+        resolver.add_transaction(funding, 0);
+        resolver.add_unspent(
+            wpkh(1),
+            Unspent {
+                outpoint,
+                height: 0,
+                amount: 1_000_000,
+            },
+        );
+
+        let spending = tx(vec![outpoint], vec![(999_500, wpkh(2))]);
+        assert_eq!(
+            Broadcast.broadcast_tx(&resolver, &spending, true, |_| None),
+            Ok(spending.txid())
+        );
+    }
+}
@@ -0,0 +1,804 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+
+use bitcoin::consensus::serialize;
+use bitcoin::TxOut;
+use wallet::descriptors;
+
+use crate::model::UtxoEntry;
+
+/// Outputs with smaller value are not relayed by the nodes, so the change
+/// below this value is left to the miners as a part of the fee
+pub const DUST_LIMIT: u64 = 546;
+
+/// Fee rate at which the outputs are expected to be spent in the long run,
+/// used to compute the waste metric
+pub const LONG_TERM_FEE_RATE: f32 = 10.0;
+
+/// Maximal number of branches tried by branch-and-bound search
+pub const BNB_MAX_TRIES: usize = 100_000;
+
+/// Weight of the input fields other than script sig and witness: previous
+/// outpoint, sequence number and script sig length
+pub const TXIN_BASE_WEIGHT: usize = (32 + 4 + 4 + 1) * 4;
+
+/// Weight of the transaction fields other than inputs and outputs: version,
+/// lock time, input and output counts and segwit marker with flag
+pub const TX_BASE_WEIGHT: usize = (4 + 4 + 1 + 1) * 4 + 2;
+
+/// Maximal size of ECDSA signature with its sighash flag
+const SIG_SIZE: usize = 72 + 1;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Available outputs have {1} sats, which is not enough to pay {0} sats
+    /// with the fees
+    InsufficientFunds(u64, u64),
+
+    /// No combination of the available outputs pays {0} sats without
+    /// creating change
+    NoChangelessSolution(u64),
+
+    /// Unrecognized coin selection mode {0}
+    UnrecognizedMode(String),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum SelectionMode {
+    /// Branch-and-bound search for the set of outputs paying the target
+    /// without creating change, picking the one with the smallest waste
+    #[display("bnb")]
+    BranchAndBound,
+
+    /// Spends outputs with the largest amounts first
+    #[display("largest")]
+    LargestFirst,
+
+    /// Spends outputs confirmed earlier first; unconfirmed outputs are
+    /// spent last
+    #[display("oldest")]
+    OldestFirst,
+}
+
+impl FromStr for SelectionMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "bnb" => SelectionMode::BranchAndBound,
+            "largest" => SelectionMode::LargestFirst,
+            "oldest" => SelectionMode::OldestFirst,
+            _ => return Err(Error::UnrecognizedMode(s.to_owned())),
+        })
+    }
+}
+
+impl SelectionMode {
+    pub fn all() -> [SelectionMode; 3] {
+        [
+            SelectionMode::BranchAndBound,
+            SelectionMode::LargestFirst,
+            SelectionMode::OldestFirst,
+        ]
+    }
+}
+
+/// Fee for the transaction data of the given weight, rounded up to whole
+/// sats
+pub fn fee_for(weight: usize, fee_rate: f32) -> u64 {
+    let vsize = (weight + 3) / 4;
+    (vsize as f32 * fee_rate).ceil() as u64
+}
+
+/// Size of the data push opcodes for the data of the given length
+fn push_len(len: usize) -> usize {
+    match len {
+        0..=75 => 1,
+        76..=0xFF => 2,
+        _ => 3,
+    }
+}
+
+/// Size of the compact-size integer encoding the given number, as used for
+/// script and witness lengths and witness item count
+fn varint_len(n: usize) -> usize {
+    match n {
+        0..=0xFC => 1,
+        0xFD..=0xFFFF => 3,
+        0x1_0000..=0xFFFF_FFFF => 5,
+        _ => 9,
+    }
+}
+
+/// Size of the public key
+fn key_size(key: &descriptors::SingleSig) -> usize {
+    match key {
+        descriptors::SingleSig::Pubkey(single) if !single.key.compressed => 65,
+        _ => 33,
+    }
+}
+
+/// Weight of the script sig pushing the data of the given sizes, not counting
+/// the first byte of the script sig length already included in
+/// [`TXIN_BASE_WEIGHT`]
+fn script_sig_weight(pushes: &[usize]) -> usize {
+    let len = pushes
+        .iter()
+        .map(|size| push_len(*size) + size)
+        .sum::<usize>();
+    (varint_len(len) - 1 + len) * 4
+}
+
+/// Weight of the witness consisting of the items of the given sizes
+fn witness_weight(items: &[usize]) -> usize {
+    varint_len(items.len())
+        + items
+            .iter()
+            .map(|size| varint_len(*size) + size)
+            .sum::<usize>()
+}
+
+/// Whether spending outputs of the category requires witness
+pub fn is_segwit(category: descriptors::Category) -> bool {
+    category == descriptors::Category::Nested
+        || category == descriptors::Category::SegWit
+}
+
+/// Weight of the signatures and scripts required to spend the output
+/// generated by the descriptor `template` under the given `category`,
+/// including both script sig and witness data. Returns `None` for the
+/// descriptors which can't be spent from yet.
+pub fn satisfaction_weight(
+    template: &descriptors::Template,
+    category: descriptors::Category,
+) -> Option<usize> {
+    match template {
+        descriptors::Template::SingleSig(key) => {
+            let items = [SIG_SIZE, key_size(key)];
+            Some(match category {
+                descriptors::Category::Bare => script_sig_weight(&[SIG_SIZE]),
+                descriptors::Category::Hashed => script_sig_weight(&items),
+                // Script sig pushes P2WPKH script, witness has the signature
+                // and the key
+                descriptors::Category::Nested => {
+                    script_sig_weight(&[22]) + witness_weight(&items)
+                }
+                descriptors::Category::SegWit => witness_weight(&items),
+                _ => return None,
+            })
+        }
+        descriptors::Template::MultiSig(multisig) => {
+            // `OP_M <keys> OP_N OP_CHECKMULTISIG`
+            let script_size = multisig
+                .pubkeys
+                .iter()
+                .map(|key| push_len(key_size(key)) + key_size(key))
+                .sum::<usize>()
+                + 3;
+            // Empty `OP_0` item followed by the signatures
+            let mut items = vec![0usize];
+            items.extend(vec![SIG_SIZE; multisig.threshold() as usize]);
+            Some(match category {
+                descriptors::Category::Bare => script_sig_weight(&items),
+                descriptors::Category::Hashed => {
+                    items.push(script_size);
+                    script_sig_weight(&items)
+                }
+                // Script sig pushes P2WSH script
+                descriptors::Category::Nested => {
+                    items.push(script_size);
+                    script_sig_weight(&[34]) + witness_weight(&items)
+                }
+                descriptors::Category::SegWit => {
+                    items.push(script_size);
+                    witness_weight(&items)
+                }
+                _ => return None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Weight of the transaction input spending output generated by the
+/// descriptor `template` under the given `category`
+pub fn input_weight(
+    template: &descriptors::Template,
+    category: descriptors::Category,
+) -> Option<usize> {
+    satisfaction_weight(template, category)
+        .map(|weight| TXIN_BASE_WEIGHT + weight)
+}
+
+pub fn output_weight(txout: &TxOut) -> usize {
+    serialize(txout).len() * 4
+}
+
+/// Parameters of the coin selection
+#[derive(Clone, PartialEq, Debug)]
+pub struct SelectionParams {
+    /// Total amount of the payments
+    pub target: u64,
+    /// Fee rate, in sats per virtual byte
+    pub fee_rate: f32,
+    pub long_term_fee_rate: f32,
+    /// Weight of the transaction without inputs and change output
+    pub base_weight: usize,
+    /// Weight of the change output; `None` if change is not allowed and
+    /// all excess value goes to the fee
+    pub change_weight: Option<usize>,
+    /// Weight of the input spending change output in the future
+    pub change_spend_weight: usize,
+}
+
+impl SelectionParams {
+    pub fn with(outputs: &[TxOut], fee_rate: f32) -> Self {
+        SelectionParams {
+            target: outputs.iter().map(|txout| txout.value).sum(),
+            fee_rate,
+            long_term_fee_rate: LONG_TERM_FEE_RATE,
+            base_weight: TX_BASE_WEIGHT
+                + outputs.iter().map(output_weight).sum::<usize>(),
+            change_weight: None,
+            change_spend_weight: 0,
+        }
+    }
+
+    /// Allows creating change output, which will be spent in the future by
+    /// the input of the given weight
+    pub fn with_change(self, change: &TxOut, spend_weight: usize) -> Self {
+        SelectionParams {
+            change_weight: Some(output_weight(change)),
+            change_spend_weight: spend_weight,
+            ..self
+        }
+    }
+
+    /// Cost of creating change output now and spending it later; without
+    /// change the dust limit is used, since values below it can't be
+    /// returned as change anyway
+    pub fn cost_of_change(&self) -> u64 {
+        match self.change_weight {
+            Some(weight) => {
+                fee_for(weight, self.fee_rate)
+                    + fee_for(self.change_spend_weight, self.long_term_fee_rate)
+            }
+            None => DUST_LIMIT,
+        }
+    }
+}
+
+/// Set of the outputs selected for spending
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Selection {
+    pub mode: SelectionMode,
+    pub utxos: Vec<UtxoEntry>,
+    /// Transaction fee, including the excess value of a changeless
+    /// transaction
+    pub fee: u64,
+    /// Value of the change output, if any
+    pub change: Option<u64>,
+    /// Waste metric, as defined by Bitcoin Core: the difference between
+    /// fees paid for the inputs now and at the long-term fee rate, plus
+    /// the cost of change or the excess value given to the miners. The
+    /// lower the better.
+    pub waste: i64,
+}
+
+impl Selection {
+    /// Total value of the selected outputs
+    pub fn amount(&self) -> u64 {
+        self.utxos.iter().map(|utxo| utxo.amount).sum()
+    }
+}
+
+/// Output which is economical to spend
+#[derive(Clone, Debug)]
+struct Candidate {
+    utxo: UtxoEntry,
+    weight: usize,
+    /// Amount minus the fee for spending the output
+    effective_value: i64,
+    /// Fee for spending the output now minus its fee at the long-term fee
+    /// rate
+    waste: i64,
+}
+
+/// Outputs which can be spent with the composer and whose value is above
+/// the fee of spending them
+fn candidates(utxos: &[UtxoEntry], params: &SelectionParams) -> Vec<Candidate> {
+    utxos
+        .iter()
+        .filter_map(|utxo| {
+            let weight = input_weight(
+                &utxo.descriptor_template,
+                utxo.descriptor_category,
+            )?;
+            let fee = fee_for(weight, params.fee_rate) as i64;
+            let long_term_fee =
+                fee_for(weight, params.long_term_fee_rate) as i64;
+            Some(Candidate {
+                utxo: utxo.clone(),
+                weight,
+                effective_value: utxo.amount as i64 - fee,
+                waste: fee - long_term_fee,
+            })
+        })
+        .filter(|candidate| candidate.effective_value > 0)
+        .collect()
+}
+
+/// Computes fee, change and waste for the selected outputs, which must pay
+/// the target and fees
+fn finish(
+    mode: SelectionMode,
+    selected: Vec<Candidate>,
+    params: &SelectionParams,
+) -> Selection {
+    let weight = params.base_weight
+        + selected
+            .iter()
+            .map(|candidate| candidate.weight)
+            .sum::<usize>();
+    let amount = selected
+        .iter()
+        .map(|candidate| candidate.utxo.amount)
+        .sum::<u64>();
+    let inputs_waste = selected
+        .iter()
+        .map(|candidate| candidate.waste)
+        .sum::<i64>();
+
+    let fee = fee_for(weight, params.fee_rate);
+    let mut selection = Selection {
+        mode,
+        utxos: selected
+            .into_iter()
+            .map(|candidate| candidate.utxo)
+            .collect(),
+        fee: amount - params.target,
+        change: None,
+        waste: inputs_waste + amount.saturating_sub(params.target + fee) as i64,
+    };
+    if let Some(change_weight) = params.change_weight {
+        let fee = fee_for(weight + change_weight, params.fee_rate);
+        if amount >= params.target + fee + DUST_LIMIT {
+            selection.fee = fee;
+            selection.change = Some(amount - params.target - fee);
+            selection.waste = inputs_waste + params.cost_of_change() as i64;
+        }
+    }
+    selection
+}
+
+/// Picks candidates in the given order until they pay the target and fees
+fn accumulate(
+    mode: SelectionMode,
+    candidates: Vec<Candidate>,
+    params: &SelectionParams,
+) -> Result<Selection, Error> {
+    let available = candidates
+        .iter()
+        .map(|candidate| candidate.utxo.amount)
+        .sum();
+    let mut selected = vec![];
+    let mut weight = params.base_weight;
+    let mut amount = 0u64;
+    for candidate in candidates {
+        weight += candidate.weight;
+        amount += candidate.utxo.amount;
+        selected.push(candidate);
+        if amount >= params.target + fee_for(weight, params.fee_rate) {
+            return Ok(finish(mode, selected, params));
+        }
+    }
+    Err(Error::InsufficientFunds(params.target, available))
+}
+
+/// Depth-first search for the set of candidates with effective value
+/// matching the target within the cost of change, as implemented in
+/// Bitcoin Core. Candidates must be sorted by effective value in
+/// descending order.
+fn branch_and_bound(
+    candidates: Vec<Candidate>,
+    params: &SelectionParams,
+) -> Result<Selection, Error> {
+    let target = params.target as i64
+        + fee_for(params.base_weight, params.fee_rate) as i64;
+    let upper = target + params.cost_of_change() as i64;
+    let values = candidates
+        .iter()
+        .map(|candidate| candidate.effective_value)
+        .collect::<Vec<_>>();
+
+    let mut available = values.iter().sum::<i64>();
+    if available < target {
+        let available = candidates
+            .iter()
+            .map(|candidate| candidate.utxo.amount)
+            .sum();
+        return Err(Error::InsufficientFunds(params.target, available));
+    }
+    // With the fee rate above the long-term one each additional input
+    // increases the waste, so the branches more wasteful than the best
+    // solution can be pruned
+    let waste_prunes = candidates
+        .first()
+        .map(|candidate| candidate.waste > 0)
+        .unwrap_or_default();
+
+    let mut value = 0i64;
+    let mut waste = 0i64;
+    let mut selection = Vec::<usize>::new();
+    let mut best: Option<(Vec<usize>, i64)> = None;
+    let mut index = 0usize;
+    for _ in 0..BNB_MAX_TRIES {
+        let best_waste = best.as_ref().map(|(_, waste)| *waste);
+        let mut backtrack = false;
+        if value + available < target
+            || value > upper
+            || (waste_prunes && best_waste.map_or(false, |best| waste > best))
+        {
+            backtrack = true;
+        } else if value >= target {
+            let total_waste = waste + value - target;
+            if best_waste.map_or(true, |best| total_waste <= best) {
+                best = Some((selection.clone(), total_waste));
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let last = match selection.last() {
+                Some(last) => *last,
+                None => break,
+            };
+            // Return omitted candidates back before trying the branch
+            // excluding the last included one
+            index -= 1;
+            while index > last {
+                available += values[index];
+                index -= 1;
+            }
+            value -= values[index];
+            waste -= candidates[index].waste;
+            selection.pop();
+        } else {
+            available -= values[index];
+            // Skip the inclusion branch if it is equivalent to the
+            // previous excluded candidate
+            if index == 0
+                || selection.last() == Some(&(index - 1))
+                || values[index] != values[index - 1]
+                || candidates[index].waste != candidates[index - 1].waste
+            {
+                selection.push(index);
+                value += values[index];
+                waste += candidates[index].waste;
+            }
+        }
+        index += 1;
+    }
+
+    let (selection, _) =
+        best.ok_or(Error::NoChangelessSolution(params.target))?;
+    let mut candidates = candidates.into_iter().map(Some).collect::<Vec<_>>();
+    let selected = selection
+        .into_iter()
+        .filter_map(|index| candidates[index].take())
+        .collect();
+    Ok(finish(SelectionMode::BranchAndBound, selected, params))
+}
+
+fn select_from(
+    mode: SelectionMode,
+    utxos: &[UtxoEntry],
+    params: &SelectionParams,
+) -> Result<Selection, Error> {
+    let mut candidates = candidates(utxos, params);
+    match mode {
+        SelectionMode::BranchAndBound => {
+            candidates.sort_by(|a, b| {
+                b.effective_value
+                    .cmp(&a.effective_value)
+                    .then(a.utxo.outpoint.cmp(&b.utxo.outpoint))
+            });
+            branch_and_bound(candidates, params)
+        }
+        SelectionMode::LargestFirst => {
+            candidates.sort_by(|a, b| {
+                b.utxo
+                    .amount
+                    .cmp(&a.utxo.amount)
+                    .then(a.utxo.outpoint.cmp(&b.utxo.outpoint))
+            });
+            accumulate(mode, candidates, params)
+        }
+        SelectionMode::OldestFirst => {
+            candidates.sort_by_key(|candidate| {
+                let utxo = &candidate.utxo;
+                (utxo.height == 0, utxo.height, utxo.outpoint)
+            });
+            accumulate(mode, candidates, params)
+        }
+    }
+}
+
+/// Selects outputs for spending from `utxos` with the given mode. With
+/// `same_descriptor` set only outputs of a single descriptor are selected,
+/// so the transaction does not link different descriptor accounts; the
+/// selection with the smallest waste is picked among the descriptors.
+///
+/// Outputs which can't be spent yet (like taproot ones) and outputs with
+/// value below the fee for spending them are never selected.
+pub fn select(
+    mode: SelectionMode,
+    utxos: &[UtxoEntry],
+    params: &SelectionParams,
+    same_descriptor: bool,
+) -> Result<Selection, Error> {
+    if !same_descriptor {
+        return select_from(mode, utxos, params);
+    }
+
+    let mut groups = Vec::<Vec<UtxoEntry>>::new();
+    for utxo in utxos {
+        match groups.iter_mut().find(|group| {
+            group[0].descriptor_template == utxo.descriptor_template
+        }) {
+            Some(group) => group.push(utxo.clone()),
+            None => groups.push(vec![utxo.clone()]),
+        }
+    }
+    // The error is reported for the richest descriptor
+    groups.sort_by_key(|group| {
+        std::cmp::Reverse(group.iter().map(|utxo| utxo.amount).sum::<u64>())
+    });
+
+    let mut err = Error::InsufficientFunds(params.target, 0);
+    let mut best: Option<Selection> = None;
+    for (no, group) in groups.iter().enumerate() {
+        match select_from(mode, group, params) {
+            Ok(selection)
+                if best
+                    .as_ref()
+                    .map_or(true, |best| selection.waste < best.waste) =>
+            {
+                best = Some(selection)
+            }
+            Ok(_) => {}
+            Err(e) if no == 0 => err = e,
+            Err(_) => {}
+        }
+    }
+    best.ok_or(err)
+}
+
+/// Runs all of the selection modes, returning successful selections with
+/// their waste metrics, ordered from the least wasteful
+pub fn select_all(
+    utxos: &[UtxoEntry],
+    params: &SelectionParams,
+    same_descriptor: bool,
+) -> Vec<Selection> {
+    let mut selections = SelectionMode::all()
+        .into_iter()
+        .filter_map(|mode| select(mode, utxos, params, same_descriptor).ok())
+        .collect::<Vec<_>>();
+    selections.sort_by_key(|selection| (selection.waste, selection.mode));
+    selections
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::{OutPoint, Script, Txid};
+
+    use super::*;
+
+    fn template(key: &str) -> descriptors::Template {
+        descriptors::Template::SingleSig(
+            descriptors::SingleSig::from_str(key).unwrap(),
+        )
+    }
+
+    fn utxo(
+        template: &descriptors::Template,
+        vout: u32,
+        amount: u64,
+        height: u32,
+    ) -> UtxoEntry {
+        UtxoEntry {
+            outpoint: OutPoint::new(Txid::default(), vout),
+            height,
+            amount,
+            descriptor_template: template.clone(),
+            descriptor_category: descriptors::Category::SegWit,
+            derivation_index: vout,
+        }
+    }
+
+    fn params() -> SelectionParams {
+        SelectionParams::with(
+            &[TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_op_return(&[]),
+            }],
+            1.0,
+        )
+        .with_change(
+            &TxOut {
+                value: 0,
+                script_pubkey: Script::from(vec![0u8; 22]),
+            },
+            273,
+        )
+    }
+
+    fn amounts(selection: &Selection) -> Vec<u64> {
+        selection.utxos.iter().map(|utxo| utxo.amount).collect()
+    }
+
+    #[test]
+    fn weight_estimation() {
+        let template = template(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        );
+        assert_eq!(
+            input_weight(&template, descriptors::Category::SegWit),
+            Some(273)
+        );
+        assert_eq!(
+            input_weight(&template, descriptors::Category::Hashed),
+            Some(164 + 108 * 4)
+        );
+        assert_eq!(
+            input_weight(&template, descriptors::Category::Taproot),
+            None
+        );
+    }
+
+    #[test]
+    fn multisig_weight_estimation() {
+        let multisig = |threshold: u8, count: usize| {
+            descriptors::Template::MultiSig(descriptors::MultiSig {
+                threshold: Some(threshold),
+                pubkeys: vec![
+                    descriptors::SingleSig::from_str(
+                        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                    )
+                    .unwrap();
+                    count
+                ],
+                reorder: false,
+            })
+        };
+        // Witness item lengths are single-byte compact-size integers
+        assert_eq!(
+            input_weight(&multisig(2, 3), descriptors::Category::SegWit),
+            Some(164 + 1 + 1 + 74 * 2 + 1 + 105)
+        );
+        // Witness script longer than 252 bytes has three-byte length
+        assert_eq!(
+            input_weight(&multisig(8, 8), descriptors::Category::SegWit),
+            Some(164 + 1 + 1 + 74 * 8 + 3 + 275)
+        );
+        // Script sig longer than 252 bytes has three-byte length
+        assert_eq!(
+            input_weight(&multisig(15, 15), descriptors::Category::Hashed),
+            Some(164 + (2 + 1 + 74 * 15 + 3 + 513) * 4)
+        );
+    }
+
+    #[test]
+    fn selection_modes() {
+        let template = template(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        );
+        let utxos = vec![
+            utxo(&template, 0, 20_000, 300),
+            utxo(&template, 1, 6_000, 100),
+            utxo(&template, 2, 4_200, 0),
+            utxo(&template, 3, 1_000, 200),
+        ];
+        let params = params();
+
+        let bnb = select(SelectionMode::BranchAndBound, &utxos, &params, false)
+            .unwrap();
+        assert_eq!(amounts(&bnb), vec![6_000, 4_200]);
+        assert_eq!(bnb.change, None);
+        assert_eq!(bnb.fee, 200);
+        assert_eq!(bnb.waste, 2 * (69 - 690) + 42);
+
+        let largest =
+            select(SelectionMode::LargestFirst, &utxos, &params, false)
+                .unwrap();
+        assert_eq!(amounts(&largest), vec![20_000]);
+        assert_eq!(largest.fee, 121);
+        assert_eq!(largest.change, Some(20_000 - 10_000 - 121));
+        assert_eq!(largest.waste, 69 - 690 + 31 + 690);
+
+        let oldest =
+            select(SelectionMode::OldestFirst, &utxos, &params, false).unwrap();
+        assert_eq!(amounts(&oldest), vec![6_000, 1_000, 20_000]);
+        assert!(oldest.change.is_some());
+
+        let all = select_all(&utxos, &params, false);
+        assert_eq!(
+            all.iter()
+                .map(|selection| selection.mode)
+                .collect::<Vec<_>>(),
+            vec![
+                SelectionMode::BranchAndBound,
+                SelectionMode::OldestFirst,
+                SelectionMode::LargestFirst
+            ]
+        );
+    }
+
+    #[test]
+    fn same_descriptor() {
+        let first = template(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        );
+        let second = template(
+            "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+        );
+        let utxos = vec![
+            utxo(&first, 0, 6_000, 100),
+            utxo(&second, 1, 20_000, 100),
+            utxo(&first, 2, 4_200, 100),
+            utxo(&second, 3, 5_000, 100),
+        ];
+        let params = params();
+
+        let selection =
+            select(SelectionMode::LargestFirst, &utxos, &params, true).unwrap();
+        assert_eq!(amounts(&selection), vec![6_000, 4_200]);
+        let selection =
+            select(SelectionMode::LargestFirst, &utxos, &params, false)
+                .unwrap();
+        assert_eq!(amounts(&selection), vec![20_000]);
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        let template = template(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        );
+        let utxos = vec![
+            utxo(&template, 0, 6_000, 100),
+            utxo(&template, 1, 4_000, 100),
+            // Below the fee for spending it
+            utxo(&template, 2, 50, 100),
+        ];
+        let params = params();
+
+        for mode in SelectionMode::all() {
+            assert_eq!(
+                select(mode, &utxos, &params, false),
+                Err(Error::InsufficientFunds(10_000, 10_000))
+            );
+        }
+        assert_eq!(
+            select(SelectionMode::BranchAndBound, &utxos[..1], &params, true),
+            Err(Error::InsufficientFunds(10_000, 6_000))
+        );
+    }
+}
@@ -0,0 +1,541 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use bitcoin::{OutPoint, Script, Transaction, TxOut, Txid};
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+use super::broadcast::{self, spent_outputs, MIN_RELAY_FEE_RATE};
+use super::coin_select::{fee_for, DUST_LIMIT};
+use super::psbt_compose::{self, Composition, PsbtCompose};
+use crate::model::{self, DescriptorAccount, TxLink, UtxoEntry};
+use crate::resolver::Resolver;
+
+/// Fee rate, in sats per virtual byte, by which a replacement transaction
+/// must pay for its own relay on top of the fee of the replaced one
+pub const INCREMENTAL_RELAY_FEE_RATE: f32 = 1.0;
+
+#[derive(Clone, PartialEq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// {0}
+    #[from]
+    Broadcast(broadcast::Error),
+
+    /// {0}
+    #[from]
+    Compose(psbt_compose::Error),
+
+    /// {0}
+    #[from]
+    Document(model::Error),
+
+    /// Transaction {0} does not signal replaceability (BIP125)
+    NotReplaceable(Txid),
+
+    /// Input #{0} spends an output which does not belong to any of the
+    /// descriptor accounts
+    ForeignInput(usize),
+
+    /// Transaction has no outputs belonging to the descriptor accounts which
+    /// may pay for the fee increase
+    NoOwnedOutput,
+
+    /// Transaction pays {1} sats, which is more than {0} sats it spends
+    NegativeFee(u64, u64),
+
+    /// Fee rate of {0} sat/vbyte is too low; it must be at least {1}
+    /// sat/vbyte
+    FeeRateTooLow(f32, f32),
+
+    /// Output of {0} sats is not enough to pay the fee of {1} sats
+    InsufficientFunds(u64, u64),
+}
+
+/// Fee paid by a transaction
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TxFee {
+    pub fee: u64,
+    pub vsize: usize,
+}
+
+impl TxFee {
+    pub fn with(tx: &Transaction, spent: &[TxOut]) -> Result<TxFee, Error> {
+        let inputs = spent.iter().map(|txout| txout.value).sum::<u64>();
+        let outputs = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        Ok(TxFee {
+            fee: inputs
+                .checked_sub(outputs)
+                .ok_or(Error::NegativeFee(inputs, outputs))?,
+            vsize: (tx.weight() + 3) / 4,
+        })
+    }
+
+    /// Fee rate in sats per virtual byte
+    pub fn fee_rate(&self) -> f32 {
+        self.fee as f32 / self.vsize as f32
+    }
+}
+
+/// Whether any of the transaction inputs signals replaceability (BIP125)
+pub fn is_replaceable(tx: &Transaction) -> bool {
+    tx.input.iter().any(|txin| txin.sequence < 0xFFFF_FFFE)
+}
+
+/// Descriptor links for the scripts which may be touched by a transaction:
+/// the ones from the known `links` and scripts of each descriptor account
+/// below its next unallocated derivation index
+fn script_links(
+    descriptors: &[DescriptorAccount],
+    links: impl IntoIterator<Item = TxLink>,
+    next_index: impl Fn(&DescriptorAccount) -> Result<u32, model::Error>,
+) -> Result<HashMap<Script, TxLink>, Error> {
+    let mut scripts = HashMap::new();
+    for link in links {
+        if let Some(script) = descriptors
+            .iter()
+            .find(|account| link.has_match(account))
+            .and_then(|account| link.script_pubkey(account))
+        {
+            scripts.insert(script, link);
+        }
+    }
+    for account in descriptors {
+        for index in 0..next_index(account)? {
+            let pubkey_scripts = match UnhardenedIndex::from_index(index)
+                .ok()
+                .and_then(|index| account.pubkey_scripts(index).ok())
+            {
+                Some(pubkey_scripts) => pubkey_scripts,
+                None => continue,
+            };
+            for (category, script) in pubkey_scripts {
+                scripts.entry(script).or_insert_with(|| TxLink {
+                    descriptor_template: account.generator.template.clone(),
+                    descriptor_category: category,
+                    derivation_index: index,
+                });
+            }
+        }
+    }
+    Ok(scripts)
+}
+
+fn owned_utxo(link: &TxLink, outpoint: OutPoint, amount: u64) -> UtxoEntry {
+    UtxoEntry {
+        outpoint,
+        height: 0,
+        amount,
+        descriptor_template: link.descriptor_template.clone(),
+        descriptor_category: link.descriptor_category,
+        derivation_index: link.derivation_index,
+    }
+}
+
+pub trait FeeBump: PsbtCompose {
+    /// Fee paid by the transaction. Transactions containing spent outputs
+    /// are fetched from the resolver only if `cached` returns `None` for
+    /// them.
+    fn tx_fee(
+        &self,
+        resolver: &dyn Resolver,
+        tx: &Transaction,
+        cached: impl Fn(Txid) -> Option<Transaction>,
+    ) -> Result<TxFee, Error> {
+        TxFee::with(tx, &spent_outputs(resolver, tx, cached)?)
+    }
+
+    /// Composes PSBT replacing the transaction (BIP125) with the one paying
+    /// `fee_rate`. Replacement spends the same inputs and pays to the same
+    /// outputs, except the last output belonging to the descriptor
+    /// accounts, which is treated as the change and pays for the fee
+    /// increase. `links` are descriptor links known for the transaction;
+    /// `next_index` provides the next unallocated derivation index of the
+    /// descriptor accounts.
+    fn rbf_psbt(
+        &self,
+        resolver: &dyn Resolver,
+        descriptors: &[DescriptorAccount],
+        links: impl IntoIterator<Item = TxLink>,
+        next_index: impl Fn(&DescriptorAccount) -> Result<u32, model::Error>,
+        tx: &Transaction,
+        fee_rate: f32,
+        cached: impl Fn(Txid) -> Option<Transaction>,
+    ) -> Result<Composition, Error> {
+        if !is_replaceable(tx) {
+            return Err(Error::NotReplaceable(tx.txid()));
+        }
+        let spent = spent_outputs(resolver, tx, &cached)?;
+        let original = TxFee::with(tx, &spent)?;
+        let min_fee_rate = original.fee_rate() + INCREMENTAL_RELAY_FEE_RATE;
+        if fee_rate < min_fee_rate {
+            return Err(Error::FeeRateTooLow(fee_rate, min_fee_rate));
+        }
+
+        let scripts = script_links(descriptors, links, next_index)?;
+        let utxos = tx
+            .input
+            .iter()
+            .zip(&spent)
+            .enumerate()
+            .map(|(vin, (txin, txout))| {
+                scripts
+                    .get(&txout.script_pubkey)
+                    .map(|link| {
+                        owned_utxo(link, txin.previous_output, txout.value)
+                    })
+                    .ok_or(Error::ForeignInput(vin))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (change_vout, change) = tx
+            .output
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(vout, txout)| {
+                let link = scripts.get(&txout.script_pubkey)?;
+                let account = descriptors
+                    .iter()
+                    .find(|account| link.has_match(account))?;
+                // Change goes to the same derivation index as in the
+                // replaced transaction
+                Some((vout, (account, link.derivation_index)))
+            })
+            .ok_or(Error::NoOwnedOutput)?;
+        let outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(vout, _)| *vout != change_vout)
+            .map(|(_, txout)| txout.clone())
+            .collect();
+
+        let composition = self.compose_psbt(
+            resolver,
+            descriptors,
+            utxos,
+            outputs,
+            fee_rate,
+            Some(change),
+            cached,
+        )?;
+        // Replacement must pay for its own relay in addition to the fee of
+        // the replaced transaction
+        let min_fee = original.fee
+            + fee_for(composition.weight, INCREMENTAL_RELAY_FEE_RATE);
+        if composition.fee < min_fee {
+            let vsize = (composition.weight + 3) / 4;
+            return Err(Error::FeeRateTooLow(
+                fee_rate,
+                min_fee as f32 / vsize as f32,
+            ));
+        }
+        Ok(composition)
+    }
+
+    /// Composes child PSBT spending the largest transaction output belonging
+    /// to the descriptor accounts, with the fee making the fee rate of the
+    /// transaction together with its child equal to `fee_rate` (CPFP).
+    /// `links` are descriptor links known for the transaction; the child
+    /// pays to the address with the derivation index from `next_index`.
+    fn cpfp_psbt(
+        &self,
+        resolver: &dyn Resolver,
+        descriptors: &[DescriptorAccount],
+        links: impl IntoIterator<Item = TxLink>,
+        next_index: impl Fn(&DescriptorAccount) -> Result<u32, model::Error>,
+        tx: &Transaction,
+        fee_rate: f32,
+        cached: impl Fn(Txid) -> Option<Transaction>,
+    ) -> Result<Composition, Error> {
+        let parent = TxFee::with(tx, &spent_outputs(resolver, tx, &cached)?)?;
+        if fee_rate < parent.fee_rate() {
+            return Err(Error::FeeRateTooLow(fee_rate, parent.fee_rate()));
+        }
+
+        let txid = tx.txid();
+        let scripts = script_links(descriptors, links, &next_index)?;
+        let (utxo, account) = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter_map(|(vout, txout)| {
+                let link = scripts.get(&txout.script_pubkey)?;
+                let account = descriptors
+                    .iter()
+                    .find(|account| link.has_match(account))?;
+                let outpoint = OutPoint::new(txid, vout as u32);
+                Some((owned_utxo(link, outpoint, txout.value), account))
+            })
+            .max_by_key(|(utxo, _)| utxo.amount)
+            .ok_or(Error::NoOwnedOutput)?;
+        let amount = utxo.amount;
+
+        let mut composition = self.compose_psbt(
+            resolver,
+            descriptors,
+            vec![utxo],
+            vec![],
+            fee_rate,
+            Some((account, next_index(account)?)),
+            |id| {
+                if id == txid {
+                    Some(tx.clone())
+                } else {
+                    cached(id)
+                }
+            },
+        )?;
+        let child_fee = fee_for(composition.weight, fee_rate);
+        if composition.change.is_none() {
+            return Err(Error::InsufficientFunds(amount, child_fee));
+        }
+
+        // Child pays for both itself and its parent
+        let vsize = (composition.weight + 3) / 4;
+        let fee = fee_for((parent.vsize + vsize) * 4, fee_rate)
+            .saturating_sub(parent.fee)
+            .max(fee_for(composition.weight, MIN_RELAY_FEE_RATE));
+        if amount < fee + DUST_LIMIT {
+            return Err(Error::InsufficientFunds(amount, fee));
+        }
+        let value = amount - fee;
+        composition.psbt.unsigned_tx.output[0].value = value;
+        composition.fee = fee;
+        composition.change = Some(value);
+        Ok(composition)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::TxIn;
+    use wallet::descriptors;
+
+    use super::*;
+    use crate::controller::psbt_compose::RBF_SEQUENCE;
+    use crate::resolver::MockResolver;
+
+    struct Bump;
+    impl PsbtCompose for Bump {}
+    impl FeeBump for Bump {}
+
+    fn account() -> DescriptorAccount {
+        DescriptorAccount {
+            name: s!("test"),
+            generator: descriptors::Generator {
+                template: descriptors::Template::SingleSig(
+                    descriptors::SingleSig::from_str(
+                        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                    )
+                    .unwrap(),
+                ),
+                variants: descriptors::Variants {
+                    bare: false,
+                    hashed: false,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+            last_used_indexes: bmap! { descriptors::Category::SegWit => 1 },
+            key_origins: empty!(),
+            tap_leaves: empty!(),
+        }
+    }
+
+    fn script(account: &DescriptorAccount, index: u32) -> Script {
+        account
+            .pubkey_scripts(UnhardenedIndex::from_index(index).unwrap())
+            .unwrap()
+            .remove(&descriptors::Category::SegWit)
+            .unwrap()
+    }
+
+    /// Funding transaction paying to the account and the transaction
+    /// spending from it with a fee of 1 sat/vbyte
+    fn transactions(
+        account: &DescriptorAccount,
+        sequence: u32,
+    ) -> (Transaction, Transaction) {
+        let funding = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: script(account, 0),
+            }],
+        };
+        let mut spending = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(funding.txid(), 0),
+                sequence,
+                ..TxIn::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: 30_000,
+                    script_pubkey: Script::new_op_return(&[]),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: script(account, 1),
+                },
+            ],
+        };
+        let vsize = (spending.weight() + 3) / 4;
+        spending.output[1].value = 70_000 - vsize as u64;
+        (funding, spending)
+    }
+
+    #[test]
+    fn replace_by_fee() {
+        let account = account();
+        let (funding, original) = transactions(&account, RBF_SEQUENCE);
+        let cached = |txid| {
+            [&funding, &original]
+                .into_iter()
+                .find(|tx| tx.txid() == txid)
+                .cloned()
+        };
+        let mut resolver = MockResolver::new();
+        assert!(resolver.estimate_fee(6).is_err());
+        resolver.set_fee_rate(5.0);
+        let descriptors = [account.clone()];
+        let original_fee = Bump.tx_fee(&resolver, &original, cached).unwrap();
+        assert_eq!(original_fee.fee_rate(), 1.0);
+
+        assert_eq!(
+            Bump.rbf_psbt(
+                &resolver,
+                &descriptors,
+                vec![],
+                |_| Ok(2),
+                &original,
+                1.5,
+                cached
+            ),
+            Err(Error::FeeRateTooLow(1.5, 2.0))
+        );
+
+        let fee_rate = resolver.estimate_fee(6).unwrap();
+        let composition = Bump
+            .rbf_psbt(
+                &resolver,
+                &descriptors,
+                vec![],
+                |_| Ok(2),
+                &original,
+                fee_rate,
+                cached,
+            )
+            .unwrap();
+        let tx = &composition.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(
+            tx.input[0].previous_output,
+            original.input[0].previous_output
+        );
+        assert_eq!(tx.input[0].sequence, RBF_SEQUENCE);
+        assert_eq!(tx.output[0], original.output[0]);
+        // Change goes to the same address
+        assert_eq!(tx.output[1].script_pubkey, script(&account, 1));
+        assert!(
+            composition.fee
+                >= original_fee.fee + fee_for(composition.weight, 1.0)
+        );
+        assert_eq!(
+            composition.fee + tx.output[1].value + tx.output[0].value,
+            100_000
+        );
+
+        let (_, final_tx) = transactions(&account, u32::MAX);
+        assert_eq!(
+            Bump.rbf_psbt(
+                &resolver,
+                &descriptors,
+                vec![],
+                |_| Ok(2),
+                &final_tx,
+                5.0,
+                cached
+            ),
+            Err(Error::NotReplaceable(final_tx.txid()))
+        );
+    }
+
+    #[test]
+    fn child_pays_for_parent() {
+        let account = account();
+        let (funding, parent) = transactions(&account, u32::MAX);
+        let cached =
+            |txid| Some(funding.clone()).filter(|tx| tx.txid() == txid);
+        let resolver = MockResolver::new();
+        let descriptors = [account.clone()];
+
+        let composition = Bump
+            .cpfp_psbt(
+                &resolver,
+                &descriptors,
+                vec![],
+                |_| Ok(2),
+                &parent,
+                10.0,
+                cached,
+            )
+            .unwrap();
+        let tx = &composition.psbt.unsigned_tx;
+        assert_eq!(
+            tx.input[0].previous_output,
+            OutPoint::new(parent.txid(), 1)
+        );
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(composition.change, Some(tx.output[0].value));
+        // Child pays to the next unallocated address
+        assert_eq!(tx.output[0].script_pubkey, script(&account, 2));
+        assert_eq!(
+            composition.fee + tx.output[0].value,
+            parent.output[1].value
+        );
+
+        // Package of the parent and the child pays the requested fee rate
+        let parent_fee = Bump.tx_fee(&resolver, &parent, cached).unwrap();
+        let vsize = parent_fee.vsize + (composition.weight + 3) / 4;
+        let package_rate =
+            (parent_fee.fee + composition.fee) as f32 / vsize as f32;
+        assert!(package_rate >= 10.0 && package_rate < 10.1);
+
+        assert_eq!(
+            Bump.cpfp_psbt(
+                &resolver,
+                &[],
+                vec![],
+                |_| Ok(2),
+                &parent,
+                10.0,
+                cached
+            ),
+            Err(Error::NoOwnedOutput)
+        );
+    }
+}
@@ -0,0 +1,235 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashSet};
+
+use bitcoin::{Transaction, Txid};
+use wallet::descriptors;
+
+use super::utxo_lookup::{
+    gap_scan, lookup_items, script_history, Error, LookupItem,
+};
+use crate::model::{DescriptorAccount, TxEntry, TxLink};
+use crate::resolver::{HistoryItem, Resolver};
+use crate::util::resolver_mode::ResolverModeType;
+
+/// Outcome of the transaction history lookup
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct HistoryUpdate {
+    /// History entries of all transactions touching the account scripts
+    pub entries: BTreeMap<Txid, TxEntry>,
+    /// Transactions from `entries` which were not known before the lookup
+    pub transactions: Vec<Transaction>,
+    /// The highest derivation index having transaction history in each of
+    /// the output categories
+    pub last_used_indexes: BTreeMap<descriptors::Category, u32>,
+}
+
+pub trait HistoryLookup {
+    /// Looks up transactions paying to or spending from the descriptor
+    /// account scripts. Transaction data are fetched from the resolver only
+    /// for the transactions for which `is_known` returns `false`.
+    fn history_lookup(
+        &self,
+        resolver: &dyn Resolver,
+        lookup_type: ResolverModeType,
+        account: &DescriptorAccount,
+        is_known: impl Fn(Txid) -> bool,
+    ) -> Result<HistoryUpdate, Error> {
+        if !resolver.provides_script_history() {
+            return Err(Error::NoScriptHistory("transaction history lookup"));
+        }
+        let mut update = HistoryUpdate::default();
+        let mut record = |item: &LookupItem, history: Vec<HistoryItem>| {
+            let last_used = update
+                .last_used_indexes
+                .entry(item.category)
+                .or_insert(item.derivation_index);
+            *last_used = (*last_used).max(item.derivation_index);
+            for history_item in history {
+                let entry =
+                    update.entries.entry(history_item.txid).or_default();
+                entry.height = history_item.height;
+                entry.links.insert(TxLink {
+                    descriptor_template: account.generator.template.clone(),
+                    descriptor_category: item.category,
+                    derivation_index: item.derivation_index,
+                });
+            }
+        };
+
+        match lookup_type {
+            ResolverModeType::While => {
+                // Scans derivation indexes one by one until the first one
+                // without transaction history
+                let mut seen = HashSet::new();
+                for index in 0u32.. {
+                    let items = lookup_items(account, [index], &mut seen)?;
+                    if items.is_empty()
+                        || script_history(resolver, &items, &mut record)? == 0
+                    {
+                        break;
+                    }
+                }
+            }
+            ResolverModeType::Gap(_) => {
+                gap_scan(resolver, account, lookup_type, &mut record)?;
+            }
+            ResolverModeType::First(_) | ResolverModeType::Random(_) => {
+                let items =
+                    lookup_items(account, lookup_type, &mut HashSet::new())?;
+                script_history(resolver, &items, &mut record)?;
+            }
+        }
+
+        for txid in update.entries.keys() {
+            if !is_known(*txid) {
+                update.transactions.push(resolver.transaction(*txid)?);
+            }
+        }
+        Ok(update)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::{OutPoint, Script, TxIn, TxOut};
+    use wallet::descriptors;
+    use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+    use super::*;
+    use crate::model::Document;
+    use crate::resolver::MockResolver;
+
+    struct Lookup;
+    impl HistoryLookup for Lookup {}
+
+    fn account() -> DescriptorAccount {
+        DescriptorAccount {
+            name: s!("test"),
+            generator: descriptors::Generator {
+                template: descriptors::Template::SingleSig(
+                    descriptors::SingleSig::from_str(
+                        "pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+                    )
+                    .unwrap(),
+                ),
+                variants: descriptors::Variants {
+                    bare: false,
+                    hashed: true,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: empty!(),
+        }
+    }
+
+    fn tx(input: Vec<OutPoint>, output: Vec<(u64, Script)>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: input
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: output
+                .into_iter()
+                .map(|(value, script_pubkey)| TxOut {
+                    value,
+                    script_pubkey,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn history_received_and_spent() {
+        let account = account();
+        let scripts = account.pubkey_scripts(UnhardenedIndex::zero()).unwrap();
+        let segwit = scripts[&descriptors::Category::SegWit].clone();
+        let hashed = scripts[&descriptors::Category::Hashed].clone();
+        let foreign = Script::new_op_return(&[]);
+
+        // Funding from outside, then spending part of it to a foreign script
+        // with the change to another script of the same account
+        let funding = tx(vec![OutPoint::default()], vec![(10_000, segwit)]);
+        let spending = tx(
+            vec![OutPoint::new(funding.txid(), 0)],
+            vec![(3_000, foreign), (6_000, hashed)],
+        );
+        let mut resolver = MockResolver::new();
+        resolver.add_transaction(funding.clone(), 100);
+        resolver.add_transaction(spending.clone(), 0);
+
+        let mut doc = Document::new();
+        doc.add_descriptor(account.clone()).unwrap();
+        let update = Lookup
+            .history_lookup(
+                &resolver,
+                ResolverModeType::from_str("gap20").unwrap(),
+                &account,
+                |txid| doc.is_transaction_known(txid),
+            )
+            .unwrap();
+        assert_eq!(
+            update.last_used_indexes,
+            bmap! {
+                descriptors::Category::Hashed => 0,
+                descriptors::Category::SegWit => 0
+            }
+        );
+        assert_eq!(update.entries.len(), 2);
+        assert_eq!(update.transactions.len(), 2);
+        assert_eq!(update.entries[&funding.txid()].height, 100);
+        // Spending transaction touches both the spent and the change scripts
+        assert_eq!(update.entries[&spending.txid()].links.len(), 2);
+        doc.update_tx_history(update.transactions, update.entries)
+            .unwrap();
+
+        let rows = doc.history_rows(&account);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].txid, funding.txid());
+        assert_eq!((rows[0].received, rows[0].spent), (10_000, 0));
+        assert_eq!(rows[0].net(), 10_000);
+        // Transactions which are not mined yet are listed last
+        assert_eq!(rows[1].txid, spending.txid());
+        assert_eq!(rows[1].height, 0);
+        assert_eq!((rows[1].received, rows[1].spent), (6_000, 10_000));
+        assert_eq!(rows[1].net(), -4_000);
+
+        // Repeated lookup does not fetch known transactions
+        let update = Lookup
+            .history_lookup(
+                &resolver,
+                ResolverModeType::While,
+                &account,
+                |txid| doc.is_transaction_known(txid),
+            )
+            .unwrap();
+        assert_eq!(update.entries.len(), 2);
+        assert!(update.transactions.is_empty());
+    }
+}
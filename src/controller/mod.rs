@@ -15,4 +15,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod broadcast;
+pub mod coin_select;
+pub mod fee_bump;
+pub mod history_lookup;
+pub mod psbt_compose;
+pub mod psbt_finalize;
 pub mod utxo_lookup;
+pub mod utxo_refresh;
@@ -0,0 +1,803 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::blockdata::opcodes::all::OP_CHECKMULTISIG;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::secp256k1::{self, SECP256K1};
+use bitcoin::util::bip32::{ChildNumber, ExtendedPubKey, KeySource};
+use bitcoin::util::psbt::{Input, Output};
+use bitcoin::{OutPoint, PublicKey, Script, Transaction, TxOut, Txid};
+use wallet::descriptors;
+use wallet::psbt::Psbt;
+
+use super::coin_select::{self, fee_for, SelectionParams, DUST_LIMIT};
+use crate::model::{DescriptorAccount, ResolverError, TxLink, UtxoEntry};
+use crate::resolver::Resolver;
+use crate::util::psbt::{add_input, add_output, empty_psbt};
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// Blockchain information provider error
+    #[display("{0}")]
+    #[from]
+    Resolver(ResolverError),
+
+    /// No unspent outputs are selected for spending
+    NoInputs,
+
+    /// Selected outputs have {2} sats, which is not enough to pay {0} sats
+    /// and the fee of {1} sats
+    InsufficientFunds(u64, u64, u64),
+
+    /// Output {0} does not belong to any of the known descriptor accounts
+    UnknownDescriptor(OutPoint),
+
+    /// Output {0} has a script or amount which does not match the one
+    /// generated by its descriptor
+    ScriptMismatch(OutPoint),
+
+    /// The actual value of the used index corresponds to a hardened index,
+    /// which can't be used in the current context
+    HardenedIndex,
+
+    /// Spending from {0} descriptors is not supported yet
+    UnsupportedTemplate(String),
+
+    /// Spending from {0} outputs is not supported yet
+    UnsupportedCategory(descriptors::Category),
+
+    /// Uncompressed public key can't be used in segwit outputs
+    UncompressedKey,
+}
+
+/// Scripts and key origins required to spend from (or to verify ownership
+/// of) an output generated by a descriptor
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SpendInfo {
+    pub script_pubkey: Script,
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+    pub bip32_derivation: BTreeMap<secp256k1::PublicKey, KeySource>,
+    /// Extended public keys of the accounts with their origins, used to
+    /// fill in the global PSBT map
+    pub xpubs: BTreeMap<ExtendedPubKey, KeySource>,
+    /// Weight of the signatures and scripts required to spend the output,
+    /// including both script sig and witness data
+    pub satisfaction_weight: usize,
+    pub is_segwit: bool,
+}
+
+/// Input sequence number signalling replaceability of the transaction
+/// (BIP125)
+pub const RBF_SEQUENCE: u32 = 0xFFFF_FFFD;
+
+/// Result of the PSBT composition
+#[derive(Clone, PartialEq, Debug)]
+pub struct Composition {
+    pub psbt: Psbt,
+    /// Estimated weight of the transaction once it is signed
+    pub weight: usize,
+    /// Fee paid by the transaction, in sats
+    pub fee: u64,
+    /// Change output amount, if the change was above the dust limit
+    pub change: Option<u64>,
+    /// Descriptor script of the change output, which must be recorded as
+    /// used by the document once the PSBT is saved
+    pub change_link: Option<TxLink>,
+}
+
+/// Derives the key for the given derivation index, returning it together
+/// with its origin (when known). Extended keys listed in `origins` are
+/// reported with the origin given there instead of their master key.
+fn derive_key(
+    key: &descriptors::SingleSig,
+    index: u32,
+    origins: &BTreeMap<ExtendedPubKey, KeySource>,
+    derivation: &mut BTreeMap<secp256k1::PublicKey, KeySource>,
+    xpubs: &mut BTreeMap<ExtendedPubKey, KeySource>,
+) -> Result<PublicKey, Error> {
+    match key {
+        descriptors::SingleSig::Pubkey(single) => {
+            if let Some(origin) = single.origin.clone() {
+                derivation.insert(single.key.inner, origin);
+            }
+            Ok(single.key)
+        }
+        descriptors::SingleSig::XPubDerivable(components) => {
+            let mut relative = components
+                .terminal_path
+                .iter()
+                .map(|index| ChildNumber::from_normal_idx(*index))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| Error::HardenedIndex)?;
+            relative.push(
+                ChildNumber::from_normal_idx(index)
+                    .map_err(|_| Error::HardenedIndex)?,
+            );
+            let xpub = components
+                .branch_xpub
+                .derive_pub(&SECP256K1, &relative)
+                .map_err(|_| Error::HardenedIndex)?;
+            let (fingerprint, branch_path) =
+                match origins.get(&components.branch_xpub) {
+                    Some(origin) => origin.clone(),
+                    None => (
+                        components.master_xpub.fingerprint(),
+                        components.branch_path.clone(),
+                    ),
+                };
+            derivation.insert(
+                xpub.public_key,
+                (fingerprint, branch_path.extend(&relative)),
+            );
+            xpubs.insert(components.branch_xpub, (fingerprint, branch_path));
+            Ok(PublicKey::new(xpub.public_key))
+        }
+        _ => Err(Error::UnsupportedTemplate(key.to_string())),
+    }
+}
+
+/// Constructs scripts and key origins for the output generated by the
+/// descriptor `account` under the given `category` and derivation `index`
+pub fn spend_info(
+    account: &DescriptorAccount,
+    category: descriptors::Category,
+    index: u32,
+) -> Result<SpendInfo, Error> {
+    let template = &account.generator.template;
+    let origins = &account.key_origins;
+    let mut bip32_derivation = bmap! {};
+    let mut xpubs = bmap! {};
+    let (script_pubkey, redeem_script, witness_script) = match template {
+        descriptors::Template::SingleSig(key) => {
+            let pk = derive_key(
+                key,
+                index,
+                origins,
+                &mut bip32_derivation,
+                &mut xpubs,
+            )?;
+            let wpkh = || {
+                pk.wpubkey_hash()
+                    .map(|hash| Script::new_v0_p2wpkh(&hash))
+                    .ok_or(Error::UncompressedKey)
+            };
+            match category {
+                descriptors::Category::Bare => {
+                    (Script::new_p2pk(&pk), None, None)
+                }
+                descriptors::Category::Hashed => {
+                    (Script::new_p2pkh(&pk.pubkey_hash()), None, None)
+                }
+                descriptors::Category::Nested => {
+                    let redeem_script = wpkh()?;
+                    (
+                        Script::new_p2sh(&redeem_script.script_hash()),
+                        Some(redeem_script),
+                        None,
+                    )
+                }
+                descriptors::Category::SegWit => (wpkh()?, None, None),
+                _ => return Err(Error::UnsupportedCategory(category)),
+            }
+        }
+        descriptors::Template::MultiSig(multisig) => {
+            let mut keys = multisig
+                .pubkeys
+                .iter()
+                .map(|key| {
+                    derive_key(
+                        key,
+                        index,
+                        origins,
+                        &mut bip32_derivation,
+                        &mut xpubs,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if multisig.reorder {
+                keys.sort_by_key(|key| key.to_bytes());
+            }
+            let script = keys
+                .iter()
+                .fold(
+                    Builder::new().push_int(multisig.threshold() as i64),
+                    |builder, key| builder.push_key(key),
+                )
+                .push_int(keys.len() as i64)
+                .push_opcode(OP_CHECKMULTISIG)
+                .into_script();
+            match category {
+                descriptors::Category::Bare => (script, None, None),
+                descriptors::Category::Hashed => (
+                    Script::new_p2sh(&script.script_hash()),
+                    Some(script),
+                    None,
+                ),
+                descriptors::Category::Nested => {
+                    let redeem_script =
+                        Script::new_v0_p2wsh(&script.wscript_hash());
+                    (
+                        Script::new_p2sh(&redeem_script.script_hash()),
+                        Some(redeem_script),
+                        Some(script),
+                    )
+                }
+                descriptors::Category::SegWit => (
+                    Script::new_v0_p2wsh(&script.wscript_hash()),
+                    None,
+                    Some(script),
+                ),
+                _ => return Err(Error::UnsupportedCategory(category)),
+            }
+        }
+        _ => return Err(Error::UnsupportedTemplate(template.to_string())),
+    };
+    Ok(SpendInfo {
+        script_pubkey,
+        redeem_script,
+        witness_script,
+        bip32_derivation,
+        xpubs,
+        satisfaction_weight: coin_select::satisfaction_weight(
+            template, category,
+        )
+        .ok_or(Error::UnsupportedCategory(category))?,
+        is_segwit: coin_select::is_segwit(category),
+    })
+}
+
+/// Picks the category for the change output, preferring the cheapest one to
+/// spend among the ones supported by the descriptor
+pub fn change_category(
+    account: &DescriptorAccount,
+) -> Result<descriptors::Category, Error> {
+    let variants = &account.generator.variants;
+    if variants.segwit {
+        Ok(descriptors::Category::SegWit)
+    } else if variants.nested {
+        Ok(descriptors::Category::Nested)
+    } else if variants.hashed {
+        Ok(descriptors::Category::Hashed)
+    } else if variants.bare {
+        Ok(descriptors::Category::Bare)
+    } else {
+        Err(Error::UnsupportedCategory(descriptors::Category::Taproot))
+    }
+}
+
+/// Coin selection parameters for paying to the `outputs`, with the change
+/// going to the `change` descriptor account address with the given
+/// derivation index
+pub fn selection_params(
+    outputs: &[TxOut],
+    fee_rate: f32,
+    change: Option<(&DescriptorAccount, u32)>,
+) -> Result<SelectionParams, Error> {
+    let params = SelectionParams::with(outputs, fee_rate);
+    let (account, index) = match change {
+        Some(change) => change,
+        None => return Ok(params),
+    };
+    let info = spend_info(account, change_category(account)?, index)?;
+    let txout = TxOut {
+        value: 0,
+        script_pubkey: info.script_pubkey,
+    };
+    Ok(params.with_change(
+        &txout,
+        coin_select::TXIN_BASE_WEIGHT + info.satisfaction_weight,
+    ))
+}
+
+pub trait PsbtCompose {
+    /// Composes PSBT spending all of the `utxos` into the `outputs`; the
+    /// rest of the funds, after paying the fee at `fee_rate` sat/vbyte,
+    /// goes to the `change` descriptor account address with the given
+    /// derivation index, which should be the next unused one.
+    /// Transactions containing spent outputs are fetched from the resolver
+    /// only when `cached` returns `None` for them.
+    fn compose_psbt(
+        &self,
+        resolver: &dyn Resolver,
+        descriptors: &[DescriptorAccount],
+        utxos: impl IntoIterator<Item = UtxoEntry>,
+        outputs: Vec<TxOut>,
+        fee_rate: f32,
+        change: Option<(&DescriptorAccount, u32)>,
+        cached: impl Fn(Txid) -> Option<Transaction>,
+    ) -> Result<Composition, Error> {
+        let mut psbt = empty_psbt();
+        let mut fetched = HashMap::<Txid, Transaction>::new();
+        let mut input_value = 0u64;
+        let mut satisfaction_weight = 0usize;
+        let mut is_segwit = false;
+
+        for utxo in utxos {
+            let outpoint = utxo.outpoint;
+            let account = descriptors
+                .iter()
+                .find(|account| utxo.has_match(account))
+                .ok_or(Error::UnknownDescriptor(outpoint))?;
+            let info = spend_info(
+                account,
+                utxo.descriptor_category,
+                utxo.derivation_index,
+            )?;
+
+            let known = cached(outpoint.txid)
+                .or_else(|| fetched.get(&outpoint.txid).cloned());
+            let tx = match known {
+                Some(tx) => tx,
+                None => {
+                    let tx = resolver.transaction(outpoint.txid)?;
+                    fetched.insert(outpoint.txid, tx.clone());
+                    tx
+                }
+            };
+            let txout = tx
+                .output
+                .get(outpoint.vout as usize)
+                .filter(|txout| {
+                    txout.script_pubkey == info.script_pubkey
+                        && txout.value == utxo.amount
+                })
+                .cloned()
+                .ok_or(Error::ScriptMismatch(outpoint))?;
+
+            input_value += txout.value;
+            satisfaction_weight += info.satisfaction_weight;
+            is_segwit |= info.is_segwit;
+            psbt.xpub.extend(info.xpubs);
+            add_input(
+                &mut psbt,
+                outpoint,
+                Input {
+                    non_witness_utxo: Some(tx),
+                    witness_utxo: if info.is_segwit {
+                        Some(txout)
+                    } else {
+                        None
+                    },
+                    redeem_script: info.redeem_script,
+                    witness_script: info.witness_script,
+                    bip32_derivation: info.bip32_derivation,
+                    ..default!()
+                },
+            );
+        }
+        if psbt.inputs.is_empty() {
+            return Err(Error::NoInputs);
+        }
+        // Composed transactions are replaceable, so their fee can be bumped
+        for txin in &mut psbt.unsigned_tx.input {
+            txin.sequence = RBF_SEQUENCE;
+        }
+
+        let output_value = outputs.iter().map(|txout| txout.value).sum::<u64>();
+        for txout in outputs {
+            add_output(&mut psbt, txout);
+        }
+
+        // Segwit marker and flag take two weight units
+        let weight = psbt.unsigned_tx.weight()
+            + satisfaction_weight
+            + if is_segwit { 2 } else { 0 };
+        let fee = fee_for(weight, fee_rate);
+        if input_value < output_value + fee {
+            return Err(Error::InsufficientFunds(
+                output_value,
+                fee,
+                input_value,
+            ));
+        }
+
+        let mut composition = Composition {
+            psbt,
+            weight,
+            fee: input_value - output_value,
+            change: None,
+            change_link: None,
+        };
+        if let Some((account, index)) = change {
+            let category = change_category(account)?;
+            let info = spend_info(account, category, index)?;
+            let txout = TxOut {
+                value: 0,
+                script_pubkey: info.script_pubkey,
+            };
+            let weight = weight + coin_select::output_weight(&txout);
+            let fee = fee_for(weight, fee_rate);
+            if input_value >= output_value + fee + DUST_LIMIT {
+                let value = input_value - output_value - fee;
+                let psbt = &mut composition.psbt;
+                add_output(psbt, TxOut { value, ..txout });
+                let output = psbt.outputs.last_mut().expect("just added");
+                *output = Output {
+                    redeem_script: info.redeem_script,
+                    witness_script: info.witness_script,
+                    bip32_derivation: info.bip32_derivation,
+                    ..default!()
+                };
+                psbt.xpub.extend(info.xpubs);
+                composition.weight = weight;
+                composition.fee = fee;
+                composition.change = Some(value);
+                composition.change_link = Some(TxLink {
+                    descriptor_template: account.generator.template.clone(),
+                    descriptor_category: category,
+                    derivation_index: index,
+                });
+            }
+        }
+
+        Ok(composition)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
+    use bitcoin::{Network, TxIn};
+    use wallet::hd::{
+        DerivationComponents, HardenedNormalSplit, SegmentIndexes,
+        UnhardenedIndex,
+    };
+
+    use super::*;
+    use crate::model::Document;
+    use crate::resolver::MockResolver;
+
+    struct Compose;
+    impl PsbtCompose for Compose {}
+
+    fn account(
+        key: descriptors::SingleSig,
+        category: descriptors::Category,
+    ) -> DescriptorAccount {
+        DescriptorAccount {
+            name: s!("test"),
+            generator: descriptors::Generator {
+                template: descriptors::Template::SingleSig(key),
+                variants: descriptors::Variants {
+                    bare: false,
+                    hashed: false,
+                    nested: category == descriptors::Category::Nested,
+                    segwit: category == descriptors::Category::SegWit,
+                    taproot: false,
+                },
+            },
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: empty!(),
+        }
+    }
+
+    fn script(
+        account: &DescriptorAccount,
+        category: descriptors::Category,
+        index: u32,
+    ) -> Script {
+        account
+            .pubkey_scripts(UnhardenedIndex::from_index(index).unwrap())
+            .unwrap()
+            .remove(&category)
+            .unwrap()
+    }
+
+    fn funding(account: &DescriptorAccount) -> Transaction {
+        let category = change_category(account).unwrap();
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: (0..2)
+                .map(|index| TxOut {
+                    value: 10_000,
+                    script_pubkey: script(account, category, index),
+                })
+                .collect(),
+        }
+    }
+
+    fn utxo(
+        account: &DescriptorAccount,
+        tx: &Transaction,
+        vout: u32,
+    ) -> UtxoEntry {
+        UtxoEntry {
+            outpoint: OutPoint::new(tx.txid(), vout),
+            height: 100,
+            amount: tx.output[vout as usize].value,
+            descriptor_template: account.generator.template.clone(),
+            descriptor_category: change_category(account).unwrap(),
+            derivation_index: vout,
+        }
+    }
+
+    fn payment(value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: Script::new_op_return(&[]),
+        }
+    }
+
+    #[test]
+    fn singlesig_with_change() {
+        let account = account(
+            descriptors::SingleSig::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            descriptors::Category::SegWit,
+        );
+        let tx = funding(&account);
+        let mut resolver = MockResolver::new();
+        resolver.add_transaction(tx.clone(), 100);
+
+        let composition = Compose
+            .compose_psbt(
+                &resolver,
+                &[account.clone()],
+                vec![utxo(&account, &tx, 0), utxo(&account, &tx, 1)],
+                vec![payment(15_000)],
+                2.0,
+                Some((&account, 2)),
+                |_| None,
+            )
+            .unwrap();
+
+        let psbt = &composition.psbt;
+        assert_eq!(psbt.inputs.len(), 2);
+        assert_eq!(psbt.outputs.len(), 2);
+        assert_eq!(psbt.inputs[0].non_witness_utxo, Some(tx.clone()));
+        assert_eq!(psbt.inputs[1].witness_utxo, Some(tx.output[1].clone()));
+        let change = &psbt.unsigned_tx.output[1];
+        assert_eq!(
+            change.script_pubkey,
+            script(&account, descriptors::Category::SegWit, 2)
+        );
+        assert_eq!(composition.change, Some(change.value));
+        assert_eq!(
+            composition
+                .change_link
+                .and_then(|link| link.script_pubkey(&account)),
+            Some(change.script_pubkey.clone())
+        );
+        assert_eq!(composition.fee + change.value + 15_000, 20_000);
+        // Two P2WPKH inputs, OP_RETURN and P2WPKH outputs take ~189 vbytes
+        assert!(composition.fee >= 370 && composition.fee <= 390);
+    }
+
+    #[test]
+    fn change_addresses_are_reserved() {
+        let account = account(
+            descriptors::SingleSig::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            descriptors::Category::SegWit,
+        );
+        let tx = funding(&account);
+        let mut doc = Document::new();
+        doc.add_descriptor(account.clone()).unwrap();
+        doc.update_utxo_set(
+            (0..2).map(|vout| utxo(&account, &tx, vout)).collect(),
+        )
+        .unwrap();
+
+        // Each PSBT pays change to a new address, even if none of them is
+        // broadcasted
+        let mut change_scripts = vec![];
+        for vout in 0..2 {
+            let index = doc.next_address_index(&account).unwrap();
+            let composition = Compose
+                .compose_psbt(
+                    &MockResolver::new(),
+                    doc.descriptors(),
+                    vec![utxo(&account, &tx, vout)],
+                    vec![payment(5_000)],
+                    1.0,
+                    Some((&account, index)),
+                    |_| Some(tx.clone()),
+                )
+                .unwrap();
+            change_scripts.push(
+                composition.psbt.unsigned_tx.output[1].script_pubkey.clone(),
+            );
+            doc.add_composed_psbt(
+                composition.psbt,
+                composition.change_link.as_ref(),
+            )
+            .unwrap();
+        }
+        let segwit = descriptors::Category::SegWit;
+        assert_eq!(
+            change_scripts,
+            vec![script(&account, segwit, 2), script(&account, segwit, 3)]
+        );
+        assert_eq!(
+            doc.address_entry(&account, segwit, 3)
+                .map(|entry| entry.label.as_str()),
+            Some("Change")
+        );
+        assert_eq!(doc.next_address_index(&account).unwrap(), 4);
+    }
+
+    #[test]
+    fn receive_and_change_addresses_differ() {
+        let account = account(
+            descriptors::SingleSig::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            descriptors::Category::SegWit,
+        );
+        let tx = funding(&account);
+        let mut doc = Document::new();
+        doc.add_descriptor(account.clone()).unwrap();
+        doc.update_utxo_set(
+            (0..2).map(|vout| utxo(&account, &tx, vout)).collect(),
+        )
+        .unwrap();
+        let segwit = descriptors::Category::SegWit;
+        let receive = doc.next_address_index(&account).unwrap();
+        doc.issue_address(&account, segwit, receive, s!("Alice"), s!(""))
+            .unwrap();
+
+        let composition = Compose
+            .compose_psbt(
+                &MockResolver::new(),
+                doc.descriptors(),
+                vec![utxo(&account, &tx, 1)],
+                vec![payment(5_000)],
+                1.0,
+                Some((&account, doc.next_address_index(&account).unwrap())),
+                |_| Some(tx.clone()),
+            )
+            .unwrap();
+        let change = composition.change_link.clone().unwrap();
+        assert_ne!(change.derivation_index, receive);
+        assert_ne!(
+            composition.psbt.unsigned_tx.output[1].script_pubkey,
+            script(&account, segwit, receive)
+        );
+        doc.add_composed_psbt(composition.psbt, Some(&change))
+            .unwrap();
+
+        // Neither of the addresses is handed out again
+        let next = doc.next_address_index(&account).unwrap();
+        assert!(next > receive && next > change.derivation_index);
+        assert_eq!(
+            doc.address_entry(&account, segwit, receive)
+                .map(|entry| entry.label.as_str()),
+            Some("Alice")
+        );
+    }
+
+    #[test]
+    fn xpub_derivation() {
+        let master =
+            ExtendedPrivKey::new_master(Network::Bitcoin, &[1u8; 32]).unwrap();
+        let master_xpub = ExtendedPubKey::from_priv(&SECP256K1, &master);
+        let path = DerivationPath::from_str("m/49'/0'/0'/1").unwrap();
+        let (branch_path, terminal_path) = path.hardened_normal_split();
+        let branch_xpub = ExtendedPubKey::from_priv(
+            &SECP256K1,
+            &master.derive_priv(&SECP256K1, &branch_path).unwrap(),
+        );
+        let account = account(
+            descriptors::SingleSig::XPubDerivable(DerivationComponents {
+                master_xpub,
+                branch_xpub,
+                branch_path: branch_path.clone(),
+                terminal_path,
+                index_ranges: None,
+            }),
+            descriptors::Category::Nested,
+        );
+        let tx = funding(&account);
+
+        let info =
+            spend_info(&account, descriptors::Category::Nested, 1).unwrap();
+        assert_eq!(info.script_pubkey, tx.output[1].script_pubkey);
+
+        let composition = Compose
+            .compose_psbt(
+                &MockResolver::new(),
+                &[account.clone()],
+                vec![utxo(&account, &tx, 1)],
+                vec![payment(9_500)],
+                1.0,
+                Some((&account, 2)),
+                |_| Some(tx.clone()),
+            )
+            .unwrap();
+
+        // Change is below the dust limit
+        assert_eq!(composition.change, None);
+        assert_eq!(composition.fee, 500);
+        let psbt = &composition.psbt;
+        let key = master
+            .derive_priv(
+                &SECP256K1,
+                &DerivationPath::from_str("m/49'/0'/0'/1/1").unwrap(),
+            )
+            .unwrap();
+        let key = ExtendedPubKey::from_priv(&SECP256K1, &key).public_key;
+        let fingerprint = master_xpub.fingerprint();
+        assert_eq!(
+            psbt.inputs[0].bip32_derivation,
+            bmap! { key => (
+                fingerprint,
+                DerivationPath::from_str("m/49'/0'/0'/1/1").unwrap()
+            )}
+        );
+        assert_eq!(
+            psbt.inputs[0].redeem_script,
+            Some(Script::new_v0_p2wpkh(
+                &PublicKey::new(key).wpubkey_hash().unwrap()
+            ))
+        );
+        assert_eq!(
+            psbt.xpub,
+            bmap! { branch_xpub => (fingerprint, branch_path) }
+        );
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        let account = account(
+            descriptors::SingleSig::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            descriptors::Category::SegWit,
+        );
+        let tx = funding(&account);
+
+        assert_eq!(
+            Compose.compose_psbt(
+                &MockResolver::new(),
+                &[account.clone()],
+                vec![],
+                vec![payment(1_000)],
+                1.0,
+                None,
+                |_| Some(tx.clone()),
+            ),
+            Err(Error::NoInputs)
+        );
+        assert!(matches!(
+            Compose.compose_psbt(
+                &MockResolver::new(),
+                &[account.clone()],
+                vec![utxo(&account, &tx, 0)],
+                vec![payment(10_000)],
+                1.0,
+                Some((&account, 2)),
+                |_| Some(tx.clone()),
+            ),
+            Err(Error::InsufficientFunds(10_000, _, 10_000))
+        ));
+    }
+}
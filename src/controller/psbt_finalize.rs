@@ -0,0 +1,342 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bitcoin::util::psbt::Input;
+use bitcoin::{OutPoint, Transaction, TxOut, Witness};
+use miniscript::psbt::PsbtInputSatisfier;
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+use wallet::psbt::Psbt;
+
+use crate::model::{DerivationError, DescriptorAccount, UtxoEntry};
+use crate::util::psbt::is_finalized;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Error {
+    /// PSBT is for a different transaction and can't be merged
+    DifferentTransaction,
+
+    /// PSBT data can't be merged: {0}
+    Merge(String),
+
+    /// Input #{0} spends output {1}, which is not found in the UTXO cache
+    UnknownInput(usize, OutPoint),
+
+    /// Output {0} does not belong to any of the known descriptor accounts
+    UnknownDescriptor(OutPoint),
+
+    /// The actual value of the used index corresponds to a hardened index,
+    /// which can't be used in the current context
+    HardenedIndex,
+
+    /// Descriptor for the output {0} can't be generated: {1}
+    Descriptor(OutPoint, DerivationError),
+
+    /// Output {0} spent by the input #{1} has a script which does not match
+    /// the one generated by its descriptor
+    ScriptMismatch(OutPoint, usize),
+
+    /// Input #{0} can't be finalized: {1}
+    Satisfaction(usize, String),
+
+    /// Input #{0} is not finalized
+    NotFinalized(usize),
+}
+
+/// Merges signatures and other data from a PSBT for the same transaction
+/// (like the ones returned by other cosigners) into the first PSBT
+pub fn merge_psbt(psbt: &mut Psbt, other: Psbt) -> Result<(), Error> {
+    if psbt.unsigned_tx.txid() != other.unsigned_tx.txid() {
+        return Err(Error::DifferentTransaction);
+    }
+    psbt.combine(other)
+        .map_err(|err| Error::Merge(err.to_string()))
+}
+
+/// Output spent by the PSBT input, if known from the input data
+fn spent_output(psbt: &Psbt, vin: usize) -> Option<&TxOut> {
+    let input = &psbt.inputs[vin];
+    input.witness_utxo.as_ref().or_else(|| {
+        let vout = psbt.unsigned_tx.input[vin].previous_output.vout;
+        input
+            .non_witness_utxo
+            .as_ref()
+            .and_then(|tx| tx.output.get(vout as usize))
+    })
+}
+
+/// Constructs final script sig and witness for the PSBT input using the
+/// descriptor of the spent output, removing data which are not needed
+/// anymore
+fn finalize_input(
+    psbt: &mut Psbt,
+    vin: usize,
+    descriptors: &[DescriptorAccount],
+    utxos: &[UtxoEntry],
+) -> Result<(), Error> {
+    let outpoint = psbt.unsigned_tx.input[vin].previous_output;
+    let utxo = utxos
+        .iter()
+        .find(|utxo| utxo.outpoint == outpoint)
+        .ok_or(Error::UnknownInput(vin, outpoint))?;
+    let account = descriptors
+        .iter()
+        .find(|account| utxo.has_match(account))
+        .ok_or(Error::UnknownDescriptor(outpoint))?;
+    let index = UnhardenedIndex::from_index(utxo.derivation_index)
+        .map_err(|_| Error::HardenedIndex)?;
+    let descriptor = account
+        .descriptors(index)
+        .map_err(|err| Error::Descriptor(outpoint, err))?
+        .remove(&utxo.descriptor_category)
+        .ok_or(Error::UnknownDescriptor(outpoint))?;
+    if let Some(txout) = spent_output(psbt, vin) {
+        if txout.script_pubkey != descriptor.script_pubkey() {
+            return Err(Error::ScriptMismatch(outpoint, vin));
+        }
+    }
+
+    let (witness, script_sig) = descriptor
+        .get_satisfaction(PsbtInputSatisfier::new(psbt, vin))
+        .map_err(|err| Error::Satisfaction(vin, err.to_string()))?;
+
+    // BIP-174 requires finalizer to clear all other input data except UTXO
+    // and unknown fields
+    let input = &mut psbt.inputs[vin];
+    *input = Input {
+        non_witness_utxo: input.non_witness_utxo.take(),
+        witness_utxo: input.witness_utxo.take(),
+        final_script_sig: Some(script_sig).filter(|s| !s.is_empty()),
+        final_script_witness: Some(Witness::from_vec(witness))
+            .filter(|w| !w.is_empty()),
+        proprietary: std::mem::take(&mut input.proprietary),
+        unknown: std::mem::take(&mut input.unknown),
+        ..Input::default()
+    };
+    Ok(())
+}
+
+/// Finalizes all PSBT inputs which are not final yet. Inputs are satisfied
+/// with the signatures and preimages present in the PSBT according to the
+/// descriptor of the spent output, which is found among the cached UTXOs.
+///
+/// The PSBT is left intact if any of the inputs can't be finalized.
+/// Returns indexes of the inputs finalized by the call.
+pub fn finalize_psbt(
+    psbt: &mut Psbt,
+    descriptors: &[DescriptorAccount],
+    utxos: &[UtxoEntry],
+) -> Result<Vec<usize>, Error> {
+    let mut finalized = psbt.clone();
+    let mut indexes = vec![];
+    for vin in 0..finalized.inputs.len() {
+        if is_finalized(&finalized.inputs[vin]) {
+            continue;
+        }
+        finalize_input(&mut finalized, vin, descriptors, utxos)?;
+        indexes.push(vin);
+    }
+    *psbt = finalized;
+    Ok(indexes)
+}
+
+/// Extracts signed transaction from a PSBT with all its inputs finalized
+pub fn extract_tx(psbt: &Psbt) -> Result<Transaction, Error> {
+    if let Some(vin) = psbt.inputs.iter().position(|i| !is_finalized(i)) {
+        return Err(Error::NotFinalized(vin));
+    }
+    Ok(psbt.clone().extract_tx())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::{Message, SecretKey, SECP256K1};
+    use bitcoin::util::ecdsa::EcdsaSig;
+    use bitcoin::util::sighash::SighashCache;
+    use bitcoin::{EcdsaSighashType, PublicKey, Script, TxIn};
+    use wallet::descriptors;
+
+    use super::*;
+    use crate::util::psbt::{add_input, add_output, empty_psbt};
+
+    fn key(seed: u8) -> (SecretKey, PublicKey) {
+        let sk = SecretKey::from_slice(&[seed; 32]).unwrap();
+        let pk = PublicKey::new(
+            bitcoin::secp256k1::PublicKey::from_secret_key(&SECP256K1, &sk),
+        );
+        (sk, pk)
+    }
+
+    fn account(pk: PublicKey) -> DescriptorAccount {
+        DescriptorAccount {
+            name: s!("test"),
+            generator: descriptors::Generator {
+                template: descriptors::Template::SingleSig(
+                    descriptors::SingleSig::from_str(&pk.to_string()).unwrap(),
+                ),
+                variants: descriptors::Variants {
+                    bare: false,
+                    hashed: false,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: empty!(),
+        }
+    }
+
+    /// PSBT spending two outputs of the funding transaction, together with
+    /// the cached UTXOs for them
+    fn psbt(account: &DescriptorAccount) -> (Psbt, Vec<UtxoEntry>) {
+        let script_pubkey = account
+            .pubkey_scripts(UnhardenedIndex::zero())
+            .unwrap()
+            .remove(&descriptors::Category::SegWit)
+            .unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: script_pubkey.clone(),
+                };
+                2
+            ],
+        };
+        let mut psbt = empty_psbt();
+        let mut utxos = vec![];
+        for (vout, txout) in funding.output.iter().enumerate() {
+            let outpoint = OutPoint::new(funding.txid(), vout as u32);
+            add_input(
+                &mut psbt,
+                outpoint,
+                Input {
+                    witness_utxo: Some(txout.clone()),
+                    ..Input::default()
+                },
+            );
+            utxos.push(UtxoEntry {
+                outpoint,
+                height: 100,
+                amount: txout.value,
+                descriptor_template: account.generator.template.clone(),
+                descriptor_category: descriptors::Category::SegWit,
+                derivation_index: 0,
+            });
+        }
+        add_output(
+            &mut psbt,
+            TxOut {
+                value: 19_000,
+                script_pubkey: Script::new_op_return(&[]),
+            },
+        );
+        (psbt, utxos)
+    }
+
+    fn sign(psbt: &Psbt, vin: usize, sk: &SecretKey, pk: PublicKey) -> Psbt {
+        let script_code = Script::new_p2pkh(&pk.pubkey_hash());
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .segwit_signature_hash(
+                vin,
+                &script_code,
+                10_000,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let sig = SECP256K1
+            .sign_ecdsa(&Message::from_slice(&sighash[..]).unwrap(), sk);
+        let mut signed = psbt.clone();
+        signed.inputs[vin]
+            .partial_sigs
+            .insert(pk, EcdsaSig::sighash_all(sig));
+        signed
+    }
+
+    #[test]
+    fn merge_finalize_extract() {
+        let (sk, pk) = key(1);
+        let account = account(pk);
+        let (mut psbt, utxos) = psbt(&account);
+
+        // Each of the signers returns PSBT with a single signature
+        let first = sign(&psbt, 0, &sk, pk);
+        let second = sign(&psbt, 1, &sk, pk);
+        merge_psbt(&mut psbt, first).unwrap();
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 1);
+        // Second input is not signed yet
+        assert!(matches!(
+            finalize_psbt(&mut psbt, &[account.clone()], &utxos),
+            Err(Error::Satisfaction(1, _))
+        ));
+        assert!(!is_finalized(&psbt.inputs[0]));
+        assert_eq!(extract_tx(&psbt), Err(Error::NotFinalized(0)));
+
+        merge_psbt(&mut psbt, second).unwrap();
+        assert_eq!(
+            finalize_psbt(&mut psbt, &[account.clone()], &utxos),
+            Ok(vec![0, 1])
+        );
+        assert!(psbt.inputs.iter().all(|input| input.partial_sigs.is_empty()
+            && input.final_script_sig.is_none()));
+
+        let tx = extract_tx(&psbt).unwrap();
+        assert_eq!(tx.txid(), psbt.unsigned_tx.txid());
+        let witness = tx.input[1].witness.to_vec();
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness[1], pk.to_bytes());
+        assert_eq!(finalize_psbt(&mut psbt, &[account], &utxos), Ok(vec![]));
+    }
+
+    #[test]
+    fn merge_different_tx() {
+        let (_, pk) = key(1);
+        let (mut psbt, _) = psbt(&account(pk));
+        let mut other = psbt.clone();
+        other.unsigned_tx.lock_time = 1;
+        assert_eq!(
+            merge_psbt(&mut psbt, other),
+            Err(Error::DifferentTransaction)
+        );
+    }
+
+    #[test]
+    fn unknown_input() {
+        let (sk, pk) = key(1);
+        let account = account(pk);
+        let (psbt, utxos) = psbt(&account);
+        let mut psbt = sign(&sign(&psbt, 0, &sk, pk), 1, &sk, pk);
+        let outpoint = psbt.unsigned_tx.input[1].previous_output;
+        let unsigned = psbt.clone();
+        assert_eq!(
+            finalize_psbt(&mut psbt, &[account.clone()], &utxos[..1]),
+            Err(Error::UnknownInput(1, outpoint))
+        );
+        assert_eq!(psbt, unsigned);
+        assert_eq!(
+            finalize_psbt(&mut psbt, &[], &utxos),
+            Err(Error::UnknownDescriptor(utxos[0].outpoint))
+        );
+    }
+}
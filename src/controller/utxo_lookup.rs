@@ -15,126 +15,474 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use gtk::prelude::GtkListStoreExtManual;
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ops::DerefMut;
 use std::rc::Rc;
 
 use bitcoin::Script;
-use electrum_client::{
-    Client as ElectrumClient, ElectrumApi, Error as ElectrumError,
-};
 use wallet::descriptors;
 use wallet::hd::{SegmentIndexes, UnhardenedIndex};
 
-use crate::model::{DescriptorAccount, UtxoEntry};
+use crate::model::{
+    DerivationError, DescriptorAccount, ResolverError, UtxoEntry,
+};
+use crate::resolver::{HistoryItem, Resolver};
 use crate::util::resolver_mode::ResolverModeType;
 
 #[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
 #[display(doc_comments)]
 pub enum Error {
-    /// Electrum error
+    /// Blockchain information provider error
     #[display("{0}")]
     #[from]
-    Electrum(String),
+    Resolver(ResolverError),
 
     /// The actual value of the used index corresponds to a hardened index,
     /// which can't be used in the current context
     HardenedIndex,
 
     /// Unable to generate key with index {0} for descriptor {1}: {2}
-    Descriptor(u32, String, descriptors::Error),
+    Descriptor(u32, String, DerivationError),
+
+    /// The configured resolver does not provide transaction history of
+    /// scripts, which is required for the {0}
+    NoScriptHistory(&'static str),
+}
+
+/// Outcome of the UTXO lookup
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct LookupResult {
+    /// Number of the found unspent outputs
+    pub found: usize,
+    /// The highest derivation index having unspent outputs or (for the
+    /// gap-limit lookup) transaction history in each of the output
+    /// categories
+    pub last_used_indexes: BTreeMap<descriptors::Category, u32>,
+}
+
+pub(super) struct LookupItem {
+    pub script_pubkey: Script,
+    pub category: descriptors::Category,
+    pub derivation_index: u32,
 }
 
-impl From<ElectrumError> for Error {
-    fn from(err: ElectrumError) -> Self {
-        Error::Electrum(format!("{:?}", err))
+/// Generates scripts for the derivation indexes, skipping the ones which are
+/// already `seen` (non-derivable keys produce the same scripts for all
+/// indexes)
+pub(super) fn lookup_items(
+    account: &DescriptorAccount,
+    indexes: impl IntoIterator<Item = u32>,
+    seen: &mut HashSet<Script>,
+) -> Result<Vec<LookupItem>, Error> {
+    let mut items = vec![];
+    for index in indexes {
+        let scripts = account
+            .pubkey_scripts(
+                UnhardenedIndex::from_index(index)
+                    .map_err(|_| Error::HardenedIndex)?,
+            )
+            .map_err(|err| {
+                Error::Descriptor(index, account.descriptor(), err)
+            })?;
+        items.extend(
+            scripts
+                .into_iter()
+                .filter(|(_, script_pubkey)| seen.insert(script_pubkey.clone()))
+                .map(|(category, script_pubkey)| LookupItem {
+                    script_pubkey,
+                    category,
+                    derivation_index: index,
+                }),
+        );
     }
+    Ok(items)
 }
 
 pub trait UtxoLookup {
+    /// Looks up unspent outputs for the descriptor account, adding them to
+    /// `utxo_set`. Each output which was not present in the set before is
+    /// reported to `on_new` callback.
     fn utxo_lookup(
         &self,
-        resolver: ElectrumClient,
+        resolver: &dyn Resolver,
         lookup_type: ResolverModeType,
         account: DescriptorAccount,
         utxo_set: Rc<RefCell<HashSet<UtxoEntry>>>,
-        uxto_store: Option<&gtk::ListStore>,
-    ) -> Result<usize, Error> {
-        struct LookupItem<'a> {
-            pub script_pubkey: Script,
-            pub descriptor_type: descriptors::Category,
-            pub descriptor_content: &'a descriptors::Template,
-            pub derivation_index: u32,
-        }
-
-        let mut total_found = 0usize;
-        let mut lookup_iter = lookup_type.into_iter();
-        loop {
-            let mut lookup: Vec<LookupItem> = Vec::with_capacity(
-                lookup_type.count() as usize
-                    * account.pubkey_scripts_count() as usize,
-            );
-            for offset in lookup_iter.by_ref() {
-                let scripts = account
-                    .pubkey_scripts(
-                        UnhardenedIndex::from_index(offset)
-                            .map_err(|_| Error::HardenedIndex)?,
-                    )
-                    .map_err(|err| {
-                        Error::Descriptor(offset, account.descriptor(), err)
-                    })?;
-                lookup.extend(scripts.into_iter().map(
-                    |(descriptor_type, script_pubkey)| LookupItem {
-                        script_pubkey,
-                        descriptor_type,
-                        descriptor_content: &account.generator.template,
-                        derivation_index: offset,
-                    },
-                ));
-            }
+        mut on_new: impl FnMut(&UtxoEntry),
+    ) -> Result<LookupResult, Error> {
+        let mut result = LookupResult::default();
+        let mut history_last_used = BTreeMap::new();
+        let mut resolve = |items: &[LookupItem]| -> Result<usize, Error> {
+            let request: Vec<_> =
+                items.iter().map(|item| item.script_pubkey.clone()).collect();
+            let response = resolver.script_list_unspent(&request)?;
             let mut found = 0usize;
-            let request: Vec<_> = lookup
-                .iter()
-                .map(|item| item.script_pubkey.clone())
-                .collect();
-            println!("Requesting lookup for: {:#?}", request);
-            let response =
-                resolver.batch_script_list_unspent(request.iter())?;
-            println!("Response:\n{:#?}", response);
-            for utxo in
-                response.into_iter().zip(lookup).flat_map(|(list, item)| {
-                    list.into_iter().map(move |res| {
-                        UtxoEntry::with(
-                            &res,
-                            item.descriptor_content.clone(),
-                            item.descriptor_type,
-                            item.derivation_index,
-                        )
-                    })
-                })
-            {
-                found += 1;
-                if utxo_set.borrow_mut().deref_mut().insert(utxo.clone()) {
-                    if let Some(utxo_store) = uxto_store {
-                        utxo_store.insert_with_values(
-                            None,
-                            &[
-                                (0, &utxo.outpoint.txid.to_string()),
-                                (1, &utxo.outpoint.vout),
-                                (2, &utxo.amount),
-                                (3, &utxo.height),
-                            ],
-                        );
+            for (list, item) in response.into_iter().zip(items) {
+                for unspent in list {
+                    let utxo = UtxoEntry::with(
+                        &unspent,
+                        account.generator.template.clone(),
+                        item.category,
+                        item.derivation_index,
+                    );
+                    found += 1;
+                    let last_used = result
+                        .last_used_indexes
+                        .entry(item.category)
+                        .or_insert(item.derivation_index);
+                    *last_used = (*last_used).max(item.derivation_index);
+                    if utxo_set.borrow_mut().deref_mut().insert(utxo.clone()) {
+                        on_new(&utxo);
                     }
                 }
             }
-            total_found += found;
-            if !lookup_type.is_while() || found == 0 {
-                break;
+            result.found += found;
+            Ok(found)
+        };
+
+        match lookup_type {
+            ResolverModeType::While => {
+                // Scans derivation indexes one by one until the first one
+                // without unspent outputs
+                let mut seen = HashSet::new();
+                for index in 0u32.. {
+                    let items = lookup_items(&account, [index], &mut seen)?;
+                    if items.is_empty() || resolve(&items)? == 0 {
+                        break;
+                    }
+                }
             }
+            ResolverModeType::Gap(_) => {
+                // Only scripts with transaction history may have unspent
+                // outputs, so each category is looked up up to its own last
+                // used index
+                history_last_used =
+                    gap_scan(resolver, &account, lookup_type, |_, _| {})?;
+                let end = history_last_used
+                    .values()
+                    .max()
+                    .map_or(0, |index| index + 1);
+                let items: Vec<_> =
+                    lookup_items(&account, 0..end, &mut HashSet::new())?
+                        .into_iter()
+                        .filter(|item| {
+                            history_last_used
+                                .get(&item.category)
+                                .map_or(false, |last_used| {
+                                    item.derivation_index <= *last_used
+                                })
+                        })
+                        .collect();
+                resolve(&items)?;
+            }
+            ResolverModeType::First(_) | ResolverModeType::Random(_) => {
+                let items =
+                    lookup_items(&account, lookup_type, &mut HashSet::new())?;
+                resolve(&items)?;
+            }
+        }
+        for (category, index) in history_last_used {
+            let last_used =
+                result.last_used_indexes.entry(category).or_insert(index);
+            *last_used = (*last_used).max(index);
+        }
+        Ok(result)
+    }
+}
+
+/// Requests transaction history for the scripts, reporting non-empty
+/// history of each script to `on_history`. Returns number of the scripts
+/// having history.
+pub(super) fn script_history(
+    resolver: &dyn Resolver,
+    items: &[LookupItem],
+    mut on_history: impl FnMut(&LookupItem, Vec<HistoryItem>),
+) -> Result<usize, Error> {
+    let request: Vec<_> =
+        items.iter().map(|item| item.script_pubkey.clone()).collect();
+    let response = resolver.script_history(&request)?;
+    let mut used = 0usize;
+    for (list, item) in response.into_iter().zip(items) {
+        if !list.is_empty() {
+            used += 1;
+            on_history(item, list);
+        }
+    }
+    Ok(used)
+}
+
+/// Scans transaction history of the account scripts in batches of the gap
+/// size, until each of the descriptor categories has a gap of unused
+/// derivation indexes after its last used one (as defined in BIP-44).
+/// History of the used scripts is reported to `on_history`. Returns the
+/// highest used derivation index of each category having any history.
+pub(super) fn gap_scan(
+    resolver: &dyn Resolver,
+    account: &DescriptorAccount,
+    lookup_type: ResolverModeType,
+    mut on_history: impl FnMut(&LookupItem, Vec<HistoryItem>),
+) -> Result<BTreeMap<descriptors::Category, u32>, Error> {
+    if !resolver.provides_script_history() {
+        return Err(Error::NoScriptHistory("gap-limit lookup"));
+    }
+    let gap = lookup_type.count() as u32;
+    let mut last_used = HashMap::<descriptors::Category, Option<u32>>::new();
+    let mut offset = 0u32;
+    let mut seen = HashSet::new();
+    loop {
+        let items = lookup_items(account, offset..offset + gap, &mut seen)?;
+        if items.is_empty() {
+            break;
+        }
+        for item in &items {
+            last_used.entry(item.category).or_default();
+        }
+        script_history(resolver, &items, |item, history| {
+            let used = last_used.entry(item.category).or_default();
+            *used = (*used).max(Some(item.derivation_index));
+            on_history(item, history);
+        })?;
+        offset += gap;
+        let complete = last_used.values().all(|used| {
+            offset >= used.map_or(0, |index| index + 1) + gap
+        });
+        if complete {
+            break;
         }
-        Ok(total_found)
+    }
+    Ok(last_used
+        .into_iter()
+        .filter_map(|(category, used)| used.map(|index| (category, index)))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+
+    use super::*;
+    use crate::model::Document;
+    use crate::resolver::{MockResolver, Unspent};
+
+    struct Lookup;
+    impl UtxoLookup for Lookup {}
+
+    fn account() -> DescriptorAccount {
+        DescriptorAccount {
+            name: s!("test"),
+            generator: descriptors::Generator {
+                template: descriptors::Template::SingleSig(
+                    descriptors::SingleSig::from_str(
+                        "pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+                    )
+                    .unwrap(),
+                ),
+                variants: descriptors::Variants {
+                    bare: false,
+                    hashed: true,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: empty!(),
+        }
+    }
+
+    #[test]
+    fn lookup_with_mock_resolver() {
+        let account = account();
+        let scripts = account.pubkey_scripts(UnhardenedIndex::zero()).unwrap();
+        let unspent = Unspent {
+            outpoint: OutPoint::new(
+                Txid::from_str(
+                    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                )
+                .unwrap(),
+                0,
+            ),
+            height: 100,
+            amount: 5000,
+        };
+        let mut resolver = MockResolver::new();
+        resolver.add_unspent(
+            scripts[&descriptors::Category::SegWit].clone(),
+            unspent,
+        );
+
+        let utxo_set = Rc::new(RefCell::new(HashSet::new()));
+        let mut new = vec![];
+        let result = Lookup
+            .utxo_lookup(
+                &resolver,
+                ResolverModeType::First(UnhardenedIndex::one()),
+                account.clone(),
+                utxo_set.clone(),
+                |utxo| new.push(utxo.clone()),
+            )
+            .unwrap();
+        assert_eq!(result.found, 1);
+        assert_eq!(
+            result.last_used_indexes,
+            bmap! { descriptors::Category::SegWit => 0 }
+        );
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].outpoint, unspent.outpoint);
+        assert_eq!(new[0].amount, 5000);
+        assert_eq!(new[0].descriptor_category, descriptors::Category::SegWit);
+        assert_eq!(*utxo_set.borrow(), new.iter().cloned().collect());
+
+        // Repeated lookup finds the same output without reporting it as new
+        let result = Lookup
+            .utxo_lookup(
+                &resolver,
+                ResolverModeType::First(UnhardenedIndex::one()),
+                account,
+                utxo_set.clone(),
+                |_| panic!("output is already known"),
+            )
+            .unwrap();
+        assert_eq!(result.found, 1);
+        assert_eq!(utxo_set.borrow().len(), 1);
+    }
+
+    #[test]
+    fn repeated_lookup_updates_document() {
+        let account = account();
+        let scripts = account.pubkey_scripts(UnhardenedIndex::zero()).unwrap();
+        let segwit = scripts[&descriptors::Category::SegWit].clone();
+        let outpoint = OutPoint::new(
+            Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            0,
+        );
+        let mut doc = Document::new();
+        doc.add_descriptor(account.clone()).unwrap();
+
+        // The same output is found in the mempool, twice, and after it gets
+        // mined
+        for height in [0, 0, 100] {
+            let mut resolver = MockResolver::new();
+            let unspent = Unspent {
+                outpoint,
+                height,
+                amount: 5000,
+            };
+            resolver.add_unspent(segwit.clone(), unspent);
+            let utxo_set = Rc::new(RefCell::new(HashSet::new()));
+            Lookup
+                .utxo_lookup(
+                    &resolver,
+                    ResolverModeType::First(UnhardenedIndex::one()),
+                    account.clone(),
+                    utxo_set.clone(),
+                    |_| {},
+                )
+                .unwrap();
+            doc.update_utxo_set(utxo_set.borrow().clone()).unwrap();
+
+            let utxos = doc.utxos(None);
+            assert_eq!(utxos.len(), 1);
+            assert_eq!(utxos[0].height, height);
+        }
+    }
+
+    #[test]
+    fn gap_lookup_uses_history() {
+        let account = account();
+        let scripts = account.pubkey_scripts(UnhardenedIndex::zero()).unwrap();
+        let segwit = scripts[&descriptors::Category::SegWit].clone();
+        let modes = [
+            ResolverModeType::from_str("gap20").unwrap(),
+            ResolverModeType::While,
+            ResolverModeType::First(UnhardenedIndex::from_index(5).unwrap()),
+        ];
+
+        // No history and no unspents: the scan terminates without results
+        let resolver = MockResolver::new();
+        for mode in modes {
+            let result = Lookup
+                .utxo_lookup(
+                    &resolver,
+                    mode,
+                    account.clone(),
+                    empty!(),
+                    |_| {},
+                )
+                .unwrap();
+            assert_eq!(result, LookupResult::default());
+        }
+
+        // Non-derivable key with the history: all indexes share the same
+        // scripts, so the scan must stop after the first one
+        let mut resolver = MockResolver::new();
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 5000,
+                script_pubkey: segwit.clone(),
+            }],
+        };
+        let unspent = Unspent {
+            outpoint: OutPoint::new(tx.txid(), 0),
+            height: 100,
+            amount: 5000,
+        };
+        resolver.add_transaction(tx, 100);
+        resolver.add_unspent(segwit, unspent);
+        for mode in modes {
+            let utxo_set = Rc::new(RefCell::new(HashSet::new()));
+            let result = Lookup
+                .utxo_lookup(
+                    &resolver,
+                    mode,
+                    account.clone(),
+                    utxo_set.clone(),
+                    |_| {},
+                )
+                .unwrap();
+            assert_eq!(
+                result,
+                LookupResult {
+                    found: 1,
+                    last_used_indexes: bmap! {
+                        descriptors::Category::SegWit => 0
+                    }
+                }
+            );
+            assert_eq!(utxo_set.borrow().len(), 1);
+        }
+    }
+
+    #[test]
+    fn gap_lookup_requires_history() {
+        let account = account();
+        let mut resolver = MockResolver::new();
+        resolver.disable_script_history();
+        assert_eq!(
+            Lookup.utxo_lookup(
+                &resolver,
+                ResolverModeType::default_gap(),
+                account.clone(),
+                empty!(),
+                |_| {},
+            ),
+            Err(Error::NoScriptHistory("gap-limit lookup"))
+        );
+        // Modes not depending on the history are still available
+        let mode = ResolverModeType::default_for(false);
+        assert_eq!(mode.to_string(), "first20");
+        assert!(Lookup
+            .utxo_lookup(&resolver, mode, account, empty!(), |_| {})
+            .is_ok());
     }
 }
@@ -0,0 +1,343 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::{Script, Transaction, Txid};
+
+use super::utxo_lookup::Error;
+use crate::model::{DescriptorAccount, TxEntry, TxLink, UtxoEntry};
+use crate::resolver::Resolver;
+
+/// Cached output which was spent on-chain
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SpentUtxo {
+    pub utxo: UtxoEntry,
+    /// Transaction spending the output, if it can be found with the
+    /// resolver
+    pub spending_txid: Option<Txid>,
+}
+
+/// Outcome of re-verification of the cached outputs against the resolver
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Reconciliation {
+    /// Number of outputs confirmed to be still unspent
+    pub unspent: usize,
+    /// Unspent outputs with the updated height, which has changed because
+    /// of a reorg or because the transaction got mined
+    pub moved: Vec<UtxoEntry>,
+    /// Outputs which are not unspent anymore
+    pub spent: Vec<SpentUtxo>,
+    /// Outputs which can't be verified since none of the descriptor accounts
+    /// generates their scripts
+    pub unverified: Vec<UtxoEntry>,
+    /// History entries of the spending transactions
+    pub entries: BTreeMap<Txid, TxEntry>,
+    /// Spending transactions which were not known before the refresh
+    pub transactions: Vec<Transaction>,
+}
+
+pub trait UtxoRefresh {
+    /// Checks each of the `utxos` against the resolver, detecting spent
+    /// outputs and transactions spending them. Transaction data are fetched
+    /// from the resolver only for the transactions for which `cached`
+    /// returns `None`.
+    fn utxo_refresh(
+        &self,
+        resolver: &dyn Resolver,
+        descriptors: &[DescriptorAccount],
+        utxos: impl IntoIterator<Item = UtxoEntry>,
+        cached: impl Fn(Txid) -> Option<Transaction>,
+    ) -> Result<Reconciliation, Error> {
+        let mut reconciliation = Reconciliation::default();
+
+        let mut checked = Vec::<(UtxoEntry, Script)>::new();
+        for utxo in utxos {
+            let script_pubkey = descriptors
+                .iter()
+                .find(|account| utxo.has_match(account))
+                .and_then(|account| TxLink::from(&utxo).script_pubkey(account));
+            match script_pubkey {
+                Some(script_pubkey) => checked.push((utxo, script_pubkey)),
+                None => reconciliation.unverified.push(utxo),
+            }
+        }
+
+        let request: Vec<_> =
+            checked.iter().map(|(_, script)| script.clone()).collect();
+        let response = resolver.script_list_unspent(&request)?;
+        let mut spent = vec![];
+        for (list, (utxo, script_pubkey)) in response.into_iter().zip(checked) {
+            let unspent = list
+                .into_iter()
+                .find(|unspent| unspent.outpoint == utxo.outpoint);
+            match unspent {
+                Some(unspent) => {
+                    reconciliation.unspent += 1;
+                    if unspent.height != utxo.height {
+                        reconciliation.moved.push(UtxoEntry {
+                            height: unspent.height,
+                            ..utxo
+                        });
+                    }
+                }
+                None => spent.push((utxo, script_pubkey)),
+            }
+        }
+
+        // Spending transaction is a part of the spent script history. Without
+        // the history spent outputs are reported with unknown spending
+        // transactions.
+        let request: Vec<_> =
+            spent.iter().map(|(_, script)| script.clone()).collect();
+        let history = if resolver.provides_script_history() {
+            resolver.script_history(&request)?
+        } else {
+            vec![vec![]; request.len()]
+        };
+        let mut fetched = HashMap::<Txid, Transaction>::new();
+        for (list, (utxo, _)) in history.into_iter().zip(spent) {
+            let mut spending_txid = None;
+            for item in list {
+                if item.txid == utxo.outpoint.txid {
+                    continue;
+                }
+                let known = cached(item.txid)
+                    .or_else(|| fetched.get(&item.txid).cloned());
+                let tx = match known {
+                    Some(tx) => tx,
+                    None => {
+                        let tx = resolver.transaction(item.txid)?;
+                        fetched.insert(item.txid, tx.clone());
+                        tx
+                    }
+                };
+                if tx
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output == utxo.outpoint)
+                {
+                    let entry =
+                        reconciliation.entries.entry(item.txid).or_default();
+                    entry.height = item.height;
+                    entry.links.insert(TxLink::from(&utxo));
+                    spending_txid = Some(item.txid);
+                    break;
+                }
+            }
+            reconciliation.spent.push(SpentUtxo {
+                utxo,
+                spending_txid,
+            });
+        }
+        reconciliation.transactions = fetched
+            .into_iter()
+            .filter(|(txid, _)| reconciliation.entries.contains_key(txid))
+            .map(|(_, tx)| tx)
+            .collect();
+
+        Ok(reconciliation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::{OutPoint, TxIn, TxOut};
+    use wallet::descriptors;
+    use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+    use super::*;
+    use crate::model::Document;
+    use crate::resolver::{MockResolver, Unspent};
+
+    struct Refresh;
+    impl UtxoRefresh for Refresh {}
+
+    fn account(key: &str) -> DescriptorAccount {
+        DescriptorAccount {
+            name: s!("test"),
+            generator: descriptors::Generator {
+                template: descriptors::Template::SingleSig(
+                    descriptors::SingleSig::from_str(key).unwrap(),
+                ),
+                variants: descriptors::Variants {
+                    bare: false,
+                    hashed: false,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: empty!(),
+        }
+    }
+
+    fn segwit(account: &DescriptorAccount, index: u32) -> Script {
+        account
+            .pubkey_scripts(UnhardenedIndex::from_index(index).unwrap())
+            .unwrap()
+            .remove(&descriptors::Category::SegWit)
+            .unwrap()
+    }
+
+    fn utxo(
+        account: &DescriptorAccount,
+        outpoint: OutPoint,
+        height: u32,
+    ) -> UtxoEntry {
+        UtxoEntry {
+            outpoint,
+            height,
+            amount: 5000,
+            descriptor_template: account.generator.template.clone(),
+            descriptor_category: descriptors::Category::SegWit,
+            derivation_index: outpoint.vout,
+        }
+    }
+
+    #[test]
+    fn spent_and_moved_outputs() {
+        let account = account(
+            "pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        );
+        let unknown = account(
+            "pk(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)",
+        );
+
+        let funding = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut {
+                    value: 5000,
+                    script_pubkey: segwit(&account, 0),
+                },
+                TxOut {
+                    value: 5000,
+                    script_pubkey: segwit(&account, 1),
+                },
+            ],
+        };
+        let spending = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(funding.txid(), 1),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 4000,
+                script_pubkey: Script::new_op_return(&[]),
+            }],
+        };
+        let remaining = OutPoint::new(funding.txid(), 0);
+
+        let mut resolver = MockResolver::new();
+        resolver.add_transaction(funding.clone(), 100);
+        resolver.add_transaction(spending.clone(), 0);
+        // After a reorg the funding transaction is mined in another block
+        resolver.add_unspent(segwit(&account, 0), Unspent {
+            outpoint: remaining,
+            height: 101,
+            amount: 5000,
+        });
+
+        let mut doc = Document::new();
+        doc.add_descriptor(account.clone()).unwrap();
+        let cached = vec![
+            utxo(&account, remaining, 100),
+            utxo(&account, OutPoint::new(funding.txid(), 1), 100),
+            utxo(&unknown, OutPoint::new(funding.txid(), 2), 100),
+        ];
+        doc.update_utxo_set(cached.iter().cloned().collect()).unwrap();
+
+        let reconciliation = Refresh
+            .utxo_refresh(
+                &resolver,
+                doc.descriptors(),
+                doc.utxos(None),
+                |txid| doc.transaction(txid).cloned(),
+            )
+            .unwrap();
+        assert_eq!(reconciliation.unspent, 1);
+        assert_eq!(reconciliation.moved, vec![utxo(&account, remaining, 101)]);
+        assert_eq!(reconciliation.spent, vec![SpentUtxo {
+            utxo: cached[1].clone(),
+            spending_txid: Some(spending.txid()),
+        }]);
+        assert_eq!(reconciliation.unverified, vec![cached[2].clone()]);
+        assert_eq!(reconciliation.transactions, vec![spending.clone()]);
+        assert!(reconciliation.entries[&spending.txid()].has_match(&account));
+
+        doc.reconcile_utxos(
+            reconciliation.moved,
+            reconciliation
+                .spent
+                .into_iter()
+                .map(|spent| spent.utxo)
+                .collect(),
+        )
+        .unwrap();
+        doc.update_tx_history(
+            reconciliation.transactions,
+            reconciliation.entries,
+        )
+        .unwrap();
+        assert_eq!(doc.utxos(None), vec![
+            utxo(&account, remaining, 101),
+            cached[2].clone()
+        ]);
+        let history = doc.history_rows(&account);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].txid, spending.txid());
+    }
+
+    #[test]
+    fn spent_outputs_without_history() {
+        let account = account(
+            "pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        );
+        let outpoint = OutPoint::new(
+            Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            0,
+        );
+        let mut resolver = MockResolver::new();
+        resolver.disable_script_history();
+
+        let reconciliation = Refresh
+            .utxo_refresh(
+                &resolver,
+                &[account.clone()],
+                [utxo(&account, outpoint, 100)],
+                |_| None,
+            )
+            .unwrap();
+        assert_eq!(reconciliation.spent, vec![SpentUtxo {
+            utxo: utxo(&account, outpoint, 100),
+            spending_txid: None,
+        }]);
+        assert!(reconciliation.entries.is_empty());
+    }
+}
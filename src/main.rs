@@ -50,11 +50,9 @@ where
     fn load_glade() -> Result<Rc<RefCell<Self>>, Error>;
 }
 
-mod controller;
-mod model;
-mod util;
 mod view_controller;
 
+use bitcoin_pro::{controller, model, util};
 use gtk::prelude::*;
 use std::cell::RefCell;
 use std::path::PathBuf;
@@ -67,23 +65,30 @@ fn main() {
         gtk::Application::new(Some("com.pandoracore.BitcoinPro"), default!());
 
     application.connect_activate(|_| {
-        fn new_app(path: Option<PathBuf>) {
-            if let Some(app_window) = view_controller::BproWin::new(path) {
+        fn new_app(path: Option<PathBuf>, passphrase: Option<String>) {
+            if let Some(app_window) =
+                view_controller::BproWin::new(path, passphrase)
+            {
                 let app_window = app_window.borrow();
                 app_window.run(
                     || {
                         let open_dlg =
                             OpenDlg::load_glade().expect("Must load");
-                        open_dlg.run(move |path| new_app(Some(path)), || {})
+                        open_dlg.run(
+                            move |path, passphrase| {
+                                new_app(Some(path), passphrase)
+                            },
+                            || {},
+                        )
                     },
                     || {
-                        new_app(None);
+                        new_app(None, None);
                     },
                 );
             }
         }
 
-        new_app(None);
+        new_app(None, None);
     });
 
     application.run();
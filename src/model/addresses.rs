@@ -0,0 +1,132 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Address book of the receive addresses handed out from the descriptor
+//! accounts, and its CSV export
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use wallet::descriptors;
+
+use super::operation::format_time;
+use super::{AddressRow, DescriptorAccount, TxLink};
+
+/// Address handed out for receiving payments, identified by the descriptor
+/// script it is derived from, with the user notes about it
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Display,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+#[display("{descriptor_category}({descriptor_template})/{derivation_index}")]
+pub struct AddressEntry {
+    pub descriptor_template: descriptors::Template,
+    pub descriptor_category: descriptors::Category,
+    pub derivation_index: u32,
+    /// Short label, like the name of the payer
+    pub label: String,
+    /// Free-form notes about the payment expected to the address
+    pub note: String,
+    /// Time when the address was handed out, in seconds since the Unix
+    /// epoch
+    pub issued: u64,
+}
+
+impl From<&AddressEntry> for TxLink {
+    fn from(entry: &AddressEntry) -> Self {
+        TxLink {
+            descriptor_template: entry.descriptor_template.clone(),
+            descriptor_category: entry.descriptor_category,
+            derivation_index: entry.derivation_index,
+        }
+    }
+}
+
+impl AddressEntry {
+    /// Creates entry for the address handed out now
+    pub fn with(
+        descriptor_account: &DescriptorAccount,
+        category: descriptors::Category,
+        index: u32,
+        label: String,
+        note: String,
+    ) -> AddressEntry {
+        AddressEntry {
+            descriptor_template: descriptor_account.generator.template.clone(),
+            descriptor_category: category,
+            derivation_index: index,
+            label,
+            note,
+            issued: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn has_match(&self, descriptor_account: &DescriptorAccount) -> bool {
+        descriptor_account.generator.template == self.descriptor_template
+            && descriptor_account
+                .generator
+                .variants
+                .has_match(self.descriptor_category)
+    }
+
+    /// Whether the entry is for the address of the descriptor account with
+    /// the given category and derivation index
+    pub fn is_address(
+        &self,
+        descriptor_account: &DescriptorAccount,
+        category: descriptors::Category,
+        index: u32,
+    ) -> bool {
+        self.descriptor_template == descriptor_account.generator.template
+            && self.descriptor_category == category
+            && self.derivation_index == index
+    }
+
+    /// Time when the address was handed out in the ISO 8601 form
+    pub fn time(&self) -> String {
+        format_time(self.issued)
+    }
+}
+
+/// Writes address rows as CSV with derivation index, category, address,
+/// address book data and usage columns
+pub fn addresses_csv(rows: &[AddressRow]) -> String {
+    let mut csv = s!("index,category,address,label,note,issued,used\n");
+    for row in rows {
+        csv += &format!(
+            "{},{},{},\"{}\",\"{}\",{},{}\n",
+            row.index,
+            row.category,
+            row.address_string(),
+            row.label.replace('"', "\"\""),
+            row.note.replace('"', "\"\""),
+            row.issued.as_deref().unwrap_or_default(),
+            row.used
+        );
+    }
+    csv
+}
@@ -0,0 +1,1129 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Conversion of descriptor accounts from and into the standard output
+//! descriptors (BIP380-386), including key origins, multipath key
+//! expressions (BIP389), MuSig2 taproot internal keys (BIP390) and
+//! descriptor checksums. Only the descriptors which can be represented by
+//! the single-sig, multi-sig and MuSig2 templates (with miniscript leaves
+//! of the taproot script trees) are supported;
+//! derivation index ranges of the keys are not part of the standard and
+//! are not exported.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::{XOnlyPublicKey, SECP256K1};
+use bitcoin::util::bip32::{
+    ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource,
+};
+use miniscript::descriptor::DescriptorSinglePub;
+use miniscript::{Miniscript, Tap, TranslatePk2};
+use wallet::descriptors::{self, Category};
+use wallet::hd::DerivationComponents;
+use wallet::slip132::FromSlip132;
+
+use super::musig;
+use super::script_cache::category_generator;
+use super::taproot::{self, TapLeaf};
+use super::DescriptorAccount;
+
+/// Characters allowed in descriptors, in the order used by the checksum
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}\
+    IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+/// Characters of the checksum
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Maximum number of keys in `multi` scripts, limited by
+/// `OP_CHECKMULTISIG`
+const MAX_MULTI_KEYS: usize = 20;
+
+/// Maximum number of keys in `multi` scripts inside `sh()`, limited by the
+/// 520 bytes of the redeem script
+const MAX_SH_MULTI_KEYS: usize = 15;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+/// Errors in the standard output descriptors
+pub enum Error {
+    /// Descriptor contains character `{0}`, which is not allowed
+    InvalidCharacter(char),
+
+    /// Descriptor checksum `{0}` is invalid; it must be `{1}`
+    ChecksumMismatch(String, String),
+
+    /// Descriptor `{0}` is not supported; only `pk`, `pkh`, `wpkh`,
+    /// `sh(wpkh)` and `tr` (including `tr(musig(...))`) descriptors, as
+    /// well as `multi` and
+    /// `sortedmulti` ones (which may be wrapped into `sh`, `wsh` or
+    /// `sh(wsh)`) can be imported
+    UnsupportedDescriptor(String),
+
+    /// Invalid key expression `{0}`: {1}
+    InvalidKey(String, String),
+
+    /// Invalid taproot script tree `{0}`: {1}
+    InvalidTapTree(String, String),
+
+    /// Key expression `{0}` uses hardened derivation after the extended
+    /// public key, which requires the private key
+    HardenedDerivation(String),
+
+    /// Multipath key expressions of the descriptor have different number of
+    /// paths
+    MultipathMismatch,
+
+    /// Multi-sig threshold `{0}` is invalid for {1} keys
+    InvalidThreshold(String, usize),
+
+    /// Multi-sig script has {0} keys, while at most {1} keys are allowed
+    /// in its output type
+    TooManyKeys(usize, usize),
+
+    /// {0} descriptors can't be exported
+    UnsupportedTemplate(String),
+
+    /// Descriptor can't be exported for {0} outputs
+    UnsupportedCategory(Category),
+}
+
+fn polymod(c: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let top = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (bit, generator) in GENERATOR.iter().enumerate() {
+        if top & (1 << bit) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Computes checksum of the descriptor (given without `#` and the checksum)
+pub fn checksum(descriptor: &str) -> Result<String, Error> {
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let pos =
+            INPUT_CHARSET.find(ch).ok_or(Error::InvalidCharacter(ch))? as u64;
+        c = polymod(c, pos & 31);
+        class = class * 3 + (pos >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8)
+        .map(|i| CHECKSUM_CHARSET[((c >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect())
+}
+
+/// Appends checksum to the descriptor
+pub fn with_checksum(descriptor: &str) -> Result<String, Error> {
+    Ok(format!("{}#{}", descriptor, checksum(descriptor)?))
+}
+
+/// Verifies checksum of the descriptor, if present, and returns the
+/// descriptor without it
+pub fn strip_checksum(descriptor: &str) -> Result<&str, Error> {
+    let (descriptor, given) = match descriptor.rsplit_once('#') {
+        Some((descriptor, given)) => (descriptor, given),
+        None => return Ok(descriptor),
+    };
+    let expected = checksum(descriptor)?;
+    if given != expected {
+        return Err(Error::ChecksumMismatch(given.to_owned(), expected));
+    }
+    Ok(descriptor)
+}
+
+/// Derivation step following the extended public key
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Step {
+    Index(u32),
+    Multipath(Vec<u32>),
+}
+
+/// Key data of the key expression
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Key {
+    Single(bitcoin::PublicKey),
+    Extended {
+        xpub: ExtendedPubKey,
+        steps: Vec<Step>,
+        wildcard: bool,
+    },
+}
+
+/// Parsed key expression
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct KeyExpr {
+    source: String,
+    origin: Option<KeySource>,
+    key: Key,
+}
+
+impl FromStr for KeyExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |err: &dyn ToString| {
+            Error::InvalidKey(s.to_owned(), err.to_string())
+        };
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest
+                    .split_once(']')
+                    .ok_or_else(|| invalid(&"unterminated key origin"))?;
+                let (fingerprint, path) =
+                    origin.split_once('/').unwrap_or((origin, ""));
+                let fingerprint =
+                    Fingerprint::from_str(fingerprint).map_err(|err| {
+                        invalid(&format!("wrong fingerprint ({})", err))
+                    })?;
+                let path = match path {
+                    "" => DerivationPath::from(vec![]),
+                    path => DerivationPath::from_str(&format!("m/{}", path))
+                        .map_err(|err| invalid(&err))?,
+                };
+                (Some((fingerprint, path)), key)
+            }
+            None => (None, s),
+        };
+
+        let mut split = key.split('/');
+        let head = split.next().unwrap_or_default();
+        if let Ok(pk) = bitcoin::PublicKey::from_str(head) {
+            if split.next().is_some() {
+                return Err(invalid(&"public key can't be derived"));
+            }
+            return Ok(KeyExpr {
+                source: s.to_owned(),
+                origin,
+                key: Key::Single(pk),
+            });
+        }
+
+        let xpub = ExtendedPubKey::from_slip132_str(head)
+            .map_err(|err| invalid(&err))?;
+        let mut steps = vec![];
+        let mut wildcard = false;
+        for step in split {
+            if wildcard {
+                return Err(invalid(&"wildcard must be the last step"));
+            }
+            match step {
+                "*" => wildcard = true,
+                step if step.starts_with('<') && step.ends_with('>') => {
+                    let indexes = step[1..step.len() - 1]
+                        .split(';')
+                        .map(|index| normal_index(index, s))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if indexes.len() < 2 {
+                        return Err(invalid(&"multipath step needs 2+ paths"));
+                    }
+                    if steps
+                        .iter()
+                        .any(|step| matches!(step, Step::Multipath(_)))
+                    {
+                        return Err(invalid(
+                            &"only one multipath step allowed",
+                        ));
+                    }
+                    steps.push(Step::Multipath(indexes));
+                }
+                step => steps.push(Step::Index(normal_index(step, s)?)),
+            }
+        }
+
+        Ok(KeyExpr {
+            source: s.to_owned(),
+            origin,
+            key: Key::Extended {
+                xpub,
+                steps,
+                wildcard,
+            },
+        })
+    }
+}
+
+/// Parses unhardened derivation index of the key expression `key`
+fn normal_index(index: &str, key: &str) -> Result<u32, Error> {
+    if index.ends_with(|c| c == 'h' || c == 'H' || c == '\'') {
+        return Err(Error::HardenedDerivation(key.to_owned()));
+    }
+    index
+        .parse::<u32>()
+        .ok()
+        .filter(|index| *index < 1 << 31)
+        .ok_or_else(|| {
+            Error::InvalidKey(
+                key.to_owned(),
+                format!("wrong derivation index `{}`", index),
+            )
+        })
+}
+
+impl KeyExpr {
+    /// Parses key expression in the position of the taproot internal key,
+    /// where x-only (32-byte) public keys are allowed (BIP386). Such keys
+    /// are lifted to the key with the even Y coordinate, so they are
+    /// exported as 33-byte keys.
+    fn from_taproot_str(s: &str) -> Result<Self, Error> {
+        let key_start = s.find(']').map_or(0, |pos| pos + 1);
+        let (origin, key) = s.split_at(key_start);
+        if XOnlyPublicKey::from_str(key).is_err() {
+            return KeyExpr::from_str(s);
+        }
+        Ok(KeyExpr {
+            source: s.to_owned(),
+            ..KeyExpr::from_str(&format!("{}02{}", origin, key))?
+        })
+    }
+
+    /// Indexes of the multipath derivation step, if any
+    fn multipath(&self) -> Option<&[u32]> {
+        match &self.key {
+            Key::Extended { steps, .. } => {
+                steps.iter().find_map(|step| match step {
+                    Step::Multipath(indexes) => Some(indexes.as_slice()),
+                    Step::Index(_) => None,
+                })
+            }
+            Key::Single(_) => None,
+        }
+    }
+
+    /// Constructs key for the path number `path` of the multipath
+    /// expression, recording origin of the extended key into `origins`
+    fn single_sig(
+        &self,
+        path: usize,
+        origins: &mut BTreeMap<ExtendedPubKey, KeySource>,
+    ) -> Result<descriptors::SingleSig, Error> {
+        let (xpub, steps, wildcard) = match &self.key {
+            Key::Single(key) => {
+                return Ok(descriptors::SingleSig::Pubkey(
+                    DescriptorSinglePub {
+                        origin: self.origin.clone(),
+                        key: *key,
+                    },
+                ))
+            }
+            Key::Extended {
+                xpub,
+                steps,
+                wildcard,
+            } => (xpub, steps, wildcard),
+        };
+        let terminal_path = steps
+            .iter()
+            .map(|step| match step {
+                Step::Index(index) => *index,
+                Step::Multipath(indexes) => indexes[path],
+            })
+            .collect::<Vec<_>>();
+
+        if *wildcard {
+            match &self.origin {
+                // Origin pointing to the key itself carries no information
+                Some((fingerprint, origin_path))
+                    if origin_path.as_ref().is_empty()
+                        && *fingerprint == xpub.fingerprint() => {}
+                Some(origin) => {
+                    origins.insert(*xpub, origin.clone());
+                }
+                None => {}
+            }
+            return Ok(descriptors::SingleSig::XPubDerivable(
+                DerivationComponents {
+                    master_xpub: *xpub,
+                    branch_path: DerivationPath::from(vec![]),
+                    branch_xpub: *xpub,
+                    terminal_path,
+                    index_ranges: None,
+                },
+            ));
+        }
+
+        let children = terminal_path
+            .into_iter()
+            .map(|index| ChildNumber::Normal { index })
+            .collect::<Vec<_>>();
+        let derived =
+            xpub.derive_pub(&SECP256K1, &children).map_err(|err| {
+                Error::InvalidKey(self.source.clone(), err.to_string())
+            })?;
+        let origin = match &self.origin {
+            Some((fingerprint, origin_path)) => {
+                Some((*fingerprint, origin_path.extend(&children)))
+            }
+            None if children.is_empty() => None,
+            None => Some((xpub.fingerprint(), DerivationPath::from(children))),
+        };
+        Ok(descriptors::SingleSig::Pubkey(DescriptorSinglePub {
+            origin,
+            key: bitcoin::PublicKey::new(derived.public_key),
+        }))
+    }
+}
+
+/// Script content of the descriptor
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Content {
+    Single(KeyExpr),
+    Multi {
+        threshold: u8,
+        keys: Vec<KeyExpr>,
+        sorted: bool,
+    },
+    /// Taproot internal key, which is either a single key or MuSig2
+    /// aggregate of the keys, with the script tree leaves, given with their
+    /// depths in the depth-first order, and the keys used by the leaves
+    Taproot {
+        keys: Vec<KeyExpr>,
+        musig: bool,
+        leaves: Vec<(u8, Miniscript<String, Tap>)>,
+        leaf_keys: Vec<KeyExpr>,
+    },
+}
+
+impl Content {
+    fn keys(&self) -> Vec<&KeyExpr> {
+        match self {
+            Content::Single(key) => vec![key],
+            Content::Multi { keys, .. } => keys.iter().collect(),
+            Content::Taproot {
+                keys, leaf_keys, ..
+            } => keys.iter().chain(leaf_keys).collect(),
+        }
+    }
+
+    fn template(
+        &self,
+        path: usize,
+        origins: &mut BTreeMap<ExtendedPubKey, KeySource>,
+    ) -> Result<descriptors::Template, Error> {
+        Ok(match self {
+            Content::Single(key) => {
+                descriptors::Template::SingleSig(key.single_sig(path, origins)?)
+            }
+            Content::Taproot {
+                keys, musig: true, ..
+            } => musig::template(
+                keys.iter()
+                    .map(|key| key.single_sig(path, origins))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Content::Taproot { keys, .. } => descriptors::Template::SingleSig(
+                keys[0].single_sig(path, origins)?,
+            ),
+            Content::Multi {
+                threshold,
+                keys,
+                sorted,
+            } => descriptors::Template::MultiSig(descriptors::MultiSig {
+                threshold: Some(*threshold),
+                pubkeys: keys
+                    .iter()
+                    .map(|key| key.single_sig(path, origins))
+                    .collect::<Result<_, _>>()?,
+                reorder: *sorted,
+            }),
+        })
+    }
+
+    /// Taproot script tree leaves with the keys for the path number `path`
+    /// of the multipath expressions
+    fn tap_leaves(
+        &self,
+        path: usize,
+        origins: &mut BTreeMap<ExtendedPubKey, KeySource>,
+    ) -> Result<Vec<TapLeaf>, Error> {
+        let leaves = match self {
+            Content::Taproot { leaves, .. } => leaves,
+            _ => return Ok(vec![]),
+        };
+        let origins = RefCell::new(origins);
+        leaves
+            .iter()
+            .map(|(depth, script)| {
+                let script = script.translate_pk2(|key: &String| {
+                    let mut origins = origins.borrow_mut();
+                    KeyExpr::from_str(key)?.single_sig(path, &mut origins)
+                })?;
+                Ok(TapLeaf {
+                    depth: *depth,
+                    script,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Splits arguments of the expression by the commas which are not nested
+/// into other expressions or script tree branches
+fn split_args(args: &str) -> Vec<&str> {
+    let mut split = vec![];
+    let mut nesting = 0usize;
+    let mut start = 0;
+    for (pos, ch) in args.char_indices() {
+        match ch {
+            '(' | '{' => nesting += 1,
+            ')' | '}' => nesting = nesting.saturating_sub(1),
+            ',' if nesting == 0 => {
+                split.push(&args[start..pos]);
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&args[start..]);
+    split
+}
+
+/// Parses taproot script tree expression, adding its leaves with their
+/// depths to `leaves` in the depth-first order
+fn parse_tap_tree(
+    tree: &str,
+    depth: u8,
+    leaves: &mut Vec<(u8, Miniscript<String, Tap>)>,
+) -> Result<(), Error> {
+    let invalid = |err: &dyn ToString| {
+        Error::InvalidTapTree(tree.to_owned(), err.to_string())
+    };
+    let branches = tree
+        .strip_prefix('{')
+        .and_then(|tree| tree.strip_suffix('}'));
+    if let Some(branches) = branches {
+        let depth = depth
+            .checked_add(1)
+            .filter(|depth| *depth <= 128)
+            .ok_or_else(|| invalid(&"script tree is too deep"))?;
+        return match split_args(branches)[..] {
+            [left, right] => {
+                parse_tap_tree(left, depth, leaves)?;
+                parse_tap_tree(right, depth, leaves)
+            }
+            _ => Err(invalid(&"branch must have exactly two children")),
+        };
+    }
+    let script = Miniscript::from_str(tree).map_err(|err| invalid(&err))?;
+    leaves.push((depth, script));
+    Ok(())
+}
+
+/// Parses `tr(KEY,TREE)` descriptor arguments, where `KEY` may be a
+/// `musig(KEY,KEY,...)` expression and `TREE` is optional
+fn parse_taproot(key: &str, tree: Option<&str>) -> Result<Content, Error> {
+    let (keys, musig) = match unwrap(key, "musig") {
+        Some(keys) => (
+            keys.split(',')
+                .map(KeyExpr::from_str)
+                .collect::<Result<Vec<_>, _>>()?,
+            true,
+        ),
+        None => (vec![KeyExpr::from_taproot_str(key)?], false),
+    };
+    let mut leaves = vec![];
+    if let Some(tree) = tree {
+        parse_tap_tree(tree, 0, &mut leaves)?;
+    }
+    let leaf_keys = RefCell::new(vec![]);
+    for (_, script) in &leaves {
+        script.translate_pk2(|key: &String| {
+            leaf_keys.borrow_mut().push(KeyExpr::from_str(key)?);
+            Ok::<_, Error>(key.clone())
+        })?;
+    }
+    Ok(Content::Taproot {
+        keys,
+        musig,
+        leaves,
+        leaf_keys: leaf_keys.into_inner(),
+    })
+}
+
+/// Returns arguments of the `name(...)` expression
+fn unwrap<'s>(s: &'s str, name: &str) -> Option<&'s str> {
+    s.strip_prefix(name)?.strip_prefix('(')?.strip_suffix(')')
+}
+
+/// Parses `multi` or `sortedmulti` script of the descriptor producing
+/// outputs of the `category`, which limits the number of keys
+fn parse_multi(
+    script: &str,
+    category: Category,
+    descriptor: &str,
+) -> Result<Content, Error> {
+    let (args, sorted) =
+        match (unwrap(script, "multi"), unwrap(script, "sortedmulti")) {
+            (Some(args), _) => (args, false),
+            (_, Some(args)) => (args, true),
+            _ => {
+                return Err(Error::UnsupportedDescriptor(descriptor.to_owned()))
+            }
+        };
+    let mut args = args.split(',');
+    let threshold = args.next().unwrap_or_default();
+    let keys = args.map(KeyExpr::from_str).collect::<Result<Vec<_>, _>>()?;
+    let max_keys = match category {
+        Category::Hashed => MAX_SH_MULTI_KEYS,
+        _ => MAX_MULTI_KEYS,
+    };
+    if keys.len() > max_keys {
+        return Err(Error::TooManyKeys(keys.len(), max_keys));
+    }
+    let threshold = threshold
+        .parse::<u8>()
+        .ok()
+        .filter(|k| *k >= 1 && *k as usize <= keys.len())
+        .ok_or_else(|| {
+            Error::InvalidThreshold(threshold.to_owned(), keys.len())
+        })?;
+    Ok(Content::Multi {
+        threshold,
+        keys,
+        sorted,
+    })
+}
+
+fn parse_descriptor(descriptor: &str) -> Result<(Category, Content), Error> {
+    let single = |category, key| {
+        KeyExpr::from_str(key).map(|key| (category, Content::Single(key)))
+    };
+    if let Some(key) = unwrap(descriptor, "pk") {
+        return single(Category::Bare, key);
+    }
+    if let Some(key) = unwrap(descriptor, "pkh") {
+        return single(Category::Hashed, key);
+    }
+    if let Some(key) = unwrap(descriptor, "wpkh") {
+        return single(Category::SegWit, key);
+    }
+    if let Some(args) = unwrap(descriptor, "tr") {
+        let (key, tree) = match split_args(args)[..] {
+            [key] if unwrap(key, "musig").is_none() => {
+                let key = KeyExpr::from_taproot_str(key)?;
+                return Ok((Category::Taproot, Content::Single(key)));
+            }
+            [key] => (key, None),
+            [key, tree] => (key, Some(tree)),
+            _ => {
+                return Err(Error::UnsupportedDescriptor(descriptor.to_owned()))
+            }
+        };
+        return Ok((Category::Taproot, parse_taproot(key, tree)?));
+    }
+    if let Some(inner) = unwrap(descriptor, "sh") {
+        if let Some(key) = unwrap(inner, "wpkh") {
+            return single(Category::Nested, key);
+        }
+        if let Some(script) = unwrap(inner, "wsh") {
+            let content = parse_multi(script, Category::Nested, descriptor)?;
+            return Ok((Category::Nested, content));
+        }
+        let content = parse_multi(inner, Category::Hashed, descriptor)?;
+        return Ok((Category::Hashed, content));
+    }
+    if let Some(script) = unwrap(descriptor, "wsh") {
+        let content = parse_multi(script, Category::SegWit, descriptor)?;
+        return Ok((Category::SegWit, content));
+    }
+    let content = parse_multi(descriptor, Category::Bare, descriptor)?;
+    Ok((Category::Bare, content))
+}
+
+fn account(
+    name: String,
+    category: Category,
+    content: &Content,
+    path: usize,
+) -> Result<DescriptorAccount, Error> {
+    let mut key_origins = bmap! {};
+    let template = content.template(path, &mut key_origins)?;
+    let tap_leaves = content.tap_leaves(path, &mut key_origins)?;
+    Ok(DescriptorAccount {
+        name,
+        generator: category_generator(template, category),
+        last_used_indexes: empty!(),
+        key_origins,
+        tap_leaves,
+    })
+}
+
+/// Imports descriptor accounts from the output descriptor, which may have
+/// a checksum. Multipath descriptors produce an account per path, named
+/// after the receive and change paths for `<0;1>` steps and after the path
+/// index otherwise.
+pub fn import(
+    name: &str,
+    descriptor: &str,
+) -> Result<Vec<DescriptorAccount>, Error> {
+    let descriptor = strip_checksum(descriptor.trim())?;
+    let (category, content) = parse_descriptor(descriptor)?;
+    let keys = content.keys();
+    let mut multipaths = keys.iter().filter_map(|key| key.multipath());
+    let paths = match multipaths.next() {
+        Some(paths) => paths,
+        None => {
+            return Ok(vec![account(name.to_owned(), category, &content, 0)?])
+        }
+    };
+    if multipaths.any(|other| other.len() != paths.len()) {
+        return Err(Error::MultipathMismatch);
+    }
+    let receive_change = *paths == [0, 1];
+    paths
+        .iter()
+        .enumerate()
+        .map(|(path, index)| {
+            let name = match (receive_change, index) {
+                (true, 0) => format!("{} (receive)", name),
+                (true, _) => format!("{} (change)", name),
+                (false, index) => format!("{} ({})", name, index),
+            };
+            account(name, category, &content, path)
+        })
+        .collect()
+}
+
+fn origin_string(origin: Option<&KeySource>) -> String {
+    origin
+        .map(|(fingerprint, path)| {
+            format!(
+                "[{}{}]",
+                fingerprint,
+                path.to_string().trim_start_matches('m')
+            )
+        })
+        .unwrap_or_default()
+}
+
+fn key_string(
+    key: &descriptors::SingleSig,
+    origins: &BTreeMap<ExtendedPubKey, KeySource>,
+) -> Result<String, Error> {
+    match key {
+        descriptors::SingleSig::Pubkey(single) => Ok(format!(
+            "{}{}",
+            origin_string(single.origin.as_ref()),
+            single.key
+        )),
+        descriptors::SingleSig::XPubDerivable(components) => {
+            let origin = match origins.get(&components.branch_xpub) {
+                Some(origin) => Some(origin.clone()),
+                None if components.branch_path.as_ref().is_empty() => None,
+                None => Some((
+                    components.master_xpub.fingerprint(),
+                    components.branch_path.clone(),
+                )),
+            };
+            let terminal = components
+                .terminal_path
+                .iter()
+                .map(|index| format!("/{}", index))
+                .collect::<String>();
+            Ok(format!(
+                "{}{}{}/*",
+                origin_string(origin.as_ref()),
+                components.branch_xpub,
+                terminal
+            ))
+        }
+        _ => Err(Error::UnsupportedTemplate(key.to_string())),
+    }
+}
+
+/// Exports descriptor account into output descriptors with checksums, one
+/// for each of the enabled categories
+pub fn export(
+    account: &DescriptorAccount,
+) -> Result<Vec<(Category, String)>, Error> {
+    account
+        .categories()
+        .into_iter()
+        .map(|category| {
+            let descriptor = descriptor_string(account, category)?;
+            Ok((category, with_checksum(&descriptor)?))
+        })
+        .collect()
+}
+
+/// Script tree of the account taproot outputs, if the account has leaves
+fn tap_tree_string(
+    account: &DescriptorAccount,
+) -> Result<Option<String>, Error> {
+    let leaves = account
+        .tap_leaves
+        .iter()
+        .map(|leaf| {
+            let script = leaf
+                .script
+                .translate_pk2(|key| key_string(key, &account.key_origins))?;
+            Ok((leaf.depth, script))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let tree = taproot::tap_tree(leaves).map_err(|err| {
+        Error::InvalidTapTree(account.descriptor(), err.to_string())
+    })?;
+    Ok(tree.map(|tree| tree.to_string()))
+}
+
+/// `tr` descriptor with the internal key expression and the script tree of
+/// the account, if any
+fn tr_string(key: &str, account: &DescriptorAccount) -> Result<String, Error> {
+    Ok(match tap_tree_string(account)? {
+        Some(tree) => format!("tr({},{})", key, tree),
+        None => format!("tr({})", key),
+    })
+}
+
+fn descriptor_string(
+    account: &DescriptorAccount,
+    category: Category,
+) -> Result<String, Error> {
+    let origins = &account.key_origins;
+    match &account.generator.template {
+        descriptors::Template::SingleSig(key) => {
+            let key = key_string(key, origins)?;
+            Ok(match category {
+                Category::Bare => format!("pk({})", key),
+                Category::Hashed => format!("pkh({})", key),
+                Category::Nested => format!("sh(wpkh({}))", key),
+                Category::SegWit => format!("wpkh({})", key),
+                Category::Taproot => tr_string(&key, account)?,
+                _ => return Err(Error::UnsupportedCategory(category)),
+            })
+        }
+        descriptors::Template::MultiSig(multisig) => {
+            let keys = multisig
+                .pubkeys
+                .iter()
+                .map(|key| key_string(key, origins))
+                .collect::<Result<Vec<_>, _>>()?;
+            let multi = format!(
+                "{}({},{})",
+                if multisig.reorder {
+                    "sortedmulti"
+                } else {
+                    "multi"
+                },
+                multisig.threshold(),
+                keys.join(",")
+            );
+            Ok(match category {
+                Category::Bare => multi,
+                Category::Hashed => format!("sh({})", multi),
+                Category::Nested => format!("sh(wsh({}))", multi),
+                Category::SegWit => format!("wsh({})", multi),
+                _ => return Err(Error::UnsupportedCategory(category)),
+            })
+        }
+        descriptors::Template::MuSigBranched(branched) => {
+            if category != Category::Taproot {
+                return Err(Error::UnsupportedCategory(category));
+            }
+            let keys = branched
+                .extra_keys
+                .iter()
+                .map(|key| key_string(key, origins))
+                .collect::<Result<Vec<_>, _>>()?;
+            tr_string(&format!("musig({})", keys.join(",")), account)
+        }
+        _ => Err(Error::UnsupportedTemplate(account.type_name())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+    use super::*;
+
+    static XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    static PUBKEY: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    static PUBKEY2: &str =
+        "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    #[test]
+    fn checksums() {
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            strip_checksum("raw(deadbeef)#89f8spxm").unwrap(),
+            "raw(deadbeef)"
+        );
+        assert_eq!(strip_checksum("raw(deadbeef)").unwrap(), "raw(deadbeef)");
+        assert_eq!(
+            strip_checksum("raw(deadbeef)#89f8spxn"),
+            Err(Error::ChecksumMismatch(s!("89f8spxn"), s!("89f8spxm")))
+        );
+        assert_eq!(
+            checksum("raw(deadbeef)\u{2013}"),
+            Err(Error::InvalidCharacter('\u{2013}'))
+        );
+    }
+
+    #[test]
+    fn multipath_round_trip() {
+        let descriptor = format!("wpkh([d34db33f/84h/0h/0h]{}/<0;1>/*)", XPUB);
+        let accounts = import("Savings", &descriptor).unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].name, "Savings (receive)");
+        assert_eq!(accounts[1].name, "Savings (change)");
+
+        let xpub = ExtendedPubKey::from_str(XPUB).unwrap();
+        let origin = (
+            Fingerprint::from_str("d34db33f").unwrap(),
+            DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+        );
+        for (account, index) in accounts.iter().zip(0..) {
+            assert_eq!(account.key_origins, bmap! { xpub => origin.clone() });
+            assert!(account.generator.variants.segwit);
+            let exported = account.to_bip380().unwrap();
+            let expected = with_checksum(&format!(
+                "wpkh([d34db33f/84'/0'/0']{}/{}/*)",
+                XPUB, index
+            ))
+            .unwrap();
+            assert_eq!(exported, vec![(Category::SegWit, expected.clone())]);
+
+            let mut reimported = import(&account.name, &expected).unwrap();
+            assert_eq!(reimported.pop(), Some(account.clone()));
+        }
+    }
+
+    #[test]
+    fn multisig_round_trip() {
+        let descriptor =
+            format!("sh(wsh(sortedmulti(1,{}/0/*,{})))", XPUB, PUBKEY);
+        let accounts = import("Multisig", &descriptor).unwrap();
+        assert_eq!(accounts.len(), 1);
+        let account = &accounts[0];
+        assert!(account.key_origins.is_empty());
+        assert!(account.generator.variants.nested);
+        match &account.generator.template {
+            descriptors::Template::MultiSig(multisig) => {
+                assert_eq!(multisig.threshold, Some(1));
+                assert_eq!(multisig.pubkeys.len(), 2);
+                assert!(multisig.reorder);
+            }
+            _ => panic!("multi-sig template expected"),
+        }
+        assert_eq!(
+            account.to_bip380().unwrap(),
+            vec![(Category::Nested, with_checksum(&descriptor).unwrap())]
+        );
+    }
+
+    #[test]
+    fn taproot_tree_round_trip() {
+        let descriptor = |hardened: &str, index: &str| {
+            let origin =
+                format!("[d34db33f/86{0}/0{0}/0{0}]{1}", hardened, XPUB);
+            format!(
+                "tr({0}/{1}/*,{{pk({2}),{{pk({0}/2/{1}/*),pk({3})}}}})",
+                origin, index, PUBKEY, PUBKEY2
+            )
+        };
+        let accounts = import("Vault", &descriptor("h", "<0;1>")).unwrap();
+        assert_eq!(accounts.len(), 2);
+
+        let key_only = import("Vault", &format!("tr({}/0/*)", XPUB))
+            .unwrap()
+            .remove(0);
+        assert!(key_only.tap_leaves.is_empty());
+        for (account, index) in accounts.iter().zip(0..) {
+            assert!(account.generator.variants.taproot);
+            assert_eq!(account.categories(), vec![Category::Taproot]);
+            assert_eq!(
+                account
+                    .tap_leaves
+                    .iter()
+                    .map(|leaf| leaf.depth)
+                    .collect::<Vec<_>>(),
+                vec![1, 2, 2]
+            );
+            let expected =
+                with_checksum(&descriptor("'", &index.to_string())).unwrap();
+            assert_eq!(
+                account.to_bip380().unwrap(),
+                vec![(Category::Taproot, expected.clone())]
+            );
+            let mut reimported = import(&account.name, &expected).unwrap();
+            assert_eq!(reimported.pop(), Some(account.clone()));
+
+            let scripts = account.pubkey_scripts(UnhardenedIndex::zero());
+            let taproot = scripts.unwrap().remove(&Category::Taproot);
+            assert!(taproot.is_some());
+            if index == 0 {
+                // Same internal key committing to a script tree
+                assert_ne!(
+                    taproot,
+                    key_only
+                        .pubkey_scripts(UnhardenedIndex::zero())
+                        .unwrap()
+                        .remove(&Category::Taproot)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn musig_round_trip() {
+        let descriptor = format!(
+            "tr(musig({0},[d34db33f/86'/0'/0']{1}/0/*),pk({2}))",
+            PUBKEY2, XPUB, PUBKEY
+        );
+        let accounts = import("MuSig", &descriptor).unwrap();
+        assert_eq!(accounts.len(), 1);
+        let account = &accounts[0];
+        assert_eq!(account.type_name(), "MuSig2");
+        assert_eq!(account.categories(), vec![Category::Taproot]);
+        assert_eq!(account.tap_leaves.len(), 1);
+        match &account.generator.template {
+            descriptors::Template::MuSigBranched(branched) => {
+                assert_eq!(branched.extra_keys.len(), 2);
+            }
+            _ => panic!("MuSig2 template expected"),
+        }
+
+        let exported = account.to_bip380().unwrap();
+        assert_eq!(
+            exported,
+            vec![(Category::Taproot, with_checksum(&descriptor).unwrap())]
+        );
+        let mut reimported = import(&account.name, &exported[0].1).unwrap();
+        assert_eq!(reimported.pop(), Some(account.clone()));
+
+        // Aggregated key does not depend on the order of the keys
+        let reordered = import(
+            "MuSig",
+            &format!(
+                "tr(musig([d34db33f/86'/0'/0']{1}/0/*,{0}),pk({2}))",
+                PUBKEY2, XPUB, PUBKEY
+            ),
+        )
+        .unwrap()
+        .remove(0);
+        let key_path_only = import(
+            "MuSig",
+            &format!("tr(musig({},[d34db33f/86'/0'/0']{}/0/*))", PUBKEY2, XPUB),
+        )
+        .unwrap()
+        .remove(0);
+        assert!(key_path_only.tap_leaves.is_empty());
+        for index in [UnhardenedIndex::zero(), UnhardenedIndex::one()] {
+            let script = account.pubkey_scripts(index).unwrap();
+            assert_eq!(reordered.pubkey_scripts(index).unwrap(), script);
+            assert_ne!(key_path_only.pubkey_scripts(index).unwrap(), script);
+        }
+        assert_ne!(
+            account.pubkey_scripts(UnhardenedIndex::zero()).unwrap(),
+            account.pubkey_scripts(UnhardenedIndex::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn multisig_key_limits() {
+        let multi = |count: usize| {
+            format!("multi(1,{})", vec![PUBKEY; count].join(","))
+        };
+        assert!(import("Test", &format!("sh({})", multi(15))).is_ok());
+        assert_eq!(
+            import("Test", &format!("sh({})", multi(16))),
+            Err(Error::TooManyKeys(16, 15))
+        );
+        assert!(import("Test", &format!("wsh({})", multi(20))).is_ok());
+        assert!(import("Test", &format!("sh(wsh({}))", multi(20))).is_ok());
+        assert_eq!(
+            import("Test", &format!("wsh({})", multi(21))),
+            Err(Error::TooManyKeys(21, 20))
+        );
+    }
+
+    #[test]
+    fn taproot_x_only_keys() {
+        let x_only = &PUBKEY[2..];
+        for descriptor in [
+            format!("tr({})", x_only),
+            format!("tr([d34db33f/86h/0h/0h]{})", x_only),
+            format!("tr({},pk({}))", x_only, PUBKEY2),
+        ] {
+            assert_eq!(
+                import("Taproot", &descriptor).unwrap(),
+                import("Taproot", &descriptor.replacen(x_only, PUBKEY, 1))
+                    .unwrap()
+            );
+        }
+        // X-only keys are allowed only in taproot descriptors
+        assert!(matches!(
+            import("Test", &format!("wpkh({})", x_only)),
+            Err(Error::InvalidKey(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_descriptors() {
+        let descriptor = with_checksum(&format!("pkh({}/0/*)", XPUB)).unwrap();
+        let mut corrupted = descriptor.clone();
+        corrupted.pop();
+        corrupted.push('x');
+        assert!(matches!(
+            import("Test", &corrupted),
+            Err(Error::ChecksumMismatch(..))
+        ));
+        assert_eq!(import("Test", &descriptor).unwrap().len(), 1);
+
+        assert!(matches!(
+            import("Test", &format!("wpkh({}/0h/*)", XPUB)),
+            Err(Error::HardenedDerivation(_))
+        ));
+        assert!(matches!(
+            import("Test", &format!("wsh(multi(3,{},{}))", PUBKEY, PUBKEY)),
+            Err(Error::InvalidThreshold(..))
+        ));
+        assert!(matches!(
+            import("Test", "raw(deadbeef)"),
+            Err(Error::UnsupportedDescriptor(_))
+        ));
+        assert!(matches!(
+            import("Test", &format!("tr({},{{pk({})}})", PUBKEY, PUBKEY)),
+            Err(Error::InvalidTapTree(..))
+        ));
+    }
+}
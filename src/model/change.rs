@@ -0,0 +1,52 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use super::Document;
+
+/// Part of the document which was modified, used in change notifications
+/// to document observers
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum DocumentChange {
+    #[display("tracking")]
+    Tracking,
+
+    #[display("descriptors")]
+    Descriptors,
+
+    #[display("addresses")]
+    Addresses,
+
+    #[display("utxos")]
+    Utxos,
+
+    #[display("transactions")]
+    Transactions,
+
+    #[display("psbts")]
+    Psbts,
+
+    #[display("assets")]
+    Assets,
+
+    #[display("settings")]
+    Settings,
+}
+
+/// Callback receiving notifications about document changes. It gets
+/// read-only access to the already updated document, so observers do not
+/// need to re-borrow document instance which is being modified.
+pub type Observer = Box<dyn Fn(&Document, DocumentChange)>;
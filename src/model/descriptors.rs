@@ -15,16 +15,61 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use bitcoin::util::bip32::{ExtendedPubKey, KeySource};
 use bitcoin::Script;
+use miniscript::Descriptor;
+use serde_with::{As, DisplayFromStr};
 use wallet::descriptors;
 use wallet::hd::UnhardenedIndex;
 
-#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+use super::bip380;
+use super::taproot::{self, TapLeaf};
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+/// Errors in the derivation of the descriptor account outputs
+pub enum DerivationError {
+    /// {0}
+    #[from]
+    Descriptor(descriptors::Error),
+
+    /// {0}
+    #[from]
+    Taproot(taproot::Error),
+}
+
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate")]
 pub struct DescriptorAccount {
     pub name: String,
+    #[serde(with = "As::<DisplayFromStr>")]
     pub generator: descriptors::Generator,
+    /// The highest derivation index known to be used in each of the output
+    /// categories, discovered during UTXO and history lookups
+    #[serde(default)]
+    pub last_used_indexes: BTreeMap<descriptors::Category, u32>,
+    /// Origins of the extended public keys whose master keys are unknown,
+    /// as given by the output descriptors the account was imported from
+    #[serde(
+        default,
+        with = "As::<BTreeMap<DisplayFromStr, (DisplayFromStr, DisplayFromStr)>>"
+    )]
+    pub key_origins: BTreeMap<ExtendedPubKey, KeySource>,
+    /// Script tree leaves committed to by the taproot outputs; taproot
+    /// outputs without leaves can be spent only with the key path
+    #[serde(default)]
+    pub tap_leaves: Vec<TapLeaf>,
 }
 
 impl DescriptorAccount {
@@ -37,13 +82,36 @@ impl DescriptorAccount {
             descriptors::Template::SingleSig(_) => s!("Single-sig."),
             descriptors::Template::MultiSig(_) => s!("Multi-sig."),
             descriptors::Template::Scripted(_) => s!("Custom script"),
-            descriptors::Template::MuSigBranched(_) => s!("Tapscript"),
+            descriptors::Template::MuSigBranched(_) => s!("MuSig2"),
             _ => s!("Unsupported"),
         }
     }
 
     pub fn descriptor(&self) -> String {
-        self.generator.to_string()
+        let leaves = self
+            .tap_leaves
+            .iter()
+            .map(|leaf| (leaf.depth, leaf.script.clone()));
+        match taproot::tap_tree(leaves) {
+            Ok(Some(tree)) => format!("{} {}", self.generator, tree),
+            _ => self.generator.to_string(),
+        }
+    }
+
+    /// Output categories enabled for the account
+    pub fn categories(&self) -> Vec<descriptors::Category> {
+        let variants = &self.generator.variants;
+        [
+            (descriptors::Category::Bare, variants.bare),
+            (descriptors::Category::Hashed, variants.hashed),
+            (descriptors::Category::Nested, variants.nested),
+            (descriptors::Category::SegWit, variants.segwit),
+            (descriptors::Category::Taproot, variants.taproot),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(category, _)| category)
+        .collect()
     }
 
     pub fn pubkey_scripts_count(&self) -> u32 {
@@ -53,8 +121,84 @@ impl DescriptorAccount {
     pub fn pubkey_scripts(
         &self,
         index: UnhardenedIndex,
-    ) -> Result<HashMap<descriptors::Category, Script>, descriptors::Error>
-    {
-        self.generator.pubkey_scripts(index)
+    ) -> Result<HashMap<descriptors::Category, Script>, DerivationError> {
+        let mut scripts = match self.non_taproot_generator() {
+            Some(generator) => generator.pubkey_scripts(index)?,
+            None => empty!(),
+        };
+        if let Some(descriptor) = self.taproot_descriptor(index)? {
+            scripts.insert(
+                descriptors::Category::Taproot,
+                descriptor.script_pubkey(),
+            );
+        }
+        Ok(scripts)
+    }
+
+    pub fn descriptors(
+        &self,
+        index: UnhardenedIndex,
+    ) -> Result<
+        HashMap<descriptors::Category, Descriptor<bitcoin::PublicKey>>,
+        DerivationError,
+    > {
+        let mut descriptors = match self.non_taproot_generator() {
+            Some(generator) => generator.descriptors(index)?,
+            None => empty!(),
+        };
+        if let Some(descriptor) = self.taproot_descriptor(index)? {
+            descriptors.insert(descriptors::Category::Taproot, descriptor);
+        }
+        Ok(descriptors)
+    }
+
+    /// Generator of the account outputs except taproot ones, which are
+    /// derived by [`taproot`] module instead of the descriptor templates.
+    /// MuSig2 templates produce taproot outputs only.
+    fn non_taproot_generator(&self) -> Option<descriptors::Generator> {
+        if let descriptors::Template::MuSigBranched(_) = self.generator.template
+        {
+            return None;
+        }
+        let mut generator = self.generator.clone();
+        generator.variants.taproot = false;
+        Some(generator)
+    }
+
+    /// Taproot output descriptor at the given index, if the account has
+    /// taproot outputs enabled. Templates which can't be used for taproot
+    /// outputs produce no descriptor.
+    fn taproot_descriptor(
+        &self,
+        index: UnhardenedIndex,
+    ) -> Result<Option<Descriptor<bitcoin::PublicKey>>, taproot::Error> {
+        if !self.generator.variants.taproot
+            || !taproot::supports(&self.generator.template)
+        {
+            return Ok(None);
+        }
+        taproot::derive_descriptor(
+            &self.generator.template,
+            &self.tap_leaves,
+            index,
+        )
+        .map(Some)
+    }
+
+    /// Imports accounts from a standard (BIP380) output descriptor; a
+    /// multipath descriptor produces an account for each of its paths
+    pub fn from_bip380(
+        name: &str,
+        descriptor: &str,
+    ) -> Result<Vec<DescriptorAccount>, bip380::Error> {
+        bip380::import(name, descriptor)
+    }
+
+    /// Standard (BIP380) output descriptors with checksums for each of the
+    /// enabled categories of the account
+    pub fn to_bip380(
+        &self,
+    ) -> Result<Vec<(descriptors::Category, String)>, bip380::Error> {
+        bip380::export(self)
     }
 }
@@ -15,32 +15,43 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use gtk::prelude::*;
 use once_cell::sync::Lazy;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::ffi::OsStr;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Seek, SeekFrom};
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::net::{AddrParseError, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Mutex;
 
-use bitcoin::OutPoint;
-use bitcoin::Transaction;
-use electrum_client::{Client as ElectrumClient, Error as ElectrumError};
+use bitcoin::{Address, Network, OutPoint, Script, Transaction, TxOut, Txid};
+use electrum_client::Error as ElectrumError;
 use lnpbp::chain::Chain;
 use rgb::{Consignment, ContractId, Genesis, Schema, SchemaId};
-use strict_encoding::{self, StrictDecode, StrictEncode};
+use serde_with::{As, DisplayFromStr};
+use strict_encoding::{self, StrictEncode};
+use wallet::descriptors;
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
 use wallet::psbt::Psbt;
 
-use super::{operation, DescriptorAccount, TrackingAccount, UtxoEntry};
+use super::edit::{Edit, ListEdit, MAX_UNDO_LEVELS};
+use super::encryption::{self, DocumentKey, EncryptionError};
+use super::export::{self, ExportError, Format, StrictHex};
+use super::migration::{self, FormatError, DOC_MAGIC, PROFILE_VERSION};
+use super::script_cache::category_account;
+use super::storage::{self, DEFAULT_BACKUP_GENERATIONS};
+use super::{
+    operation, AddressEntry, AddressRow, AssetRow, DescriptorAccount,
+    DescriptorRow, DocumentChange, HistoryRow, LogEntry, LogFormat, LogRow,
+    Observer, Operation, PsbtRow, ScriptCache, ScriptMatch, ScriptOwner,
+    TrackingAccount, TrackingRow, TransactionRow, TxEntry, TxInputRow, TxLink,
+    TxOutputRow, UtxoEntry, UtxoRow,
+};
+use crate::resolver::{self, Resolver};
 
-/// Equals to first 4 bytes of SHA256("pandoracore:bpro")
-/// = dbe2b664ee4e81d3a55d53aeba1915c468927c79a03587ddfc5c3aec483028ab
-/// Check with `echo -n "pandoracore:bpro" | shasum -a 256`
-const DOC_MAGIC: u32 = 0xdbe2b664;
 const DOC_NAME: &str = "Untitled";
 static DOC_NO: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
 
@@ -57,12 +68,38 @@ pub enum Error {
     /// I/O error (file etc)
     Io(io::ErrorKind),
 
+    /// Unsupported document format: {0}
+    #[from]
+    Format(FormatError),
+
+    /// The document file is truncated or corrupted; it may be recovered
+    /// from a backup
+    Truncated,
+
+    /// There are no readable backups of the document
+    NoBackups,
+
+    /// {0}
+    #[from]
+    Encryption(EncryptionError),
+
+    /// {0}
+    #[from]
+    Export(ExportError),
+
     /// Wrong position: no item exists at position {0}
     WrongPosition(usize),
 
     /// Attempt to add contract that already exits; if you are trying to
     /// update the version please remove older version first
     DuplicatedContract(ContractId),
+
+    /// Contract {0} is not known to the document
+    UnknownContract(ContractId),
+
+    /// All unhardened derivation indexes of the descriptor account {0} are
+    /// already handed out or used
+    IndexesExhausted(String),
 }
 
 impl From<io::Error> for Error {
@@ -71,11 +108,53 @@ impl From<io::Error> for Error {
     }
 }
 
-#[derive(Default)]
+impl Error {
+    /// Whether the error means that the document file is damaged, so the
+    /// document may be recovered from a backup. Failed decryption is
+    /// always reported as a wrong passphrase and is not such an error.
+    pub fn is_damaged_file(&self) -> bool {
+        matches!(
+            self,
+            Error::Truncated
+                | Error::DataEncoding(_)
+                | Error::Encryption(EncryptionError::TruncatedHeader)
+        )
+    }
+}
+
 pub struct Document {
     name: String,
-    file: Option<File>,
+    path: Option<PathBuf>,
+    backup_generations: usize,
+    /// Whether the file content from before the current session was already
+    /// kept as a backup, so the following saves do not rotate backups
+    backed_up: bool,
     profile: Profile,
+    key: Option<DocumentKey>,
+    observers: Vec<Observer>,
+    /// Edits reverting the recent user modifications, together with the
+    /// logged operations they revert
+    undo_stack: Vec<(Operation, Edit)>,
+    redo_stack: Vec<(Operation, Edit)>,
+    /// Scripts derived from the account keys for the reverse lookups
+    script_cache: ScriptCache,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document {
+            name: s!(""),
+            path: None,
+            backup_generations: DEFAULT_BACKUP_GENERATIONS,
+            backed_up: false,
+            profile: Profile::default(),
+            key: None,
+            observers: vec![],
+            undo_stack: vec![],
+            redo_stack: vec![],
+            script_cache: ScriptCache::default(),
+        }
+    }
 }
 
 impl Document {
@@ -88,14 +167,25 @@ impl Document {
     }
 
     pub fn load(path: PathBuf) -> Result<Document, Error> {
-        let file = File::open(path.clone())?;
-        let mut profile = Profile::strict_decode(&file)?;
-        // TODO: Change this to checking document magic number once all docs
-        //       will be updated
-        profile.magic = DOC_MAGIC;
-        let file = OpenOptions::new().write(true).open(path.clone())?;
+        Self::load_with_passphrase(path, None)
+    }
+
+    /// Loads document, decrypting it with the passphrase if the document is
+    /// encrypted. Fails with [`EncryptionError::PassphraseRequired`] if the
+    /// passphrase is needed but was not provided.
+    pub fn load_with_passphrase(
+        path: PathBuf,
+        passphrase: Option<&str>,
+    ) -> Result<Document, Error> {
+        let data = fs::read(&path)?;
+        let (profile, key) = if encryption::is_encrypted(&data) {
+            let (plaintext, key) = encryption::decrypt(&data, passphrase)?;
+            (migration::decode_profile(Cursor::new(plaintext))?, Some(key))
+        } else {
+            (migration::decode_profile(Cursor::new(data))?, None)
+        };
         Ok(Document {
-            file: Some(file),
+            key,
             name: path
                 .file_stem()
                 .and_then(OsStr::to_str)
@@ -104,12 +194,170 @@ impl Document {
                     *DOC_NO.lock().unwrap() += 1;
                     format!("{}{}", DOC_NAME, *DOC_NO.lock().unwrap())
                 }),
+            path: Some(path),
             profile,
+            ..Default::default()
         })
     }
 
+    /// Lists backups of the document at `path`, most recent first
+    pub fn backups(path: &Path) -> Vec<PathBuf> {
+        storage::backups(path)
+    }
+
+    /// Recovers document from the most recent readable backup, skipping
+    /// backups which can't be read or decoded. The returned document is
+    /// bound to the original `path`, so its next save replaces the damaged
+    /// file without rotating the backups. Unencrypted backups are never
+    /// used for recovering an encrypted document.
+    pub fn recover(
+        path: PathBuf,
+        passphrase: Option<&str>,
+    ) -> Result<Document, Error> {
+        let encrypted = Self::is_encrypted_file(&path).unwrap_or_default();
+        for backup in storage::backups(&path) {
+            match Self::is_encrypted_file(&backup) {
+                Ok(false) if encrypted => continue,
+                Err(_) => continue,
+                _ => {}
+            }
+            if let Ok(mut doc) = Self::load_with_passphrase(backup, passphrase)
+            {
+                if let Some(name) = path.file_stem().and_then(OsStr::to_str) {
+                    doc.name = name.to_owned();
+                }
+                doc.path = Some(path);
+                // The damaged file must not push the good backups out
+                doc.backed_up = true;
+                return Ok(doc);
+            }
+        }
+        Err(Error::NoBackups)
+    }
+
+    /// Sets number of backup generations kept for the document; a backup
+    /// is made on the first save of each session
+    pub fn set_backup_generations(&mut self, generations: usize) {
+        self.backup_generations = generations;
+    }
+
+    /// Registers observer which will be notified about each change of the
+    /// document data
+    pub fn subscribe(
+        &mut self,
+        observer: impl Fn(&Document, DocumentChange) + 'static,
+    ) {
+        self.observers.push(Box::new(observer));
+    }
+
+    fn notify(&self, change: DocumentChange) {
+        self.observers
+            .iter()
+            .for_each(|observer| observer(self, change));
+    }
+
+    /// Records the operation into the document operation log; it is saved
+    /// with the next [`Document::commit`]
+    fn log(&mut self, operation: Operation) {
+        self.profile.history.push(LogEntry::now(operation));
+    }
+
+    /// Applies user edit which may be undone later, recording it into the
+    /// operation log
+    fn perform(
+        &mut self,
+        operation: Operation,
+        edit: Edit,
+    ) -> Result<bool, Error> {
+        let changes = edit.changes();
+        let undo = edit.apply(&mut self.profile)?;
+        self.log(operation.clone());
+        if self.undo_stack.len() >= MAX_UNDO_LEVELS {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push((operation, undo));
+        self.redo_stack.clear();
+        self.commit_all(changes)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Operation which will be reverted by [`Document::undo`]
+    pub fn undo_operation(&self) -> Option<&Operation> {
+        self.undo_stack.last().map(|(operation, _)| operation)
+    }
+
+    /// Operation which will be repeated by [`Document::redo`]
+    pub fn redo_operation(&self) -> Option<&Operation> {
+        self.redo_stack.last().map(|(operation, _)| operation)
+    }
+
+    /// Reverts the most recent user edit. Returns `Ok(false)` if there is
+    /// nothing to undo or the document has no file to be saved into. The
+    /// edit remains to be undone if it fails.
+    pub fn undo(&mut self) -> Result<bool, Error> {
+        let (operation, edit) = match self.undo_stack.pop() {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let changes = edit.changes();
+        let redo = match edit.clone().apply(&mut self.profile) {
+            Ok(redo) => redo,
+            Err(err) => {
+                self.undo_stack.push((operation, edit));
+                return Err(err);
+            }
+        };
+        self.log(Operation::Undone(operation.to_string()));
+        self.redo_stack.push((operation, redo));
+        self.commit_all(changes)
+    }
+
+    /// Repeats the most recently undone edit. Returns `Ok(false)` if there
+    /// is nothing to redo or the document has no file to be saved into. The
+    /// edit remains to be redone if it fails.
+    pub fn redo(&mut self) -> Result<bool, Error> {
+        let (operation, edit) = match self.redo_stack.pop() {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let changes = edit.changes();
+        let undo = match edit.clone().apply(&mut self.profile) {
+            Ok(undo) => undo,
+            Err(err) => {
+                self.redo_stack.push((operation, edit));
+                return Err(err);
+            }
+        };
+        self.log(Operation::Redone(operation.to_string()));
+        self.undo_stack.push((operation, undo));
+        self.commit_all(changes)
+    }
+
+    /// Notifies observers about the change and saves the document
+    fn commit(&mut self, change: DocumentChange) -> Result<bool, Error> {
+        self.commit_all(vec![change])
+    }
+
+    /// Notifies observers about each of the changes and saves the document
+    fn commit_all(
+        &mut self,
+        changes: Vec<DocumentChange>,
+    ) -> Result<bool, Error> {
+        for change in changes {
+            self.notify(change);
+        }
+        self.save()
+    }
+
     pub fn save(&mut self) -> Result<bool, Error> {
-        if self.file.is_some() {
+        if self.path.is_some() {
             self.save_internal()?;
             Ok(true)
         } else {
@@ -118,39 +366,126 @@ impl Document {
     }
 
     pub fn save_as(&mut self, path: PathBuf) -> Result<(), Error> {
-        let file = File::create(path)?;
-        self.file = Some(file);
+        if self.path.as_ref() != Some(&path) {
+            self.backed_up = false;
+        }
+        self.path = Some(path);
         self.save_internal()?;
         Ok(())
     }
 
     fn save_internal(&mut self) -> Result<(), Error> {
-        let file = self
-            .file
-            .as_mut()
-            .expect("Method always called with file initialized");
-        file.seek(SeekFrom::Start(0))?;
-        file.set_len(0)?;
-        self.profile.strict_encode(file)?;
+        let mut data = vec![];
+        self.profile.strict_encode(&mut data)?;
+        if let Some(ref key) = self.key {
+            data = key.encrypt(&data);
+        }
+
+        let path = self
+            .path
+            .as_ref()
+            .expect("Method always called with file path initialized");
+        // Backups keep the document versions from the previous sessions
+        // rather than the most recent edits
+        let generations = match self.backed_up {
+            true => 0,
+            false => self.backup_generations,
+        };
+        storage::write_atomic(path, &data, generations)?;
+        self.backed_up = true;
         Ok(())
     }
 
+    /// Checks whether the file at the path contains encrypted document
+    pub fn is_encrypted_file(path: &Path) -> Result<bool, Error> {
+        let mut magic = [0u8; 4];
+        let read = File::open(path)?.read(&mut magic)?;
+        Ok(encryption::is_encrypted(&magic[..read]))
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Sets new passphrase for the document encryption and re-saves the
+    /// document; `None` or empty passphrase removes the encryption. Backups
+    /// of the document, which are protected with the previous passphrase
+    /// or not protected at all, are removed.
+    pub fn set_passphrase(
+        &mut self,
+        passphrase: Option<&str>,
+    ) -> Result<bool, Error> {
+        let key = match passphrase {
+            Some(passphrase) if !passphrase.is_empty() => {
+                Some(DocumentKey::generate(passphrase)?)
+            }
+            _ => None,
+        };
+        if self.key.is_none() && key.is_none() {
+            return self.save();
+        }
+        self.log(Operation::PassphraseChanged);
+        self.key = key;
+        // The file with the previous encryption must not become a backup
+        self.backed_up = true;
+        let saved = self.save()?;
+        if let Some(ref path) = self.path {
+            storage::remove_backups(path)?;
+        }
+        Ok(saved)
+    }
+
+    /// Exports the whole document profile into a human-readable format
+    pub fn export(&self, format: Format) -> Result<String, Error> {
+        export::export_profile(&self.profile, format)
+    }
+
+    /// Creates new untitled document from the profile previously exported
+    /// with [`Document::export`]
+    pub fn import(data: &str, format: Format) -> Result<Document, Error> {
+        let mut doc = Document::new();
+        doc.profile = export::import_profile(data, format)?;
+        Ok(doc)
+    }
+
     pub fn is_dirty(&self) -> bool {
-        self.file.is_some()
+        self.path.is_some()
     }
 
     pub fn name(&self) -> String {
         self.name.clone()
     }
 
+    pub fn description(&self) -> Option<&str> {
+        self.profile.description.as_deref()
+    }
+
+    pub fn resolver_config(&self) -> &ChainResolver {
+        &self.profile.settings.resolver
+    }
+
     pub fn chain(&self) -> &Chain {
         &self.profile.settings.chain
     }
 
     pub fn set_chain(&mut self, chain_name: &str) -> Result<bool, Error> {
-        self.profile.settings.chain =
-            Chain::from_str(chain_name).unwrap_or(Chain::Testnet3);
-        self.save()
+        let chain = Chain::from_str(chain_name).unwrap_or(Chain::Testnet3);
+        if self.profile.settings.chain != chain {
+            self.log(Operation::ChainChanged(chain.clone()));
+        }
+        self.profile.settings.chain = chain;
+        self.commit(DocumentChange::Settings)
+    }
+
+    /// Bitcoin network matching the document chain, if any
+    pub fn network(&self) -> Option<Network> {
+        match self.profile.settings.chain {
+            Chain::Mainnet => Some(Network::Bitcoin),
+            Chain::Testnet3 => Some(Network::Testnet),
+            Chain::Regtest(_) => Some(Network::Regtest),
+            Chain::Signet(_) => Some(Network::Signet),
+            _ => None,
+        }
     }
 
     pub fn electrum(&self) -> Option<String> {
@@ -164,22 +499,47 @@ impl Document {
     }
 
     pub fn set_electrum(&mut self, addr: SocketAddr) -> Result<bool, Error> {
-        self.profile.settings.resolver = ChainResolver::Electrum(addr);
-        self.save()
+        self.set_resolver(ChainResolver::Electrum(addr));
+        self.commit(DocumentChange::Settings)
     }
 
-    pub fn fill_tracking_store(&self, store: &gtk::ListStore) {
-        store.clear();
-        self.profile.tracking.iter().for_each(|tracking_account| {
-            store.insert_with_values(
-                None,
-                &[
-                    (0, &tracking_account.name()),
-                    (1, &tracking_account.details()),
-                    (2, &tracking_account.count()),
-                ],
-            );
-        });
+    pub fn bitcoin_core(&self) -> Option<CoreConnection> {
+        if let ChainResolver::BitcoinCore(addr) =
+            self.profile.settings.resolver
+        {
+            Some(CoreConnection {
+                addr,
+                auth: self.profile.settings.core_auth.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn set_bitcoin_core(
+        &mut self,
+        connection: CoreConnection,
+    ) -> Result<bool, Error> {
+        self.set_resolver(ChainResolver::BitcoinCore(connection.addr));
+        self.profile.settings.core_auth = connection.auth;
+        self.commit(DocumentChange::Settings)
+    }
+
+    /// Changes the resolver, logging the change only if the resolver is a
+    /// different one, since the settings are updated while they are typed
+    fn set_resolver(&mut self, resolver: ChainResolver) {
+        if self.profile.settings.resolver != resolver {
+            self.log(Operation::ResolverChanged(resolver.clone()));
+        }
+        self.profile.settings.resolver = resolver;
+    }
+
+    pub fn tracking_rows(&self) -> Vec<TrackingRow> {
+        self.profile.tracking.iter().map(TrackingRow::from).collect()
+    }
+
+    pub fn tracking_accounts(&self) -> &[TrackingAccount] {
+        &self.profile.tracking
     }
 
     pub fn tracking_account_at(&self, pos: usize) -> Option<TrackingAccount> {
@@ -201,8 +561,11 @@ impl Document {
         &mut self,
         tracking_account: TrackingAccount,
     ) -> Result<bool, Error> {
-        self.profile.tracking.push(tracking_account);
-        self.save()
+        let pos = self.profile.tracking.len();
+        self.perform(
+            Operation::TrackingAdded(tracking_account.name.clone()),
+            Edit::Tracking(ListEdit::Insert(pos, tracking_account)),
+        )
     }
 
     pub fn update_tracking_account(
@@ -210,15 +573,17 @@ impl Document {
         tracking_account: &TrackingAccount,
         new_tracking_account: TrackingAccount,
     ) -> Result<bool, Error> {
-        if let Some(account) = self
+        match self
             .profile
             .tracking
-            .iter_mut()
-            .find(|a| *a == tracking_account)
+            .iter()
+            .position(|a| a == tracking_account)
         {
-            *account = new_tracking_account
+            Some(pos) => {
+                self.update_tracking_account_at(pos, new_tracking_account)
+            }
+            None => self.commit(DocumentChange::Tracking),
         }
-        self.save()
     }
 
     pub fn update_tracking_account_at(
@@ -226,53 +591,54 @@ impl Document {
         pos: usize,
         tracking_account: TrackingAccount,
     ) -> Result<bool, Error> {
-        if self.profile.tracking.len() <= pos {
-            Err(Error::WrongPosition(pos))
-        } else {
-            self.profile.tracking[pos] = tracking_account;
-            self.save()
-        }
+        self.perform(
+            Operation::TrackingUpdated(tracking_account.name.clone()),
+            Edit::Tracking(ListEdit::Replace(pos, tracking_account)),
+        )
     }
 
     pub fn remove_tracking_account(
         &mut self,
         tracking_account: TrackingAccount,
     ) -> Result<bool, Error> {
-        self.profile
+        match self
+            .profile
             .tracking
             .iter()
             .position(|a| *a == tracking_account)
-            .map(|i| self.profile.tracking.remove(i));
-        self.save()
+        {
+            Some(pos) => self.remove_tracking_account_at(pos),
+            None => self.commit(DocumentChange::Tracking),
+        }
     }
 
     pub fn remove_tracking_account_at(
         &mut self,
         pos: usize,
     ) -> Result<bool, Error> {
-        if self.profile.tracking.len() <= pos {
-            Err(Error::WrongPosition(pos))
-        } else {
-            self.profile.tracking.remove(pos);
-            self.save()
-        }
+        let account = self
+            .tracking_account_at(pos)
+            .ok_or(Error::WrongPosition(pos))?;
+        self.perform(
+            Operation::TrackingRemoved(account.name),
+            Edit::Tracking(ListEdit::Remove(pos)),
+        )
     }
 
-    pub fn fill_descriptor_store(&self, store: &gtk::ListStore) {
-        store.clear();
+    pub fn descriptor_rows(&self) -> Vec<DescriptorRow> {
         self.profile
             .descriptors
             .iter()
-            .for_each(|descriptor_generator| {
-                store.insert_with_values(
-                    None,
-                    &[
-                        (0, &descriptor_generator.name()),
-                        (1, &descriptor_generator.type_name()),
-                        (2, &descriptor_generator.descriptor()),
-                    ],
-                );
-            });
+            .map(DescriptorRow::from)
+            .collect()
+    }
+
+    pub fn descriptors(&self) -> &[DescriptorAccount] {
+        &self.profile.descriptors
+    }
+
+    pub fn descriptor_at(&self, pos: usize) -> Option<DescriptorAccount> {
+        self.profile.descriptors.get(pos).cloned()
     }
 
     pub fn descriptor_by_generator(
@@ -301,8 +667,11 @@ impl Document {
         &mut self,
         descriptor_generator: DescriptorAccount,
     ) -> Result<bool, Error> {
-        self.profile.descriptors.push(descriptor_generator);
-        self.save()
+        let pos = self.profile.descriptors.len();
+        self.perform(
+            Operation::DescriptorAdded(descriptor_generator.name()),
+            Edit::Descriptors(ListEdit::Insert(pos, descriptor_generator)),
+        )
     }
 
     pub fn update_descriptor(
@@ -310,59 +679,378 @@ impl Document {
         descriptor_generator: &DescriptorAccount,
         new_descriptor_generator: DescriptorAccount,
     ) -> Result<bool, Error> {
-        if let Some(descriptor) = self
+        match self
+            .profile
+            .descriptors
+            .iter()
+            .position(|d| d == descriptor_generator)
+        {
+            Some(pos) => self.perform(
+                Operation::DescriptorUpdated(new_descriptor_generator.name()),
+                Edit::Descriptors(ListEdit::Replace(
+                    pos,
+                    new_descriptor_generator,
+                )),
+            ),
+            None => self.commit(DocumentChange::Descriptors),
+        }
+    }
+
+    /// Records the highest used derivation indexes discovered for the
+    /// output categories of the descriptor account, keeping the previously
+    /// known ones if they are higher
+    pub fn update_last_used_indexes(
+        &mut self,
+        descriptor_generator: &DescriptorAccount,
+        last_used_indexes: &BTreeMap<descriptors::Category, u32>,
+    ) -> Result<bool, Error> {
+        let descriptor = match self
             .profile
             .descriptors
             .iter_mut()
-            .find(|d| *d == descriptor_generator)
+            .find(|d| d.generator == descriptor_generator.generator)
         {
-            *descriptor = new_descriptor_generator
+            Some(descriptor) => descriptor,
+            None => return Ok(false),
+        };
+        let mut updated = false;
+        for (category, index) in last_used_indexes {
+            let last_used = descriptor.last_used_indexes.get(category);
+            if last_used.map_or(true, |last_used| last_used < index) {
+                descriptor.last_used_indexes.insert(*category, *index);
+                updated = true;
+            }
         }
-        self.save()
+        if !updated {
+            return Ok(false);
+        }
+        let name = descriptor.name();
+        self.log(Operation::DescriptorUpdated(name));
+        self.commit(DocumentChange::Descriptors)
     }
 
     pub fn remove_descriptor(
         &mut self,
         descriptor_generator: DescriptorAccount,
     ) -> Result<bool, Error> {
-        self.profile
+        match self
+            .profile
             .descriptors
             .iter()
             .position(|d| *d == descriptor_generator)
-            .map(|i| self.profile.descriptors.remove(i));
-        self.save()
+        {
+            Some(pos) => self.perform(
+                Operation::DescriptorRemoved(descriptor_generator.name),
+                Edit::Descriptors(ListEdit::Remove(pos)),
+            ),
+            None => self.commit(DocumentChange::Descriptors),
+        }
     }
 
-    pub fn fill_utxo_store(
+    /// Addresses of the descriptor account for the output category, starting
+    /// from the derivation index `from`, together with their address book
+    /// data and usage known from the UTXO cache and transaction history
+    pub fn address_rows(
         &self,
-        store: &gtk::ListStore,
-        filter_by: Option<&DescriptorAccount>,
-    ) {
-        store.clear();
-        self.profile.utxo_cache.iter().for_each(|utxo| {
-            if filter_by
-                .map(|generator| utxo.has_match(generator))
-                .unwrap_or(true)
-            {
-                store.insert_with_values(
-                    None,
-                    &[
-                        (0, &utxo.outpoint.txid.to_string()),
-                        (1, &utxo.outpoint.vout),
-                        (2, &utxo.amount),
-                        (3, &utxo.height),
-                    ],
-                );
+        account: &DescriptorAccount,
+        category: descriptors::Category,
+        from: u32,
+        count: u32,
+    ) -> Vec<AddressRow> {
+        let network = self.network();
+        let used = self.used_links();
+        (from..from.saturating_add(count))
+            .filter_map(|index| {
+                let link = TxLink {
+                    descriptor_template: account.generator.template.clone(),
+                    descriptor_category: category,
+                    derivation_index: index,
+                };
+                let script_pubkey = link.script_pubkey(account)?;
+                let entry = self.address_entry(account, category, index);
+                Some(AddressRow {
+                    index,
+                    category,
+                    address: network.and_then(|network| {
+                        Address::from_script(&script_pubkey, network)
+                    }),
+                    script_pubkey,
+                    label: entry
+                        .map(|entry| entry.label.clone())
+                        .unwrap_or_default(),
+                    note: entry
+                        .map(|entry| entry.note.clone())
+                        .unwrap_or_default(),
+                    issued: entry.map(AddressEntry::time),
+                    used: used.contains(&link),
+                })
+            })
+            .collect()
+    }
+
+    /// Descriptor scripts paid or spent by the outputs and transactions
+    /// known to the document
+    fn used_links(&self) -> HashSet<TxLink> {
+        self.profile
+            .utxo_cache
+            .iter()
+            .map(TxLink::from)
+            .chain(
+                self.profile
+                    .tx_history
+                    .values()
+                    .flat_map(|entry| entry.links.iter().cloned()),
+            )
+            .collect()
+    }
+
+    /// Addresses of the descriptor account which were handed out
+    pub fn address_book(
+        &self,
+        account: &DescriptorAccount,
+    ) -> Vec<AddressEntry> {
+        self.profile
+            .address_book
+            .iter()
+            .filter(|entry| entry.has_match(account))
+            .cloned()
+            .collect()
+    }
+
+    pub fn address_entry(
+        &self,
+        account: &DescriptorAccount,
+        category: descriptors::Category,
+        index: u32,
+    ) -> Option<&AddressEntry> {
+        self.profile
+            .address_book
+            .iter()
+            .find(|entry| entry.is_address(account, category, index))
+    }
+
+    /// Derivation index of the next address to hand out, either for
+    /// receiving payments or as a change: the one following all addresses
+    /// which were handed out or are known to be used. Addresses of all
+    /// output categories with the same index share the key, so the index is
+    /// allocated for the account as a whole.
+    pub fn next_address_index(
+        &self,
+        account: &DescriptorAccount,
+    ) -> Result<u32, Error> {
+        let template = &account.generator.template;
+        let issued = self
+            .address_book(account)
+            .into_iter()
+            .map(|entry| entry.derivation_index);
+        let used = self
+            .used_links()
+            .into_iter()
+            .filter(|link| &link.descriptor_template == template)
+            .map(|link| link.derivation_index);
+        let last = issued
+            .chain(used)
+            .chain(account.last_used_indexes.values().copied())
+            .max();
+        match last {
+            None => Ok(0),
+            Some(last) => last
+                .checked_add(1)
+                .filter(|next| UnhardenedIndex::from_index(*next).is_ok())
+                .ok_or_else(|| Error::IndexesExhausted(account.name())),
+        }
+    }
+
+    /// Records the address as handed out, or updates its label and notes if
+    /// it is already in the address book
+    pub fn issue_address(
+        &mut self,
+        account: &DescriptorAccount,
+        category: descriptors::Category,
+        index: u32,
+        label: String,
+        note: String,
+    ) -> Result<bool, Error> {
+        let descriptor = account.name();
+        match self
+            .profile
+            .address_book
+            .iter()
+            .position(|entry| entry.is_address(account, category, index))
+        {
+            Some(pos) => {
+                let entry = AddressEntry {
+                    label,
+                    note,
+                    ..self.profile.address_book[pos].clone()
+                };
+                self.perform(
+                    Operation::AddressUpdated { descriptor, index },
+                    Edit::AddressBook(ListEdit::Replace(pos, entry)),
+                )
             }
-        });
+            None => {
+                let pos = self.profile.address_book.len();
+                let entry =
+                    AddressEntry::with(account, category, index, label, note);
+                self.perform(
+                    Operation::AddressIssued { descriptor, index },
+                    Edit::AddressBook(ListEdit::Insert(pos, entry)),
+                )
+            }
+        }
+    }
+
+    pub fn remove_address(
+        &mut self,
+        account: &DescriptorAccount,
+        category: descriptors::Category,
+        index: u32,
+    ) -> Result<bool, Error> {
+        match self
+            .profile
+            .address_book
+            .iter()
+            .position(|entry| entry.is_address(account, category, index))
+        {
+            Some(pos) => self.perform(
+                Operation::AddressRemoved {
+                    descriptor: account.name(),
+                    index,
+                },
+                Edit::AddressBook(ListEdit::Remove(pos)),
+            ),
+            None => self.commit(DocumentChange::Addresses),
+        }
+    }
+
+    /// Finds which of the descriptor and public key tracking accounts
+    /// derive the script within the first `window` derivation indexes,
+    /// checking every output category enabled for the account. Scripts
+    /// derived during the search are cached for the following searches.
+    pub fn find_script(
+        &mut self,
+        script: &Script,
+        window: u32,
+    ) -> Vec<ScriptMatch> {
+        let descriptors = self.profile.descriptors.iter().enumerate().flat_map(
+            |(position, account)| {
+                let owner = ScriptOwner::Descriptor {
+                    position,
+                    name: account.name(),
+                };
+                let template = &account.generator.template;
+                let leaves = &account.tap_leaves;
+                account.categories().into_iter().map(move |category| {
+                    (owner.clone(), template.clone(), leaves.clone(), category)
+                })
+            },
+        );
+        let tracking = self.profile.tracking.iter().enumerate().flat_map(
+            |(position, account)| {
+                let owner = ScriptOwner::Tracking {
+                    position,
+                    name: account.name.clone(),
+                };
+                let template =
+                    descriptors::Template::SingleSig(account.key.clone());
+                account.categories().into_iter().map(move |category| {
+                    (owner.clone(), template.clone(), vec![], category)
+                })
+            },
+        );
+        let candidates = descriptors
+            .chain(tracking)
+            .map(|(owner, template, leaves, category)| {
+                let account = category_account(template, category, leaves);
+                (owner, category, account)
+            })
+            .collect::<Vec<_>>();
+
+        self.script_cache.retain(
+            &candidates
+                .iter()
+                .map(|(_, _, account)| account.descriptor())
+                .collect(),
+        );
+        let cache = &mut self.script_cache;
+        candidates
+            .into_iter()
+            .filter_map(|(owner, category, account)| {
+                cache
+                    .find(&account, script, window)
+                    .map(|derivation_index| ScriptMatch {
+                        owner,
+                        category,
+                        derivation_index,
+                    })
+            })
+            .collect()
     }
 
+    pub fn utxo_rows(
+        &self,
+        filter_by: Option<&DescriptorAccount>,
+    ) -> Vec<UtxoRow> {
+        self.utxos(filter_by).iter().map(UtxoRow::from).collect()
+    }
+
+    pub fn utxos(
+        &self,
+        filter_by: Option<&DescriptorAccount>,
+    ) -> Vec<UtxoEntry> {
+        let mut utxos = self
+            .profile
+            .utxo_cache
+            .iter()
+            .filter(|utxo| {
+                filter_by
+                    .map(|generator| utxo.has_match(generator))
+                    .unwrap_or(true)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        utxos.sort();
+        utxos
+    }
+
+    /// Adds outputs found by a lookup to the cache, replacing cached entries
+    /// with the same outpoints
     pub fn update_utxo_set(
         &mut self,
         utxo_set_update: HashSet<UtxoEntry>,
     ) -> Result<bool, Error> {
+        self.log(Operation::UtxosFound(utxo_set_update.len() as u32));
+        // Outputs found again replace the cached ones, which may have an
+        // outdated height
+        let outpoints = utxo_set_update
+            .iter()
+            .map(|utxo| utxo.outpoint)
+            .collect::<HashSet<_>>();
+        self.profile
+            .utxo_cache
+            .retain(|utxo| !outpoints.contains(&utxo.outpoint));
         self.profile.utxo_cache.extend(utxo_set_update);
-        self.save()
+        self.commit(DocumentChange::Utxos)
+    }
+
+    /// Applies results of the cached outputs re-verification: replaces
+    /// `moved` outputs with the same outpoint and drops `spent` ones
+    pub fn reconcile_utxos(
+        &mut self,
+        moved: Vec<UtxoEntry>,
+        spent: Vec<UtxoEntry>,
+    ) -> Result<bool, Error> {
+        self.profile.utxo_cache.retain(|utxo| {
+            !spent.contains(utxo)
+                && !moved.iter().any(|m| m.outpoint == utxo.outpoint)
+        });
+        self.log(Operation::UtxosReconciled {
+            moved: moved.len() as u32,
+            spent: spent.len() as u32,
+        });
+        self.profile.utxo_cache.extend(moved);
+        self.commit(DocumentChange::Utxos)
     }
 
     pub fn utxo_by_outpoint(&self, outpoint: OutPoint) -> Option<UtxoEntry> {
@@ -374,64 +1062,416 @@ impl Document {
     }
 
     pub fn remove_utxo(&mut self, utxo: UtxoEntry) -> Result<bool, Error> {
-        self.profile.utxo_cache.remove(&utxo);
-        self.save()
+        if self.profile.utxo_cache.contains(&utxo) {
+            self.perform(
+                Operation::UtxoRemoved(utxo.outpoint),
+                Edit::RemoveUtxos(vec![utxo]),
+            )
+        } else {
+            self.commit(DocumentChange::Utxos)
+        }
     }
 
     pub fn remove_utxo_by_descriptor(
         &mut self,
         descriptor_generator: DescriptorAccount,
     ) -> Result<bool, Error> {
-        self.profile.utxo_cache = self
+        let utxos = self.utxos(Some(&descriptor_generator));
+        if utxos.is_empty() {
+            return self.commit(DocumentChange::Utxos);
+        }
+        self.perform(
+            Operation::UtxosCleared(descriptor_generator.name),
+            Edit::RemoveUtxos(utxos),
+        )
+    }
+
+    pub fn is_outpoint_known(&self, outpoint: OutPoint) -> bool {
+        self.profile
+            .utxo_cache
+            .iter()
+            .any(|utxo| utxo.outpoint == outpoint)
+    }
+
+    /// Transaction history of the descriptor account, ordered by the
+    /// confirmation height with not yet mined transactions at the end
+    pub fn history_rows(&self, account: &DescriptorAccount) -> Vec<HistoryRow> {
+        let entries = self
+            .profile
+            .tx_history
+            .iter()
+            .filter(|(_, entry)| entry.has_match(account))
+            .collect::<Vec<_>>();
+        let scripts = entries
+            .iter()
+            .flat_map(|(_, entry)| &entry.links)
+            .filter(|link| link.has_match(account))
+            .filter_map(|link| link.script_pubkey(account))
+            .collect::<HashSet<_>>();
+
+        let mut rows = entries
+            .into_iter()
+            .filter_map(|(txid, entry)| {
+                let tx = self.transaction(*txid)?;
+                let received = tx
+                    .output
+                    .iter()
+                    .filter(|txout| scripts.contains(&txout.script_pubkey))
+                    .map(|txout| txout.value)
+                    .sum();
+                let spent = tx
+                    .input
+                    .iter()
+                    .filter_map(|txin| {
+                        let prev =
+                            self.transaction(txin.previous_output.txid)?;
+                        prev.output
+                            .get(txin.previous_output.vout as usize)
+                            .filter(|txout| {
+                                scripts.contains(&txout.script_pubkey)
+                            })
+                            .map(|txout| txout.value)
+                    })
+                    .sum();
+                Some(HistoryRow {
+                    txid: *txid,
+                    height: entry.height,
+                    received,
+                    spent,
+                })
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|row| (row.height == 0, row.height, row.txid));
+        rows
+    }
+
+    pub fn transaction(&self, txid: Txid) -> Option<&Transaction> {
+        self.profile.tx_cache.iter().find(|tx| tx.txid() == txid)
+    }
+
+    pub fn is_transaction_known(&self, txid: Txid) -> bool {
+        self.transaction(txid).is_some()
+    }
+
+    pub fn transaction_rows(&self) -> Vec<TransactionRow> {
+        self.profile
+            .tx_cache
+            .iter()
+            .map(|tx| {
+                let txid = tx.txid();
+                TransactionRow {
+                    txid,
+                    height: self.transaction_height(txid),
+                    inputs: tx.input.len(),
+                    outputs: tx.output.len(),
+                }
+            })
+            .collect()
+    }
+
+    /// Confirmation height of the transaction from the descriptor history;
+    /// zero for the transactions which are not mined yet
+    pub fn transaction_height(&self, txid: Txid) -> Option<u32> {
+        self.profile.tx_history.get(&txid).map(|entry| entry.height)
+    }
+
+    /// Transaction from the cache spending the output
+    pub fn spending_txid(&self, outpoint: OutPoint) -> Option<Txid> {
+        self.profile
+            .tx_cache
+            .iter()
+            .find(|tx| {
+                tx.input.iter().any(|txin| txin.previous_output == outpoint)
+            })
+            .map(Transaction::txid)
+    }
+
+    /// Descriptor links of the transaction known from the descriptor history
+    /// and the cached unspent outputs
+    pub fn tx_links(&self, txid: Txid) -> Vec<TxLink> {
+        let history_links = self
+            .profile
+            .tx_history
+            .get(&txid)
+            .into_iter()
+            .flat_map(|entry| entry.links.iter().cloned());
+        let utxo_links = self
             .profile
             .utxo_cache
             .iter()
-            .filter(|utxo| !utxo.has_match(&descriptor_generator))
+            .filter(|utxo| utxo.outpoint.txid == txid)
+            .map(TxLink::from);
+        history_links.chain(utxo_links).collect()
+    }
+
+    /// Scripts of the descriptor accounts which are touched by the
+    /// transaction, mapped to the account names
+    pub fn descriptor_scripts(&self, txid: Txid) -> HashMap<Script, String> {
+        self.tx_links(txid)
+            .into_iter()
+            .filter_map(|link| {
+                let account = self
+                    .profile
+                    .descriptors
+                    .iter()
+                    .find(|account| link.has_match(account))?;
+                Some((link.script_pubkey(account)?, account.name()))
+            })
+            .collect()
+    }
+
+    /// Spent output, if the previous transaction is in the cache
+    fn previous_output(&self, outpoint: OutPoint) -> Option<&TxOut> {
+        self.transaction(outpoint.txid)?
+            .output
+            .get(outpoint.vout as usize)
+    }
+
+    pub fn tx_input_rows(&self, tx: &Transaction) -> Vec<TxInputRow> {
+        let scripts = self.descriptor_scripts(tx.txid());
+        tx.input
+            .iter()
+            .enumerate()
+            .map(|(no, txin)| TxInputRow {
+                no: no as u32,
+                previous_output: txin.previous_output,
+                sequence: txin.sequence,
+                descriptor: self
+                    .previous_output(txin.previous_output)
+                    .and_then(|txout| scripts.get(&txout.script_pubkey))
+                    .cloned(),
+            })
+            .collect()
+    }
+
+    pub fn tx_output_rows(&self, tx: &Transaction) -> Vec<TxOutputRow> {
+        let txid = tx.txid();
+        let scripts = self.descriptor_scripts(txid);
+        tx.output
+            .iter()
+            .enumerate()
+            .map(|(no, txout)| TxOutputRow {
+                no: no as u32,
+                value: txout.value,
+                script_pubkey: txout.script_pubkey.clone(),
+                spent: self
+                    .spending_txid(OutPoint::new(txid, no as u32))
+                    .is_some(),
+                descriptor: scripts.get(&txout.script_pubkey).cloned(),
+            })
+            .collect()
+    }
+
+    /// Transaction fee, if all spent outputs are known
+    pub fn tx_fee(&self, tx: &Transaction) -> Option<u64> {
+        let spent = tx
+            .input
+            .iter()
+            .map(|txin| {
+                self.previous_output(txin.previous_output)
+                    .map(|txout| txout.value)
+            })
+            .sum::<Option<u64>>()?;
+        let paid = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        spent.checked_sub(paid)
+    }
+
+    /// Adds transactions to the cache and merges their history entries with
+    /// the known ones
+    pub fn update_tx_history(
+        &mut self,
+        transactions: Vec<Transaction>,
+        entries: BTreeMap<Txid, TxEntry>,
+    ) -> Result<bool, Error> {
+        let count = transactions.len().max(entries.len());
+        self.log(Operation::TransactionsUpdated(count as u32));
+        self.merge_tx_history(transactions, entries);
+        self.commit(DocumentChange::Transactions)
+    }
+
+    fn merge_tx_history(
+        &mut self,
+        transactions: Vec<Transaction>,
+        entries: BTreeMap<Txid, TxEntry>,
+    ) {
+        for tx in transactions {
+            if !self.is_transaction_known(tx.txid()) {
+                self.profile.tx_cache.push(tx);
+            }
+        }
+        for (txid, entry) in entries {
+            self.profile
+                .tx_history
+                .entry(txid)
+                .or_default()
+                .update(entry);
+        }
+    }
+
+    /// Records transaction broadcasted from the document: adds it to the
+    /// cache and to the history as not mined yet, linked to the spent cached
+    /// outputs, which are removed from the UTXO cache
+    pub fn record_broadcast(&mut self, tx: Transaction) -> Result<bool, Error> {
+        let spent = self
+            .profile
+            .utxo_cache
+            .iter()
+            .filter(|utxo| {
+                tx.input
+                    .iter()
+                    .any(|txin| txin.previous_output == utxo.outpoint)
+            })
             .cloned()
-            .collect();
-        self.save()
+            .collect::<Vec<_>>();
+        let entry = TxEntry {
+            height: 0,
+            links: spent.iter().map(TxLink::from).collect(),
+        };
+        if !spent.is_empty() {
+            self.profile.utxo_cache.retain(|utxo| !spent.contains(utxo));
+            self.notify(DocumentChange::Utxos);
+        }
+        self.log(Operation::TxBroadcast(tx.txid()));
+        self.merge_tx_history(vec![tx.clone()], bmap! { tx.txid() => entry });
+        self.commit(DocumentChange::Transactions)
     }
 
-    pub fn is_outpoint_known(&self, outpoint: OutPoint) -> bool {
+    pub fn psbts(&self) -> &[Psbt] {
+        &self.profile.psbts
+    }
+
+    pub fn psbt_at(&self, pos: usize) -> Option<&Psbt> {
+        self.profile.psbts.get(pos)
+    }
+
+    pub fn psbt_rows(&self) -> Vec<PsbtRow> {
         self.profile
-            .utxo_cache
+            .psbts
             .iter()
-            .any(|utxo| utxo.outpoint == outpoint)
+            .map(|psbt| PsbtRow {
+                txid: psbt.unsigned_tx.txid(),
+                inputs: psbt.inputs.len(),
+                outputs: psbt.outputs.len(),
+                signed: signed_inputs(psbt),
+            })
+            .collect()
     }
 
-    pub fn fill_asset_store(&self, store: &gtk::ListStore) {
-        store.clear();
-        self.profile.assets.iter().for_each(|(contract_id, _)| {
-            if let Some((asset, _)) = self.asset_by_id(*contract_id) {
-                store.insert_with_values(
-                    None,
-                    &[
-                        (0, &asset.ticker()),
-                        (1, &asset.name()),
-                        (
-                            2,
-                            &asset.known_filtered_value(|allocation| {
-                                self.is_outpoint_known(*allocation.outpoint())
-                            }),
-                        ),
-                        (
-                            3,
-                            &asset
-                                .precise_supply(
-                                    rgb20::SupplyMeasure::KnownCirculating,
-                                )
-                                .as_ref()
-                                .map(u64::to_string)
-                                .unwrap_or(s!("?")),
-                        ),
-                        (4, &1),
-                        (5, &(!asset.known_inflation().is_empty())),
-                        (6, &0),
-                        (7, &contract_id.to_string()),
-                    ],
-                );
-            };
+    pub fn add_psbt(&mut self, psbt: Psbt) -> Result<bool, Error> {
+        let pos = self.profile.psbts.len();
+        self.perform(
+            Operation::PsbtAdded(psbt.unsigned_tx.txid()),
+            Edit::Psbts(ListEdit::Insert(pos, psbt)),
+        )
+    }
+
+    /// Adds PSBT composed by the application, recording its change address
+    /// in the address book, so it is neither handed out nor used as a change
+    /// of other PSBTs. Undoing the addition releases the change address.
+    pub fn add_composed_psbt(
+        &mut self,
+        psbt: Psbt,
+        change: Option<&TxLink>,
+    ) -> Result<bool, Error> {
+        let txid = psbt.unsigned_tx.txid();
+        let account = change.and_then(|link| {
+            self.descriptors()
+                .iter()
+                .find(|account| link.has_match(account))
+                .cloned()
         });
+        let mut edits = vec![];
+        if let Some((link, account)) = change.zip(account) {
+            let category = link.descriptor_category;
+            let index = link.derivation_index;
+            // Replacement transactions pay change to the already reserved
+            // address
+            if self.address_entry(&account, category, index).is_none() {
+                let entry = AddressEntry::with(
+                    &account,
+                    category,
+                    index,
+                    s!("Change"),
+                    format!("Change of {}", txid),
+                );
+                let pos = self.profile.address_book.len();
+                edits.push(Edit::AddressBook(ListEdit::Insert(pos, entry)));
+            }
+        }
+        let pos = self.profile.psbts.len();
+        edits.push(Edit::Psbts(ListEdit::Insert(pos, psbt)));
+        self.perform(Operation::PsbtAdded(txid), Edit::Compound(edits))
+    }
+
+    pub fn update_psbt_at(
+        &mut self,
+        pos: usize,
+        psbt: Psbt,
+    ) -> Result<bool, Error> {
+        let operation = Operation::PsbtUpdated {
+            txid: psbt.unsigned_tx.txid(),
+            signed: signed_inputs(&psbt) as u32,
+        };
+        self.perform(operation, Edit::Psbts(ListEdit::Replace(pos, psbt)))
+    }
+
+    pub fn remove_psbt_at(&mut self, pos: usize) -> Result<bool, Error> {
+        let txid = self
+            .psbt_at(pos)
+            .ok_or(Error::WrongPosition(pos))?
+            .unsigned_tx
+            .txid();
+        self.perform(
+            Operation::PsbtRemoved(txid),
+            Edit::Psbts(ListEdit::Remove(pos)),
+        )
+    }
+
+    /// Name of the tracking account having the given key, or a key derived
+    /// from the master key with the given fingerprint
+    pub fn key_account(&self, key: &str, fingerprint: &str) -> Option<String> {
+        let origin = format!("[{}", fingerprint);
+        self.profile
+            .tracking
+            .iter()
+            .find(|account| {
+                let details = account.details();
+                details.contains(key) || details.contains(&origin)
+            })
+            .map(|account| account.name.clone())
+    }
+
+    pub fn asset_rows(&self) -> Vec<AssetRow> {
+        self.profile
+            .assets
+            .keys()
+            .filter_map(|contract_id| {
+                let (asset, _) = self.asset_by_id(*contract_id)?;
+                Some(AssetRow {
+                    contract_id: *contract_id,
+                    ticker: asset.ticker().clone(),
+                    name: asset.name().clone(),
+                    known_value: asset.known_filtered_value(|allocation| {
+                        self.is_outpoint_known(*allocation.outpoint())
+                    }),
+                    circulating: asset.precise_supply(
+                        rgb20::SupplyMeasure::KnownCirculating,
+                    ),
+                    inflatable: !asset.known_inflation().is_empty(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn asset_ids(&self) -> Vec<ContractId> {
+        self.profile.assets.keys().copied().collect()
+    }
+
+    pub fn asset_consignment(
+        &self,
+        asset_id: ContractId,
+    ) -> Option<&Consignment> {
+        self.profile.assets.get(&asset_id)
     }
 
     pub fn asset_by_id(
@@ -449,56 +1489,111 @@ impl Document {
         &mut self,
         consignment: Consignment,
     ) -> Result<bool, Error> {
-        let contract_id = consignment.genesis.contract_id();
-        if self.profile.assets.contains_key(&contract_id) {
-            return Err(Error::DuplicatedContract(contract_id));
-        }
-        self.profile.assets.insert(contract_id, consignment);
-        self.save()
+        self.perform(
+            Operation::AssetAdded(consignment.genesis.contract_id()),
+            Edit::InsertAsset(Box::new(consignment)),
+        )
     }
 
     pub fn remove_asset(
         &mut self,
         contract_id: ContractId,
     ) -> Result<bool, Error> {
-        self.profile.assets.remove(&contract_id);
-        self.save()
-    }
-
-    pub fn resolver(&self) -> Result<ElectrumClient, ResolverError> {
-        if let ChainResolver::Electrum(addr) = self.profile.settings.resolver {
-            Ok(ElectrumClient::new(&addr.to_string())?)
+        if self.profile.assets.contains_key(&contract_id) {
+            self.perform(
+                Operation::AssetRemoved(contract_id),
+                Edit::RemoveAsset(contract_id),
+            )
         } else {
-            Err(ResolverError::ElectrumRequired)
+            self.commit(DocumentChange::Assets)
         }
     }
+
+    pub fn operation_log(&self) -> &[LogEntry] {
+        &self.profile.history
+    }
+
+    pub fn log_rows(&self) -> Vec<LogRow> {
+        self.profile.history.iter().map(LogRow::from).collect()
+    }
+
+    /// Exports the operation log for the external audit
+    pub fn export_log(&self, format: LogFormat) -> Result<String, Error> {
+        Ok(match format {
+            LogFormat::Csv => operation::log_csv(&self.profile.history),
+            LogFormat::Json => {
+                serde_json::to_string_pretty(&self.profile.history)
+                    .map_err(ExportError::from)?
+            }
+        })
+    }
+
+    pub fn resolver(&self) -> Result<Box<dyn Resolver>, ResolverError> {
+        resolver::connect(&self.profile.settings)
+    }
+}
+
+/// Number of PSBT inputs having signatures or final scripts
+fn signed_inputs(psbt: &Psbt) -> usize {
+    psbt.inputs
+        .iter()
+        .filter(|input| {
+            !input.partial_sigs.is_empty()
+                || input.final_script_sig.is_some()
+                || input.final_script_witness.is_some()
+        })
+        .count()
 }
 
-#[derive(Clone, PartialEq, Debug, StrictEncode, StrictDecode)]
+#[derive(
+    Clone,
+    PartialEq,
+    Debug,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
 pub struct Profile {
     pub magic: u32,
     pub version: u16,
     pub description: Option<String>,
     pub tracking: Vec<TrackingAccount>,
     pub descriptors: Vec<DescriptorAccount>,
+    #[serde(serialize_with = "export::serialize_utxos")]
     pub utxo_cache: HashSet<UtxoEntry>,
     pub tx_cache: Vec<Transaction>,
+    #[serde(with = "As::<Vec<StrictHex>>")]
     pub psbts: Vec<Psbt>,
+    #[serde(with = "As::<BTreeMap<StrictHex, StrictHex>>")]
     pub schemata: BTreeMap<SchemaId, Schema>,
+    #[serde(with = "As::<BTreeMap<DisplayFromStr, StrictHex>>")]
     pub assets: BTreeMap<ContractId, Consignment>,
+    #[serde(with = "As::<BTreeMap<DisplayFromStr, StrictHex>>")]
     pub nfts: BTreeMap<ContractId, Consignment>,
+    #[serde(with = "As::<BTreeMap<DisplayFromStr, StrictHex>>")]
     pub identities: BTreeMap<ContractId, Consignment>,
+    #[serde(with = "As::<BTreeMap<DisplayFromStr, StrictHex>>")]
     pub auditlogs: BTreeMap<ContractId, Consignment>,
+    #[serde(with = "As::<BTreeMap<DisplayFromStr, StrictHex>>")]
     pub contracts: BTreeMap<ContractId, Consignment>,
     pub history: Vec<operation::LogEntry>,
     pub settings: Settings,
+    /// Transactions of the descriptor accounts, whose data are stored in
+    /// `tx_cache`
+    #[serde(default)]
+    pub tx_history: BTreeMap<Txid, TxEntry>,
+    /// Receive addresses handed out from the descriptor accounts
+    #[serde(default)]
+    pub address_book: Vec<AddressEntry>,
 }
 
 impl Default for Profile {
     fn default() -> Self {
         Profile {
             magic: DOC_MAGIC,
-            version: 0,
+            version: PROFILE_VERSION,
             description: None,
             tracking: vec![],
             descriptors: vec![],
@@ -513,20 +1608,38 @@ impl Default for Profile {
             contracts: bmap![],
             history: vec![],
             settings: Settings::default(),
+            tx_history: bmap![],
+            address_book: vec![],
         }
     }
 }
 
-#[derive(Clone, Debug, Display, Error, From)]
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
 #[display(doc_comments)]
 pub enum ResolverError {
     /// Electrum-specific error
     #[display("{0}")]
     Electrum(String),
 
-    /// The current version supports only Electrum server; please specify
-    /// server connection string in document settings
-    ElectrumRequired,
+    /// Bitcoin Core RPC error: {0}
+    BitcoinCore(String),
+
+    /// Bitcoin Core authentication cookie location is unknown for the
+    /// current chain; please specify RPC user name and password
+    NoCookie,
+
+    /// Blockchain information provider {0} is not supported yet; please
+    /// use Electrum server or Bitcoin Core
+    Unsupported(ChainResolver),
+
+    /// {0} is unable to {1}
+    UnsupportedOperation(&'static str, &'static str),
+
+    /// Transaction {0} is not known to the blockchain information provider
+    UnknownTransaction(Txid),
+
+    /// Fee rate for the confirmation within {0} blocks can't be estimated
+    NoFeeEstimate(u16),
 }
 
 impl From<ElectrumError> for ResolverError {
@@ -535,7 +1648,18 @@ impl From<ElectrumError> for ResolverError {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Display,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
 pub enum ChainResolver {
     #[display("bitcoinCore({0})")]
     BitcoinCore(SocketAddr),
@@ -555,11 +1679,89 @@ impl Default for ChainResolver {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub enum CoreAuth {
+    /// Cookie file authentication; if no path is given, the cookie is read
+    /// from the default node data directory for the document chain
+    Cookie(Option<String>),
+
+    /// RPC user name and password
+    UserPass(String, String),
+}
+
+impl Default for CoreAuth {
+    fn default() -> Self {
+        CoreAuth::Cookie(None)
+    }
+}
+
+/// Bitcoin Core RPC connection in form of `[user:password@]host:port`;
+/// connections without user name and password use cookie authentication
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CoreConnection {
+    pub addr: SocketAddr,
+    pub auth: CoreAuth,
+}
+
+impl Display for CoreConnection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let CoreAuth::UserPass(ref user, ref pass) = self.auth {
+            write!(f, "{}:{}@", user, pass)?;
+        }
+        Display::fmt(&self.addr, f)
+    }
+}
+
+impl FromStr for CoreConnection {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.rsplit_once('@') {
+            Some((credentials, addr)) => {
+                let (user, pass) =
+                    credentials.split_once(':').unwrap_or((credentials, ""));
+                CoreConnection {
+                    addr: addr.parse()?,
+                    auth: CoreAuth::UserPass(user.to_owned(), pass.to_owned()),
+                }
+            }
+            None => CoreConnection {
+                addr: s.parse()?,
+                auth: CoreAuth::default(),
+            },
+        })
+    }
+}
+
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
 pub struct Settings {
+    #[serde(with = "As::<DisplayFromStr>")]
     pub chain: Chain,
     pub resolver: ChainResolver,
     pub bifrost: Option<SocketAddr>,
+    /// Authentication used with [`ChainResolver::BitcoinCore`]
+    #[serde(default)]
+    pub core_auth: CoreAuth,
 }
 
 impl Default for Settings {
@@ -568,6 +1770,806 @@ impl Default for Settings {
             chain: Chain::Testnet3,
             resolver: Default::default(),
             bifrost: None,
+            core_auth: CoreAuth::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::str::FromStr;
+
+    use bitcoin::{OutPoint, TxIn, Txid};
+    use wallet::descriptors;
+
+    use super::*;
+    use crate::util::psbt::empty_psbt;
+
+    fn key() -> descriptors::SingleSig {
+        descriptors::SingleSig::from_str(
+            "pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap()
+    }
+
+    fn tracking_account(name: &str) -> TrackingAccount {
+        TrackingAccount {
+            name: name.to_owned(),
+            key: key(),
         }
     }
+
+    fn descriptor_account(name: &str) -> DescriptorAccount {
+        DescriptorAccount {
+            name: name.to_owned(),
+            generator: descriptors::Generator {
+                template: descriptors::Template::SingleSig(key()),
+                variants: descriptors::Variants {
+                    bare: false,
+                    hashed: true,
+                    nested: false,
+                    segwit: true,
+                    taproot: false,
+                },
+            },
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: empty!(),
+        }
+    }
+
+    fn utxo(vout: u32, account: &DescriptorAccount) -> UtxoEntry {
+        UtxoEntry {
+            outpoint: OutPoint::new(
+                Txid::from_str(
+                    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                )
+                .unwrap(),
+                vout,
+            ),
+            height: 100 + vout,
+            amount: 1000 * (vout as u64 + 1),
+            descriptor_template: account.generator.template.clone(),
+            descriptor_category: descriptors::Category::SegWit,
+            derivation_index: vout,
+        }
+    }
+
+    #[test]
+    fn tracking_accounts_add_update_remove() {
+        let mut doc = Document::new();
+        assert_eq!(doc.add_tracking_account(tracking_account("a")), Ok(false));
+        assert_eq!(doc.add_tracking_account(tracking_account("b")), Ok(false));
+        assert_eq!(doc.tracking_rows().len(), 2);
+        assert_eq!(doc.tracking_rows()[1].name, "b");
+
+        doc.update_tracking_account_at(0, tracking_account("c"))
+            .unwrap();
+        assert_eq!(doc.tracking_account_at(0), Some(tracking_account("c")));
+
+        assert_eq!(
+            doc.remove_tracking_account_at(5),
+            Err(Error::WrongPosition(5))
+        );
+        doc.remove_tracking_account_at(0).unwrap();
+        assert_eq!(doc.tracking_accounts(), &[tracking_account("b")]);
+    }
+
+    #[test]
+    fn descriptor_rows() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        doc.add_descriptor(account.clone()).unwrap();
+
+        let rows = doc.descriptor_rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].name, "savings");
+        assert_eq!(rows[0].type_name, account.type_name());
+        assert_eq!(rows[0].descriptor, account.descriptor());
+        assert_eq!(
+            doc.descriptor_by_generator(&account.descriptor()),
+            Some(account.clone())
+        );
+
+        doc.remove_descriptor(account).unwrap();
+        assert!(doc.descriptor_rows().is_empty());
+    }
+
+    #[test]
+    fn last_used_indexes_per_category() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        doc.add_descriptor(account.clone()).unwrap();
+
+        let hashed = descriptors::Category::Hashed;
+        let segwit = descriptors::Category::SegWit;
+        doc.update_last_used_indexes(&account, &bmap! { segwit => 7 })
+            .unwrap();
+        // Lower index of one category does not override the known one, while
+        // the other category is tracked on its own
+        doc.update_last_used_indexes(
+            &account,
+            &bmap! { hashed => 2, segwit => 3 },
+        )
+        .unwrap();
+        let account = doc.descriptor_at(0).unwrap();
+        assert_eq!(
+            account.last_used_indexes,
+            bmap! { hashed => 2, segwit => 7 }
+        );
+        assert_eq!(doc.next_address_index(&account).unwrap(), 8);
+    }
+
+    #[test]
+    fn address_indexes_exhausted() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        doc.add_descriptor(account.clone()).unwrap();
+        let segwit = descriptors::Category::SegWit;
+
+        let last = UnhardenedIndex::largest().first_index();
+        doc.update_last_used_indexes(&account, &bmap! { segwit => last - 1 })
+            .unwrap();
+        let account = doc.descriptor_at(0).unwrap();
+        assert_eq!(doc.next_address_index(&account).unwrap(), last);
+
+        doc.issue_address(&account, segwit, last, s!(""), s!(""))
+            .unwrap();
+        assert_eq!(
+            doc.next_address_index(&account),
+            Err(Error::IndexesExhausted(s!("savings")))
+        );
+        doc.issue_address(&account, segwit, u32::MAX, s!(""), s!(""))
+            .unwrap();
+        assert_eq!(
+            doc.next_address_index(&account),
+            Err(Error::IndexesExhausted(s!("savings")))
+        );
+    }
+
+    #[test]
+    fn utxo_rows_filtered_by_descriptor() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        let utxos = (0..3).map(|vout| utxo(vout, &account)).collect();
+        doc.update_utxo_set(utxos).unwrap();
+
+        let rows = doc.utxo_rows(None);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].outpoint.vout, 0);
+        assert_eq!(rows[2].amount, 3000);
+        assert_eq!(doc.utxo_rows(Some(&account)).len(), 3);
+
+        let first = utxo(0, &account);
+        assert!(doc.is_outpoint_known(first.outpoint));
+        doc.remove_utxo(first.clone()).unwrap();
+        assert!(!doc.is_outpoint_known(first.outpoint));
+
+        doc.remove_utxo_by_descriptor(account).unwrap();
+        assert!(doc.utxo_rows(None).is_empty());
+    }
+
+    #[test]
+    fn address_book() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        let segwit = descriptors::Category::SegWit;
+        assert_eq!(doc.next_address_index(&account).unwrap(), 0);
+
+        doc.update_utxo_set(set![utxo(2, &account)]).unwrap();
+        assert_eq!(doc.next_address_index(&account).unwrap(), 3);
+        doc.issue_address(&account, segwit, 3, s!("Alice"), s!("Order \"42\""))
+            .unwrap();
+        assert_eq!(doc.next_address_index(&account).unwrap(), 4);
+        // Index is shared by all output categories
+        doc.issue_address(
+            &account,
+            descriptors::Category::Hashed,
+            4,
+            s!(""),
+            s!(""),
+        )
+        .unwrap();
+        assert_eq!(doc.next_address_index(&account).unwrap(), 5);
+        doc.undo().unwrap();
+
+        let rows = doc.address_rows(&account, segwit, 0, 5);
+        assert_eq!(rows.len(), 5);
+        assert!(rows[0].address_string().starts_with("tb1q"));
+        assert_eq!(
+            Some(&rows[2].script_pubkey),
+            TxLink::from(&utxo(2, &account))
+                .script_pubkey(&account)
+                .as_ref()
+        );
+        assert!(rows[2].used);
+        assert_eq!(rows[2].issued, None);
+        assert!(!rows[3].used);
+        assert_eq!(rows[3].label, "Alice");
+        let issued = rows[3].issued.clone();
+        assert!(issued.is_some());
+        assert!(doc
+            .address_rows(&account, descriptors::Category::Taproot, 0, 5)
+            .is_empty());
+
+        let csv = addresses_csv(&rows[2..4]);
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.lines().nth(2).unwrap().starts_with(&format!(
+            "3,segwit,{},\"Alice\",\"Order \"\"42\"\"\",",
+            rows[3].address_string()
+        )));
+
+        doc.issue_address(&account, segwit, 3, s!("Bob"), s!(""))
+            .unwrap();
+        assert_eq!(doc.address_book(&account).len(), 1);
+        let rows = doc.address_rows(&account, segwit, 3, 1);
+        assert_eq!((rows[0].label.as_str(), &rows[0].issued), ("Bob", &issued));
+
+        doc.remove_address(&account, segwit, 3).unwrap();
+        assert!(doc.address_book(&account).is_empty());
+        assert_eq!(doc.next_address_index(&account).unwrap(), 3);
+        doc.undo().unwrap();
+        doc.undo().unwrap();
+        assert_eq!(
+            doc.address_entry(&account, segwit, 3)
+                .map(|entry| entry.label.as_str()),
+            Some("Alice")
+        );
+        assert_eq!(
+            doc.undo_operation(),
+            Some(&Operation::AddressIssued {
+                descriptor: s!("savings"),
+                index: 3
+            })
+        );
+    }
+
+    #[test]
+    fn find_script() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        doc.add_descriptor(account.clone()).unwrap();
+        doc.add_tracking_account(tracking_account("cold")).unwrap();
+        let segwit = descriptors::Category::SegWit;
+        let script = doc.address_rows(&account, segwit, 0, 1)[0]
+            .script_pubkey
+            .clone();
+
+        assert!(doc.find_script(&script, 0).is_empty());
+        let matches = doc.find_script(&script, 10);
+        assert_eq!(
+            matches,
+            vec![
+                ScriptMatch {
+                    owner: ScriptOwner::Descriptor {
+                        position: 0,
+                        name: s!("savings")
+                    },
+                    category: segwit,
+                    derivation_index: 0,
+                },
+                ScriptMatch {
+                    owner: ScriptOwner::Tracking {
+                        position: 0,
+                        name: s!("cold")
+                    },
+                    category: segwit,
+                    derivation_index: 0,
+                },
+            ]
+        );
+
+        // Repeated search uses the cached scripts only
+        let cached = doc.script_cache.len();
+        assert!(cached > 0);
+        assert_eq!(doc.find_script(&script, 10), matches);
+        assert_eq!(doc.script_cache.len(), cached);
+        assert!(doc.find_script(&Script::new(), 10).is_empty());
+
+        doc.remove_descriptor(account).unwrap();
+        assert_eq!(doc.find_script(&script, 10), matches[1..]);
+    }
+
+    #[test]
+    fn observers_receive_changes() {
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let mut doc = Document::new();
+        let log = changes.clone();
+        doc.subscribe(move |doc, change| {
+            log.borrow_mut().push((change, doc.tracking_rows().len()))
+        });
+
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.add_descriptor(descriptor_account("d")).unwrap();
+        doc.set_chain("testnet").unwrap();
+        doc.remove_tracking_account_at(0).unwrap();
+
+        assert_eq!(*changes.borrow(), vec![
+            (DocumentChange::Tracking, 1),
+            (DocumentChange::Descriptors, 1),
+            (DocumentChange::Settings, 1),
+            (DocumentChange::Tracking, 0),
+        ]);
+    }
+
+    #[test]
+    fn operations_logged() {
+        let mut doc = Document::new();
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.add_descriptor(descriptor_account("d")).unwrap();
+        doc.set_chain("testnet").unwrap();
+        doc.set_chain(&Chain::Mainnet.to_string()).unwrap();
+        doc.remove_tracking_account_at(0).unwrap();
+        doc.remove_psbt_at(0).unwrap_err();
+
+        let operations = doc
+            .operation_log()
+            .iter()
+            .map(|entry| entry.operation.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(operations, vec![
+            Operation::TrackingAdded(s!("a")),
+            Operation::DescriptorAdded(s!("d")),
+            Operation::ChainChanged(Chain::Mainnet),
+            Operation::TrackingRemoved(s!("a")),
+        ]);
+        assert_eq!(doc.log_rows()[2].operation, "chainChanged");
+
+        let csv = doc.export_log(LogFormat::Csv).unwrap();
+        assert_eq!(csv.lines().count(), 5);
+        assert!(csv.lines().nth(4).unwrap().ends_with(
+            ",trackingRemoved,\"public key `a` removed\""
+        ));
+        let json = doc.export_log(LogFormat::Json).unwrap();
+        let entries: Vec<LogEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(entries, doc.operation_log());
+
+        let mut data = vec![];
+        doc.profile.strict_encode(&mut data).unwrap();
+        let profile = migration::decode_profile(Cursor::new(data)).unwrap();
+        assert_eq!(profile.history, doc.operation_log());
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut doc = Document::new();
+        let account = descriptor_account("d");
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.add_tracking_account(tracking_account("b")).unwrap();
+        doc.add_descriptor(account.clone()).unwrap();
+        let utxos = (0..3).map(|vout| utxo(vout, &account)).collect();
+        doc.update_utxo_set(utxos).unwrap();
+        doc.remove_tracking_account_at(0).unwrap();
+        doc.remove_utxo_by_descriptor(account.clone()).unwrap();
+        assert!(!doc.can_redo());
+
+        assert_eq!(
+            doc.undo_operation(),
+            Some(&Operation::UtxosCleared(s!("d")))
+        );
+        doc.undo().unwrap();
+        assert_eq!(doc.utxos(None).len(), 3);
+        doc.undo().unwrap();
+        assert_eq!(doc.tracking_rows()[0].name, "a");
+        assert_eq!(doc.tracking_rows()[1].name, "b");
+        assert_eq!(
+            doc.redo_operation(),
+            Some(&Operation::TrackingRemoved(s!("a")))
+        );
+
+        // UTXO lookups can't be undone, so the descriptor is the next one
+        doc.undo().unwrap();
+        assert!(doc.descriptors().is_empty());
+        doc.redo().unwrap();
+        doc.redo().unwrap();
+        assert_eq!(doc.tracking_rows().len(), 1);
+        assert_eq!(doc.tracking_rows()[0].name, "b");
+
+        // A new edit discards the undone ones
+        doc.remove_descriptor(account).unwrap();
+        assert!(!doc.can_redo());
+        assert_eq!(doc.redo(), Ok(false));
+        assert_eq!(
+            doc.operation_log().last().unwrap().operation,
+            Operation::DescriptorRemoved(s!("d"))
+        );
+        let undone = Operation::Undone(s!("public key `a` removed"));
+        assert!(doc
+            .operation_log()
+            .iter()
+            .any(|entry| entry.operation == undone));
+
+        while doc.can_undo() {
+            doc.undo().unwrap();
+        }
+        assert!(doc.tracking_rows().is_empty());
+        assert!(doc.descriptors().is_empty());
+        assert_eq!(doc.utxos(None).len(), 3);
+        assert_eq!(doc.undo(), Ok(false));
+    }
+
+    #[test]
+    fn failed_undo_is_kept() {
+        let mut doc = Document::new();
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.profile.tracking.clear();
+        assert_eq!(doc.undo(), Err(Error::WrongPosition(0)));
+        assert_eq!(
+            doc.undo_operation(),
+            Some(&Operation::TrackingAdded(s!("a")))
+        );
+
+        doc.profile.tracking.push(tracking_account("a"));
+        doc.remove_tracking_account_at(0).unwrap();
+        doc.undo().unwrap();
+        doc.profile.tracking.clear();
+        assert_eq!(doc.redo(), Err(Error::WrongPosition(0)));
+        assert_eq!(
+            doc.redo_operation(),
+            Some(&Operation::TrackingRemoved(s!("a")))
+        );
+        doc.profile.tracking.push(tracking_account("a"));
+        doc.redo().unwrap();
+        assert!(doc.tracking_accounts().is_empty());
+    }
+
+    #[test]
+    fn composed_psbt_undo_releases_change() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        doc.add_descriptor(account.clone()).unwrap();
+        let segwit = descriptors::Category::SegWit;
+        let change = TxLink {
+            descriptor_template: account.generator.template.clone(),
+            descriptor_category: segwit,
+            derivation_index: 0,
+        };
+        doc.add_composed_psbt(empty_psbt(), Some(&change)).unwrap();
+        assert_eq!(doc.psbts().len(), 1);
+        assert!(doc.address_entry(&account, segwit, 0).is_some());
+        assert_eq!(doc.next_address_index(&account).unwrap(), 1);
+
+        doc.undo().unwrap();
+        assert!(doc.psbts().is_empty());
+        assert!(doc.address_entry(&account, segwit, 0).is_none());
+        assert_eq!(doc.next_address_index(&account).unwrap(), 0);
+
+        doc.redo().unwrap();
+        assert_eq!(doc.psbts().len(), 1);
+        assert!(doc.address_entry(&account, segwit, 0).is_some());
+    }
+
+    #[test]
+    fn encrypted_save_load() {
+        let path = std::env::temp_dir().join("bpro-test-encrypted.bpro");
+        let mut doc = Document::new();
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.set_passphrase(Some("correct horse")).unwrap();
+        doc.save_as(path.clone()).unwrap();
+        assert!(Document::is_encrypted_file(&path).unwrap());
+
+        assert_eq!(
+            Document::load(path.clone()).err(),
+            Some(Error::Encryption(EncryptionError::PassphraseRequired))
+        );
+        assert_eq!(
+            Document::load_with_passphrase(path.clone(), Some("wrong")).err(),
+            Some(Error::Encryption(EncryptionError::WrongPassphrase))
+        );
+        let mut doc =
+            Document::load_with_passphrase(path.clone(), Some("correct horse"))
+                .unwrap();
+        assert!(doc.is_encrypted());
+        assert_eq!(doc.tracking_accounts(), &[tracking_account("a")]);
+
+        doc.set_passphrase(None).unwrap();
+        assert!(!Document::is_encrypted_file(&path).unwrap());
+        assert_eq!(
+            Document::load(path.clone()).unwrap().tracking_accounts(),
+            &[tracking_account("a")]
+        );
+        for backup in Document::backups(&path) {
+            fs::remove_file(backup).unwrap();
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn passphrase_change_removes_backups() {
+        let path = std::env::temp_dir().join("bpro-test-rekey.bpro");
+        let mut doc = Document::new();
+        doc.save_as(path.clone()).unwrap();
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        let mut doc = Document::load(path.clone()).unwrap();
+        doc.add_tracking_account(tracking_account("b")).unwrap();
+        let backups = Document::backups(&path);
+        assert_eq!(backups.len(), 1);
+        assert!(!Document::is_encrypted_file(&backups[0]).unwrap());
+
+        doc.set_passphrase(Some("correct horse")).unwrap();
+        assert!(Document::is_encrypted_file(&path).unwrap());
+        assert!(Document::backups(&path).is_empty());
+        doc.add_tracking_account(tracking_account("c")).unwrap();
+        assert!(Document::backups(&path).is_empty());
+
+        let mut doc =
+            Document::load_with_passphrase(path.clone(), Some("correct horse"))
+                .unwrap();
+        doc.add_tracking_account(tracking_account("d")).unwrap();
+        let backups = Document::backups(&path);
+        assert_eq!(backups.len(), 1);
+        assert!(Document::is_encrypted_file(&backups[0]).unwrap());
+
+        for backup in backups {
+            fs::remove_file(backup).unwrap();
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_file_recovery() {
+        let path = std::env::temp_dir().join("bpro-test-recovery.bpro");
+        let mut doc = Document::new();
+        doc.save_as(path.clone()).unwrap();
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.add_tracking_account(tracking_account("b")).unwrap();
+        // Backups are made once per session
+        assert!(Document::backups(&path).is_empty());
+        for name in ["c", "d"] {
+            let mut doc = Document::load(path.clone()).unwrap();
+            doc.add_tracking_account(tracking_account(name)).unwrap();
+        }
+        assert_eq!(Document::backups(&path).len(), 2);
+
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert_eq!(Document::load(path.clone()).err(), Some(Error::Truncated));
+
+        let accounts = ["a", "b", "c"]
+            .map(tracking_account)
+            .into_iter()
+            .collect::<Vec<_>>();
+        let doc = Document::recover(path.clone(), None).unwrap();
+        assert_eq!(doc.tracking_accounts(), accounts);
+        assert_eq!(doc.name(), "bpro-test-recovery");
+
+        // Damaged backups are skipped
+        fs::write(storage::backup_path(&path, 1), b"damaged").unwrap();
+        let backups = Document::backups(&path)
+            .into_iter()
+            .map(|backup| fs::read(backup).unwrap())
+            .collect::<Vec<_>>();
+        let mut doc = Document::recover(path.clone(), None).unwrap();
+        assert_eq!(doc.tracking_accounts(), &accounts[..2]);
+        doc.save().unwrap();
+        assert_eq!(
+            Document::load(path.clone()).unwrap().tracking_accounts(),
+            &accounts[..2]
+        );
+        // Saving the recovered document keeps the backups as they were
+        assert_eq!(
+            Document::backups(&path)
+                .into_iter()
+                .map(|backup| fs::read(backup).unwrap())
+                .collect::<Vec<_>>(),
+            backups
+        );
+
+        for backup in Document::backups(&path) {
+            fs::remove_file(backup).unwrap();
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_file_recovery() {
+        let path = std::env::temp_dir().join("bpro-test-enc-recovery.bpro");
+        let mut doc = Document::new();
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.save_as(path.clone()).unwrap();
+        let plain = fs::read(&path).unwrap();
+        doc.set_passphrase(Some("correct horse")).unwrap();
+        let encrypted = fs::read(&path).unwrap();
+        doc.add_tracking_account(tracking_account("b")).unwrap();
+        // Plain-text backup is more recent than the encrypted one
+        fs::write(storage::backup_path(&path, 1), plain).unwrap();
+        fs::write(storage::backup_path(&path, 2), encrypted).unwrap();
+
+        let err = Document::load_with_passphrase(path.clone(), Some("wrong"))
+            .unwrap_err();
+        assert!(!err.is_damaged_file());
+
+        // Damaged encrypted file can't be told from a wrong passphrase
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let err =
+            Document::load_with_passphrase(path.clone(), Some("correct horse"))
+                .unwrap_err();
+        assert_eq!(err, Error::Encryption(EncryptionError::WrongPassphrase));
+        assert!(!err.is_damaged_file());
+        assert!(Error::Truncated.is_damaged_file());
+
+        let doc =
+            Document::recover(path.clone(), Some("correct horse")).unwrap();
+        assert!(doc.is_encrypted());
+        assert_eq!(doc.tracking_accounts(), &[tracking_account("a")]);
+
+        for backup in Document::backups(&path) {
+            fs::remove_file(backup).unwrap();
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let mut doc = Document::new();
+        let account = descriptor_account("a");
+        doc.add_tracking_account(tracking_account("a")).unwrap();
+        doc.add_descriptor(account.clone()).unwrap();
+        doc.profile.utxo_cache.insert(utxo(0, &account));
+        doc.profile.utxo_cache.insert(utxo(1, &account));
+        doc.profile.description = Some(s!("Test document"));
+
+        for format in [Format::Json, Format::Yaml] {
+            let data = doc.export(format).unwrap();
+            let imported = Document::import(&data, format).unwrap();
+            assert_eq!(imported.profile, doc.profile);
+            assert_eq!(imported.export(format).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn core_connection_strings() {
+        let addr = "127.0.0.1:18332".parse().unwrap();
+        let cookie = CoreConnection {
+            addr,
+            auth: CoreAuth::Cookie(None),
+        };
+        let user_pass = CoreConnection {
+            addr,
+            auth: CoreAuth::UserPass(s!("user"), s!("p@ss:word")),
+        };
+        assert_eq!(
+            CoreConnection::from_str("127.0.0.1:18332"),
+            Ok(cookie.clone())
+        );
+        assert_eq!(
+            CoreConnection::from_str("user:p@ss:word@127.0.0.1:18332"),
+            Ok(user_pass.clone())
+        );
+        assert_eq!(cookie.to_string(), "127.0.0.1:18332");
+        assert_eq!(user_pass.to_string(), "user:p@ss:word@127.0.0.1:18332");
+        assert!(CoreConnection::from_str("user@localhost").is_err());
+
+        let mut doc = Document::new();
+        assert_eq!(doc.bitcoin_core(), None);
+        doc.set_bitcoin_core(user_pass.clone()).unwrap();
+        assert_eq!(doc.bitcoin_core(), Some(user_pass));
+        assert_eq!(doc.electrum(), None);
+    }
+
+    #[test]
+    fn transaction_inputs_outputs_and_fee() {
+        use bitcoin::{TxIn, Witness};
+
+        let account = descriptor_account("a");
+        let link = TxLink::from(&utxo(0, &account));
+        let script_pubkey = link.script_pubkey(&account).unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: script_pubkey.clone(),
+            }],
+        };
+        let spending = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(funding.txid(), 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFD,
+                witness: Witness::default(),
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new_op_return(&[]),
+            }],
+        };
+        let entry = TxEntry {
+            height: 0,
+            links: set![link],
+        };
+
+        let mut doc = Document::new();
+        doc.add_descriptor(account.clone()).unwrap();
+        doc.update_tx_history(
+            vec![funding.clone(), spending.clone()],
+            bmap! { funding.txid() => entry.clone(), spending.txid() => entry },
+        )
+        .unwrap();
+
+        assert_eq!(doc.tx_fee(&spending), Some(1_000));
+        assert_eq!(doc.tx_fee(&funding), None);
+        assert_eq!(
+            doc.spending_txid(OutPoint::new(funding.txid(), 0)),
+            Some(spending.txid())
+        );
+
+        let inputs = doc.tx_input_rows(&spending);
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].sequence, 0xFFFFFFFD);
+        assert_eq!(inputs[0].descriptor, Some(account.name()));
+
+        let outputs = doc.tx_output_rows(&funding);
+        assert_eq!(outputs[0].value, 10_000);
+        assert!(outputs[0].spent);
+        assert_eq!(outputs[0].descriptor, Some(account.name()));
+        assert_eq!(doc.tx_output_rows(&spending)[0].descriptor, None);
+        assert_eq!(doc.transaction_rows().len(), 2);
+    }
+
+    #[test]
+    fn psbts_add_update_remove() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let mut doc = Document::new();
+        assert_eq!(doc.add_psbt(psbt.clone()), Ok(false));
+        assert_eq!(doc.psbt_rows()[0].inputs, 0);
+
+        psbt.unsigned_tx.lock_time = 1;
+        doc.update_psbt_at(0, psbt.clone()).unwrap();
+        assert_eq!(doc.psbt_at(0), Some(&psbt));
+        assert_eq!(doc.psbt_rows()[0].txid, psbt.unsigned_tx.txid());
+
+        assert_eq!(doc.update_psbt_at(1, psbt), Err(Error::WrongPosition(1)));
+        doc.remove_psbt_at(0).unwrap();
+        assert!(doc.psbts().is_empty());
+    }
+
+    #[test]
+    fn broadcast_recorded() {
+        let mut doc = Document::new();
+        let account = descriptor_account("savings");
+        let utxos = (0..3).map(|vout| utxo(vout, &account)).collect();
+        doc.update_utxo_set(utxos).unwrap();
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: utxo(1, &account).outpoint,
+                ..TxIn::default()
+            }],
+            output: vec![],
+        };
+        doc.record_broadcast(tx.clone()).unwrap();
+        assert_eq!(doc.transaction(tx.txid()), Some(&tx));
+        assert_eq!(doc.transaction_height(tx.txid()), Some(0));
+        assert_eq!(doc.utxos(None).len(), 2);
+        assert_eq!(doc.utxo_by_outpoint(utxo(1, &account).outpoint), None);
+        let rows = doc.history_rows(&account);
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].txid, rows[0].height), (tx.txid(), 0));
+    }
+
+    #[test]
+    fn failed_modification_does_not_notify() {
+        let notified = Rc::new(RefCell::new(false));
+        let mut doc = Document::new();
+        let flag = notified.clone();
+        doc.subscribe(move |_, _| *flag.borrow_mut() = true);
+
+        assert!(doc.remove_tracking_account_at(0).is_err());
+        assert!(!*notified.borrow());
+    }
 }
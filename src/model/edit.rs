@@ -0,0 +1,211 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Reversible document modifications, used to undo and redo user edits
+
+use std::mem;
+
+use rgb::{Consignment, ContractId};
+use wallet::psbt::Psbt;
+
+use super::{
+    AddressEntry, DescriptorAccount, DocumentChange, Error, Profile,
+    TrackingAccount, UtxoEntry,
+};
+
+/// Maximal number of edits which may be undone
+pub const MAX_UNDO_LEVELS: usize = 100;
+
+/// Modification of a list of the document items
+#[derive(Clone, PartialEq, Debug)]
+pub enum ListEdit<T> {
+    /// Inserts item at the given position
+    Insert(usize, T),
+
+    /// Replaces item at the given position
+    Replace(usize, T),
+
+    /// Removes item at the given position
+    Remove(usize),
+}
+
+impl<T> ListEdit<T> {
+    /// Applies modification to the list, returning the modification which
+    /// reverts it
+    pub fn apply(self, list: &mut Vec<T>) -> Result<ListEdit<T>, Error> {
+        match self {
+            ListEdit::Insert(pos, item) if pos <= list.len() => {
+                list.insert(pos, item);
+                Ok(ListEdit::Remove(pos))
+            }
+            ListEdit::Replace(pos, item) if pos < list.len() => {
+                let prev = mem::replace(&mut list[pos], item);
+                Ok(ListEdit::Replace(pos, prev))
+            }
+            ListEdit::Remove(pos) if pos < list.len() => {
+                Ok(ListEdit::Insert(pos, list.remove(pos)))
+            }
+            ListEdit::Insert(pos, _)
+            | ListEdit::Replace(pos, _)
+            | ListEdit::Remove(pos) => Err(Error::WrongPosition(pos)),
+        }
+    }
+}
+
+/// Document modification which can be reverted
+#[derive(Clone, PartialEq, Debug)]
+pub enum Edit {
+    Tracking(ListEdit<TrackingAccount>),
+    Descriptors(ListEdit<DescriptorAccount>),
+    AddressBook(ListEdit<AddressEntry>),
+    Psbts(ListEdit<Psbt>),
+    InsertUtxos(Vec<UtxoEntry>),
+    RemoveUtxos(Vec<UtxoEntry>),
+    InsertAsset(Box<Consignment>),
+    RemoveAsset(ContractId),
+    /// Edits applied in the given order and undone together, as a single
+    /// user edit
+    Compound(Vec<Edit>),
+}
+
+impl Edit {
+    /// Parts of the document modified by the edit
+    pub fn changes(&self) -> Vec<DocumentChange> {
+        match self {
+            Edit::Tracking(_) => vec![DocumentChange::Tracking],
+            Edit::Descriptors(_) => vec![DocumentChange::Descriptors],
+            Edit::AddressBook(_) => vec![DocumentChange::Addresses],
+            Edit::Psbts(_) => vec![DocumentChange::Psbts],
+            Edit::InsertUtxos(_) | Edit::RemoveUtxos(_) => {
+                vec![DocumentChange::Utxos]
+            }
+            Edit::InsertAsset(_) | Edit::RemoveAsset(_) => {
+                vec![DocumentChange::Assets]
+            }
+            Edit::Compound(edits) => {
+                let mut changes = vec![];
+                for change in edits.iter().flat_map(Edit::changes) {
+                    if !changes.contains(&change) {
+                        changes.push(change);
+                    }
+                }
+                changes
+            }
+        }
+    }
+
+    /// Applies the edit to the document profile, returning the edit which
+    /// reverts it. Profile is left unmodified if the edit fails.
+    pub fn apply(self, profile: &mut Profile) -> Result<Edit, Error> {
+        Ok(match self {
+            Edit::Tracking(edit) => {
+                Edit::Tracking(edit.apply(&mut profile.tracking)?)
+            }
+            Edit::Descriptors(edit) => {
+                Edit::Descriptors(edit.apply(&mut profile.descriptors)?)
+            }
+            Edit::AddressBook(edit) => {
+                Edit::AddressBook(edit.apply(&mut profile.address_book)?)
+            }
+            Edit::Psbts(edit) => Edit::Psbts(edit.apply(&mut profile.psbts)?),
+            Edit::InsertUtxos(utxos) => Edit::RemoveUtxos(
+                utxos
+                    .into_iter()
+                    .filter(|utxo| profile.utxo_cache.insert(utxo.clone()))
+                    .collect(),
+            ),
+            Edit::RemoveUtxos(utxos) => Edit::InsertUtxos(
+                utxos
+                    .into_iter()
+                    .filter(|utxo| profile.utxo_cache.remove(utxo))
+                    .collect(),
+            ),
+            Edit::InsertAsset(consignment) => {
+                let contract_id = consignment.genesis.contract_id();
+                if profile.assets.contains_key(&contract_id) {
+                    return Err(Error::DuplicatedContract(contract_id));
+                }
+                profile.assets.insert(contract_id, *consignment);
+                Edit::RemoveAsset(contract_id)
+            }
+            Edit::RemoveAsset(contract_id) => Edit::InsertAsset(Box::new(
+                profile
+                    .assets
+                    .remove(&contract_id)
+                    .ok_or(Error::UnknownContract(contract_id))?,
+            )),
+            Edit::Compound(edits) => {
+                let mut undo = Vec::with_capacity(edits.len());
+                for edit in edits {
+                    match edit.apply(profile) {
+                        Ok(revert) => undo.push(revert),
+                        Err(err) => {
+                            for revert in undo.into_iter().rev() {
+                                revert.apply(profile).expect(
+                                    "reverting just applied edit never fails",
+                                );
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                undo.reverse();
+                Edit::Compound(undo)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn list_edits_revert() {
+        let mut list = vec![1, 2, 3];
+
+        let undo = ListEdit::Insert(1, 4).apply(&mut list).unwrap();
+        assert_eq!(list, vec![1, 4, 2, 3]);
+        let redo = undo.apply(&mut list).unwrap();
+        assert_eq!(list, vec![1, 2, 3]);
+        assert_eq!(redo, ListEdit::Insert(1, 4));
+
+        let undo = ListEdit::Replace(2, 5).apply(&mut list).unwrap();
+        assert_eq!(undo, ListEdit::Replace(2, 3));
+        undo.apply(&mut list).unwrap();
+        assert_eq!(list, vec![1, 2, 3]);
+
+        let undo = ListEdit::Remove(0).apply(&mut list).unwrap();
+        assert_eq!(list, vec![2, 3]);
+        undo.apply(&mut list).unwrap();
+        assert_eq!(list, vec![1, 2, 3]);
+
+        assert_eq!(
+            ListEdit::Insert(4, 0).apply(&mut list),
+            Err(Error::WrongPosition(4))
+        );
+        assert_eq!(
+            ListEdit::Replace(3, 0).apply(&mut list),
+            Err(Error::WrongPosition(3))
+        );
+        assert_eq!(
+            ListEdit::<u8>::Remove(3).apply(&mut vec![]),
+            Err(Error::WrongPosition(3))
+        );
+        assert_eq!(list, vec![1, 2, 3]);
+    }
+}
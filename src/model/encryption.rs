@@ -0,0 +1,208 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! At-rest encryption of the documents. Encrypted document consists of the
+//! plain-text header, containing [`ENC_MAGIC`], encryption scheme id, salt
+//! for the passphrase key derivation and nonce, followed by the
+//! strict-encoded [`super::Profile`] encrypted with ChaCha20-Poly1305. The
+//! header is authenticated as an associated data.
+
+use argon2::Argon2;
+use bitcoin::secp256k1::rand::{thread_rng, RngCore};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// Equals to first 4 bytes of SHA256("pandoracore:bpro:encrypted")
+/// = d51d53a9f308ae32e87c4eb22117cb7e2045a98c2dd0c9bc848b57da8776aabb
+/// Check with `echo -n "pandoracore:bpro:encrypted" | shasum -a 256`
+pub const ENC_MAGIC: u32 = 0xd51d53a9;
+
+/// Argon2id key derivation with ChaCha20-Poly1305 authenticated encryption
+pub const SCHEME_ARGON2_CHACHA20POLY1305: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = 4 + 1 + SALT_LEN + NONCE_LEN;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+/// Errors happening during document encryption and decryption
+pub enum EncryptionError {
+    /// The document is encrypted; passphrase is required to open it
+    PassphraseRequired,
+
+    /// Wrong passphrase or the encrypted document is corrupted
+    WrongPassphrase,
+
+    /// Encrypted document header is truncated
+    TruncatedHeader,
+
+    /// Document is encrypted with unknown scheme {0}
+    UnknownScheme(u8),
+
+    /// Unable to derive encryption key from the passphrase
+    KeyDerivation,
+}
+
+/// Encryption key derived from a passphrase, together with the salt used
+/// in derivation, so the document can be re-encrypted on each save without
+/// keeping the passphrase itself in memory
+#[derive(Clone, PartialEq, Eq)]
+pub struct DocumentKey {
+    salt: [u8; SALT_LEN],
+    key: [u8; 32],
+}
+
+impl DocumentKey {
+    /// Derives key from the passphrase using new random salt
+    pub fn generate(passphrase: &str) -> Result<Self, EncryptionError> {
+        let mut salt = [0u8; SALT_LEN];
+        thread_rng().fill_bytes(&mut salt);
+        Self::derive(passphrase, salt)
+    }
+
+    pub fn derive(
+        passphrase: &str,
+        salt: [u8; SALT_LEN],
+    ) -> Result<Self, EncryptionError> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|_| EncryptionError::KeyDerivation)?;
+        Ok(DocumentKey { salt, key })
+    }
+
+    /// Encrypts serialized profile, producing the full content of the
+    /// encrypted document file
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+
+        let mut data = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        data.extend(ENC_MAGIC.to_le_bytes());
+        data.push(SCHEME_ARGON2_CHACHA20POLY1305);
+        data.extend(self.salt);
+        data.extend(nonce);
+
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.key));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload {
+                msg: plaintext,
+                aad: &data,
+            })
+            .expect("ChaCha20-Poly1305 encryption of in-memory data");
+        data.extend(ciphertext);
+        data
+    }
+}
+
+impl std::fmt::Debug for DocumentKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DocumentKey(..)")
+    }
+}
+
+/// Detects whether the document file content is encrypted
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.len() >= 4 && data[..4] == ENC_MAGIC.to_le_bytes()
+}
+
+/// Decrypts document file content, returning serialized profile and the
+/// key which should be used for the further document saves
+pub fn decrypt(
+    data: &[u8],
+    passphrase: Option<&str>,
+) -> Result<(Vec<u8>, DocumentKey), EncryptionError> {
+    if data.len() < HEADER_LEN {
+        return Err(EncryptionError::TruncatedHeader);
+    }
+    let (header, ciphertext) = data.split_at(HEADER_LEN);
+    let scheme = header[4];
+    if scheme != SCHEME_ARGON2_CHACHA20POLY1305 {
+        return Err(EncryptionError::UnknownScheme(scheme));
+    }
+    let passphrase = passphrase.ok_or(EncryptionError::PassphraseRequired)?;
+
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&header[5..5 + SALT_LEN]);
+    let nonce = &header[5 + SALT_LEN..];
+
+    let key = DocumentKey::derive(passphrase, salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key.key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload {
+            msg: ciphertext,
+            aad: header,
+        })
+        .map_err(|_| EncryptionError::WrongPassphrase)?;
+    Ok((plaintext, key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt() {
+        let key = DocumentKey::generate("correct horse").unwrap();
+        let data = key.encrypt(b"profile data");
+        assert!(is_encrypted(&data));
+        assert!(!is_encrypted(b"profile data"));
+
+        let (plaintext, decrypted_key) =
+            decrypt(&data, Some("correct horse")).unwrap();
+        assert_eq!(plaintext, b"profile data");
+        assert_eq!(decrypted_key, key);
+    }
+
+    #[test]
+    fn nonce_is_unique() {
+        let key = DocumentKey::generate("correct horse").unwrap();
+        assert_ne!(key.encrypt(b"profile data"), key.encrypt(b"profile data"));
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let data = DocumentKey::generate("correct horse")
+            .unwrap()
+            .encrypt(b"profile data");
+        assert_eq!(
+            decrypt(&data, Some("battery staple")),
+            Err(EncryptionError::WrongPassphrase)
+        );
+        assert_eq!(
+            decrypt(&data, None),
+            Err(EncryptionError::PassphraseRequired)
+        );
+    }
+
+    #[test]
+    fn tampered_header() {
+        let mut data = DocumentKey::generate("correct horse")
+            .unwrap()
+            .encrypt(b"profile data");
+        data[10] ^= 0x01;
+        assert_eq!(
+            decrypt(&data, Some("correct horse")),
+            Err(EncryptionError::WrongPassphrase)
+        );
+        assert_eq!(
+            decrypt(&data[..20], Some("correct horse")),
+            Err(EncryptionError::TruncatedHeader)
+        );
+    }
+}
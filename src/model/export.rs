@@ -0,0 +1,222 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Human-readable representation of the whole [`Profile`] in JSON or YAML,
+//! suitable for diffing documents under version control, reviewing and
+//! hand-editing them. Keys, descriptors and settings are written in their
+//! string form; binary data without readable representation (PSBTs, RGB
+//! schemata and consignments) is written as hex of its strict encoding,
+//! which keeps the round trip back to the `.bpro` file lossless.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use serde_crate::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_with::{DeserializeAs, SerializeAs};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::migration::{self, Header};
+use super::{Error, Profile, UtxoEntry};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+/// Human-readable document formats
+pub enum Format {
+    #[display("json")]
+    Json,
+
+    #[display("yaml")]
+    Yaml,
+}
+
+impl Format {
+    /// Detects format from the file extension
+    pub fn with_path(path: &Path) -> Option<Format> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, Default, Error)]
+#[display(doc_comments)]
+/// Unknown export format; possible values are `json` and `yaml`
+pub struct FormatParseError;
+
+impl FromStr for Format {
+    type Err = FormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => Err(FormatParseError),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+/// Errors converting profile to and from human-readable formats
+pub enum ExportError {
+    /// JSON serialization error: {0}
+    Json(String),
+
+    /// YAML serialization error: {0}
+    Yaml(String),
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(err: serde_json::Error) -> Self {
+        ExportError::Json(err.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for ExportError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ExportError::Yaml(err.to_string())
+    }
+}
+
+/// Serializes profile into the given human-readable format
+pub fn export_profile(
+    profile: &Profile,
+    format: Format,
+) -> Result<String, Error> {
+    Ok(match format {
+        Format::Json => serde_json::to_string_pretty(profile)
+            .map_err(ExportError::from)?,
+        Format::Yaml => {
+            serde_yaml::to_string(profile).map_err(ExportError::from)?
+        }
+    })
+}
+
+/// Deserializes profile from the given human-readable format, validating
+/// its header and upgrading it to the current profile version
+pub fn import_profile(data: &str, format: Format) -> Result<Profile, Error> {
+    let profile: Profile = match format {
+        Format::Json => {
+            serde_json::from_str(data).map_err(ExportError::from)?
+        }
+        Format::Yaml => {
+            serde_yaml::from_str(data).map_err(ExportError::from)?
+        }
+    };
+    let version = profile.version;
+    Header {
+        magic: profile.magic,
+        version,
+    }
+    .validate()?;
+    Ok(migration::upgrade(profile, version))
+}
+
+/// Serializes strict-encodable data as a hex string
+pub struct StrictHex;
+
+impl<T> SerializeAs<T> for StrictHex
+where
+    T: StrictEncode,
+{
+    fn serialize_as<S>(source: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let data = source
+            .strict_serialize()
+            .map_err(serde_crate::ser::Error::custom)?;
+        serializer.serialize_str(&data.to_hex())
+    }
+}
+
+impl<'de, T> DeserializeAs<'de, T> for StrictHex
+where
+    T: StrictDecode,
+{
+    fn deserialize_as<D>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let data = Vec::<u8>::from_hex(&s).map_err(de::Error::custom)?;
+        T::strict_deserialize(data).map_err(de::Error::custom)
+    }
+}
+
+/// Serializes UTXO set as a sorted list, so the output is stable across
+/// exports
+pub(super) fn serialize_utxos<S>(
+    utxos: &HashSet<UtxoEntry>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut utxos = utxos.iter().collect::<Vec<_>>();
+    utxos.sort();
+    utxos.serialize(serializer)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use super::super::migration::{self, FormatError};
+    use super::*;
+
+    fn example(name: &str) -> Profile {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("examples");
+        path.push(name);
+        let data = fs::read(path).unwrap();
+        migration::decode_profile(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn format_detection() {
+        assert_eq!(Format::with_path(Path::new("a.json")), Some(Format::Json));
+        assert_eq!(Format::with_path(Path::new("a.YML")), Some(Format::Yaml));
+        assert_eq!(Format::with_path(Path::new("a.bpro")), None);
+        assert_eq!(Format::with_path(Path::new("a")), None);
+    }
+
+    #[test]
+    fn example_round_trip() {
+        for name in ["BIP32 test vectors.bpro", "RGB tests.bpro"] {
+            let profile = example(name);
+            for format in [Format::Json, Format::Yaml] {
+                let data = export_profile(&profile, format).unwrap();
+                assert_eq!(import_profile(&data, format).unwrap(), profile);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_magic() {
+        let profile = Profile {
+            magic: 0xdeadbeef,
+            ..Profile::default()
+        };
+        let data = export_profile(&profile, Format::Json).unwrap();
+        assert_eq!(
+            import_profile(&data, Format::Json),
+            Err(Error::Format(FormatError::WrongMagic(0xdeadbeef)))
+        );
+    }
+}
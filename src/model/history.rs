@@ -0,0 +1,116 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeSet;
+
+use bitcoin::Script;
+use wallet::descriptors;
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+use super::{DescriptorAccount, UtxoEntry};
+
+/// Descriptor script touched by a transaction (either being paid or spent)
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Display,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+#[display("{descriptor_category}({descriptor_template})/{derivation_index}")]
+pub struct TxLink {
+    pub descriptor_template: descriptors::Template,
+    pub descriptor_category: descriptors::Category,
+    pub derivation_index: u32,
+}
+
+impl From<&UtxoEntry> for TxLink {
+    fn from(utxo: &UtxoEntry) -> Self {
+        TxLink {
+            descriptor_template: utxo.descriptor_template.clone(),
+            descriptor_category: utxo.descriptor_category,
+            derivation_index: utxo.derivation_index,
+        }
+    }
+}
+
+impl TxLink {
+    pub fn has_match(&self, descriptor_account: &DescriptorAccount) -> bool {
+        descriptor_account.generator.template == self.descriptor_template
+            && descriptor_account
+                .generator
+                .variants
+                .has_match(self.descriptor_category)
+    }
+
+    /// Script pubkey which is linked, as generated by the matching
+    /// descriptor account
+    pub fn script_pubkey(
+        &self,
+        descriptor_account: &DescriptorAccount,
+    ) -> Option<Script> {
+        let index = UnhardenedIndex::from_index(self.derivation_index).ok()?;
+        descriptor_account
+            .pubkey_scripts(index)
+            .ok()?
+            .remove(&self.descriptor_category)
+    }
+}
+
+/// Transaction from the history of the document descriptors. Transaction
+/// data itself are kept in the document transaction cache.
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Default,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct TxEntry {
+    /// Height of the block containing the transaction; zero for the
+    /// transactions which are not mined yet
+    pub height: u32,
+    /// Descriptor scripts touched by the transaction
+    pub links: BTreeSet<TxLink>,
+}
+
+impl TxEntry {
+    pub fn has_match(&self, descriptor_account: &DescriptorAccount) -> bool {
+        self.links
+            .iter()
+            .any(|link| link.has_match(descriptor_account))
+    }
+
+    /// Merges information from a more recent lookup
+    pub fn update(&mut self, other: TxEntry) {
+        self.height = other.height;
+        self.links.extend(other.links);
+    }
+}
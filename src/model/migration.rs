@@ -0,0 +1,475 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Versioning of the document file format. Each document starts with
+//! [`Header`] (which is the beginning of the strict-encoded [`Profile`]),
+//! which is validated before decoding the rest of the document. Documents
+//! created by older versions of the application are decoded using the
+//! layout of their version and then upgraded step by step with
+//! [`MIGRATIONS`] until they match [`PROFILE_VERSION`].
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use strict_encoding::StrictDecode;
+
+use super::{document::Error, Profile};
+
+/// Equals to first 4 bytes of SHA256("pandoracore:bpro")
+/// = dbe2b664ee4e81d3a55d53aeba1915c468927c79a03587ddfc5c3aec483028ab
+/// Check with `echo -n "pandoracore:bpro" | shasum -a 256`
+pub const DOC_MAGIC: u32 = 0xdbe2b664;
+
+/// Version of the [`Profile`] layout produced by this application
+pub const PROFILE_VERSION: u16 = 1;
+
+/// Upgrade of a profile from version `N` (which is the index in
+/// [`MIGRATIONS`]) to `N + 1`
+pub type Migration = fn(Profile) -> Profile;
+
+/// Chain of migrations; item at index `N` upgrades profile from version `N`
+pub const MIGRATIONS: [Migration; PROFILE_VERSION as usize] = [v0_to_v1];
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+/// Errors in the document header
+pub enum FormatError {
+    /// The file is not a Bitcoin Pro document (magic number {0:#010x} does
+    /// not match)
+    WrongMagic(u32),
+
+    /// The document has format version {0}, which is newer than the one
+    /// supported by this version of Bitcoin Pro; please update the
+    /// application
+    UnsupportedVersion(u16),
+}
+
+/// Document header, matching first fields of the strict-encoded [`Profile`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+pub struct Header {
+    pub magic: u32,
+    pub version: u16,
+}
+
+impl Header {
+    pub fn validate(self) -> Result<(), FormatError> {
+        match self {
+            Header { version, .. } if version > PROFILE_VERSION => {
+                Err(FormatError::UnsupportedVersion(version))
+            }
+            // Version 0 documents were written before magic number was
+            // enforced and may contain zero instead of it
+            Header { magic: 0, version: 0 } => Ok(()),
+            Header { magic, .. } if magic != DOC_MAGIC => {
+                Err(FormatError::WrongMagic(magic))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads profile from a document of any supported version, validating its
+/// header and upgrading it to [`PROFILE_VERSION`]
+pub fn decode_profile(mut reader: impl Read + Seek) -> Result<Profile, Error> {
+    let start = reader.stream_position()?;
+    let header = Header::strict_decode(&mut reader).map_err(truncated)?;
+    header.validate()?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    let profile = match header.version {
+        // Previous versions are decoded with their own data types and
+        // converted into the latest one before applying the rest of
+        // migrations.
+        0 => v0::Profile::strict_decode(&mut reader)
+            .map_err(truncated)?
+            .into(),
+        1 => Profile::strict_decode(&mut reader).map_err(truncated)?,
+        _ => unreachable!("header version is validated above"),
+    };
+    Ok(upgrade(profile, header.version))
+}
+
+/// Applies migrations to the profile decoded from the given `version`
+pub fn upgrade(mut profile: Profile, version: u16) -> Profile {
+    for migration in &MIGRATIONS[version as usize..] {
+        profile = migration(profile);
+    }
+    profile
+}
+
+/// Distinguishes data which ended prematurely (as happens with files
+/// partially written before a crash) from other decoding errors
+fn truncated(err: strict_encoding::Error) -> Error {
+    match err {
+        strict_encoding::Error::Io(io::ErrorKind::UnexpectedEof) => {
+            Error::Truncated
+        }
+        err => Error::DataEncoding(err),
+    }
+}
+
+/// Version 0 documents did not validate magic number; upgrading sets it.
+/// Data added in version 1 are filled with defaults during decoding of the
+/// version 0 layout:
+/// - Bitcoin Core RPC authentication uses the node cookie file;
+/// - last used derivation indexes and key origins of the descriptor accounts
+///   remain unknown until the next UTXO lookup or descriptor import;
+/// - taproot outputs of the descriptor accounts have no script tree;
+/// - transaction history, operation log and address book are empty.
+fn v0_to_v1(mut profile: Profile) -> Profile {
+    profile.magic = DOC_MAGIC;
+    profile.version = 1;
+    profile
+}
+
+/// Document layout used by version 0. The types are frozen copies of the
+/// ones written by the application at that time and must not follow the
+/// changes of the current types.
+mod v0 {
+    use std::collections::{BTreeMap, HashSet};
+    use std::net::SocketAddr;
+
+    use bitcoin::{OutPoint, Transaction};
+    use lnpbp::chain::Chain;
+    use rgb::{Consignment, ContractId, Schema, SchemaId};
+    use wallet::descriptors;
+    use wallet::psbt::Psbt;
+
+    #[derive(StrictEncode, StrictDecode)]
+    pub struct Profile {
+        pub magic: u32,
+        pub version: u16,
+        pub description: Option<String>,
+        pub tracking: Vec<TrackingAccount>,
+        pub descriptors: Vec<DescriptorAccount>,
+        pub utxo_cache: HashSet<UtxoEntry>,
+        pub tx_cache: Vec<Transaction>,
+        pub psbts: Vec<Psbt>,
+        pub schemata: BTreeMap<SchemaId, Schema>,
+        pub assets: BTreeMap<ContractId, Consignment>,
+        pub nfts: BTreeMap<ContractId, Consignment>,
+        pub identities: BTreeMap<ContractId, Consignment>,
+        pub auditlogs: BTreeMap<ContractId, Consignment>,
+        pub contracts: BTreeMap<ContractId, Consignment>,
+        pub history: Vec<LogEntry>,
+        pub settings: Settings,
+    }
+
+    #[derive(StrictEncode, StrictDecode)]
+    pub struct TrackingAccount {
+        pub name: String,
+        pub key: descriptors::SingleSig,
+    }
+
+    #[derive(StrictEncode, StrictDecode)]
+    pub struct DescriptorAccount {
+        pub name: String,
+        pub generator: descriptors::Generator,
+    }
+
+    #[derive(
+        PartialEq, Eq, PartialOrd, Ord, Hash, Debug, StrictEncode, StrictDecode,
+    )]
+    pub struct UtxoEntry {
+        pub outpoint: OutPoint,
+        pub height: u32,
+        pub amount: u64,
+        pub descriptor_template: descriptors::Template,
+        pub descriptor_category: descriptors::Category,
+        pub derivation_index: u32,
+    }
+
+    /// Operation log entry, which had no data in version 0
+    #[derive(StrictEncode, StrictDecode)]
+    pub struct LogEntry {}
+
+    #[derive(StrictEncode, StrictDecode)]
+    pub struct Settings {
+        pub chain: Chain,
+        pub resolver: ChainResolver,
+        pub bifrost: Option<SocketAddr>,
+    }
+
+    #[derive(StrictEncode, StrictDecode)]
+    pub enum ChainResolver {
+        BitcoinCore(SocketAddr),
+        Electrum(SocketAddr),
+        BpNode(SocketAddr),
+    }
+
+    impl From<TrackingAccount> for super::super::TrackingAccount {
+        fn from(v0: TrackingAccount) -> Self {
+            super::super::TrackingAccount {
+                name: v0.name,
+                key: v0.key,
+            }
+        }
+    }
+
+    impl From<DescriptorAccount> for super::super::DescriptorAccount {
+        fn from(v0: DescriptorAccount) -> Self {
+            super::super::DescriptorAccount {
+                name: v0.name,
+                generator: v0.generator,
+                last_used_indexes: empty!(),
+                key_origins: empty!(),
+                tap_leaves: empty!(),
+            }
+        }
+    }
+
+    impl From<UtxoEntry> for super::super::UtxoEntry {
+        fn from(v0: UtxoEntry) -> Self {
+            super::super::UtxoEntry {
+                outpoint: v0.outpoint,
+                height: v0.height,
+                amount: v0.amount,
+                descriptor_template: v0.descriptor_template,
+                descriptor_category: v0.descriptor_category,
+                derivation_index: v0.derivation_index,
+            }
+        }
+    }
+
+    impl From<ChainResolver> for super::super::ChainResolver {
+        fn from(v0: ChainResolver) -> Self {
+            match v0 {
+                ChainResolver::BitcoinCore(addr) => {
+                    super::super::ChainResolver::BitcoinCore(addr)
+                }
+                ChainResolver::Electrum(addr) => {
+                    super::super::ChainResolver::Electrum(addr)
+                }
+                ChainResolver::BpNode(addr) => {
+                    super::super::ChainResolver::BpNode(addr)
+                }
+            }
+        }
+    }
+
+    impl From<Settings> for super::super::Settings {
+        fn from(v0: Settings) -> Self {
+            super::super::Settings {
+                chain: v0.chain,
+                resolver: v0.resolver.into(),
+                bifrost: v0.bifrost,
+                core_auth: Default::default(),
+            }
+        }
+    }
+
+    impl From<Profile> for super::Profile {
+        fn from(v0: Profile) -> Self {
+            super::Profile {
+                magic: v0.magic,
+                version: v0.version,
+                description: v0.description,
+                tracking: v0.tracking.into_iter().map(From::from).collect(),
+                descriptors: v0
+                    .descriptors
+                    .into_iter()
+                    .map(From::from)
+                    .collect(),
+                utxo_cache: v0.utxo_cache.into_iter().map(From::from).collect(),
+                tx_cache: v0.tx_cache,
+                psbts: v0.psbts,
+                schemata: v0.schemata,
+                assets: v0.assets,
+                nfts: v0.nfts,
+                identities: v0.identities,
+                auditlogs: v0.auditlogs,
+                contracts: v0.contracts,
+                // Log entries of version 0 carry no data
+                history: empty!(),
+                settings: v0.settings.into(),
+                tx_history: empty!(),
+                address_book: empty!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use std::net::SocketAddr;
+
+    use bitcoin::OutPoint;
+    use lnpbp::chain::Chain;
+    use strict_encoding::{StrictDecode, StrictEncode};
+    use wallet::descriptors::Category;
+
+    use super::super::{ChainResolver, CoreAuth, DescriptorAccount, Settings};
+    use super::*;
+
+    fn example(name: &str) -> Vec<u8> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("examples");
+        path.push(name);
+        fs::read(path).expect("shipped example must be present")
+    }
+
+    fn assert_round_trip(data: Vec<u8>) -> Profile {
+        let profile = decode_profile(Cursor::new(data)).unwrap();
+        assert_eq!(profile.magic, DOC_MAGIC);
+        assert_eq!(profile.version, PROFILE_VERSION);
+
+        let mut encoded = vec![];
+        profile.strict_encode(&mut encoded).unwrap();
+        let decoded = decode_profile(Cursor::new(&encoded)).unwrap();
+        assert_eq!(decoded, profile);
+        assert_eq!(Profile::strict_decode(&encoded[..]).unwrap(), profile);
+        profile
+    }
+
+    #[test]
+    fn header_validation() {
+        let header = |magic, version| Header { magic, version };
+        assert_eq!(header(0, 0).validate(), Ok(()));
+        assert_eq!(header(DOC_MAGIC, 0).validate(), Ok(()));
+        assert_eq!(header(DOC_MAGIC, PROFILE_VERSION).validate(), Ok(()));
+        assert_eq!(
+            header(0, PROFILE_VERSION).validate(),
+            Err(FormatError::WrongMagic(0))
+        );
+        assert_eq!(
+            header(0xdeadbeef, 0).validate(),
+            Err(FormatError::WrongMagic(0xdeadbeef))
+        );
+        assert_eq!(
+            header(DOC_MAGIC, PROFILE_VERSION + 1).validate(),
+            Err(FormatError::UnsupportedVersion(PROFILE_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_non_bpro_file() {
+        let data = b"{\"this is\": \"not a bpro document\"}".to_vec();
+        assert!(matches!(
+            decode_profile(Cursor::new(data)),
+            Err(Error::Format(FormatError::WrongMagic(_)))
+        ));
+    }
+
+    #[test]
+    fn rejects_future_version() {
+        let mut data = vec![];
+        Header {
+            magic: DOC_MAGIC,
+            version: PROFILE_VERSION + 1,
+        }
+        .strict_encode(&mut data)
+        .unwrap();
+        assert_eq!(
+            decode_profile(Cursor::new(data)),
+            Err(Error::Format(FormatError::UnsupportedVersion(
+                PROFILE_VERSION + 1
+            )))
+        );
+    }
+
+    #[test]
+    fn detects_truncated_file() {
+        let mut data = example("BIP32 test vectors.bpro");
+        data.truncate(data.len() / 2);
+        assert_eq!(decode_profile(Cursor::new(data)), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn default_profile_round_trip() {
+        let mut data = vec![];
+        Profile::default().strict_encode(&mut data).unwrap();
+        assert_eq!(assert_round_trip(data), Profile::default());
+    }
+
+    #[test]
+    fn v0_layout_upgrade() {
+        let account = DescriptorAccount::from_bip380(
+            "Savings",
+            "wpkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap()
+        .remove(0);
+        let utxo = v0::UtxoEntry {
+            outpoint: OutPoint::default(),
+            height: 700_000,
+            amount: 10_000,
+            descriptor_template: account.generator.template.clone(),
+            descriptor_category: Category::SegWit,
+            derivation_index: 5,
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], 8332));
+        let v0 = v0::Profile {
+            magic: 0,
+            version: 0,
+            description: Some(s!("Old document")),
+            tracking: vec![],
+            descriptors: vec![v0::DescriptorAccount {
+                name: account.name.clone(),
+                generator: account.generator.clone(),
+            }],
+            utxo_cache: set![utxo],
+            tx_cache: vec![],
+            psbts: vec![],
+            schemata: bmap! {},
+            assets: bmap! {},
+            nfts: bmap! {},
+            identities: bmap! {},
+            auditlogs: bmap! {},
+            contracts: bmap! {},
+            history: vec![v0::LogEntry {}],
+            settings: v0::Settings {
+                chain: Chain::Mainnet,
+                resolver: v0::ChainResolver::BitcoinCore(addr),
+                bifrost: None,
+            },
+        };
+
+        let upgraded = assert_round_trip(v0.strict_serialize().unwrap());
+        assert_eq!(upgraded.description, Some(s!("Old document")));
+        assert_eq!(upgraded.descriptors, vec![account]);
+        let utxo = upgraded.utxo_cache.into_iter().next().unwrap();
+        assert_eq!(utxo.descriptor_category, Category::SegWit);
+        assert_eq!(utxo.derivation_index, 5);
+        assert!(upgraded.history.is_empty());
+        assert_eq!(
+            upgraded.settings,
+            Settings {
+                chain: Chain::Mainnet,
+                resolver: ChainResolver::BitcoinCore(addr),
+                bifrost: None,
+                core_auth: CoreAuth::default(),
+            }
+        );
+        assert!(upgraded.tx_history.is_empty());
+        assert!(upgraded.address_book.is_empty());
+    }
+
+    #[test]
+    fn bip32_test_vectors_example() {
+        let profile = assert_round_trip(example("BIP32 test vectors.bpro"));
+        assert_eq!(profile.tracking.len(), 3);
+    }
+
+    #[test]
+    fn rgb_tests_example() {
+        let profile = assert_round_trip(example("RGB tests.bpro"));
+        assert_eq!(profile.tracking.len(), 2);
+    }
+}
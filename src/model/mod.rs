@@ -15,13 +15,38 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+mod addresses;
+pub mod bip380;
+mod change;
 mod descriptors;
 mod document;
+pub mod edit;
+pub mod encryption;
+pub mod export;
+mod history;
+pub mod migration;
+pub mod musig;
 pub mod operation;
+mod rows;
+mod script_cache;
+pub mod storage;
+pub mod taproot;
 mod tracking;
 mod utxo;
 
-pub use descriptors::DescriptorAccount;
-pub use document::{Document, Error, Profile, ResolverError};
+pub use addresses::{addresses_csv, AddressEntry};
+pub use change::{DocumentChange, Observer};
+pub use descriptors::{DerivationError, DescriptorAccount};
+pub use document::{
+    ChainResolver, CoreAuth, CoreConnection, Document, Error, Profile,
+    ResolverError, Settings,
+};
+pub use history::{TxEntry, TxLink};
+pub use operation::{LogEntry, LogFormat, Operation};
+pub use rows::{
+    AddressRow, AssetRow, DescriptorRow, HistoryRow, LogRow, PsbtRow,
+    TrackingRow, TransactionRow, TxInputRow, TxOutputRow, UtxoRow,
+};
+pub use script_cache::{ScriptCache, ScriptMatch, ScriptOwner};
 pub use tracking::TrackingAccount;
 pub use utxo::UtxoEntry;
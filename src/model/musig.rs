@@ -0,0 +1,213 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! MuSig2 public key aggregation (BIP327), used for the internal keys of
+//! taproot outputs. Only aggregation of the keys is implemented; signing
+//! sessions are run by the signers themselves.
+//!
+//! MuSig2 accounts use [`descriptors::Template::MuSigBranched`] template:
+//! `extra_keys` are the aggregated keys and `tapscript` is the n-of-n
+//! policy of the key path spending. Script path branches are kept by the
+//! descriptor account as taproot script tree leaves.
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, Verification};
+use miniscript::policy;
+use wallet::descriptors;
+
+/// Order of the secp256k1 group, big-endian
+const CURVE_ORDER: [u8; 32] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFE, 0xBA, 0xAE, 0xDC, 0xE6, 0xAF, 0x48, 0xA0, 0x3B,
+    0xBF, 0xD2, 0x5E, 0x8C, 0xD0, 0x36, 0x41, 0x41,
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+/// Errors of MuSig2 key aggregation
+pub enum Error {
+    /// No keys are given for the aggregation
+    NoKeys,
+
+    /// Aggregated key is the point at infinity
+    Infinity,
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for chunk in data {
+        engine.input(chunk);
+    }
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Reduces 256-bit big-endian integer modulo the curve order. Since the
+/// order is close to 2^256, a single subtraction is always sufficient.
+fn reduce(mut scalar: [u8; 32]) -> [u8; 32] {
+    if scalar < CURVE_ORDER {
+        return scalar;
+    }
+    let mut borrow = false;
+    for (byte, order) in scalar.iter_mut().zip(CURVE_ORDER.iter()).rev() {
+        let (diff, underflow) = byte.overflowing_sub(*order);
+        let (diff, borrowed) = diff.overflowing_sub(borrow as u8);
+        *byte = diff;
+        borrow = underflow || borrowed;
+    }
+    scalar
+}
+
+/// Sorts keys lexicographically by their compressed serialization (BIP327
+/// `KeySort`), making the aggregated key independent of the key order
+pub fn key_sort(keys: &mut [PublicKey]) {
+    keys.sort_by_key(PublicKey::serialize);
+}
+
+/// Aggregates keys in the given order (BIP327 `KeyAgg`). The x coordinate
+/// of the returned key is the x-only aggregated key.
+pub fn key_agg<C: Verification>(
+    ctx: &Secp256k1<C>,
+    keys: &[PublicKey],
+) -> Result<PublicKey, Error> {
+    let serialized = keys.iter().map(PublicKey::serialize).collect::<Vec<_>>();
+    let first = serialized.first().ok_or(Error::NoKeys)?;
+    // Keys equal to the second distinct key in the list have unit
+    // coefficient
+    let second = serialized.iter().find(|key| *key != first);
+    let list_hash = tagged_hash(
+        "KeyAgg list",
+        &serialized.iter().map(|key| &key[..]).collect::<Vec<_>>(),
+    );
+
+    let mut points = Vec::with_capacity(keys.len());
+    for (key, serialized) in keys.iter().zip(&serialized) {
+        let mut point = *key;
+        if Some(serialized) != second {
+            let coefficient = reduce(tagged_hash(
+                "KeyAgg coefficient",
+                &[&list_hash, serialized],
+            ));
+            point
+                .mul_assign(ctx, &coefficient)
+                .map_err(|_| Error::Infinity)?;
+        }
+        points.push(point);
+    }
+    PublicKey::combine_keys(&points.iter().collect::<Vec<_>>())
+        .map_err(|_| Error::Infinity)
+}
+
+/// Sorts the keys and aggregates them
+pub fn key_agg_sorted<C: Verification>(
+    ctx: &Secp256k1<C>,
+    keys: &[PublicKey],
+) -> Result<PublicKey, Error> {
+    let mut keys = keys.to_vec();
+    key_sort(&mut keys);
+    key_agg(ctx, &keys)
+}
+
+/// Constructs template of the taproot outputs with the MuSig2 aggregate of
+/// the keys, derived at the output index, as the internal key
+pub fn template(keys: Vec<descriptors::SingleSig>) -> descriptors::Template {
+    let policy = policy::Concrete::Threshold(
+        keys.len(),
+        keys.iter().cloned().map(policy::Concrete::Key).collect(),
+    );
+    descriptors::Template::MuSigBranched(descriptors::MuSigBranched {
+        extra_keys: keys,
+        tapscript: descriptors::ScriptConstruction::MiniscriptPolicy(policy),
+        source: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::secp256k1::{PublicKey, SECP256K1};
+
+    use super::*;
+
+    // Keys from BIP327 key aggregation test vectors
+    fn keys() -> [PublicKey; 3] {
+        [
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+        ]
+        .map(|key| PublicKey::from_str(key).unwrap())
+    }
+
+    fn aggregated(indexes: &[usize]) -> String {
+        let keys = keys();
+        let keys = indexes.iter().map(|i| keys[*i]).collect::<Vec<_>>();
+        key_agg(SECP256K1, &keys).unwrap().serialize()[1..].to_hex()
+    }
+
+    #[test]
+    fn bip327_key_agg_vectors() {
+        assert_eq!(
+            aggregated(&[0, 1, 2]),
+            "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c"
+        );
+        assert_eq!(
+            aggregated(&[2, 1, 0]),
+            "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b"
+        );
+        assert_eq!(
+            aggregated(&[0, 0, 0]),
+            "b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935"
+        );
+        assert_eq!(
+            aggregated(&[0, 0, 1, 1]),
+            "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e"
+        );
+        assert_eq!(key_agg(SECP256K1, &[]), Err(Error::NoKeys));
+    }
+
+    #[test]
+    fn sorted_aggregation() {
+        let [a, b, c] = keys();
+        let mut sorted = vec![a, b, c];
+        key_sort(&mut sorted);
+        assert_eq!(sorted, vec![c, a, b]);
+        assert_eq!(
+            key_agg_sorted(SECP256K1, &[a, b, c]),
+            key_agg_sorted(SECP256K1, &[b, c, a])
+        );
+        assert_eq!(
+            key_agg_sorted(SECP256K1, &[a, b, c]),
+            key_agg(SECP256K1, &sorted)
+        );
+    }
+
+    #[test]
+    fn scalar_reduction() {
+        let mut above = CURVE_ORDER;
+        above[31] += 5;
+        let mut expected = [0u8; 32];
+        expected[31] = 5;
+        assert_eq!(reduce(above), expected);
+        assert_eq!(reduce(expected), expected);
+        assert_eq!(reduce([0xFF; 32])[..15], [0u8; 15]);
+    }
+}
@@ -15,6 +15,293 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-#[derive(Clone, PartialEq, Eq, Debug, Display, StrictEncode, StrictDecode)]
-#[display("")]
-pub struct LogEntry {}
+//! Log of the operations modifying the document, kept inside the document
+//! [`Profile`](super::Profile) for the audit purposes
+
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{OutPoint, Txid};
+use lnpbp::chain::Chain;
+use rgb::ContractId;
+use serde_with::{As, DisplayFromStr};
+
+use super::ChainResolver;
+
+/// Document modification recorded into the operation log
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Display,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub enum Operation {
+    #[display("public key `{0}` added")]
+    TrackingAdded(String),
+
+    #[display("public key `{0}` updated")]
+    TrackingUpdated(String),
+
+    #[display("public key `{0}` removed")]
+    TrackingRemoved(String),
+
+    #[display("descriptor `{0}` added")]
+    DescriptorAdded(String),
+
+    #[display("descriptor `{0}` updated")]
+    DescriptorUpdated(String),
+
+    #[display("descriptor `{0}` removed")]
+    DescriptorRemoved(String),
+
+    #[display("UTXO lookup found {0} outputs")]
+    UtxosFound(u32),
+
+    #[display("UTXO refresh moved {moved} and removed {spent} spent outputs")]
+    UtxosReconciled { moved: u32, spent: u32 },
+
+    #[display("UTXO {0} removed")]
+    UtxoRemoved(OutPoint),
+
+    #[display("UTXOs of descriptor `{0}` removed")]
+    UtxosCleared(String),
+
+    #[display("{0} transactions added to the history")]
+    TransactionsUpdated(u32),
+
+    #[display("PSBT {0} added")]
+    PsbtAdded(Txid),
+
+    #[display("PSBT {txid} updated, {signed} inputs signed")]
+    PsbtUpdated { txid: Txid, signed: u32 },
+
+    #[display("PSBT {0} removed")]
+    PsbtRemoved(Txid),
+
+    #[display("transaction {0} broadcasted")]
+    TxBroadcast(Txid),
+
+    #[display("asset {0} added")]
+    AssetAdded(#[serde(with = "As::<DisplayFromStr>")] ContractId),
+
+    #[display("asset {0} removed")]
+    AssetRemoved(#[serde(with = "As::<DisplayFromStr>")] ContractId),
+
+    #[display("chain changed to {0}")]
+    ChainChanged(#[serde(with = "As::<DisplayFromStr>")] Chain),
+
+    #[display("resolver changed to {0}")]
+    ResolverChanged(ChainResolver),
+
+    #[display("document passphrase changed")]
+    PassphraseChanged,
+
+    #[display("undone: {0}")]
+    Undone(String),
+
+    #[display("redone: {0}")]
+    Redone(String),
+
+    #[display("address #{index} of descriptor `{descriptor}` handed out")]
+    AddressIssued { descriptor: String, index: u32 },
+
+    #[display("address #{index} of descriptor `{descriptor}` updated")]
+    AddressUpdated { descriptor: String, index: u32 },
+
+    #[display("address #{index} of descriptor `{descriptor}` removed")]
+    AddressRemoved { descriptor: String, index: u32 },
+}
+
+impl Operation {
+    /// Name of the operation type, used in the log exports
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::TrackingAdded(_) => "trackingAdded",
+            Operation::TrackingUpdated(_) => "trackingUpdated",
+            Operation::TrackingRemoved(_) => "trackingRemoved",
+            Operation::DescriptorAdded(_) => "descriptorAdded",
+            Operation::DescriptorUpdated(_) => "descriptorUpdated",
+            Operation::DescriptorRemoved(_) => "descriptorRemoved",
+            Operation::UtxosFound(_) => "utxosFound",
+            Operation::UtxosReconciled { .. } => "utxosReconciled",
+            Operation::UtxoRemoved(_) => "utxoRemoved",
+            Operation::UtxosCleared(_) => "utxosCleared",
+            Operation::TransactionsUpdated(_) => "transactionsUpdated",
+            Operation::PsbtAdded(_) => "psbtAdded",
+            Operation::PsbtUpdated { .. } => "psbtUpdated",
+            Operation::PsbtRemoved(_) => "psbtRemoved",
+            Operation::TxBroadcast(_) => "txBroadcast",
+            Operation::AssetAdded(_) => "assetAdded",
+            Operation::AssetRemoved(_) => "assetRemoved",
+            Operation::ChainChanged(_) => "chainChanged",
+            Operation::ResolverChanged(_) => "resolverChanged",
+            Operation::PassphraseChanged => "passphraseChanged",
+            Operation::Undone(_) => "undone",
+            Operation::Redone(_) => "redone",
+            Operation::AddressIssued { .. } => "addressIssued",
+            Operation::AddressUpdated { .. } => "addressUpdated",
+            Operation::AddressRemoved { .. } => "addressRemoved",
+        }
+    }
+}
+
+/// Operation log entry
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate")]
+pub struct LogEntry {
+    /// Time of the operation, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub operation: Operation,
+}
+
+impl LogEntry {
+    /// Creates entry for the operation happening now
+    pub fn now(operation: Operation) -> LogEntry {
+        LogEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            operation,
+        }
+    }
+
+    /// Time of the operation in the ISO 8601 form
+    pub fn time(&self) -> String {
+        format_time(self.timestamp)
+    }
+}
+
+impl Display for LogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.time(), self.operation)
+    }
+}
+
+/// Formats Unix timestamp as UTC time in ISO 8601 form
+pub fn format_time(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+    // Conversion of the days since the epoch into the civil date, see
+    // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+/// Operation log export formats
+pub enum LogFormat {
+    #[display("csv")]
+    Csv,
+
+    #[display("json")]
+    Json,
+}
+
+impl LogFormat {
+    /// Detects format from the file extension
+    pub fn with_path(path: &Path) -> Option<LogFormat> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, Default, Error)]
+#[display(doc_comments)]
+/// Unknown log export format; possible values are `csv` and `json`
+pub struct LogFormatParseError;
+
+impl FromStr for LogFormat {
+    type Err = LogFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(LogFormat::Csv),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(LogFormatParseError),
+        }
+    }
+}
+
+/// Writes log entries as CSV with time, operation type and its description
+/// columns
+pub fn log_csv(entries: &[LogEntry]) -> String {
+    let mut csv = s!("time,timestamp,operation,details\n");
+    for entry in entries {
+        csv += &format!(
+            "{},{},{},\"{}\"\n",
+            entry.time(),
+            entry.timestamp,
+            entry.operation.name(),
+            entry.operation.to_string().replace('"', "\"\"")
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_formatting() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_time(1651406400), "2022-05-01T12:00:00Z");
+        assert_eq!(format_time(4107542399), "2100-02-28T23:59:59Z");
+    }
+
+    #[test]
+    fn csv_export() {
+        let entries = vec![
+            LogEntry {
+                timestamp: 1651406400,
+                operation: Operation::DescriptorAdded(s!("\"main\" wallet")),
+            },
+            LogEntry {
+                timestamp: 1651406460,
+                operation: Operation::UtxosFound(2),
+            },
+        ];
+        assert_eq!(
+            log_csv(&entries),
+            "time,timestamp,operation,details\n\
+             2022-05-01T12:00:00Z,1651406400,descriptorAdded,\
+             \"descriptor `\"\"main\"\" wallet` added\"\n\
+             2022-05-01T12:01:00Z,1651406460,utxosFound,\
+             \"UTXO lookup found 2 outputs\"\n"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Plain data rows representing document content in the form suitable for
+//! presentation by any front-end (GTK views, command-line tool etc).
+
+use bitcoin::{Address, OutPoint, Script, Txid};
+use rgb::{AtomicValue, ContractId};
+use wallet::descriptors;
+
+use super::operation::LogEntry;
+use super::{DescriptorAccount, TrackingAccount, UtxoEntry};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TrackingRow {
+    pub name: String,
+    pub details: String,
+    pub count: u32,
+}
+
+impl From<&TrackingAccount> for TrackingRow {
+    fn from(account: &TrackingAccount) -> Self {
+        TrackingRow {
+            name: account.name.clone(),
+            details: account.details(),
+            count: account.count(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DescriptorRow {
+    pub name: String,
+    pub type_name: String,
+    pub descriptor: String,
+}
+
+impl From<&DescriptorAccount> for DescriptorRow {
+    fn from(account: &DescriptorAccount) -> Self {
+        DescriptorRow {
+            name: account.name(),
+            type_name: account.type_name(),
+            descriptor: account.descriptor(),
+        }
+    }
+}
+
+/// Address derived from a descriptor account, together with its address
+/// book data
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AddressRow {
+    pub index: u32,
+    pub category: descriptors::Category,
+    pub script_pubkey: Script,
+    /// Address for the document network, if the script has one
+    pub address: Option<Address>,
+    pub label: String,
+    pub note: String,
+    /// Time when the address was handed out, if it was
+    pub issued: Option<String>,
+    /// Whether outputs or transactions paying to the address are known to
+    /// the document
+    pub used: bool,
+}
+
+impl AddressRow {
+    /// Address string, or the script assembly for the scripts which have
+    /// no address form
+    pub fn address_string(&self) -> String {
+        self.address
+            .as_ref()
+            .map(Address::to_string)
+            .unwrap_or_else(|| self.script_pubkey.asm())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UtxoRow {
+    pub outpoint: OutPoint,
+    pub amount: u64,
+    pub height: u32,
+}
+
+impl From<&UtxoEntry> for UtxoRow {
+    fn from(utxo: &UtxoEntry) -> Self {
+        UtxoRow {
+            outpoint: utxo.outpoint,
+            amount: utxo.amount,
+            height: utxo.height,
+        }
+    }
+}
+
+/// Transaction from the document transaction cache
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TransactionRow {
+    pub txid: Txid,
+    /// Confirmation height, if the transaction is a part of the descriptor
+    /// history
+    pub height: Option<u32>,
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TxInputRow {
+    pub no: u32,
+    pub previous_output: OutPoint,
+    pub sequence: u32,
+    /// Name of the descriptor account owning the spent output
+    pub descriptor: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TxOutputRow {
+    pub no: u32,
+    pub value: u64,
+    pub script_pubkey: Script,
+    /// Whether a transaction spending the output is known to the document
+    pub spent: bool,
+    /// Name of the descriptor account owning the output
+    pub descriptor: Option<String>,
+}
+
+/// PSBT stored in the document
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PsbtRow {
+    /// Id of the unsigned transaction
+    pub txid: Txid,
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Number of inputs having signatures or final scripts
+    pub signed: usize,
+}
+
+/// Transaction affecting descriptor account, with the amounts it received
+/// to and spent from the account scripts
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HistoryRow {
+    pub txid: Txid,
+    pub height: u32,
+    pub received: u64,
+    pub spent: u64,
+}
+
+impl HistoryRow {
+    /// Change of the account balance made by the transaction
+    pub fn net(&self) -> i64 {
+        self.received as i64 - self.spent as i64
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AssetRow {
+    pub contract_id: ContractId,
+    pub ticker: String,
+    pub name: String,
+    /// Asset value allocated to the outputs known to the document
+    pub known_value: AtomicValue,
+    /// Known circulating supply, if it can be precisely computed
+    pub circulating: Option<AtomicValue>,
+    pub inflatable: bool,
+}
+
+/// Entry of the document operation log
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogRow {
+    pub time: String,
+    pub operation: String,
+    pub details: String,
+}
+
+impl From<&LogEntry> for LogRow {
+    fn from(entry: &LogEntry) -> Self {
+        LogRow {
+            time: entry.time(),
+            operation: entry.operation.name().to_owned(),
+            details: entry.operation.to_string(),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Reverse lookup of the scripts among the ones derived from the document
+//! accounts, with a cache of the derived scripts
+
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::Script;
+use wallet::descriptors;
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+use super::taproot::TapLeaf;
+use super::DescriptorAccount;
+
+/// Account deriving a script found by the reverse lookup
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum ScriptOwner {
+    /// Descriptor account at the given position in the document
+    #[display("descriptor `{name}`")]
+    Descriptor { position: usize, name: String },
+
+    /// Public key tracking account at the given position in the document
+    #[display("key `{name}`")]
+    Tracking { position: usize, name: String },
+}
+
+/// Derivation of a script found by the reverse lookup
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{owner}, {category} #{derivation_index}")]
+pub struct ScriptMatch {
+    pub owner: ScriptOwner,
+    pub category: descriptors::Category,
+    pub derivation_index: u32,
+}
+
+/// Scripts derived from a single-category account
+#[derive(Clone, Debug, Default)]
+struct DerivedScripts {
+    /// Number of the derivation indexes, starting from zero, which were
+    /// derived
+    derived: u32,
+    /// Set when derivation failed, so the following indexes are never
+    /// derived
+    exhausted: bool,
+    /// The lowest derivation index producing each of the scripts
+    scripts: HashMap<Script, u32>,
+}
+
+/// Cache of the scripts derived from the account keys, making repeated
+/// reverse lookups fast. Scripts are kept per account descriptor string, so
+/// edited accounts do not reuse the scripts of their previous versions.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptCache {
+    generators: HashMap<String, DerivedScripts>,
+}
+
+impl ScriptCache {
+    /// Lowest derivation index below `window` at which the account
+    /// produces the script, deriving scripts which are not cached yet
+    pub fn find(
+        &mut self,
+        account: &DescriptorAccount,
+        script: &Script,
+        window: u32,
+    ) -> Option<u32> {
+        let derived = self.generators.entry(account.descriptor()).or_default();
+        while derived.derived < window && !derived.exhausted {
+            let index = derived.derived;
+            match UnhardenedIndex::from_index(index)
+                .ok()
+                .and_then(|index| account.pubkey_scripts(index).ok())
+            {
+                Some(scripts) => {
+                    for script_pubkey in scripts.into_values() {
+                        derived.scripts.entry(script_pubkey).or_insert(index);
+                    }
+                    derived.derived += 1;
+                }
+                None => derived.exhausted = true,
+            }
+        }
+        derived
+            .scripts
+            .get(script)
+            .copied()
+            .filter(|index| *index < window)
+    }
+
+    /// Drops scripts of the account descriptors which are no longer used
+    pub fn retain(&mut self, generators: &HashSet<String>) {
+        self.generators
+            .retain(|generator, _| generators.contains(generator));
+    }
+
+    /// Number of the scripts kept in the cache
+    pub fn len(&self) -> usize {
+        self.generators
+            .values()
+            .map(|derived| derived.scripts.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Generator producing scripts of a single output category from the
+/// template
+pub(super) fn category_generator(
+    template: descriptors::Template,
+    category: descriptors::Category,
+) -> descriptors::Generator {
+    descriptors::Generator {
+        template,
+        variants: descriptors::Variants {
+            bare: category == descriptors::Category::Bare,
+            hashed: category == descriptors::Category::Hashed,
+            nested: category == descriptors::Category::Nested,
+            segwit: category == descriptors::Category::SegWit,
+            taproot: category == descriptors::Category::Taproot,
+        },
+    }
+}
+
+/// Account producing scripts of a single output category from the template
+/// and, for taproot outputs, the script tree leaves
+pub(super) fn category_account(
+    template: descriptors::Template,
+    category: descriptors::Category,
+    tap_leaves: Vec<TapLeaf>,
+) -> DescriptorAccount {
+    DescriptorAccount {
+        name: s!(""),
+        generator: category_generator(template, category),
+        last_used_indexes: empty!(),
+        key_origins: empty!(),
+        tap_leaves,
+    }
+}
@@ -0,0 +1,203 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Crash-safe document storage. The document is never overwritten in
+//! place: new content is written into a temporary file next to the
+//! document, synced to the disk and atomically renamed over the original
+//! file. Before that, the previous version of the document is kept as a
+//! backup, with backups rotated over a fixed number of generations
+//! (`doc.bpro.bak.1` being the most recent one).
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Default number of backup generations kept for each document
+pub const DEFAULT_BACKUP_GENERATIONS: usize = 3;
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_else(|| OsString::from("document"));
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Path to the backup of the given generation, starting from 1 for the most
+/// recent backup
+pub fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    sibling_path(path, &format!(".bak.{}", generation))
+}
+
+/// Path of the temporary file used during document saving
+pub fn temp_path(path: &Path) -> PathBuf {
+    sibling_path(path, ".tmp")
+}
+
+/// Lists existing backups of the document, from the most recent to the
+/// oldest one
+pub fn backups(path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|generation| backup_path(path, generation))
+        .take_while(|backup| backup.is_file())
+        .collect()
+}
+
+/// Removes all backups of the document, for instance when they may contain
+/// data which is no longer protected the same way as the document
+pub fn remove_backups(path: &Path) -> io::Result<()> {
+    for backup in backups(path) {
+        fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+/// Shifts existing backups by one generation, dropping ones exceeding the
+/// number of `generations`, and copies the current document into the most
+/// recent backup
+fn rotate_backups(path: &Path, generations: usize) -> io::Result<()> {
+    if generations == 0 || !path.is_file() {
+        return Ok(());
+    }
+    let oldest = backup_path(path, generations);
+    if oldest.is_file() {
+        fs::remove_file(oldest)?;
+    }
+    for generation in (1..generations).rev() {
+        let backup = backup_path(path, generation);
+        if backup.is_file() {
+            fs::rename(backup, backup_path(path, generation + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+/// Syncs directory entry, so the rename of the file inside it survives a
+/// crash
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Atomically replaces content of the file at `path` with `data`, keeping
+/// previous content as a backup
+pub fn write_atomic(
+    path: &Path,
+    data: &[u8],
+    backup_generations: usize,
+) -> io::Result<()> {
+    let temp = temp_path(path);
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+
+        rotate_backups(path, backup_generations)?;
+        fs::rename(&temp, path)?;
+        sync_dir(path)
+    })();
+    if result.is_err() {
+        // Original document is left untouched; just clean up
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("bpro-storage-test").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("doc.bpro")
+    }
+
+    #[test]
+    fn paths() {
+        let path = Path::new("/home/user/doc.bpro");
+        assert_eq!(
+            backup_path(path, 1),
+            Path::new("/home/user/doc.bpro.bak.1")
+        );
+        assert_eq!(temp_path(path), Path::new("/home/user/doc.bpro.tmp"));
+    }
+
+    #[test]
+    fn write_creates_file_without_backup() {
+        let path = test_path("create");
+        write_atomic(&path, b"v1", 3).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"v1");
+        assert!(backups(&path).is_empty());
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn backups_are_rotated() {
+        let path = test_path("rotate");
+        for version in 1..=5 {
+            write_atomic(&path, format!("v{}", version).as_bytes(), 3)
+                .unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap(), b"v5");
+        let backups = backups(&path);
+        assert_eq!(backups.len(), 3);
+        assert_eq!(fs::read(&backups[0]).unwrap(), b"v4");
+        assert_eq!(fs::read(&backups[1]).unwrap(), b"v3");
+        assert_eq!(fs::read(&backups[2]).unwrap(), b"v2");
+    }
+
+    #[test]
+    fn backups_are_removed() {
+        let path = test_path("remove");
+        for version in 1..=3 {
+            write_atomic(&path, format!("v{}", version).as_bytes(), 3)
+                .unwrap();
+        }
+        assert_eq!(backups(&path).len(), 2);
+        remove_backups(&path).unwrap();
+        assert!(backups(&path).is_empty());
+        assert_eq!(fs::read(&path).unwrap(), b"v3");
+    }
+
+    #[test]
+    fn no_backups_when_disabled() {
+        let path = test_path("disabled");
+        write_atomic(&path, b"v1", 0).unwrap();
+        write_atomic(&path, b"v2", 0).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"v2");
+        assert!(backups(&path).is_empty());
+    }
+}
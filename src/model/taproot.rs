@@ -0,0 +1,365 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Taproot outputs of the descriptor accounts. Descriptor templates can't
+//! derive taproot outputs, so these are constructed here from the template
+//! key, used as the internal key, and an optional tree of miniscript leaves
+//! kept by the account (`tr(KEY)` and `tr(KEY,TREE)` descriptors from
+//! BIP386). The internal key may also be a MuSig2 aggregate of several
+//! keys (see [`super::musig`]).
+
+use std::sync::Arc;
+
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::bip32::ChildNumber;
+use miniscript::descriptor::TapTree;
+use miniscript::{Descriptor, Miniscript, Tap, TranslatePk2};
+use serde_with::{As, DisplayFromStr};
+use wallet::descriptors;
+use wallet::hd::{SegmentIndexes, UnhardenedIndex};
+
+use super::musig;
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, From, Error)]
+#[display(doc_comments)]
+/// Errors in the construction of taproot outputs
+pub enum Error {
+    /// Descriptor template can't produce taproot outputs
+    UnsupportedTemplate,
+
+    /// Key `{0}` can't be derived at index {1}
+    Derivation(String, u32),
+
+    /// Depths of the script tree leaves do not form a binary tree
+    InconsistentLeafDepths,
+
+    /// Invalid taproot output: {0}
+    Miniscript(String),
+
+    /// MuSig2 key aggregation failed: {0}
+    #[from]
+    MuSig(musig::Error),
+}
+
+/// Script leaf of a taproot script tree, given with its depth in the tree.
+/// Leaves are listed in the depth-first order of the tree traversal, as in
+/// BIP371 `PSBT_OUT_TAP_TREE` field.
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate")]
+pub struct TapLeaf {
+    pub depth: u8,
+    #[serde(with = "As::<DisplayFromStr>")]
+    pub script: Miniscript<descriptors::SingleSig, Tap>,
+}
+
+/// Whether the internal key of taproot outputs can be constructed from the
+/// template
+pub fn supports(template: &descriptors::Template) -> bool {
+    matches!(
+        template,
+        descriptors::Template::SingleSig(_)
+            | descriptors::Template::MuSigBranched(_)
+    )
+}
+
+/// Assembles script tree out of the leaves, given with their depths in the
+/// depth-first order; returns `None` if there are no leaves
+pub fn tap_tree<Pk: miniscript::MiniscriptKey>(
+    leaves: impl IntoIterator<Item = (u8, Miniscript<Pk, Tap>)>,
+) -> Result<Option<TapTree<Pk>>, Error> {
+    // Subtrees which are not yet complete, with their depths
+    let mut stack = Vec::<(u8, TapTree<Pk>)>::new();
+    for (depth, script) in leaves {
+        let mut node = (depth, TapTree::Leaf(Arc::new(script)));
+        while let Some((depth, _)) = stack.last() {
+            if *depth != node.0 {
+                break;
+            }
+            let (depth, sibling) = stack.pop().expect("checked above");
+            let depth =
+                depth.checked_sub(1).ok_or(Error::InconsistentLeafDepths)?;
+            node = (depth, TapTree::Tree(Arc::new(sibling), Arc::new(node.1)));
+        }
+        stack.push(node);
+    }
+    match (stack.pop(), stack.is_empty()) {
+        (None, _) => Ok(None),
+        (Some((0, tree)), true) => Ok(Some(tree)),
+        _ => Err(Error::InconsistentLeafDepths),
+    }
+}
+
+/// Derives the key at the given index, in the same way the descriptor
+/// templates do
+fn derive_key(
+    key: &descriptors::SingleSig,
+    index: UnhardenedIndex,
+) -> Result<bitcoin::PublicKey, Error> {
+    let failure = || Error::Derivation(key.to_string(), index.first_index());
+    match key {
+        descriptors::SingleSig::Pubkey(single) => Ok(single.key),
+        descriptors::SingleSig::XPubDerivable(components) => {
+            let path = components
+                .terminal_path
+                .iter()
+                .copied()
+                .chain(Some(index.first_index()))
+                .map(ChildNumber::from_normal_idx)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| failure())?;
+            let xpub = components
+                .branch_xpub
+                .derive_pub(&SECP256K1, &path)
+                .map_err(|_| failure())?;
+            Ok(bitcoin::PublicKey::new(xpub.public_key))
+        }
+        _ => Err(failure()),
+    }
+}
+
+/// Internal key of the taproot output at the given index
+pub fn internal_key(
+    template: &descriptors::Template,
+    index: UnhardenedIndex,
+) -> Result<bitcoin::PublicKey, Error> {
+    match template {
+        descriptors::Template::SingleSig(key) => derive_key(key, index),
+        descriptors::Template::MuSigBranched(branched) => {
+            let keys = branched
+                .extra_keys
+                .iter()
+                .map(|key| Ok(derive_key(key, index)?.inner))
+                .collect::<Result<Vec<_>, Error>>()?;
+            let key = musig::key_agg_sorted(&SECP256K1, &keys)?;
+            Ok(bitcoin::PublicKey::new(key))
+        }
+        _ => Err(Error::UnsupportedTemplate),
+    }
+}
+
+/// Taproot output descriptor at the given index, with the internal key
+/// constructed from the template and all keys of the leaves derived at the
+/// same index
+pub fn derive_descriptor(
+    template: &descriptors::Template,
+    leaves: &[TapLeaf],
+    index: UnhardenedIndex,
+) -> Result<Descriptor<bitcoin::PublicKey>, Error> {
+    let internal_key = internal_key(template, index)?;
+    let leaves = leaves
+        .iter()
+        .map(|leaf| {
+            let script =
+                leaf.script.translate_pk2(|key| derive_key(key, index))?;
+            Ok((leaf.depth, script))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Descriptor::new_tr(internal_key, tap_tree(leaves)?)
+        .map_err(|err| Error::Miniscript(err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey};
+    use wallet::hd::DerivationComponents;
+
+    use super::*;
+    use crate::model::DescriptorAccount;
+
+    static KEY_A: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    static KEY_B: &str =
+        "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    static KEY_C: &str =
+        "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+    /// Template for the account key m/86'/0'/0' from BIP86 test vectors,
+    /// deriving receive addresses
+    fn bip86_template() -> descriptors::Template {
+        let xpub = ExtendedPubKey::from_str(
+            "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ",
+        )
+        .unwrap();
+        descriptors::Template::SingleSig(descriptors::SingleSig::XPubDerivable(
+            DerivationComponents {
+                master_xpub: xpub,
+                branch_path: DerivationPath::from(vec![]),
+                branch_xpub: xpub,
+                terminal_path: vec![0],
+                index_ranges: None,
+            },
+        ))
+    }
+
+    fn leaf(depth: u8, key: &str) -> TapLeaf {
+        TapLeaf {
+            depth,
+            script: Miniscript::from_str(&format!("pk({})", key)).unwrap(),
+        }
+    }
+
+    #[test]
+    fn bip86_key_path_output() {
+        let descriptor =
+            derive_descriptor(&bip86_template(), &[], UnhardenedIndex::zero())
+                .unwrap();
+        assert_eq!(
+            descriptor.script_pubkey().to_hex(),
+            "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c"
+        );
+    }
+
+    #[test]
+    fn script_tree() {
+        let leaves = [leaf(1, KEY_A), leaf(2, KEY_B), leaf(2, KEY_C)];
+        let template = bip86_template();
+        let index = UnhardenedIndex::zero();
+        let tree = derive_descriptor(&template, &leaves, index).unwrap();
+        let key_only = derive_descriptor(&template, &[], index).unwrap();
+        assert_ne!(tree.script_pubkey(), key_only.script_pubkey());
+        match tree {
+            Descriptor::Tr(tr) => {
+                assert_eq!(
+                    tr.iter_scripts()
+                        .map(|(depth, script)| (depth, script.to_string()))
+                        .collect::<Vec<_>>(),
+                    leaves
+                        .iter()
+                        .map(|leaf| (leaf.depth, leaf.script.to_string()))
+                        .collect::<Vec<_>>()
+                );
+            }
+            _ => panic!("taproot descriptor expected"),
+        }
+    }
+
+    #[test]
+    fn inconsistent_leaf_depths() {
+        let script = |leaf: TapLeaf| (leaf.depth, leaf.script);
+        assert!(tap_tree(vec![script(leaf(0, KEY_A))]).unwrap().is_some());
+        assert!(matches!(
+            tap_tree(vec![script(leaf(1, KEY_A))]),
+            Err(Error::InconsistentLeafDepths)
+        ));
+        assert!(matches!(
+            tap_tree(vec![
+                script(leaf(1, KEY_A)),
+                script(leaf(1, KEY_B)),
+                script(leaf(1, KEY_C))
+            ]),
+            Err(Error::InconsistentLeafDepths)
+        ));
+    }
+
+    #[test]
+    fn musig_internal_key() {
+        let key = |hex: &str| descriptors::SingleSig::from_str(hex).unwrap();
+        let xpub = match bip86_template() {
+            descriptors::Template::SingleSig(xpub) => xpub,
+            _ => unreachable!(),
+        };
+        let template = musig::template(vec![key(KEY_A), key(KEY_B), xpub]);
+        let reordered = match &template {
+            descriptors::Template::MuSigBranched(branched) => {
+                let mut keys = branched.extra_keys.clone();
+                keys.rotate_left(1);
+                musig::template(keys)
+            }
+            _ => panic!("MuSig2 template expected"),
+        };
+        assert!(supports(&template));
+
+        let index = UnhardenedIndex::zero();
+        let aggregated = internal_key(&template, index).unwrap();
+        assert_eq!(internal_key(&reordered, index).unwrap(), aggregated);
+        assert_eq!(
+            aggregated.inner,
+            musig::key_agg_sorted(
+                &SECP256K1,
+                &[
+                    derive_key(&key(KEY_A), index).unwrap().inner,
+                    derive_key(&key(KEY_B), index).unwrap().inner,
+                    internal_key(&bip86_template(), index).unwrap().inner,
+                ]
+            )
+            .unwrap()
+        );
+        // Extended keys are derived before the aggregation
+        assert_ne!(
+            internal_key(&template, UnhardenedIndex::one()).unwrap(),
+            aggregated
+        );
+        assert!(matches!(
+            internal_key(&musig::template(vec![]), index),
+            Err(Error::MuSig(musig::Error::NoKeys))
+        ));
+    }
+
+    #[test]
+    fn mixed_variant_account() {
+        let mut generator = descriptors::Generator {
+            template: bip86_template(),
+            variants: descriptors::Variants {
+                bare: false,
+                hashed: false,
+                nested: false,
+                segwit: true,
+                taproot: true,
+            },
+        };
+        let account = DescriptorAccount {
+            name: s!("Mixed"),
+            generator: generator.clone(),
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: vec![leaf(0, KEY_A)],
+        };
+        let index = UnhardenedIndex::zero();
+        let scripts = account.pubkey_scripts(index).unwrap();
+        assert_eq!(scripts.len(), 2);
+
+        generator.variants.taproot = false;
+        assert_eq!(
+            scripts[&descriptors::Category::SegWit],
+            generator.pubkey_scripts(index).unwrap()
+                [&descriptors::Category::SegWit]
+        );
+        assert_eq!(
+            scripts[&descriptors::Category::Taproot],
+            derive_descriptor(
+                &account.generator.template,
+                &account.tap_leaves,
+                index
+            )
+            .unwrap()
+            .script_pubkey()
+        );
+        assert_eq!(account.descriptors(index).unwrap().len(), 2);
+    }
+}
@@ -15,11 +15,24 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use serde_with::{As, DisplayFromStr};
 use wallet::descriptors;
 
-#[derive(Getters, Clone, PartialEq, Eq, Debug, StrictEncode, StrictDecode)]
+#[derive(
+    Getters,
+    Clone,
+    PartialEq,
+    Eq,
+    Debug,
+    Serialize,
+    Deserialize,
+    StrictEncode,
+    StrictDecode,
+)]
+#[serde(crate = "serde_crate")]
 pub struct TrackingAccount {
     pub name: String,
+    #[serde(with = "As::<DisplayFromStr>")]
     pub key: descriptors::SingleSig,
 }
 
@@ -31,4 +44,15 @@ impl TrackingAccount {
     pub fn count(&self) -> u32 {
         self.key.count()
     }
+
+    /// Output categories of the scripts which may be made with the key
+    pub fn categories(&self) -> Vec<descriptors::Category> {
+        vec![
+            descriptors::Category::Bare,
+            descriptors::Category::Hashed,
+            descriptors::Category::Nested,
+            descriptors::Category::SegWit,
+            descriptors::Category::Taproot,
+        ]
+    }
 }
@@ -16,10 +16,10 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use bitcoin::OutPoint;
-use electrum_client::ListUnspentRes;
 use wallet::descriptors;
 
 use super::DescriptorAccount;
+use crate::resolver::Unspent;
 
 #[derive(
     Clone,
@@ -30,9 +30,12 @@ use super::DescriptorAccount;
     Hash,
     Debug,
     Display,
+    Serialize,
+    Deserialize,
     StrictEncode,
     StrictDecode,
 )]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
 #[display("{amount}@{outpoint} {descriptor_category}({descriptor_template})")]
 pub struct UtxoEntry {
     pub outpoint: OutPoint,
@@ -45,18 +48,15 @@ pub struct UtxoEntry {
 
 impl UtxoEntry {
     pub fn with(
-        res: &ListUnspentRes,
+        unspent: &Unspent,
         descriptor_template: descriptors::Template,
         descriptor_category: descriptors::Category,
         derivation_index: u32,
     ) -> Self {
         UtxoEntry {
-            outpoint: OutPoint {
-                txid: res.tx_hash,
-                vout: res.tx_pos as u32,
-            },
-            height: res.height as u32,
-            amount: res.value,
+            outpoint: unspent.outpoint,
+            height: unspent.height,
+            amount: unspent.amount,
             descriptor_template,
             descriptor_category,
             derivation_index,
@@ -0,0 +1,336 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Bitcoin Core JSON-RPC backend. Unspent outputs are found with
+//! `scantxoutset` over the node UTXO set, which does not require the node
+//! wallet or importing descriptors and works with pruned nodes.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::{OutPoint, Script, Transaction, Txid};
+use bitcoincore_rpc::json::ScanTxOutRequest;
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use lnpbp::chain::Chain;
+
+use super::{BlockTip, HistoryItem, Resolver, Unspent};
+use crate::model::{CoreAuth, ResolverError};
+
+impl From<bitcoincore_rpc::Error> for ResolverError {
+    fn from(err: bitcoincore_rpc::Error) -> Self {
+        ResolverError::BitcoinCore(err.to_string())
+    }
+}
+
+/// Bitcoin Core node accessed via JSON-RPC
+pub struct BitcoinCoreResolver {
+    client: Client,
+}
+
+impl BitcoinCoreResolver {
+    pub fn new(
+        addr: SocketAddr,
+        auth: &CoreAuth,
+        chain: &Chain,
+    ) -> Result<Self, ResolverError> {
+        let auth = match auth {
+            CoreAuth::Cookie(Some(path)) => {
+                Auth::CookieFile(PathBuf::from(path))
+            }
+            CoreAuth::Cookie(None) => Auth::CookieFile(
+                default_cookie_path(chain).ok_or(ResolverError::NoCookie)?,
+            ),
+            CoreAuth::UserPass(user, pass) => {
+                Auth::UserPass(user.clone(), pass.clone())
+            }
+        };
+        let client = Client::new(&format!("http://{}", addr), auth)?;
+        // Checks connectivity and credentials, like Electrum client does
+        // on its creation
+        client.get_block_count()?;
+        Ok(BitcoinCoreResolver { client })
+    }
+}
+
+/// Location of the authentication cookie of a node running with the
+/// default data directory
+pub fn default_cookie_path(chain: &Chain) -> Option<PathBuf> {
+    let mut path = PathBuf::from(std::env::var_os("HOME")?);
+    if cfg!(target_os = "macos") {
+        path.push("Library/Application Support/Bitcoin");
+    } else {
+        path.push(".bitcoin");
+    }
+    match chain {
+        Chain::Mainnet => {}
+        Chain::Testnet3 => path.push("testnet3"),
+        Chain::Regtest(_) => path.push("regtest"),
+        Chain::Signet(_) => path.push("signet"),
+        _ => return None,
+    }
+    path.push(".cookie");
+    Some(path)
+}
+
+impl Resolver for BitcoinCoreResolver {
+    fn provides_script_history(&self) -> bool {
+        false
+    }
+
+    fn script_history(
+        &self,
+        _scripts: &[Script],
+    ) -> Result<Vec<Vec<HistoryItem>>, ResolverError> {
+        // Bitcoin Core does not index transactions by scripts
+        Err(ResolverError::UnsupportedOperation(
+            "Bitcoin Core",
+            "provide script transaction history",
+        ))
+    }
+
+    fn script_list_unspent(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<Unspent>>, ResolverError> {
+        let request = scripts
+            .iter()
+            .map(|script| {
+                ScanTxOutRequest::Single(format!("raw({})", script.to_hex()))
+            })
+            .collect::<Vec<_>>();
+        let result = self.client.scan_tx_out_set_blocking(&request)?;
+
+        let mut unspents = HashMap::<Script, Vec<Unspent>>::new();
+        for utxo in result.unspents {
+            unspents.entry(utxo.script_pub_key).or_default().push(Unspent {
+                outpoint: OutPoint::new(utxo.txid, utxo.vout),
+                height: utxo.height as u32,
+                amount: utxo.amount.as_sat(),
+            });
+        }
+        Ok(scripts
+            .iter()
+            .map(|script| unspents.get(script).cloned().unwrap_or_default())
+            .collect())
+    }
+
+    fn is_unspent(
+        &self,
+        outpoint: OutPoint,
+        _script_pubkey: &Script,
+    ) -> Result<bool, ResolverError> {
+        // Unlike `scantxoutset`, `gettxout` is cheap and sees the mempool
+        Ok(self
+            .client
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+            .is_some())
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ResolverError> {
+        // Requires either `txindex` enabled on the node, or the transaction
+        // being in the mempool
+        Ok(self.client.get_raw_transaction(&txid, None)?)
+    }
+
+    fn tip(&self) -> Result<BlockTip, ResolverError> {
+        let info = self.client.get_blockchain_info()?;
+        Ok(BlockTip {
+            height: info.blocks as u32,
+            header: self.client.get_block_header(&info.best_block_hash)?,
+        })
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, ResolverError> {
+        Ok(self.client.send_raw_transaction(tx)?)
+    }
+
+    fn estimate_fee(&self, target: u16) -> Result<f32, ResolverError> {
+        self.client
+            .estimate_smart_fee(target, None)?
+            .fee_rate
+            // Fee rate is reported per virtual kilobyte
+            .map(|fee_rate| fee_rate.as_sat() as f32 / 1000.0)
+            .ok_or(ResolverError::NoFeeEstimate(target))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::str::FromStr;
+    use std::thread;
+
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::Txid;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Reads single HTTP request with JSON body; returns `None` once the
+    /// connection is closed
+    fn read_request(reader: &mut impl BufRead) -> Option<Value> {
+        let mut len = 0usize;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end().to_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("content-length:") {
+                len = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body).ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+
+    /// Serves JSON-RPC requests, replying to each known method with the
+    /// given result
+    fn mock_rpc(results: Vec<(&'static str, Value)>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while let Some(request) = read_request(&mut reader) {
+                    let result = results
+                        .iter()
+                        .find(|(method, _)| request["method"] == *method)
+                        .map(|(_, result)| result.clone())
+                        .expect("unexpected RPC method");
+                    let response = json!({
+                        "result": result,
+                        "error": null,
+                        "id": request["id"],
+                    })
+                    .to_string();
+                    write!(
+                        &stream,
+                        "HTTP/1.1 200 OK\r\n\
+                         Content-Type: application/json\r\n\
+                         Content-Length: {}\r\n\r\n{}",
+                        response.len(),
+                        response
+                    )
+                    .unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn scantxoutset_lookup() {
+        let txid = Txid::from_str(
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        )
+        .unwrap();
+        let script =
+            |hex: &str| Script::from(Vec::<u8>::from_hex(hex).unwrap());
+        let found = script("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let missing = script("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1");
+        let scan = json!({
+            "success": true,
+            "txouts": 1,
+            "height": 120,
+            "bestblock": "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            "unspents": [{
+                "txid": txid,
+                "vout": 1,
+                "scriptPubKey": found.to_hex(),
+                "desc": format!("raw({})#00000000", found.to_hex()),
+                "amount": 0.0001,
+                "height": 101,
+            }],
+            "total_amount": 0.0001,
+        });
+        let addr = mock_rpc(vec![
+            ("getblockcount", json!(120)),
+            ("scantxoutset", scan),
+        ]);
+
+        let resolver = BitcoinCoreResolver::new(
+            addr,
+            &CoreAuth::UserPass(s!("user"), s!("pass")),
+            &Chain::Testnet3,
+        )
+        .unwrap();
+        let unspents =
+            resolver.script_list_unspent(&[missing, found]).unwrap();
+        assert_eq!(
+            unspents,
+            vec![
+                vec![],
+                vec![Unspent {
+                    outpoint: OutPoint::new(txid, 1),
+                    height: 101,
+                    amount: 10_000,
+                }]
+            ]
+        );
+    }
+
+    #[test]
+    fn gettxout_check() {
+        let outpoint = OutPoint::new(
+            Txid::from_str(
+                "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            )
+            .unwrap(),
+            0,
+        );
+        let script = Script::from(
+            Vec::<u8>::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6")
+                .unwrap(),
+        );
+        let auth = CoreAuth::UserPass(s!("user"), s!("pass"));
+
+        // Output of a transaction which is still in the mempool
+        let txout = json!({
+            "bestblock": "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            "confirmations": 0,
+            "value": 0.0001,
+            "scriptPubKey": {
+                "asm": "0 751e76e8199196d454941c45d1b3a323f1433bd6",
+                "hex": script.to_hex(),
+            },
+            "coinbase": false,
+        });
+        let addr =
+            mock_rpc(vec![("getblockcount", json!(120)), ("gettxout", txout)]);
+        let resolver =
+            BitcoinCoreResolver::new(addr, &auth, &Chain::Testnet3).unwrap();
+        assert!(resolver.is_unspent(outpoint, &script).unwrap());
+
+        // Spent outputs are not returned
+        let addr = mock_rpc(vec![
+            ("getblockcount", json!(120)),
+            ("gettxout", Value::Null),
+        ]);
+        let resolver =
+            BitcoinCoreResolver::new(addr, &auth, &Chain::Testnet3).unwrap();
+        assert!(!resolver.is_unspent(outpoint, &script).unwrap());
+    }
+}
@@ -0,0 +1,89 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use bitcoin::{OutPoint, Script, Transaction, Txid};
+use electrum_client::{Client as ElectrumClient, ElectrumApi};
+
+use super::{BlockTip, HistoryItem, Resolver, Unspent};
+use crate::model::ResolverError;
+
+impl Resolver for ElectrumClient {
+    fn script_history(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<HistoryItem>>, ResolverError> {
+        Ok(self
+            .batch_script_get_history(scripts)?
+            .into_iter()
+            .map(|list| {
+                list.into_iter()
+                    .map(|res| HistoryItem {
+                        txid: res.tx_hash,
+                        // Electrum uses negative heights for the mempool
+                        // transactions with unconfirmed inputs
+                        height: res.height.max(0) as u32,
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn script_list_unspent(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<Unspent>>, ResolverError> {
+        Ok(self
+            .batch_script_list_unspent(scripts)?
+            .into_iter()
+            .map(|list| {
+                list.into_iter()
+                    .map(|res| Unspent {
+                        outpoint: OutPoint::new(res.tx_hash, res.tx_pos as u32),
+                        height: res.height as u32,
+                        amount: res.value,
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ResolverError> {
+        Ok(self.transaction_get(&txid)?)
+    }
+
+    fn tip(&self) -> Result<BlockTip, ResolverError> {
+        let notification = self.block_headers_subscribe()?;
+        Ok(BlockTip {
+            height: notification.height as u32,
+            header: notification.header,
+        })
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, ResolverError> {
+        Ok(self.transaction_broadcast(tx)?)
+    }
+
+    fn estimate_fee(&self, target: u16) -> Result<f32, ResolverError> {
+        // Electrum reports fee rate in BTC per kilobyte, or -1 if the server
+        // does not have enough data for the estimation
+        let btc_per_kb = ElectrumApi::estimate_fee(self, target as usize)?;
+        if btc_per_kb <= 0.0 {
+            return Err(ResolverError::NoFeeEstimate(target));
+        }
+        Ok((btc_per_kb * 100_000.0) as f32)
+    }
+}
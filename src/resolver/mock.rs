@@ -0,0 +1,170 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::{Network, Script, Transaction, Txid};
+
+use super::{BlockTip, HistoryItem, Resolver, Unspent};
+use crate::model::ResolverError;
+
+/// In-memory resolver returning pre-defined data. Broadcasted transactions
+/// are recorded and may be fetched back from the resolver.
+#[derive(Debug)]
+pub struct MockResolver {
+    history: HashMap<Script, Vec<HistoryItem>>,
+    unspents: HashMap<Script, Vec<Unspent>>,
+    transactions: RefCell<HashMap<Txid, Transaction>>,
+    broadcasted: RefCell<Vec<Transaction>>,
+    tip: BlockTip,
+    fee_rate: Option<f32>,
+    script_history: bool,
+}
+
+impl Default for MockResolver {
+    fn default() -> Self {
+        MockResolver {
+            history: none!(),
+            unspents: none!(),
+            transactions: none!(),
+            broadcasted: none!(),
+            tip: BlockTip {
+                height: 0,
+                header: genesis_block(Network::Regtest).header,
+            },
+            fee_rate: None,
+            script_history: true,
+        }
+    }
+}
+
+impl MockResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds transaction to the resolver, registering it in the history of
+    /// the scripts of its outputs and of the spent outputs of the already
+    /// known transactions
+    pub fn add_transaction(&mut self, tx: Transaction, height: u32) {
+        let txid = tx.txid();
+        let spent = tx
+            .input
+            .iter()
+            .filter_map(|txin| {
+                let transactions = self.transactions.borrow();
+                let prev = transactions.get(&txin.previous_output.txid)?;
+                prev.output
+                    .get(txin.previous_output.vout as usize)
+                    .map(|txout| txout.script_pubkey.clone())
+            })
+            .collect::<Vec<_>>();
+        let scripts = tx.output.iter().map(|txout| &txout.script_pubkey);
+        for script in scripts.chain(&spent) {
+            let history = self.history.entry(script.clone()).or_default();
+            if !history.iter().any(|item| item.txid == txid) {
+                history.push(HistoryItem { txid, height });
+            }
+        }
+        self.transactions.borrow_mut().insert(txid, tx);
+    }
+
+    /// Adds unspent output for the script
+    pub fn add_unspent(&mut self, script: Script, unspent: Unspent) {
+        self.unspents.entry(script).or_default().push(unspent);
+    }
+
+    pub fn set_tip(&mut self, tip: BlockTip) {
+        self.tip = tip;
+    }
+
+    /// Sets fee rate returned for any confirmation target; without it the
+    /// fee estimation fails
+    pub fn set_fee_rate(&mut self, fee_rate: f32) {
+        self.fee_rate = Some(fee_rate);
+    }
+
+    /// Makes the resolver behave like a backend without script index
+    /// (Bitcoin Core), failing all script history requests
+    pub fn disable_script_history(&mut self) {
+        self.script_history = false;
+    }
+
+    /// Transactions broadcasted through the resolver
+    pub fn broadcasted(&self) -> Vec<Transaction> {
+        self.broadcasted.borrow().clone()
+    }
+}
+
+impl Resolver for MockResolver {
+    fn provides_script_history(&self) -> bool {
+        self.script_history
+    }
+
+    fn script_history(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<HistoryItem>>, ResolverError> {
+        if !self.script_history {
+            return Err(ResolverError::UnsupportedOperation(
+                "Mock resolver",
+                "provide script transaction history",
+            ));
+        }
+        Ok(scripts
+            .iter()
+            .map(|script| self.history.get(script).cloned().unwrap_or_default())
+            .collect())
+    }
+
+    fn script_list_unspent(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<Unspent>>, ResolverError> {
+        Ok(scripts
+            .iter()
+            .map(|script| {
+                self.unspents.get(script).cloned().unwrap_or_default()
+            })
+            .collect())
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ResolverError> {
+        self.transactions
+            .borrow()
+            .get(&txid)
+            .cloned()
+            .ok_or(ResolverError::UnknownTransaction(txid))
+    }
+
+    fn tip(&self) -> Result<BlockTip, ResolverError> {
+        Ok(self.tip)
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, ResolverError> {
+        let txid = tx.txid();
+        self.transactions.borrow_mut().insert(txid, tx.clone());
+        self.broadcasted.borrow_mut().push(tx.clone());
+        Ok(txid)
+    }
+
+    fn estimate_fee(&self, target: u16) -> Result<f32, ResolverError> {
+        self.fee_rate.ok_or(ResolverError::NoFeeEstimate(target))
+    }
+}
@@ -0,0 +1,134 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Blockchain information providers. Each [`ChainResolver`] configuration
+//! variant is served by a backend implementing [`Resolver`] trait, so the
+//! rest of the application does not depend on a specific client library.
+//! [`MockResolver`] keeps all the data in memory and is used in tests.
+
+mod bitcoin_core;
+mod electrum;
+mod mock;
+
+pub use bitcoin_core::BitcoinCoreResolver;
+pub use mock::MockResolver;
+
+use bitcoin::{BlockHeader, OutPoint, Script, Transaction, Txid};
+
+use crate::model::{ChainResolver, ResolverError, Settings};
+
+/// Unspent transaction output found by a resolver
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Unspent {
+    pub outpoint: OutPoint,
+    /// Height of the block containing the transaction; zero for the
+    /// transactions which are not mined yet
+    pub height: u32,
+    /// Output value in satoshis
+    pub amount: u64,
+}
+
+/// Transaction affecting a script (either spending from it or paying to it)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HistoryItem {
+    pub txid: Txid,
+    /// Height of the block containing the transaction; zero for the
+    /// transactions which are not mined yet
+    pub height: u32,
+}
+
+/// The most recent block known to the resolver
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlockTip {
+    pub height: u32,
+    pub header: BlockHeader,
+}
+
+/// Common interface of the blockchain information providers. Methods taking
+/// a list of scripts return vector of the same length and order as
+/// `scripts`.
+pub trait Resolver {
+    /// Whether the backend supports [`Resolver::script_history`]; the
+    /// lookups depending on it must not be started otherwise
+    fn provides_script_history(&self) -> bool {
+        true
+    }
+
+    /// Lists transactions for each of the provided scripts
+    fn script_history(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<HistoryItem>>, ResolverError>;
+
+    /// Lists unspent outputs for each of the provided scripts
+    fn script_list_unspent(
+        &self,
+        scripts: &[Script],
+    ) -> Result<Vec<Vec<Unspent>>, ResolverError>;
+
+    /// Checks whether the output paying to `script_pubkey` is not spent yet,
+    /// including by the transactions in the mempool. Outputs of the mempool
+    /// transactions are considered unspent.
+    ///
+    /// Default implementation searches for the output among the unspent
+    /// outputs of the script.
+    fn is_unspent(
+        &self,
+        outpoint: OutPoint,
+        script_pubkey: &Script,
+    ) -> Result<bool, ResolverError> {
+        Ok(self
+            .script_list_unspent(&[script_pubkey.clone()])?
+            .into_iter()
+            .flatten()
+            .any(|unspent| unspent.outpoint == outpoint))
+    }
+
+    /// Fetches transaction by its id
+    fn transaction(&self, txid: Txid) -> Result<Transaction, ResolverError>;
+
+    /// Returns the most recent block
+    fn tip(&self) -> Result<BlockTip, ResolverError>;
+
+    /// Broadcasts signed transaction to the network
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, ResolverError>;
+
+    /// Estimates fee rate, in sats per virtual byte, required for the
+    /// transaction to be confirmed within `target` blocks
+    fn estimate_fee(&self, target: u16) -> Result<f32, ResolverError>;
+}
+
+/// Connects to the resolver specified in the document settings
+pub fn connect(
+    settings: &Settings,
+) -> Result<Box<dyn Resolver>, ResolverError> {
+    match settings.resolver {
+        ChainResolver::Electrum(addr) => Ok(Box::new(
+            electrum_client::Client::new(&addr.to_string())?,
+        )),
+        ChainResolver::BitcoinCore(addr) => {
+            Ok(Box::new(BitcoinCoreResolver::new(
+                addr,
+                &settings.core_auth,
+                &settings.chain,
+            )?))
+        }
+        ChainResolver::BpNode(_) => {
+            Err(ResolverError::Unsupported(settings.resolver.clone()))
+        }
+    }
+}
@@ -15,4 +15,6 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod psbt;
 pub mod resolver_mode;
+pub mod script;
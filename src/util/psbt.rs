@@ -0,0 +1,700 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Encodings of PSBTs and presentation of their key-value maps as rows of
+//! strings, which can be edited and converted back into the maps
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::util::bip32::{DerivationPath, Fingerprint, KeySource};
+use bitcoin::util::ecdsa::EcdsaSig;
+use bitcoin::util::psbt::{raw, Input};
+use bitcoin::{
+    Address, Network, OutPoint, PublicKey, Script, Transaction, TxIn, TxOut,
+    Witness,
+};
+use wallet::psbt::Psbt;
+
+/// Magic bytes starting binary PSBT serialization
+pub const PSBT_MAGIC: [u8; 5] = *b"psbt\xff";
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+/// Errors reading PSBT data
+pub enum PsbtParseError {
+    /// Data are neither binary PSBT nor its base64 or hex encoding
+    UnknownEncoding,
+
+    /// Invalid PSBT data: {0}
+    InvalidPsbt(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+/// Errors converting edited rows back into PSBT data
+pub enum MapError {
+    /// Invalid hex string `{0}`
+    Hex(String),
+
+    /// Invalid key `{0}`
+    Key(String),
+
+    /// Invalid master key fingerprint `{0}`
+    Fingerprint(String),
+
+    /// Invalid derivation path `{0}`
+    Derivation(String),
+
+    /// Invalid signature `{0}`
+    Signature(String),
+
+    /// Invalid script or address `{0}`
+    Script(String),
+
+    /// Hash `{0}` must be prefixed with `sha256:`, `hash256:`, `ripemd160:`
+    /// or `hash160:`
+    HashType(String),
+
+    /// Preimage does not match hash `{0}`
+    PreimageMismatch(String),
+
+    /// Key `{0}` is repeated
+    Duplicate(String),
+}
+
+/// Reads PSBT from its binary serialization or from base64 or hex
+/// encodings of it
+pub fn parse_psbt(data: &[u8]) -> Result<Psbt, PsbtParseError> {
+    let binary = if data.starts_with(&PSBT_MAGIC) {
+        data.to_vec()
+    } else {
+        let text = std::str::from_utf8(data)
+            .map_err(|_| PsbtParseError::UnknownEncoding)?
+            .trim();
+        base64::decode(text)
+            .ok()
+            .filter(|data| data.starts_with(&PSBT_MAGIC))
+            .or_else(|| Vec::<u8>::from_hex(text).ok())
+            .ok_or(PsbtParseError::UnknownEncoding)?
+    };
+    deserialize(&binary)
+        .map_err(|err| PsbtParseError::InvalidPsbt(err.to_string()))
+}
+
+/// Binary serialization of PSBT
+pub fn psbt_binary(psbt: &Psbt) -> Vec<u8> {
+    serialize(psbt)
+}
+
+/// Base64 encoding of PSBT, which is the form used by most of the wallets
+pub fn psbt_base64(psbt: &Psbt) -> String {
+    base64::encode(serialize(psbt))
+}
+
+/// PSBT with a version 2 transaction having no inputs and outputs
+pub fn empty_psbt() -> Psbt {
+    Psbt::from_unsigned_tx(Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![],
+        output: vec![],
+    })
+    .expect("transaction without inputs is always unsigned")
+}
+
+/// Whether the PSBT input has final script sig or witness
+pub fn is_finalized(input: &Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+/// Whether the PSBT input has signatures or is already finalized
+pub fn is_signed(input: &Input) -> bool {
+    !input.partial_sigs.is_empty() || is_finalized(input)
+}
+
+/// PSBT input, as presented in the list of inputs
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PsbtInputRow {
+    pub previous_output: OutPoint,
+    /// Whether the spent output (and thus its script) is known
+    pub utxo_known: bool,
+    /// Number of partial signatures, or `final` for finalized inputs
+    pub signatures: String,
+}
+
+pub fn input_rows(psbt: &Psbt) -> Vec<PsbtInputRow> {
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .map(|(txin, input)| PsbtInputRow {
+            previous_output: txin.previous_output,
+            utxo_known: input.witness_utxo.is_some()
+                || input.non_witness_utxo.is_some(),
+            signatures: if is_finalized(input) {
+                s!("final")
+            } else {
+                input.partial_sigs.len().to_string()
+            },
+        })
+        .collect()
+}
+
+/// PSBT output, as presented in the list of outputs
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PsbtOutputRow {
+    pub value: u64,
+    /// Address of the output or, if the script has no address form, its hex
+    pub script: String,
+}
+
+pub fn output_rows(
+    psbt: &Psbt,
+    network: Option<Network>,
+) -> Vec<PsbtOutputRow> {
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .map(|txout| PsbtOutputRow {
+            value: txout.value,
+            script: network
+                .and_then(|network| {
+                    Address::from_script(&txout.script_pubkey, network)
+                })
+                .map(|address| address.to_string())
+                .unwrap_or_else(|| txout.script_pubkey.as_bytes().to_hex()),
+        })
+        .collect()
+}
+
+/// Parses output script given as an address or as a hex string
+pub fn parse_script(s: &str) -> Result<Script, MapError> {
+    let s = s.trim();
+    if let Ok(address) = Address::from_str(s) {
+        return Ok(address.script_pubkey());
+    }
+    Vec::<u8>::from_hex(s)
+        .map(Script::from)
+        .map_err(|_| MapError::Script(s.to_owned()))
+}
+
+pub fn add_input(psbt: &mut Psbt, previous_output: OutPoint, input: Input) {
+    psbt.unsigned_tx.input.push(TxIn {
+        previous_output,
+        script_sig: Script::new(),
+        sequence: u32::MAX,
+        witness: Witness::default(),
+    });
+    psbt.inputs.push(input);
+}
+
+pub fn add_output(psbt: &mut Psbt, txout: TxOut) {
+    psbt.unsigned_tx.output.push(txout);
+    psbt.outputs.push(default!());
+}
+
+/// Removes input with the given index; returns `false` if there is no such
+/// input
+pub fn remove_input(psbt: &mut Psbt, index: usize) -> bool {
+    if index >= psbt.inputs.len() {
+        return false;
+    }
+    psbt.unsigned_tx.input.remove(index);
+    psbt.inputs.remove(index);
+    true
+}
+
+/// Removes output with the given index; returns `false` if there is no such
+/// output
+pub fn remove_output(psbt: &mut Psbt, index: usize) -> bool {
+    if index >= psbt.outputs.len() {
+        return false;
+    }
+    psbt.unsigned_tx.output.remove(index);
+    psbt.outputs.remove(index);
+    true
+}
+
+/// Swaps two inputs together with their PSBT data; returns `false` if any
+/// of the inputs does not exist
+pub fn swap_inputs(psbt: &mut Psbt, a: usize, b: usize) -> bool {
+    if a.max(b) >= psbt.inputs.len() {
+        return false;
+    }
+    psbt.unsigned_tx.input.swap(a, b);
+    psbt.inputs.swap(a, b);
+    true
+}
+
+/// Swaps two outputs together with their PSBT data; returns `false` if any
+/// of the outputs does not exist
+pub fn swap_outputs(psbt: &mut Psbt, a: usize, b: usize) -> bool {
+    if a.max(b) >= psbt.outputs.len() {
+        return false;
+    }
+    psbt.unsigned_tx.output.swap(a, b);
+    psbt.outputs.swap(a, b);
+    true
+}
+
+/// Public or extended public key with its origin, used by the global
+/// extended key map and input and output derivation maps
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct KeyOriginRow {
+    pub key: String,
+    pub fingerprint: String,
+    pub derivation: String,
+    /// Name of the tracking account owning the key; it is not a part of
+    /// PSBT data and is ignored when the row is read back
+    pub account: String,
+}
+
+/// Vendor-specific (proprietary) key-value pair
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ProprietaryRow {
+    pub prefix: String,
+    pub subtype: u8,
+    pub key: String,
+    pub value: String,
+}
+
+/// Key-value pair of a type unknown to the PSBT parser
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct UnknownRow {
+    pub type_value: u8,
+    pub key: String,
+    pub value: String,
+}
+
+/// Partial signature of a PSBT input
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SigRow {
+    pub pubkey: String,
+    pub signature: String,
+    /// Origin of the signing key from the input derivation map; it is
+    /// ignored when the row is read back
+    pub origin: String,
+}
+
+/// Hash preimage of a PSBT input; the hash is prefixed with its type, like
+/// `sha256:`
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PreimageRow {
+    pub hash: String,
+    pub preimage: String,
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, MapError> {
+    Vec::<u8>::from_hex(s.trim()).map_err(|_| MapError::Hex(s.to_owned()))
+}
+
+fn insert_unique<K: Ord, V>(
+    map: &mut BTreeMap<K, V>,
+    key: K,
+    value: V,
+    name: &str,
+) -> Result<(), MapError> {
+    match map.insert(key, value) {
+        Some(_) => Err(MapError::Duplicate(name.to_owned())),
+        None => Ok(()),
+    }
+}
+
+pub fn key_origin_rows<K: Display>(
+    map: &BTreeMap<K, KeySource>,
+) -> Vec<KeyOriginRow> {
+    map.iter()
+        .map(|(key, (fingerprint, derivation))| KeyOriginRow {
+            key: key.to_string(),
+            fingerprint: fingerprint.to_string(),
+            derivation: derivation.to_string(),
+            account: none!(),
+        })
+        .collect()
+}
+
+/// Reads key origin map from the rows. Rows with empty keys are skipped,
+/// so clearing the key removes the entry.
+pub fn key_origin_map<K: FromStr + Ord>(
+    rows: &[KeyOriginRow],
+) -> Result<BTreeMap<K, KeySource>, MapError> {
+    let mut map = bmap! {};
+    for row in rows.iter().filter(|row| !row.key.trim().is_empty()) {
+        let key = K::from_str(row.key.trim())
+            .map_err(|_| MapError::Key(row.key.clone()))?;
+        let fingerprint = parse_hex(&row.fingerprint)
+            .ok()
+            .filter(|data| data.len() == 4)
+            .map(|data| Fingerprint::from(&data[..]))
+            .ok_or_else(|| MapError::Fingerprint(row.fingerprint.clone()))?;
+        let derivation = DerivationPath::from_str(row.derivation.trim())
+            .map_err(|_| MapError::Derivation(row.derivation.clone()))?;
+        insert_unique(&mut map, key, (fingerprint, derivation), &row.key)?;
+    }
+    Ok(map)
+}
+
+pub fn proprietary_rows(
+    map: &BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+) -> Vec<ProprietaryRow> {
+    map.iter()
+        .map(|(key, value)| ProprietaryRow {
+            prefix: key.prefix.to_hex(),
+            subtype: key.subtype,
+            key: key.key.to_hex(),
+            value: value.to_hex(),
+        })
+        .collect()
+}
+
+/// Reads proprietary key map from the rows. Rows with empty prefix, key and
+/// value are skipped.
+pub fn proprietary_map(
+    rows: &[ProprietaryRow],
+) -> Result<BTreeMap<raw::ProprietaryKey, Vec<u8>>, MapError> {
+    let mut map = bmap! {};
+    for row in rows.iter().filter(|row| {
+        !(row.prefix.is_empty() && row.key.is_empty() && row.value.is_empty())
+    }) {
+        let key = raw::ProprietaryKey {
+            prefix: parse_hex(&row.prefix)?,
+            subtype: row.subtype,
+            key: parse_hex(&row.key)?,
+        };
+        let name = format!("{}:{}:{}", row.prefix, row.subtype, row.key);
+        insert_unique(&mut map, key, parse_hex(&row.value)?, &name)?;
+    }
+    Ok(map)
+}
+
+pub fn unknown_rows(map: &BTreeMap<raw::Key, Vec<u8>>) -> Vec<UnknownRow> {
+    map.iter()
+        .map(|(key, value)| UnknownRow {
+            type_value: key.type_value,
+            key: key.key.to_hex(),
+            value: value.to_hex(),
+        })
+        .collect()
+}
+
+/// Reads unknown key map from the rows. Rows with empty key and value are
+/// skipped.
+pub fn unknown_map(
+    rows: &[UnknownRow],
+) -> Result<BTreeMap<raw::Key, Vec<u8>>, MapError> {
+    let mut map = bmap! {};
+    for row in rows
+        .iter()
+        .filter(|row| !(row.key.is_empty() && row.value.is_empty()))
+    {
+        let key = raw::Key {
+            type_value: row.type_value,
+            key: parse_hex(&row.key)?,
+        };
+        let name = format!("{}:{}", row.type_value, row.key);
+        insert_unique(&mut map, key, parse_hex(&row.value)?, &name)?;
+    }
+    Ok(map)
+}
+
+pub fn sig_rows(input: &Input) -> Vec<SigRow> {
+    input
+        .partial_sigs
+        .iter()
+        .map(|(pubkey, sig)| SigRow {
+            pubkey: pubkey.to_string(),
+            signature: sig.to_vec().to_hex(),
+            origin: input
+                .bip32_derivation
+                .get(&pubkey.inner)
+                .map(|(fingerprint, derivation)| {
+                    format!("[{}]{}", fingerprint, derivation)
+                })
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Replaces input partial signatures with the ones from the rows. Rows with
+/// empty public keys are skipped.
+pub fn set_sigs(input: &mut Input, rows: &[SigRow]) -> Result<(), MapError> {
+    let mut map = bmap! {};
+    for row in rows.iter().filter(|row| !row.pubkey.trim().is_empty()) {
+        let pubkey = PublicKey::from_str(row.pubkey.trim())
+            .map_err(|_| MapError::Key(row.pubkey.clone()))?;
+        let sig = EcdsaSig::from_slice(&parse_hex(&row.signature)?)
+            .map_err(|_| MapError::Signature(row.signature.clone()))?;
+        insert_unique(&mut map, pubkey, sig, &row.pubkey)?;
+    }
+    input.partial_sigs = map;
+    Ok(())
+}
+
+fn preimages<H: Hash + Display>(
+    prefix: &str,
+    map: &BTreeMap<H, Vec<u8>>,
+) -> impl Iterator<Item = PreimageRow> + '_ {
+    let prefix = prefix.to_owned();
+    map.iter().map(move |(hash, preimage)| PreimageRow {
+        hash: format!("{}:{}", prefix, hash),
+        preimage: preimage.to_hex(),
+    })
+}
+
+pub fn preimage_rows(input: &Input) -> Vec<PreimageRow> {
+    preimages("sha256", &input.sha256_preimages)
+        .chain(preimages("hash256", &input.hash256_preimages))
+        .chain(preimages("ripemd160", &input.ripemd160_preimages))
+        .chain(preimages("hash160", &input.hash160_preimages))
+        .collect()
+}
+
+fn insert_preimage<H: Hash + FromHex + Ord>(
+    map: &mut BTreeMap<H, Vec<u8>>,
+    hash: &str,
+    preimage: Vec<u8>,
+    row: &PreimageRow,
+) -> Result<(), MapError> {
+    let hash =
+        H::from_hex(hash).map_err(|_| MapError::Hex(row.hash.clone()))?;
+    if H::hash(&preimage) != hash {
+        return Err(MapError::PreimageMismatch(row.hash.clone()));
+    }
+    insert_unique(map, hash, preimage, &row.hash)
+}
+
+/// Replaces all input hash preimages with the ones from the rows, checking
+/// that the preimages match their hashes. Rows with empty hashes are
+/// skipped.
+pub fn set_preimages(
+    input: &mut Input,
+    rows: &[PreimageRow],
+) -> Result<(), MapError> {
+    let mut sha256_preimages = bmap! {};
+    let mut hash256_preimages = bmap! {};
+    let mut ripemd160_preimages = bmap! {};
+    let mut hash160_preimages = bmap! {};
+    for row in rows.iter().filter(|row| !row.hash.trim().is_empty()) {
+        let preimage = parse_hex(&row.preimage)?;
+        match row.hash.trim().split_once(':') {
+            Some(("sha256", hash)) => insert_preimage::<sha256::Hash>(
+                &mut sha256_preimages,
+                hash,
+                preimage,
+                row,
+            )?,
+            Some(("hash256", hash)) => insert_preimage::<sha256d::Hash>(
+                &mut hash256_preimages,
+                hash,
+                preimage,
+                row,
+            )?,
+            Some(("ripemd160", hash)) => insert_preimage::<ripemd160::Hash>(
+                &mut ripemd160_preimages,
+                hash,
+                preimage,
+                row,
+            )?,
+            Some(("hash160", hash)) => insert_preimage::<hash160::Hash>(
+                &mut hash160_preimages,
+                hash,
+                preimage,
+                row,
+            )?,
+            _ => return Err(MapError::HashType(row.hash.clone())),
+        }
+    }
+    input.sha256_preimages = sha256_preimages;
+    input.hash256_preimages = hash256_preimages;
+    input.ripemd160_preimages = ripemd160_preimages;
+    input.hash160_preimages = hash160_preimages;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1;
+    use bitcoin::util::bip32::ExtendedPubKey;
+    use bitcoin::Txid;
+
+    use super::*;
+
+    static XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    static PUBKEY: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn psbt() -> Psbt {
+        let mut psbt = empty_psbt();
+        let mut input = Input::default();
+        input
+            .sha256_preimages
+            .insert(sha256::Hash::hash(b"secret"), b"secret".to_vec());
+        input.bip32_derivation.insert(
+            secp256k1::PublicKey::from_str(PUBKEY).unwrap(),
+            (
+                Fingerprint::from(&[0xd3, 0x4d, 0xb3, 0x3f][..]),
+                DerivationPath::from_str("m/84'/0'/0'/0/1").unwrap(),
+            ),
+        );
+        add_input(&mut psbt, OutPoint::default(), input);
+        add_output(
+            &mut psbt,
+            TxOut {
+                value: 1000,
+                script_pubkey: Script::new_op_return(&[]),
+            },
+        );
+        psbt.unknown.insert(
+            raw::Key {
+                type_value: 0xAB,
+                key: vec![1, 2],
+            },
+            vec![3],
+        );
+        psbt
+    }
+
+    #[test]
+    fn psbt_encodings() {
+        let psbt = psbt();
+        let binary = psbt_binary(&psbt);
+        assert!(binary.starts_with(&PSBT_MAGIC));
+        assert_eq!(parse_psbt(&binary), Ok(psbt.clone()));
+        assert_eq!(parse_psbt(psbt_base64(&psbt).as_bytes()), Ok(psbt.clone()));
+        assert_eq!(
+            parse_psbt(format!(" {}\n", binary.to_hex()).as_bytes()),
+            Ok(psbt)
+        );
+        assert_eq!(
+            parse_psbt(b"not a psbt"),
+            Err(PsbtParseError::UnknownEncoding)
+        );
+        assert!(matches!(
+            parse_psbt(&PSBT_MAGIC),
+            Err(PsbtParseError::InvalidPsbt(_))
+        ));
+    }
+
+    #[test]
+    fn map_rows_round_trip() {
+        let mut psbt = psbt();
+        psbt.xpub = key_origin_map(&[KeyOriginRow {
+            key: s!(XPUB),
+            fingerprint: s!("d34db33f"),
+            derivation: s!("m/84'/0'/0'"),
+            account: s!("ignored"),
+        }])
+        .unwrap();
+        let rows = key_origin_rows(&psbt.xpub);
+        assert_eq!(rows[0].key, XPUB);
+        assert_eq!(rows[0].account, "");
+        assert_eq!(
+            key_origin_map::<ExtendedPubKey>(&rows),
+            Ok(psbt.xpub.clone())
+        );
+
+        let rows = unknown_rows(&psbt.unknown);
+        assert_eq!(rows[0].key, "0102");
+        assert_eq!(unknown_map(&rows), Ok(psbt.unknown.clone()));
+
+        let mut rows = preimage_rows(&psbt.inputs[0]);
+        assert!(rows[0].hash.starts_with("sha256:"));
+        let mut input = psbt.inputs[0].clone();
+        set_preimages(&mut input, &rows).unwrap();
+        assert_eq!(input, psbt.inputs[0]);
+        rows[0].preimage = s!("00");
+        assert!(matches!(
+            set_preimages(&mut input, &rows),
+            Err(MapError::PreimageMismatch(_))
+        ));
+
+        // Clearing the key removes the entry
+        let rows = vec![
+            ProprietaryRow::default(),
+            ProprietaryRow {
+                prefix: s!("525342"),
+                subtype: 1,
+                key: none!(),
+                value: s!("ff"),
+            },
+        ];
+        let map = proprietary_map(&rows).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_eq!(proprietary_rows(&map), rows[1..]);
+
+        assert_eq!(
+            key_origin_map::<secp256k1::PublicKey>(&[
+                KeyOriginRow {
+                    key: s!(PUBKEY),
+                    fingerprint: s!("d34db33f"),
+                    derivation: s!("m/0"),
+                    account: none!(),
+                },
+                KeyOriginRow {
+                    key: s!(PUBKEY),
+                    fingerprint: s!("d34db33f"),
+                    derivation: s!("m/1"),
+                    account: none!(),
+                }
+            ]),
+            Err(MapError::Duplicate(s!(PUBKEY)))
+        );
+    }
+
+    #[test]
+    fn inputs_and_outputs() {
+        let mut psbt = psbt();
+        let outpoint = OutPoint::new(Txid::from_inner([1; 32]), 1);
+        add_input(&mut psbt, outpoint, Input::default());
+        assert!(swap_inputs(&mut psbt, 0, 1));
+        assert_eq!(psbt.unsigned_tx.input[0].previous_output, outpoint);
+        assert!(psbt.inputs[0].sha256_preimages.is_empty());
+        assert!(!swap_inputs(&mut psbt, 1, 2));
+        assert!(remove_input(&mut psbt, 0));
+        assert_eq!(psbt.inputs.len(), psbt.unsigned_tx.input.len());
+        assert_eq!(input_rows(&psbt)[0].signatures, "0");
+
+        let script =
+            parse_script("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        assert!(script.is_v0_p2wpkh());
+        assert_eq!(
+            parse_script(&script.as_bytes().to_hex()),
+            Ok(script.clone())
+        );
+        add_output(
+            &mut psbt,
+            TxOut {
+                value: 5000,
+                script_pubkey: script,
+            },
+        );
+        let rows = output_rows(&psbt, Some(Network::Bitcoin));
+        assert_eq!(rows[0].script, "6a00");
+        assert_eq!(
+            rows[1].script,
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert!(remove_output(&mut psbt, 0));
+        assert!(!remove_output(&mut psbt, 1));
+        assert_eq!(psbt.outputs.len(), 1);
+    }
+}
@@ -35,6 +35,9 @@ pub enum ParseError {
 
     /// Unrecognized resolver mode name {0}
     UnrecognizedTypeName(String),
+
+    /// Gap limit must be greater than zero
+    ZeroGap,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Display)]
@@ -47,8 +50,16 @@ pub enum ResolverModeType {
 
     #[display("random{0}")]
     Random(UnhardenedIndex),
+
+    /// Scans derivation indexes until the given number of consecutive
+    /// indexes without transaction history is found (BIP-44 gap limit)
+    #[display("gap{0}")]
+    Gap(UnhardenedIndex),
 }
 
+/// Gap limit recommended by BIP-44
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
 impl FromStr for ResolverModeType {
     type Err = ParseError;
 
@@ -71,6 +82,19 @@ impl FromStr for ResolverModeType {
                         .map_err(|_| ParseError::HardenedIndex)?,
                 )
             }
+        } else if let Some(s) = s.strip_prefix("gap") {
+            let gap = if s.is_empty() {
+                DEFAULT_GAP_LIMIT
+            } else {
+                u32::from_str(s)?
+            };
+            if gap == 0 {
+                return Err(ParseError::ZeroGap);
+            }
+            ResolverModeType::Gap(
+                UnhardenedIndex::from_index(gap)
+                    .map_err(|_| ParseError::HardenedIndex)?,
+            )
         } else if s == "while" {
             ResolverModeType::While
         } else {
@@ -80,11 +104,34 @@ impl FromStr for ResolverModeType {
 }
 
 impl ResolverModeType {
+    /// Gap-limit scan with the gap recommended by BIP-44
+    pub fn default_gap() -> Self {
+        ResolverModeType::Gap(
+            UnhardenedIndex::from_index(DEFAULT_GAP_LIMIT)
+                .expect("default gap limit is an unhardened index"),
+        )
+    }
+
+    /// Default lookup mode: gap-limit scan when the resolver provides
+    /// transaction history of scripts, or the first [`DEFAULT_GAP_LIMIT`]
+    /// keys otherwise
+    pub fn default_for(script_history: bool) -> Self {
+        if script_history {
+            ResolverModeType::default_gap()
+        } else {
+            ResolverModeType::First(
+                UnhardenedIndex::from_index(DEFAULT_GAP_LIMIT)
+                    .expect("default gap limit is an unhardened index"),
+            )
+        }
+    }
+
     pub fn count(self) -> usize {
         match self {
             ResolverModeType::While => 1usize,
             ResolverModeType::First(count) => count.first_index() as usize,
             ResolverModeType::Random(count) => count.first_index() as usize,
+            ResolverModeType::Gap(gap) => gap.first_index() as usize,
         }
     }
 
@@ -136,3 +183,27 @@ impl Iterator for ResolverModeIter {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mode_strings() {
+        for s in ["while", "first1", "first20", "random10", "gap20", "gap5"] {
+            assert_eq!(ResolverModeType::from_str(s).unwrap().to_string(), s);
+        }
+        assert_eq!(
+            ResolverModeType::from_str("gap"),
+            ResolverModeType::from_str("gap20")
+        );
+        assert_eq!(
+            ResolverModeType::from_str("gap0"),
+            Err(ParseError::ZeroGap)
+        );
+        assert_eq!(
+            ResolverModeType::from_str("last5"),
+            Err(ParseError::UnrecognizedTypeName(s!("last5")))
+        );
+    }
+}
@@ -0,0 +1,197 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Human-readable representations of bitcoin scripts
+
+use std::str::FromStr;
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::{Address, Network, Script, TxIn};
+use miniscript::{Legacy, Miniscript, Segwitv0};
+
+/// Form in which a script is presented
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum ScriptFormat {
+    #[display("hex")]
+    Hex,
+
+    #[display("asm")]
+    Asm,
+
+    #[display("miniscript")]
+    Miniscript,
+
+    #[display("witnessProgram")]
+    WitnessProgram,
+
+    #[display("opreturn")]
+    OpReturn,
+
+    #[display("address")]
+    Address,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display("unknown script format `{0}`")]
+pub struct ScriptFormatParseError(String);
+
+impl FromStr for ScriptFormat {
+    type Err = ScriptFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "hex" => ScriptFormat::Hex,
+            "asm" => ScriptFormat::Asm,
+            "miniscript" => ScriptFormat::Miniscript,
+            "witnessProgram" => ScriptFormat::WitnessProgram,
+            "opreturn" => ScriptFormat::OpReturn,
+            "address" => ScriptFormat::Address,
+            _ => return Err(ScriptFormatParseError(s.to_owned())),
+        })
+    }
+}
+
+/// Presents script in the given format. Returns `None` if the script can't
+/// be represented in this format (for instance, it is not a miniscript or
+/// does not have an address form). Scripts are lifted to miniscript using
+/// segwit rules if `segwit` is set and pre-segwit rules otherwise.
+pub fn format_script(
+    script: &Script,
+    format: ScriptFormat,
+    segwit: bool,
+    network: Option<Network>,
+) -> Option<String> {
+    match format {
+        ScriptFormat::Hex => Some(script.as_bytes().to_hex()),
+        ScriptFormat::Asm => Some(script.asm()),
+        ScriptFormat::Miniscript if segwit => {
+            Miniscript::<bitcoin::PublicKey, Segwitv0>::parse(script)
+                .ok()
+                .map(|ms| ms.to_string())
+        }
+        ScriptFormat::Miniscript => {
+            Miniscript::<bitcoin::PublicKey, Legacy>::parse(script)
+                .ok()
+                .map(|ms| ms.to_string())
+        }
+        ScriptFormat::WitnessProgram if script.is_witness_program() => {
+            Some(script.as_bytes()[2..].to_hex())
+        }
+        ScriptFormat::WitnessProgram => None,
+        ScriptFormat::OpReturn if script.is_op_return() => Some(
+            script
+                .instructions()
+                .filter_map(|instruction| match instruction {
+                    Ok(Instruction::PushBytes(data)) => Some(data.to_hex()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        ScriptFormat::OpReturn => None,
+        ScriptFormat::Address => network
+            .and_then(|network| Address::from_script(script, network))
+            .map(|address| address.to_string()),
+    }
+}
+
+/// Redeem script of a P2SH input, which is the last push of its sig script
+pub fn redeem_script(txin: &TxIn) -> Option<Script> {
+    match txin.script_sig.instructions().last()? {
+        Ok(Instruction::PushBytes(data)) if !data.is_empty() => {
+            Some(Script::from(data.to_vec()))
+        }
+        _ => None,
+    }
+}
+
+/// Witness script of a P2WSH input, which is the last witness stack element
+pub fn witness_script(txin: &TxIn) -> Option<Script> {
+    txin.witness
+        .last()
+        .filter(|data| !data.is_empty())
+        .map(|data| Script::from(data.to_vec()))
+}
+
+/// Witness stack elements in hex, separated with spaces
+pub fn witness_stack(txin: &TxIn) -> String {
+    txin.witness
+        .iter()
+        .map(|data| data.to_hex())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::hex::FromHex;
+
+    use super::*;
+
+    #[test]
+    fn script_formats() {
+        // P2WSH of `and_v(v:pk(K1),pk(K2))`
+        let witness_script = Script::from(
+            Vec::<u8>::from_hex(
+                "210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ad2102c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5ac",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            format_script(&witness_script, ScriptFormat::Miniscript, true, None),
+            Some(s!(
+                "and_v(v:pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798),pk(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5))"
+            ))
+        );
+
+        let script_pubkey =
+            Script::new_v0_p2wsh(&witness_script.wscript_hash());
+        assert_eq!(
+            format_script(
+                &script_pubkey,
+                ScriptFormat::WitnessProgram,
+                true,
+                None
+            ),
+            Some(witness_script.wscript_hash().to_hex())
+        );
+        assert!(format_script(
+            &script_pubkey,
+            ScriptFormat::Address,
+            true,
+            Some(Network::Bitcoin)
+        )
+        .unwrap()
+        .starts_with("bc1q"));
+        assert_eq!(
+            format_script(&script_pubkey, ScriptFormat::OpReturn, true, None),
+            None
+        );
+
+        let op_return = Script::new_op_return(&[0xde, 0xad]);
+        assert_eq!(
+            format_script(&op_return, ScriptFormat::OpReturn, false, None),
+            Some(s!("dead"))
+        );
+        assert_eq!(
+            format_script(&op_return, ScriptFormat::Hex, false, None),
+            Some(s!("6a02dead"))
+        );
+        assert_eq!("witnessProgram".parse(), Ok(ScriptFormat::WitnessProgram));
+    }
+}
@@ -0,0 +1,448 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::gdk;
+use gtk::gdk_pixbuf::{InterpType, PixbufLoader};
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use qrcode_generator::QrCodeEcc;
+use wallet::descriptors;
+
+use crate::model::{addresses_csv, AddressRow, DescriptorAccount, Document};
+use crate::view_controller::{fill_store, SaveDlg};
+
+static UI: &str = include_str!("../view/addresses.glade");
+
+/// Receive addresses of a descriptor account with the address book entries
+/// for the addresses handed out
+pub struct AddressesWin {
+    window: gtk::ApplicationWindow,
+    header_bar: gtk::HeaderBar,
+    issue_btn: gtk::Button,
+    export_btn: gtk::Button,
+    category_combo: gtk::ComboBoxText,
+    from_spin: gtk::SpinButton,
+    count_spin: gtk::SpinButton,
+    address_tree: gtk::TreeView,
+    address_store: gtk::ListStore,
+    label_renderer: gtk::CellRendererText,
+    note_renderer: gtk::CellRendererText,
+    label_column: gtk::TreeViewColumn,
+    qr_save_btn: gtk::ToolButton,
+    remove_btn: gtk::ToolButton,
+    qr_image: gtk::Image,
+    address_display: gtk::Entry,
+    account: RefCell<Option<DescriptorAccount>>,
+}
+
+impl AddressesWin {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let header_bar = builder.object("headerBar")?;
+        let issue_btn = builder.object("issueBtn")?;
+        let export_btn = builder.object("exportBtn")?;
+
+        let category_combo = builder.object("categoryCombo")?;
+        let from_spin = builder.object("fromSpin")?;
+        let count_spin = builder.object("countSpin")?;
+
+        let address_tree = builder.object("addressTree")?;
+        let address_store = builder.object("addressStore")?;
+        let label_renderer = builder.object("labelRenderer")?;
+        let note_renderer = builder.object("noteRenderer")?;
+        let label_column = builder.object("labelColumn")?;
+
+        let qr_save_btn = builder.object("qrSave")?;
+        let remove_btn = builder.object("addressRemove")?;
+        let qr_image = builder.object("qrImage")?;
+        let address_display = builder.object("addressDisplay")?;
+
+        let me = Rc::new(Self {
+            window: glade_load!(builder, "addressesWin").ok()?,
+            header_bar,
+            issue_btn,
+            export_btn,
+            category_combo,
+            from_spin,
+            count_spin,
+            address_tree,
+            address_store,
+            label_renderer,
+            note_renderer,
+            label_column,
+            qr_save_btn,
+            remove_btn,
+            qr_image,
+            address_display,
+            account: empty!(),
+        });
+
+        Some(me)
+    }
+}
+
+impl AddressesWin {
+    /// Shows the window with the addresses of the given descriptor account,
+    /// starting from the first address which was neither handed out nor
+    /// used
+    pub fn run(
+        self: Rc<Self>,
+        doc: Rc<RefCell<Document>>,
+        account: DescriptorAccount,
+    ) {
+        self.header_bar.set_subtitle(Some(&account.name()));
+        for category in account.categories() {
+            self.category_combo.append_text(&category.to_string());
+        }
+        self.category_combo.set_active(Some(0));
+        *self.account.borrow_mut() = Some(account);
+
+        self.category_combo.connect_changed(
+            clone!(@weak self as me, @strong doc => move |_| {
+                me.display(&doc.borrow());
+            }),
+        );
+        self.from_spin.connect_value_changed(
+            clone!(@weak self as me, @strong doc => move |_| {
+                me.display(&doc.borrow());
+            }),
+        );
+        self.count_spin.connect_value_changed(
+            clone!(@weak self as me, @strong doc => move |_| {
+                me.display(&doc.borrow());
+            }),
+        );
+
+        self.address_tree.selection().connect_changed(
+            clone!(@weak self as me => move |_| {
+                me.update_qr();
+                me.update_ui();
+            }),
+        );
+
+        self.label_renderer.connect_edited(
+            clone!(@weak self as me, @strong doc => move |_, path, text| {
+                me.edit_address(&doc, path, Some(text), None);
+            }),
+        );
+        self.note_renderer.connect_edited(
+            clone!(@weak self as me, @strong doc => move |_, path, text| {
+                me.edit_address(&doc, path, None, Some(text));
+            }),
+        );
+
+        self.issue_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                me.issue_next(&doc);
+            }),
+        );
+
+        self.remove_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let (account, category, index) =
+                    match me.account().zip(me.category()).zip(me.selection()) {
+                        Some(((account, category), index)) => {
+                            (account, category, index)
+                        }
+                        None => return,
+                    };
+                let result = doc
+                    .borrow_mut()
+                    .remove_address(&account, category, index);
+                if let Err(err) = result {
+                    me.show_error(&format!(
+                        "Unable to remove the address: {}",
+                        err
+                    ));
+                }
+                me.display(&doc.borrow());
+            }),
+        );
+
+        self.export_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let account = match me.account() {
+                    Some(account) => account,
+                    None => return,
+                };
+                let save_dlg = SaveDlg::load_glade().expect("Must load");
+                let name = format!("{} addresses.csv", account.name());
+                save_dlg.run(name, clone!(@weak me, @strong doc => move |path| {
+                    let csv = addresses_csv(&me.rows(&doc.borrow()));
+                    if let Err(err) = fs::write(&path, csv) {
+                        me.show_error(&format!(
+                            "Unable to export the addresses: {}",
+                            err
+                        ));
+                    }
+                }), || {})
+            }),
+        );
+
+        self.qr_save_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                let png = match me.qr_png() {
+                    Some(png) => png,
+                    None => return,
+                };
+                let save_dlg = SaveDlg::load_glade().expect("Must load");
+                let name = format!("{}.png", me.address_display.text());
+                save_dlg.run(name, clone!(@weak me => move |path| {
+                    if let Err(err) = fs::write(&path, &png) {
+                        me.show_error(&format!(
+                            "Unable to save the QR code: {}",
+                            err
+                        ));
+                    }
+                }), || {})
+            }));
+
+        self.address_display.connect_icon_press(|entry, _, _| {
+            gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD)
+                .set_text(&entry.text());
+        });
+
+        // The window owns the controller until it is closed
+        self.window.connect_delete_event(
+            clone!(@strong self as me => move |_, _| {
+                me.account.borrow_mut().take();
+                gtk::Inhibit(false)
+            }),
+        );
+
+        if let Some(account) = self.account() {
+            match doc.borrow().next_address_index(&account) {
+                Ok(next) => self.from_spin.set_value(next as f64),
+                Err(err) => self.show_error(&err.to_string()),
+            }
+        }
+        self.display(&doc.borrow());
+
+        self.window.show();
+    }
+
+    fn account(&self) -> Option<DescriptorAccount> {
+        self.account.borrow().clone()
+    }
+
+    fn category(&self) -> Option<descriptors::Category> {
+        let active = self.category_combo.active()? as usize;
+        self.account()?.categories().get(active).copied()
+    }
+
+    /// Derivation index of the selected address
+    fn selection(&self) -> Option<u32> {
+        let (model, iter) = self.address_tree.selection().selected()?;
+        model.value(&iter, 0).get::<u32>().ok()
+    }
+
+    fn rows(&self, doc: &Document) -> Vec<AddressRow> {
+        match self.account().zip(self.category()) {
+            Some((account, category)) => doc.address_rows(
+                &account,
+                category,
+                self.from_spin.value_as_int() as u32,
+                self.count_spin.value_as_int() as u32,
+            ),
+            None => vec![],
+        }
+    }
+
+    fn display(&self, doc: &Document) {
+        let selected = self.selection();
+        fill_store(&self.address_store, &self.rows(doc));
+        if let Some(index) = selected {
+            self.select(index);
+        }
+        self.update_qr();
+        self.update_ui();
+    }
+
+    /// Selects the row of the address with the given derivation index,
+    /// returning its tree path if the address is displayed
+    fn select(&self, index: u32) -> Option<gtk::TreePath> {
+        let iter = self.address_store.iter_first()?;
+        loop {
+            if self.address_store.value(&iter, 0).get::<u32>().ok()
+                == Some(index)
+            {
+                let path = self.address_store.path(&iter)?;
+                self.address_tree.selection().select_iter(&iter);
+                self.address_tree.scroll_to_cell(
+                    Some(&path),
+                    None::<&gtk::TreeViewColumn>,
+                    false,
+                    0.0,
+                    0.0,
+                );
+                return Some(path);
+            }
+            if !self.address_store.iter_next(&iter) {
+                return None;
+            }
+        }
+    }
+
+    /// Hands out the address at the edited row, or updates its address
+    /// book entry if the address was already handed out
+    fn edit_address(
+        &self,
+        doc: &Rc<RefCell<Document>>,
+        path: gtk::TreePath,
+        label: Option<&str>,
+        note: Option<&str>,
+    ) {
+        let index = match self
+            .address_store
+            .iter(&path)
+            .and_then(|iter| self.address_store.value(&iter, 0).get().ok())
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let (account, category) = match self.account().zip(self.category()) {
+            Some(data) => data,
+            None => return,
+        };
+        let entry = doc
+            .borrow()
+            .address_entry(&account, category, index)
+            .cloned();
+        let label = label
+            .map(str::to_owned)
+            .or_else(|| entry.as_ref().map(|entry| entry.label.clone()))
+            .unwrap_or_default();
+        let note = note
+            .map(str::to_owned)
+            .or_else(|| entry.map(|entry| entry.note))
+            .unwrap_or_default();
+        let result = doc
+            .borrow_mut()
+            .issue_address(&account, category, index, label, note);
+        if let Err(err) = result {
+            self.show_error(&format!("Unable to save the address: {}", err));
+        }
+        self.display(&doc.borrow());
+    }
+
+    /// Hands out the first address which was neither handed out nor used
+    /// and starts editing its label
+    fn issue_next(&self, doc: &Rc<RefCell<Document>>) {
+        let (account, category) = match self.account().zip(self.category()) {
+            Some(data) => data,
+            None => return,
+        };
+        let index = match doc.borrow().next_address_index(&account) {
+            Ok(index) => index,
+            Err(err) => {
+                self.show_error(&err.to_string());
+                return;
+            }
+        };
+        let result = doc.borrow_mut().issue_address(
+            &account,
+            category,
+            index,
+            s!(""),
+            s!(""),
+        );
+        if let Err(err) = result {
+            self.show_error(&format!("Unable to save the address: {}", err));
+            return;
+        }
+
+        let from = self.from_spin.value_as_int() as u32;
+        let count = self.count_spin.value_as_int() as u32;
+        if index < from || index >= from.saturating_add(count) {
+            // Changing the value re-displays the addresses
+            self.from_spin.set_value(index as f64);
+        } else {
+            self.display(&doc.borrow());
+        }
+        if let Some(path) = self.select(index) {
+            self.address_tree
+                .set_cursor(&path, Some(&self.label_column), true);
+        }
+    }
+
+    fn qr_png(&self) -> Option<Vec<u8>> {
+        self.selection()?;
+        let address = self.address_display.text();
+        qrcode_generator::to_png_to_vec(
+            format!("bitcoin:{}", address),
+            QrCodeEcc::Low,
+            1024,
+        )
+        .ok()
+    }
+
+    fn update_qr(&self) {
+        let address = self.address_tree.selection().selected().and_then(
+            |(model, iter)| model.value(&iter, 1).get::<String>().ok(),
+        );
+        self.address_display.set_text(&address.unwrap_or_default());
+        let pixbuf = self
+            .qr_png()
+            .and_then(|vec| {
+                let loader = PixbufLoader::new();
+                loader.write(&vec).ok()?;
+                loader.pixbuf()
+            })
+            .and_then(|pixbuf| {
+                pixbuf.scale_simple(250, 250, InterpType::Bilinear)
+            });
+        match pixbuf {
+            Some(pixbuf) => self.qr_image.set_from_pixbuf(Some(&pixbuf)),
+            None => self.qr_image.set_from_icon_name(
+                Some("image-missing"),
+                gtk::IconSize::Dialog,
+            ),
+        }
+    }
+
+    fn update_ui(&self) {
+        let is_selected = self.selection().is_some();
+        let is_issued = self
+            .address_tree
+            .selection()
+            .selected()
+            .and_then(|(model, iter)| {
+                model.value(&iter, 4).get::<String>().ok()
+            })
+            .map(|issued| !issued.is_empty())
+            .unwrap_or_default();
+        self.qr_save_btn.set_sensitive(is_selected);
+        self.remove_btn.set_sensitive(is_issued);
+        self.issue_btn.set_sensitive(self.category().is_some());
+    }
+
+    fn show_error(&self, msg: &str) {
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Close,
+            msg,
+        );
+        dlg.run();
+        dlg.hide();
+    }
+}
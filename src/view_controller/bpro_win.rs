@@ -20,6 +20,8 @@ use gtk::gdk_pixbuf::{InterpType, PixbufLoader};
 use gtk::prelude::*;
 use qrcode_generator::QrCodeEcc;
 use std::cell::RefCell;
+use std::fs;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
@@ -27,9 +29,17 @@ use std::str::FromStr;
 use bitcoin::{OutPoint, Txid};
 use rgb::{Consignment, ContractId, ToBech32};
 use rgb20::SupplyMeasure;
+use wallet::descriptors;
 
-use crate::model::Document;
-use crate::view_controller::{AssetDlg, DescriptorDlg, PubkeyDlg, SaveDlg};
+use crate::controller::history_lookup::HistoryLookup;
+use crate::controller::utxo_refresh::UtxoRefresh;
+use crate::model::encryption::EncryptionError;
+use crate::model::{self, CoreConnection, Document, DocumentChange, LogFormat};
+use crate::util::resolver_mode::ResolverModeType;
+use crate::view_controller::{
+    fill_store, AddressesWin, AssetDlg, DescriptorDlg, PassphraseDlg,
+    PsbtComposeDlg, PsbtWin, PubkeyDlg, SaveDlg, TransactionWin,
+};
 
 static UI: &str = include_str!("../view/bpro.glade");
 
@@ -56,19 +66,37 @@ pub struct BproWin {
     utxo_descr_store: gtk::ListStore,
     utxo_tree: gtk::TreeView,
     utxo_store: gtk::ListStore,
+    history_descr_tree: gtk::TreeView,
+    history_descr_store: gtk::ListStore,
+    tx_tree: gtk::TreeView,
+    tx_store: gtk::ListStore,
+    psbt_tree: gtk::TreeView,
+    psbt_store: gtk::ListStore,
     asset_tree: gtk::TreeView,
     asset_store: gtk::ListStore,
+    log_store: gtk::ListStore,
     header_bar: gtk::HeaderBar,
     new_btn: gtk::Button,
     open_btn: gtk::Button,
+    undo_btn: gtk::Button,
+    redo_btn: gtk::Button,
     pubkey_edit_btn: gtk::ToolButton,
     pubkey_remove_btn: gtk::ToolButton,
     descriptor_edit_btn: gtk::ToolButton,
+    descriptor_addresses_btn: gtk::ToolButton,
     descriptor_remove_btn: gtk::ToolButton,
     utxo_descr_remove_btn: gtk::ToolButton,
     utxo_descr_clear_btn: gtk::ToolButton,
+    utxo_refresh_btn: gtk::ToolButton,
     utxo_remove_btn: gtk::ToolButton,
+    history_descr_refresh_btn: gtk::ToolButton,
+    tx_open_btn: gtk::ToolButton,
+    psbt_new_btn: gtk::ToolButton,
+    psbt_compose_btn: gtk::ToolButton,
+    psbt_edit_btn: gtk::ToolButton,
+    psbt_remove_btn: gtk::ToolButton,
     asset_remove_btn: gtk::ToolButton,
+    log_export_btn: gtk::ToolButton,
     asset_id_display: gtk::Entry,
     asset_genesis_display: gtk::Entry,
     asset_contract_display: gtk::TextBuffer,
@@ -92,16 +120,28 @@ impl BproWin {
 
         let new_btn: gtk::Button = builder.object("new")?;
         let open_btn: gtk::Button = builder.object("open")?;
+        let undo_btn: gtk::Button = builder.object("undo")?;
+        let redo_btn: gtk::Button = builder.object("redo")?;
         let header_bar: gtk::HeaderBar = builder.object("headerBar")?;
 
         let pubkey_edit_btn = builder.object("pubkeyEdit")?;
         let pubkey_remove_btn = builder.object("pubkeyRemove")?;
         let descriptor_edit_btn = builder.object("descriptorEdit")?;
+        let descriptor_addresses_btn = builder.object("descriptorAddresses")?;
         let descriptor_remove_btn = builder.object("descriptorRemove")?;
         let utxo_descr_remove_btn = builder.object("utxoDescrRemove")?;
         let utxo_descr_clear_btn = builder.object("utxoDescrClear")?;
+        let utxo_refresh_btn = builder.object("utxoRefresh")?;
         let utxo_remove_btn = builder.object("utxoRemove")?;
+        let history_descr_refresh_btn =
+            builder.object("historyDescrRefresh")?;
+        let tx_open_btn = builder.object("txOpen")?;
+        let psbt_new_btn = builder.object("psbtNew")?;
+        let psbt_compose_btn = builder.object("psbtCompose")?;
+        let psbt_edit_btn = builder.object("psbtEdit")?;
+        let psbt_remove_btn = builder.object("psbtRemove")?;
         let asset_remove_btn = builder.object("assetRemove")?;
+        let log_export_btn = builder.object("logExport")?;
 
         let pubkey_tree = builder.object("pubkeyTree")?;
         let pubkey_store = builder.object("pubkeyStore")?;
@@ -111,8 +151,15 @@ impl BproWin {
         let utxo_descr_store = builder.object("utxoDescrStore")?;
         let utxo_tree = builder.object("utxoTree")?;
         let utxo_store = builder.object("utxoStore")?;
+        let history_descr_tree = builder.object("historyDescrTree")?;
+        let history_descr_store = builder.object("historyDescrStore")?;
+        let tx_tree = builder.object("txTree")?;
+        let tx_store = builder.object("txStore")?;
+        let psbt_tree = builder.object("psbtTree")?;
+        let psbt_store = builder.object("psbtStore")?;
         let asset_tree = builder.object("assetTree")?;
         let asset_store = builder.object("assetStore")?;
+        let log_store = builder.object("logStore")?;
 
         let asset_id_display = builder.object("assetIdDisplay")?;
         let asset_genesis_display = builder.object("assetGenesisDisplay")?;
@@ -123,20 +170,32 @@ impl BproWin {
         let asset_qr_image = builder.object("assetQR")?;
 
         let chain_combo: gtk::ComboBox = builder.object("chainCombo")?;
+        let core_radio: gtk::RadioButton = builder.object("bitcoinCore")?;
+        let core_field: gtk::Entry = builder.object("bitcoinCoreField")?;
+        let core_btn: gtk::Button = builder.object("bitcoinCoreBtn")?;
         let electrum_radio: gtk::RadioButton = builder.object("electrum")?;
         let electrum_field: gtk::Entry = builder.object("electrumField")?;
         let electrum_btn: gtk::Button = builder.object("electrumBtn")?;
 
-        doc.borrow().fill_tracking_store(&pubkey_store);
-        doc.borrow().fill_descriptor_store(&descriptor_store);
-        doc.borrow().fill_utxo_store(&utxo_store, None);
-        doc.borrow().fill_asset_store(&asset_store);
+        fill_store(&pubkey_store, &doc.borrow().tracking_rows());
+        fill_store(&descriptor_store, &doc.borrow().descriptor_rows());
+        fill_store(&utxo_store, &doc.borrow().utxo_rows(None));
+        fill_store(&tx_store, &doc.borrow().transaction_rows());
+        fill_store(&psbt_store, &doc.borrow().psbt_rows());
+        fill_store(&asset_store, &doc.borrow().asset_rows());
+        fill_store(&log_store, &doc.borrow().log_rows());
 
         header_bar.set_subtitle(Some(&doc.borrow().name()));
 
         chain_combo.set_active_id(Some(&doc.borrow().chain().to_string()));
-        electrum_radio.set_active(true);
-        electrum_field.set_text(&doc.borrow().electrum().unwrap_or_default());
+        if let Some(connection) = doc.borrow().bitcoin_core() {
+            core_radio.set_active(true);
+            core_field.set_text(&connection.to_string());
+        } else {
+            electrum_radio.set_active(true);
+            electrum_field
+                .set_text(&doc.borrow().electrum().unwrap_or_default());
+        }
 
         let me = Rc::new(RefCell::new(Self {
             window: glade_load!(builder, "appWindow").ok()?,
@@ -148,19 +207,37 @@ impl BproWin {
             utxo_descr_store,
             utxo_tree,
             utxo_store,
+            history_descr_tree,
+            history_descr_store,
+            tx_tree,
+            tx_store,
+            psbt_tree,
+            psbt_store,
             asset_tree,
             asset_store,
+            log_store,
             header_bar,
             new_btn,
             open_btn,
+            undo_btn,
+            redo_btn,
             pubkey_edit_btn,
             pubkey_remove_btn,
             descriptor_edit_btn,
+            descriptor_addresses_btn,
             descriptor_remove_btn,
             utxo_descr_remove_btn,
             utxo_descr_clear_btn,
+            utxo_refresh_btn,
             utxo_remove_btn,
+            history_descr_refresh_btn,
+            tx_open_btn,
+            psbt_new_btn,
+            psbt_compose_btn,
+            psbt_edit_btn,
+            psbt_remove_btn,
             asset_remove_btn,
+            log_export_btn,
             asset_id_display,
             asset_genesis_display,
             asset_contract_display,
@@ -170,6 +247,16 @@ impl BproWin {
             asset_qr_image,
         }));
 
+        me.borrow().update_undo(&doc.borrow());
+
+        // Views are refreshed once the document modification completes and
+        // the document is not mutably borrowed anymore
+        doc.borrow_mut().subscribe(clone!(@weak me, @weak doc => move |_, change| {
+            glib::idle_add_local_once(clone!(@weak me, @weak doc => move || {
+                me.borrow().refresh(&doc.borrow(), change);
+            }));
+        }));
+
         chain_combo.connect_changed(
             clone!(@weak chain_combo, @strong doc => move |_| {
                 if let Some(chain_name) = chain_combo.active_id() {
@@ -179,44 +266,51 @@ impl BproWin {
         );
 
         electrum_field.connect_changed(
-            clone!(@strong doc, @weak electrum_field => move |_| {
-                match electrum_field.text().to_string().parse() {
-                    Ok(addr) => {
-                        electrum_field.set_secondary_icon_name(None);
-                        electrum_field.set_secondary_icon_tooltip_text(
-                            Some("")
-                        );
+            clone!(@strong doc, @weak electrum_radio => move |field| {
+                if let Some(addr) = parse_field::<SocketAddr>(field) {
+                    if electrum_radio.is_active() {
                         let _ = doc.borrow_mut().set_electrum(addr);
                     }
-                    Err(err) => {
-                        electrum_field.set_secondary_icon_name(
-                            Some("dialog-error")
-                        );
-                        electrum_field.set_secondary_icon_tooltip_text(
-                            Some(&err.to_string())
-                        );
+                }
+            }),
+        );
+
+        core_field.connect_changed(
+            clone!(@strong doc, @weak core_radio => move |field| {
+                let connection = parse_field::<CoreConnection>(field);
+                if let Some(connection) = connection {
+                    if core_radio.is_active() {
+                        let _ = doc.borrow_mut().set_bitcoin_core(connection);
                     }
                 }
             }),
         );
 
+        electrum_radio.connect_toggled(
+            clone!(@weak electrum_field => move |radio| {
+                if radio.is_active() {
+                    electrum_field.emit_by_name::<()>("changed", &[]);
+                }
+            }),
+        );
+
+        core_radio.connect_toggled(
+            clone!(@weak core_field => move |radio| {
+                if radio.is_active() {
+                    core_field.emit_by_name::<()>("changed", &[]);
+                }
+            }),
+        );
+
         electrum_btn.connect_clicked(
             clone!(@strong doc, @weak electrum_field => move |_| {
-                if let Err(err) = doc.borrow().resolver() {
-                    electrum_field.set_secondary_icon_name(
-                        Some("dialog-error")
-                    );
-                    electrum_field.set_secondary_icon_tooltip_text(
-                        Some(&err.to_string())
-                    );
-                } else {
-                    electrum_field.set_secondary_icon_name(
-                        Some("dialog-ok")
-                    );
-                    electrum_field.set_secondary_icon_tooltip_text(
-                        Some("")
-                    );
-                }
+                test_resolver(&doc.borrow(), &electrum_field);
+            }),
+        );
+
+        core_btn.connect_clicked(
+            clone!(@strong doc, @weak core_field => move |_| {
+                test_resolver(&doc.borrow(), &core_field);
             }),
         );
 
@@ -237,13 +331,8 @@ impl BproWin {
         tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let pubkey_dlg = PubkeyDlg::load_glade().expect("Must load");
             let chain = doc.borrow().chain().clone();
-            pubkey_dlg.run(None, &chain, clone!(@weak me, @strong doc =>
+            pubkey_dlg.run(None, &chain, clone!(@strong doc =>
                 move |tracking_account| {
-                    let me = me.borrow();
-                    me.pubkey_store.insert_with_values(
-                        None,
-                        &[(0, &tracking_account.name()), (1, &tracking_account.details()), (2, &tracking_account.count())]
-                    );
                     let _ = doc.borrow_mut().add_tracking_account(tracking_account);
                 }),
                 || {},
@@ -253,18 +342,14 @@ impl BproWin {
         me.borrow().pubkey_edit_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let meb = me.borrow();
             let pubkey_dlg = PubkeyDlg::load_glade().expect("Must load");
-            if let Some((keyname, _, iter)) = meb.pubkey_selection() {
+            if let Some((keyname, _, _)) = meb.pubkey_selection() {
                 let tracking_account = doc
                     .borrow()
                     .tracking_account_by_key(&keyname)
                     .expect("Tracking account must be known since it is selected");
                 let chain = doc.borrow().chain().clone();
-                pubkey_dlg.run(Some(tracking_account.clone()), &chain, clone!(@weak me, @strong doc =>
+                pubkey_dlg.run(Some(tracking_account.clone()), &chain, clone!(@strong doc =>
                     move |new_tracking_account| {
-                        let me = me.borrow();
-                        me.pubkey_store.set_value(&iter, 0, &new_tracking_account.name().to_value());
-                        me.pubkey_store.set_value(&iter, 1, &new_tracking_account.details().to_value());
-                        me.pubkey_store.set_value(&iter, 2, &new_tracking_account.count().to_value());
                         let _ = doc.borrow_mut().update_tracking_account(&tracking_account, new_tracking_account);
                     }),
                     || {},
@@ -274,7 +359,7 @@ impl BproWin {
 
         me.borrow().pubkey_remove_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let me = me.borrow();
-            if let Some((keyname, _, iter)) = me.pubkey_selection() {
+            if let Some((keyname, _, _)) = me.pubkey_selection() {
                 let tracking_account = doc
                     .borrow()
                     .tracking_account_by_key(&keyname)
@@ -290,7 +375,6 @@ impl BproWin {
                     )
                 );
                 if dlg.run() == gtk::ResponseType::Yes {
-                    me.pubkey_store.remove(&iter);
                     let _ = doc.borrow_mut().remove_tracking_account(tracking_account);
                 }
                 dlg.hide();
@@ -300,35 +384,21 @@ impl BproWin {
         me.borrow().descriptor_tree.selection().connect_changed(
             clone!(@weak me, @strong doc => move |_| {
                 let me = me.borrow();
-                me.utxo_descr_store.clear();
-                if let Some((generator, _, _)) = me.descriptor_selection() {
-                    if let Some(descriptor_generator) = doc.borrow().descriptor_by_generator(&generator) {
-                        doc.borrow().fill_utxo_store(&me.utxo_descr_store, Some(&descriptor_generator));
-                    }
-                    me.descriptor_edit_btn.set_sensitive(true);
-                    me.descriptor_remove_btn.set_sensitive(true);
-                } else {
-                    me.descriptor_edit_btn.set_sensitive(false);
-                    me.descriptor_remove_btn.set_sensitive(false);
-                }
-                me.utxo_descr_clear_btn.set_sensitive(me.utxo_descr_store.iter_first().is_some());
+                let is_selected = me.descriptor_selection().is_some();
+                me.descriptor_edit_btn.set_sensitive(is_selected);
+                me.descriptor_addresses_btn.set_sensitive(is_selected);
+                me.descriptor_remove_btn.set_sensitive(is_selected);
+                me.history_descr_refresh_btn.set_sensitive(is_selected);
+                me.refresh_descriptor_utxos(&doc.borrow());
+                me.refresh_descriptor_history(&doc.borrow());
             }),
         );
 
         let tb: gtk::ToolButton = builder.object("descriptorAdd")?;
         tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let descriptor_dlg = DescriptorDlg::load_glade().expect("Must load");
-            descriptor_dlg.run(doc.clone(), None, clone!(@weak me, @strong doc =>
+            descriptor_dlg.run(doc.clone(), None, clone!(@strong doc =>
                 move |descriptor_generator, utxo_set_update| {
-                    let me = me.borrow();
-                    me.descriptor_store.insert_with_values(
-                        None,
-                        &[
-                            (0, &descriptor_generator.name()),
-                            (1, &descriptor_generator.type_name()),
-                            (2, &descriptor_generator.descriptor()),
-                        ],
-                    );
                     let _ = doc.borrow_mut().add_descriptor(descriptor_generator);
                     let _ = doc.borrow_mut().update_utxo_set(utxo_set_update);
                 }),
@@ -339,31 +409,42 @@ impl BproWin {
         me.borrow().descriptor_edit_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let meb = me.borrow();
             let descriptor_dlg = DescriptorDlg::load_glade().expect("Must load");
-            if let Some((generator, _, iter)) = meb.descriptor_selection() {
+            if let Some((generator, _, _)) = meb.descriptor_selection() {
                 let descriptor_generator = doc
                     .borrow()
                     .descriptor_by_generator(&generator)
                     .expect("Descriptor account must be known since it is selected");
-                descriptor_dlg.run(doc.clone(), Some(descriptor_generator.clone()), clone!(@weak me, @strong doc =>
+                if let descriptors::Template::MuSigBranched(_) = descriptor_generator.generator.template {
+                    // The dialog does not support MuSig2 templates
+                    Self::show_error("MuSig2 accounts can't be edited; they are imported from output descriptors");
+                    return;
+                }
+                descriptor_dlg.run(doc.clone(), Some(descriptor_generator.clone()), clone!(@strong doc =>
                     move |new_descriptor_generator, utxo_set_update| {
-                        let me = me.borrow();
-                        me.utxo_descr_clear_btn.set_sensitive(!utxo_set_update.is_empty());
-                        me.descriptor_store.set_value(&iter, 0, &new_descriptor_generator.name().to_value());
-                        me.descriptor_store.set_value(&iter, 1, &new_descriptor_generator.type_name().to_value());
-                        me.descriptor_store.set_value(&iter, 2, &new_descriptor_generator.descriptor().to_value());
                         let _ = doc.borrow_mut().update_descriptor(&descriptor_generator, new_descriptor_generator);
                         let _ = doc.borrow_mut().update_utxo_set(utxo_set_update);
-                        doc.borrow().fill_utxo_store(&me.utxo_descr_store, Some(&descriptor_generator));
-                        doc.borrow().fill_utxo_store(&me.utxo_store, None);
                     }),
                     || {},
                 );
             }
         }));
 
+        me.borrow().descriptor_addresses_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            if let Some((generator, _, _)) = me.descriptor_selection() {
+                let descriptor_generator = doc
+                    .borrow()
+                    .descriptor_by_generator(&generator)
+                    .expect("Descriptor account must be known since it is selected");
+                if let Some(win) = AddressesWin::load_glade() {
+                    win.run(doc.clone(), descriptor_generator);
+                }
+            }
+        }));
+
         me.borrow().descriptor_remove_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let me = me.borrow();
-            if let Some((generator, _, iter)) = me.descriptor_selection() {
+            if let Some((generator, _, _)) = me.descriptor_selection() {
                 let descriptor_generator = doc
                     .borrow()
                     .descriptor_by_generator(&generator)
@@ -380,7 +461,6 @@ impl BproWin {
                     )
                 );
                 if dlg.run() == gtk::ResponseType::Yes {
-                    me.descriptor_store.remove(&iter);
                     let _ = doc.borrow_mut().remove_descriptor(descriptor_generator);
                 }
                 dlg.hide();
@@ -396,7 +476,7 @@ impl BproWin {
 
         me.borrow().utxo_descr_remove_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let me = me.borrow();
-            if let Some((outpoint, _, iter)) = Self::utxo_selection(&me.utxo_descr_tree) {
+            if let Some((outpoint, _, _)) = Self::utxo_selection(&me.utxo_descr_tree) {
                 let utxo = doc
                     .borrow()
                     .utxo_by_outpoint(outpoint)
@@ -409,9 +489,7 @@ impl BproWin {
                     &format!("Please confirm deletion of {}", utxo)
                 );
                 if dlg.run() == gtk::ResponseType::Yes {
-                    me.utxo_descr_store.remove(&iter);
                     let _ = doc.borrow_mut().remove_utxo(utxo);
-                    doc.borrow().fill_utxo_store(&me.utxo_store, None);
                 }
                 dlg.hide();
             }
@@ -432,15 +510,89 @@ impl BproWin {
                     &format!("Please confirm deletion of all UTXOs for {}", generator)
                 );
                 if dlg.run() == gtk::ResponseType::Yes {
-                    me.utxo_descr_store.clear();
                     let _ = doc.borrow_mut().remove_utxo_by_descriptor(descriptor_generator);
-                    doc.borrow().fill_utxo_store(&me.utxo_store, None);
-                    me.utxo_descr_clear_btn.set_sensitive(false);
                 }
                 dlg.hide();
             }
         }));
 
+        me.borrow().history_descr_refresh_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            if let Some((generator, _, _)) = me.descriptor_selection() {
+                let account = doc
+                    .borrow()
+                    .descriptor_by_generator(&generator)
+                    .expect("Descriptor must be known since it is selected");
+                let update = doc
+                    .borrow()
+                    .resolver()
+                    .map_err(|err| err.to_string())
+                    .and_then(|resolver| {
+                        me.history_lookup(
+                            &*resolver,
+                            ResolverModeType::default_gap(),
+                            &account,
+                            |txid| doc.borrow().is_transaction_known(txid),
+                        )
+                        .map_err(|err| err.to_string())
+                    });
+                match update {
+                    Ok(update) => {
+                        let mut doc = doc.borrow_mut();
+                        let _ = doc.update_tx_history(update.transactions, update.entries);
+                        let _ = doc.update_last_used_indexes(&account, &update.last_used_indexes);
+                    }
+                    Err(err) => Self::show_error(&err),
+                }
+            }
+        }));
+
+        me.borrow().utxo_refresh_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let me = me.borrow();
+            let reconciliation = doc
+                .borrow()
+                .resolver()
+                .map_err(|err| err.to_string())
+                .and_then(|resolver| {
+                    let doc = doc.borrow();
+                    me.utxo_refresh(
+                        &*resolver,
+                        doc.descriptors(),
+                        doc.utxos(None),
+                        |txid| doc.transaction(txid).cloned(),
+                    )
+                    .map_err(|err| err.to_string())
+                });
+            match reconciliation {
+                Ok(reconciliation) => {
+                    let msg = format!(
+                        "{} outputs are unspent, {} were spent and removed; heights of {} outputs were updated",
+                        reconciliation.unspent,
+                        reconciliation.spent.len(),
+                        reconciliation.moved.len(),
+                    );
+                    {
+                        let mut doc = doc.borrow_mut();
+                        let _ = doc.reconcile_utxos(
+                            reconciliation.moved,
+                            reconciliation.spent.into_iter().map(|spent| spent.utxo).collect(),
+                        );
+                        let _ = doc.update_tx_history(reconciliation.transactions, reconciliation.entries);
+                    }
+                    let dlg = gtk::MessageDialog::new(
+                        Some(&me.window),
+                        gtk::DialogFlags::MODAL,
+                        gtk::MessageType::Info,
+                        gtk::ButtonsType::Close,
+                        &msg,
+                    );
+                    dlg.run();
+                    dlg.hide();
+                }
+                Err(err) => Self::show_error(&err),
+            }
+        }));
+
         me.borrow().utxo_tree.selection().connect_changed(
             clone!(@weak me => move |_| {
                 let me = me.borrow();
@@ -450,7 +602,7 @@ impl BproWin {
 
         me.borrow().utxo_remove_btn.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let me = me.borrow();
-            if let Some((outpoint, _, iter)) = Self::utxo_selection(&me.utxo_tree) {
+            if let Some((outpoint, _, _)) = Self::utxo_selection(&me.utxo_tree) {
                 let utxo = doc
                     .borrow()
                     .utxo_by_outpoint(outpoint)
@@ -463,23 +615,117 @@ impl BproWin {
                     &format!("Please confirm deletion of {}", utxo)
                 );
                 if dlg.run() == gtk::ResponseType::Yes {
-                    me.utxo_store.remove(&iter);
                     let _ = doc.borrow_mut().remove_utxo(utxo);
-                    if let Some((generator, _, _)) = me.descriptor_selection() {
-                        let descriptor_generator = doc
-                            .borrow()
-                            .descriptor_by_generator(&generator)
-                            .expect("Descriptor must be known since it is selected");
-                        doc.borrow().fill_utxo_store(&me.utxo_descr_store, Some(&descriptor_generator));
-                    } else {
-                        me.utxo_descr_store.clear();
-                    }
-                    me.utxo_descr_clear_btn.set_sensitive(me.utxo_descr_store.iter_first().is_some());
                 }
                 dlg.hide();
             }
         }));
 
+        me.borrow().utxo_descr_tree.connect_row_activated(
+            clone!(@strong doc => move |tree, _, _| {
+                if let Some((outpoint, _, _)) = Self::utxo_selection(tree) {
+                    Self::open_transaction(doc.clone(), Some(outpoint.txid));
+                }
+            }),
+        );
+
+        me.borrow().utxo_tree.connect_row_activated(
+            clone!(@strong doc => move |tree, _, _| {
+                if let Some((outpoint, _, _)) = Self::utxo_selection(tree) {
+                    Self::open_transaction(doc.clone(), Some(outpoint.txid));
+                }
+            }),
+        );
+
+        me.borrow().history_descr_tree.connect_row_activated(
+            clone!(@strong doc => move |tree, _, _| {
+                if let Some((txid, _, _)) = Self::txid_selection(tree) {
+                    Self::open_transaction(doc.clone(), Some(txid));
+                }
+            }),
+        );
+
+        me.borrow().tx_tree.connect_row_activated(
+            clone!(@strong doc => move |tree, _, _| {
+                if let Some((txid, _, _)) = Self::txid_selection(tree) {
+                    Self::open_transaction(doc.clone(), Some(txid));
+                }
+            }),
+        );
+
+        me.borrow().tx_open_btn.connect_clicked(
+            clone!(@strong doc => move |_| {
+                Self::open_transaction(doc.clone(), None);
+            }),
+        );
+
+        me.borrow().psbt_tree.selection().connect_changed(
+            clone!(@weak me => move |_| {
+                me.borrow().update_ui();
+            }),
+        );
+
+        me.borrow().psbt_tree.connect_row_activated(
+            clone!(@strong doc => move |tree, path, _| {
+                let pos = path.indices().first().map(|pos| *pos as usize);
+                Self::open_psbt(doc.clone(), pos);
+            }),
+        );
+
+        me.borrow().psbt_new_btn.connect_clicked(
+            clone!(@strong doc => move |_| {
+                Self::open_psbt(doc.clone(), None);
+            }),
+        );
+
+        me.borrow().psbt_compose_btn.connect_clicked(
+            clone!(@strong doc => move |_| {
+                let compose_dlg =
+                    PsbtComposeDlg::load_glade().expect("Must load");
+                compose_dlg.run(
+                    doc.clone(),
+                    clone!(@strong doc => move |composition| {
+                        let pos = doc.borrow().psbts().len();
+                        let result = doc.borrow_mut().add_composed_psbt(
+                            composition.psbt,
+                            composition.change_link.as_ref(),
+                        );
+                        if result.is_ok() {
+                            Self::open_psbt(doc.clone(), Some(pos));
+                        }
+                    }),
+                    || {},
+                );
+            }),
+        );
+
+        me.borrow().psbt_edit_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                if let Some(pos) = me.borrow().psbt_selection() {
+                    Self::open_psbt(doc.clone(), Some(pos));
+                }
+            }),
+        );
+
+        me.borrow().psbt_remove_btn.connect_clicked(
+            clone!(@weak me, @strong doc => move |_| {
+                let me = me.borrow();
+                if let Some(pos) = me.psbt_selection() {
+                    let dlg = gtk::MessageDialog::new(
+                        Some(&me.window),
+                        gtk::DialogFlags::MODAL,
+                        gtk::MessageType::Question,
+                        gtk::ButtonsType::YesNo,
+                        "Please confirm deletion of the selected PSBT",
+                    );
+                    if dlg.run() == gtk::ResponseType::Yes {
+                        let _ = doc.borrow_mut().remove_psbt_at(pos);
+                    }
+                    dlg.hide();
+                }
+            }),
+        );
+
         me.borrow().asset_tree.selection().connect_changed(
             clone!(@weak me, @strong doc => move |_| {
                 let me = me.borrow();
@@ -518,26 +764,9 @@ impl BproWin {
         let tb: gtk::ToolButton = builder.object("assetCreate")?;
         tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
             let issue_dlg = AssetDlg::load_glade().expect("Must load");
-            issue_dlg.run(doc.clone(), None, clone!(@weak me, @strong doc =>
-                move |asset, genesis| {
-                    let contract_id = genesis.contract_id();
+            issue_dlg.run(doc.clone(), None, clone!(@strong doc =>
+                move |_, genesis| {
                     let consignment = Consignment::with(genesis, none!(), none!(), none!());
-                    let me = me.borrow();
-                    me.asset_store.insert_with_values(
-                        None,
-                        &[
-                            (0, &asset.ticker()),
-                            (1, &asset.name()),
-                            (2, &asset.known_filtered_accounting_value(|allocation| {
-                                doc.borrow().is_outpoint_known(*allocation.outpoint())
-                            })),
-                            (3, &asset.accounting_supply(SupplyMeasure::KnownCirculating)),
-                            (4, &1),
-                            (5, &(!asset.known_inflation().is_empty())),
-                            (6, &0),
-                            (7, &contract_id.to_string())
-                        ],
-                    );
                     let _ = doc.borrow_mut().add_asset(consignment);
                 }),
                 || {},
@@ -547,7 +776,7 @@ impl BproWin {
         me.borrow().asset_remove_btn.connect_clicked(
             clone!(@weak me, @strong doc => move |_| {
                 let me = me.borrow();
-                if let Some((contract_id, _, iter)) = me.asset_selection() {
+                if let Some((contract_id, _, _)) = me.asset_selection() {
                     let dlg = gtk::MessageDialog::new(
                         Some(&me.window),
                         gtk::DialogFlags::MODAL,
@@ -559,7 +788,6 @@ impl BproWin {
                         )
                     );
                     if dlg.run() == gtk::ResponseType::Yes {
-                        me.asset_store.remove(&iter);
                         let _ = doc.borrow_mut().remove_asset(contract_id);
                     }
                     dlg.hide();
@@ -582,6 +810,82 @@ impl BproWin {
             }));
         }
 
+        let tb: gtk::Button = builder.object("passphrase")?;
+        tb.connect_clicked(clone!(@weak me, @strong doc => move |_| {
+            let passphrase_dlg = PassphraseDlg::load_glade().expect("Must load");
+            let is_encrypted = doc.borrow().is_encrypted();
+            passphrase_dlg.run(is_encrypted, clone!(@weak me, @strong doc => move |passphrase| {
+                if let Err(err) = doc.borrow_mut().set_passphrase(passphrase.as_deref()) {
+                    let me = me.borrow();
+                    let dlg = gtk::MessageDialog::new(
+                        Some(&me.window),
+                        gtk::DialogFlags::MODAL,
+                        gtk::MessageType::Error,
+                        gtk::ButtonsType::Close,
+                        &format!("Unable to change document passphrase: {}", err),
+                    );
+                    dlg.run();
+                    dlg.hide();
+                }
+            }), || {});
+        }));
+
+        me.borrow().log_export_btn.connect_clicked(
+            clone!(@strong doc => move |_| {
+                let save_dlg = SaveDlg::load_glade().expect("Must load");
+                let name = format!("{} log.csv", doc.borrow().name());
+                save_dlg.run(name, clone!(@strong doc => move |path| {
+                    let format =
+                        LogFormat::with_path(&path).unwrap_or(LogFormat::Csv);
+                    let result = doc
+                        .borrow()
+                        .export_log(format)
+                        .map_err(|err| err.to_string())
+                        .and_then(|log| {
+                            fs::write(&path, log)
+                                .map_err(|err| err.to_string())
+                        });
+                    if let Err(err) = result {
+                        Self::show_error(&format!(
+                            "Unable to export the operation log: {}",
+                            err
+                        ));
+                    }
+                }), || {})
+            }),
+        );
+
+        let accel_group = gtk::AccelGroup::new();
+        me.borrow().window.add_accel_group(&accel_group);
+        let undo_btn = me.borrow().undo_btn.clone();
+        let redo_btn = me.borrow().redo_btn.clone();
+        for (btn, accelerator) in [
+            (&undo_btn, "<Primary>z"),
+            (&redo_btn, "<Primary><Shift>z"),
+            (&redo_btn, "<Primary>y"),
+        ] {
+            let (key, modifier) = gtk::accelerator_parse(accelerator);
+            btn.add_accelerator(
+                "clicked",
+                &accel_group,
+                key,
+                modifier,
+                gtk::AccelFlags::VISIBLE,
+            );
+        }
+
+        undo_btn.connect_clicked(clone!(@strong doc => move |_| {
+            if let Err(err) = doc.borrow_mut().undo() {
+                Self::show_error(&format!("Unable to undo the change: {}", err));
+            }
+        }));
+
+        redo_btn.connect_clicked(clone!(@strong doc => move |_| {
+            if let Err(err) = doc.borrow_mut().redo() {
+                Self::show_error(&format!("Unable to redo the change: {}", err));
+            }
+        }));
+
         let tb: gtk::Button = builder.object("save")?;
         tb.set_sensitive(needs_save);
         tb.connect_clicked(clone!(@strong doc, @weak tb => move |_| {
@@ -601,9 +905,42 @@ impl BproWin {
 }
 
 impl BproWin {
-    pub fn new(path: Option<PathBuf>) -> Option<Rc<RefCell<Self>>> {
+    pub fn new(
+        path: Option<PathBuf>,
+        passphrase: Option<String>,
+    ) -> Option<Rc<RefCell<Self>>> {
         let doc = if let Some(path) = path {
-            Some(Document::load(path).ok()?)
+            let mut passphrase = passphrase;
+            loop {
+                match Document::load_with_passphrase(
+                    path.clone(),
+                    passphrase.as_deref(),
+                ) {
+                    Ok(doc) => break Some(doc),
+                    Err(model::Error::Encryption(
+                        err @ (EncryptionError::PassphraseRequired
+                        | EncryptionError::WrongPassphrase),
+                    )) => {
+                        passphrase = Some(Self::ask_passphrase(&format!(
+                            "{}.\n\nPlease enter the document passphrase.",
+                            err
+                        ))?);
+                    }
+                    Err(err)
+                        if err.is_damaged_file()
+                            && !Document::backups(&path).is_empty() =>
+                    {
+                        break Some(Self::recover(path, passphrase, err)?);
+                    }
+                    Err(err) => {
+                        Self::show_error(&format!(
+                            "Unable to open the document: {}",
+                            err
+                        ));
+                        return None;
+                    }
+                }
+            }
         } else {
             None
         };
@@ -611,6 +948,99 @@ impl BproWin {
         Some(me)
     }
 
+    /// Offers recovery of the damaged document from its most recent
+    /// readable backup
+    fn recover(
+        path: PathBuf,
+        passphrase: Option<String>,
+        err: model::Error,
+    ) -> Option<Document> {
+        let dlg = gtk::MessageDialog::new(
+            None::<&gtk::Window>,
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::YesNo,
+            &format!(
+                "Unable to open the document: {}\n\nDo you want to recover \
+                 it from the most recent backup?",
+                err
+            ),
+        );
+        let response = dlg.run();
+        dlg.hide();
+        if response != gtk::ResponseType::Yes {
+            return None;
+        }
+        Document::recover(path, passphrase.as_deref())
+            .map_err(|err| {
+                Self::show_error(&format!(
+                    "Unable to recover the document: {}",
+                    err
+                ))
+            })
+            .ok()
+    }
+
+    /// Asks for the passphrase of the encrypted document; returns `None` if
+    /// the user cancels opening the document
+    fn ask_passphrase(msg: &str) -> Option<String> {
+        let dlg = gtk::MessageDialog::new(
+            None::<&gtk::Window>,
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            msg,
+        );
+        let entry = gtk::Entry::new();
+        entry.set_visibility(false);
+        entry.set_activates_default(true);
+        entry.set_margin_start(13);
+        entry.set_margin_end(13);
+        dlg.content_area().add(&entry);
+        dlg.set_default_response(gtk::ResponseType::Ok);
+        entry.show();
+        let response = dlg.run();
+        let passphrase = entry.text().to_string();
+        dlg.hide();
+        Some(passphrase).filter(|_| response == gtk::ResponseType::Ok)
+    }
+
+    fn show_error(msg: &str) {
+        let dlg = gtk::MessageDialog::new(
+            None::<&gtk::Window>,
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Close,
+            msg,
+        );
+        dlg.run();
+        dlg.hide();
+    }
+
+    /// Opens transaction viewer for the transaction from the document cache
+    /// or blockchain; without transaction id the viewer opens empty
+    fn open_transaction(doc: Rc<RefCell<Document>>, txid: Option<Txid>) {
+        let tx = txid
+            .map(|txid| TransactionWin::fetch(&doc.borrow(), txid))
+            .transpose();
+        match tx {
+            Ok(tx) => {
+                if let Some(win) = TransactionWin::load_glade() {
+                    win.run(doc, tx);
+                }
+            }
+            Err(err) => Self::show_error(&err),
+        }
+    }
+
+    /// Opens PSBT editor for the PSBT stored in the document at the given
+    /// position, or for a new PSBT
+    fn open_psbt(doc: Rc<RefCell<Document>>, pos: Option<usize>) {
+        if let Some(win) = PsbtWin::load_glade() {
+            win.run(doc, pos);
+        }
+    }
+
     pub fn run(
         &self,
         on_open: impl Fn() + 'static,
@@ -674,6 +1104,29 @@ impl BproWin {
         })
     }
 
+    pub fn txid_selection(
+        tree: &gtk::TreeView,
+    ) -> Option<(Txid, gtk::TreeModel, gtk::TreeIter)> {
+        tree.selection().selected().and_then(|(model, iter)| {
+            model
+                .value(&iter, 0)
+                .get::<String>()
+                .ok()
+                .and_then(|txid| Txid::from_str(&txid).ok())
+                .map(|txid| (txid, model, iter))
+        })
+    }
+
+    /// Position of the selected PSBT in the document
+    pub fn psbt_selection(&self) -> Option<usize> {
+        self.psbt_tree
+            .selection()
+            .selected()
+            .and_then(|(model, iter)| model.path(&iter))
+            .and_then(|path| path.indices().first().copied())
+            .map(|pos| pos as usize)
+    }
+
     pub fn asset_selection(
         &self,
     ) -> Option<(ContractId, gtk::TreeModel, gtk::TreeIter)> {
@@ -690,5 +1143,119 @@ impl BproWin {
             })
     }
 
-    pub fn update_ui(&self) {}
+    /// Re-reads the part of the document which was changed into the views
+    pub fn refresh(&self, doc: &Document, change: DocumentChange) {
+        match change {
+            DocumentChange::Tracking => {
+                fill_store(&self.pubkey_store, &doc.tracking_rows());
+            }
+            DocumentChange::Descriptors => {
+                fill_store(&self.descriptor_store, &doc.descriptor_rows());
+                self.refresh_descriptor_utxos(doc);
+                self.refresh_descriptor_history(doc);
+            }
+            // Address book is presented by the addresses windows only
+            DocumentChange::Addresses => {}
+            DocumentChange::Utxos => {
+                fill_store(&self.utxo_store, &doc.utxo_rows(None));
+                self.refresh_descriptor_utxos(doc);
+                // Known asset values depend on the set of known outputs
+                fill_store(&self.asset_store, &doc.asset_rows());
+            }
+            DocumentChange::Transactions => {
+                fill_store(&self.tx_store, &doc.transaction_rows());
+                self.refresh_descriptor_history(doc);
+            }
+            DocumentChange::Psbts => {
+                fill_store(&self.psbt_store, &doc.psbt_rows());
+            }
+            DocumentChange::Assets => {
+                fill_store(&self.asset_store, &doc.asset_rows());
+            }
+            DocumentChange::Settings => {}
+        }
+        // Any change to the document is recorded into the operation log
+        fill_store(&self.log_store, &doc.log_rows());
+        self.update_undo(doc);
+        self.update_ui();
+    }
+
+    fn refresh_descriptor_utxos(&self, doc: &Document) {
+        let rows = self
+            .descriptor_selection()
+            .and_then(|(generator, _, _)| {
+                doc.descriptor_by_generator(&generator)
+            })
+            .map(|descriptor| doc.utxo_rows(Some(&descriptor)))
+            .unwrap_or_default();
+        fill_store(&self.utxo_descr_store, &rows);
+        self.utxo_descr_clear_btn.set_sensitive(!rows.is_empty());
+    }
+
+    fn refresh_descriptor_history(&self, doc: &Document) {
+        let rows = self
+            .descriptor_selection()
+            .and_then(|(generator, _, _)| {
+                doc.descriptor_by_generator(&generator)
+            })
+            .map(|descriptor| doc.history_rows(&descriptor))
+            .unwrap_or_default();
+        fill_store(&self.history_descr_store, &rows);
+    }
+
+    fn update_undo(&self, doc: &Document) {
+        let undo_tip = match doc.undo_operation() {
+            Some(operation) => format!("Undo: {} (Ctrl+Z)", operation),
+            None => s!("Undo (Ctrl+Z)"),
+        };
+        let redo_tip = match doc.redo_operation() {
+            Some(operation) => format!("Redo: {} (Ctrl+Shift+Z)", operation),
+            None => s!("Redo (Ctrl+Shift+Z)"),
+        };
+        self.undo_btn.set_sensitive(doc.can_undo());
+        self.undo_btn.set_tooltip_text(Some(&undo_tip));
+        self.redo_btn.set_sensitive(doc.can_redo());
+        self.redo_btn.set_tooltip_text(Some(&redo_tip));
+    }
+
+    pub fn update_ui(&self) {
+        let psbt_selected = self.psbt_selection().is_some();
+        self.psbt_edit_btn.set_sensitive(psbt_selected);
+        self.psbt_remove_btn.set_sensitive(psbt_selected);
+    }
+}
+
+impl HistoryLookup for BproWin {}
+impl UtxoRefresh for BproWin {}
+
+/// Parses the entry text, displaying parse error as the entry icon
+fn parse_field<T>(field: &gtk::Entry) -> Option<T>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    match field.text().parse::<T>() {
+        Ok(value) => {
+            field.set_secondary_icon_name(None);
+            field.set_secondary_icon_tooltip_text(Some(""));
+            Some(value)
+        }
+        Err(err) => {
+            field.set_secondary_icon_name(Some("dialog-error"));
+            field.set_secondary_icon_tooltip_text(Some(&err.to_string()));
+            None
+        }
+    }
+}
+
+/// Connects to the blockchain information provider configured in the
+/// document, displaying the result as the entry icon
+fn test_resolver(doc: &Document, field: &gtk::Entry) {
+    if let Err(err) = doc.resolver() {
+        field.set_secondary_icon_name(Some("dialog-error"));
+        field.set_secondary_icon_tooltip_text(Some(&err.to_string()));
+    } else {
+        field.set_secondary_icon_name(Some("dialog-ok"));
+        field.set_secondary_icon_tooltip_text(Some(""));
+    }
 }
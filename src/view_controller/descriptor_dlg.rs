@@ -17,16 +17,20 @@
 
 use gtk::prelude::*;
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::rc::Rc;
 use std::str::FromStr;
 
+use bitcoin::util::bip32::{ExtendedPubKey, KeySource};
+
 use crate::controller::utxo_lookup::{self, UtxoLookup};
+use crate::model::taproot::TapLeaf;
 use crate::model::{
     DescriptorAccount, Document, ResolverError, TrackingAccount, UtxoEntry,
+    UtxoRow,
 };
 use crate::util::resolver_mode::{self, ResolverModeType};
-use crate::view_controller::PubkeySelectDlg;
+use crate::view_controller::{PubkeySelectDlg, StoreRow};
 
 static UI: &str = include_str!("../view/descriptor.glade");
 
@@ -59,7 +63,7 @@ pub enum Error {
     #[from]
     LookupTypeUnrecognized(resolver_mode::ParseError),
 
-    /// Error with Electrum server connection configuration
+    /// Error with blockchain information provider configuration
     #[display("{0}")]
     #[from]
     Resolver(ResolverError),
@@ -76,6 +80,9 @@ pub struct DescriptorDlg {
     key: Rc<RefCell<Option<descriptor::SingleSig>>>,
     keyset: Rc<RefCell<Vec<descriptor::SingleSig>>>,
     utxo_set: Rc<RefCell<HashSet<UtxoEntry>>>,
+    last_used_indexes: RefCell<BTreeMap<descriptor::Category, u32>>,
+    key_origins: RefCell<BTreeMap<ExtendedPubKey, KeySource>>,
+    tap_leaves: RefCell<Vec<TapLeaf>>,
 
     msg_box: gtk::Box,
     msg_label: gtk::Label,
@@ -171,6 +178,9 @@ impl DescriptorDlg {
             key: none!(),
             keyset: empty!(),
             utxo_set: empty!(),
+            last_used_indexes: empty!(),
+            key_origins: empty!(),
+            tap_leaves: empty!(),
 
             msg_box,
             msg_image,
@@ -393,6 +403,9 @@ impl DescriptorDlg {
         account: DescriptorAccount,
     ) {
         self.name_entry.set_text(&account.name);
+        *self.last_used_indexes.borrow_mut() = account.last_used_indexes;
+        *self.key_origins.borrow_mut() = account.key_origins;
+        *self.tap_leaves.borrow_mut() = account.tap_leaves;
         match account.generator.template {
             descriptor::Template::SingleSig(key) => {
                 self.singlesig_radio.set_active(true);
@@ -459,6 +472,9 @@ impl DescriptorDlg {
         Ok(DescriptorAccount {
             name,
             generator: descriptor::Generator { template, variants },
+            last_used_indexes: self.last_used_indexes.borrow().clone(),
+            key_origins: self.key_origins.borrow().clone(),
+            tap_leaves: self.tap_leaves.borrow().clone(),
         })
     }
 
@@ -548,8 +564,12 @@ impl DescriptorDlg {
         doc: Rc<RefCell<Document>>,
         generator: DescriptorAccount,
     ) -> Result<(), Error> {
-        self.utxo_lookup(
-            doc.borrow().resolver()?,
+        let resolver = doc.borrow().resolver()?;
+        if !resolver.provides_script_history() {
+            self.replace_gap_lookup();
+        }
+        let result = self.utxo_lookup(
+            &*resolver,
             ResolverModeType::from_str(
                 &*self
                     .lookup_combo
@@ -558,12 +578,29 @@ impl DescriptorDlg {
             )?,
             generator,
             self.utxo_set.clone(),
-            Some(&self.utxo_store),
+            |utxo| UtxoRow::from(utxo).append_to(&self.utxo_store),
         )?;
+        let mut last_used_indexes = self.last_used_indexes.borrow_mut();
+        for (category, index) in result.last_used_indexes {
+            let last_used = last_used_indexes.entry(category).or_insert(index);
+            *last_used = (*last_used).max(index);
+        }
 
         Ok(())
     }
 
+    /// Gap-limit lookup requires transaction history of the scripts; with
+    /// the resolver not providing it the lookup of the first keys is
+    /// selected instead
+    fn replace_gap_lookup(&self) {
+        let default_gap = ResolverModeType::default_gap().to_string();
+        if self.lookup_combo.active_id().as_deref() == Some(&*default_gap) {
+            self.lookup_combo.set_active_id(Some(
+                &ResolverModeType::default_for(false).to_string(),
+            ));
+        }
+    }
+
     pub fn display_info(&self, msg: impl ToString) {
         self.msg_label.set_text(&msg.to_string());
         self.msg_image.set_from_icon_name(
@@ -0,0 +1,231 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::prelude::*;
+use gtk::ResponseType;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use bitcoin::{Transaction, Txid};
+
+use crate::controller::fee_bump::{self, is_replaceable, FeeBump};
+use crate::controller::psbt_compose::{Composition, PsbtCompose};
+use crate::model::{DescriptorAccount, Document, ResolverError};
+
+static UI: &str = include_str!("../view/fee_bump.glade");
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+/// Errors from bumping transaction fee
+pub enum Error {
+    /// {0}
+    #[from]
+    FeeBump(fee_bump::Error),
+
+    /// Resolver configuration error: {0}
+    #[from]
+    Resolver(ResolverError),
+}
+
+/// Dialog composing PSBT which bumps the fee of the cached transaction,
+/// either replacing it (RBF) or spending its output (CPFP)
+pub struct FeeBumpDlg {
+    dialog: gtk::Dialog,
+    info_label: gtk::Label,
+    rbf_radio: gtk::RadioButton,
+    cpfp_radio: gtk::RadioButton,
+    fee_rate_adj: gtk::Adjustment,
+    blocks_adj: gtk::Adjustment,
+    estimate_btn: gtk::Button,
+    msg_box: gtk::Box,
+    msg_image: gtk::Image,
+    msg_label: gtk::Label,
+    compose_btn: gtk::Button,
+    cancel_btn: gtk::Button,
+}
+
+impl FeeBumpDlg {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let info_label = builder.object("infoLabel")?;
+        let rbf_radio = builder.object("rbfRadio")?;
+        let cpfp_radio = builder.object("cpfpRadio")?;
+        let fee_rate_adj = builder.object("feeRateAdj")?;
+        let blocks_adj = builder.object("blocksAdj")?;
+        let estimate_btn = builder.object("estimateBtn")?;
+
+        let msg_box = builder.object("messageBox")?;
+        let msg_image = builder.object("messageImage")?;
+        let msg_label = builder.object("messageLabel")?;
+
+        let compose_btn = builder.object("compose")?;
+        let cancel_btn = builder.object("cancel")?;
+
+        let me = Rc::new(Self {
+            dialog: glade_load!(builder, "feeBumpDlg").ok()?,
+            info_label,
+            rbf_radio,
+            cpfp_radio,
+            fee_rate_adj,
+            blocks_adj,
+            estimate_btn,
+            msg_box,
+            msg_image,
+            msg_label,
+            compose_btn,
+            cancel_btn,
+        });
+
+        for ctl in &[&me.rbf_radio, &me.cpfp_radio] {
+            ctl.connect_toggled(clone!(@weak me => move |_| {
+                me.msg_box.set_visible(false)
+            }));
+        }
+        me.fee_rate_adj
+            .connect_value_changed(clone!(@weak me => move |_| {
+                me.msg_box.set_visible(false)
+            }));
+
+        Some(me)
+    }
+}
+
+impl FeeBumpDlg {
+    /// Runs the dialog for the transaction; `on_compose` receives the
+    /// composed replacement or child PSBT
+    pub fn run(
+        self: Rc<Self>,
+        doc: Rc<RefCell<Document>>,
+        tx: Transaction,
+        on_compose: impl Fn(Composition) + 'static,
+        on_cancel: impl Fn() + 'static,
+    ) {
+        let txid = tx.txid();
+        let fee = doc.borrow().tx_fee(&tx);
+        match fee {
+            Some(fee) => {
+                let fee_rate = fee as f64 / tx.vsize() as f64;
+                self.info_label.set_text(&format!(
+                    "Transaction pays {} sat, {:.1} sat/vbyte",
+                    fee, fee_rate
+                ));
+                self.fee_rate_adj.set_value(
+                    (fee_rate + fee_bump::INCREMENTAL_RELAY_FEE_RATE as f64)
+                        .ceil(),
+                );
+            }
+            None => self.info_label.set_text("Transaction fee is unknown"),
+        }
+
+        // Confirmed transactions can't be bumped; unconfirmed ones not
+        // signalling replaceability can be bumped with CPFP only
+        let is_mined =
+            doc.borrow().transaction_height(txid).unwrap_or_default() > 0;
+        self.rbf_radio
+            .set_sensitive(!is_mined && is_replaceable(&tx));
+        self.cpfp_radio.set_sensitive(!is_mined);
+        if !is_replaceable(&tx) {
+            self.cpfp_radio.set_active(true);
+        }
+
+        self.estimate_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let blocks = me.blocks_adj.value() as u16;
+                let fee_rate = doc
+                    .borrow()
+                    .resolver()
+                    .and_then(|resolver| resolver.estimate_fee(blocks));
+                match fee_rate {
+                    Ok(fee_rate) => me.fee_rate_adj.set_value(fee_rate as f64),
+                    Err(err) => me.display_error(err),
+                }
+            }),
+        );
+
+        self.cancel_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                me.dialog.response(ResponseType::Cancel);
+                on_cancel();
+            }));
+
+        self.compose_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let result = me.compose(&doc.borrow(), &tx);
+                match result {
+                    Ok(composition) => {
+                        me.dialog.response(ResponseType::Ok);
+                        on_compose(composition);
+                    }
+                    Err(err) => me.display_error(err),
+                }
+            }),
+        );
+
+        self.msg_box.set_visible(false);
+        self.compose_btn.set_sensitive(!is_mined);
+
+        self.dialog.run();
+        self.dialog.hide();
+    }
+
+    pub fn compose(
+        &self,
+        doc: &Document,
+        tx: &Transaction,
+    ) -> Result<Composition, Error> {
+        let resolver = doc.resolver()?;
+        let links = doc.tx_links(tx.txid());
+        let next_index =
+            |account: &DescriptorAccount| doc.next_address_index(account);
+        let fee_rate = self.fee_rate_adj.value() as f32;
+        let cached = |txid: Txid| doc.transaction(txid).cloned();
+        Ok(if self.cpfp_radio.is_active() {
+            self.cpfp_psbt(
+                &*resolver,
+                doc.descriptors(),
+                links,
+                next_index,
+                tx,
+                fee_rate,
+                cached,
+            )?
+        } else {
+            self.rbf_psbt(
+                &*resolver,
+                doc.descriptors(),
+                links,
+                next_index,
+                tx,
+                fee_rate,
+                cached,
+            )?
+        })
+    }
+
+    pub fn display_error(&self, msg: impl std::error::Error) {
+        self.msg_label.set_text(&msg.to_string());
+        self.msg_image.set_from_icon_name(
+            Some("dialog-error"),
+            gtk::IconSize::SmallToolbar,
+        );
+        self.msg_box.set_visible(true);
+    }
+}
+
+impl PsbtCompose for FeeBumpDlg {}
+impl FeeBump for FeeBumpDlg {}
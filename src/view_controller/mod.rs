@@ -15,20 +15,34 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+mod addresses_win;
 mod asset_dlg;
 mod bpro_win;
 mod descriptor_dlg;
+mod fee_bump_dlg;
 mod open_dlg;
+mod passphrase_dlg;
+mod psbt_compose_dlg;
+mod psbt_win;
 mod pubkey_dlg;
 mod pubkey_select_dlg;
 mod save_dlg;
+mod store;
+mod transaction_win;
 mod utxo_select_dlg;
 
+pub use addresses_win::AddressesWin;
 pub use asset_dlg::AssetDlg;
 pub use bpro_win::{BproWin, Error as AppError};
 pub use descriptor_dlg::DescriptorDlg;
+pub use fee_bump_dlg::FeeBumpDlg;
 pub use open_dlg::OpenDlg;
+pub use passphrase_dlg::PassphraseDlg;
+pub use psbt_compose_dlg::PsbtComposeDlg;
+pub use psbt_win::PsbtWin;
 pub use pubkey_dlg::PubkeyDlg;
 pub use pubkey_select_dlg::PubkeySelectDlg;
 pub use save_dlg::SaveDlg;
+pub use store::{fill_store, read_store, EditableRow, StoreRow};
+pub use transaction_win::TransactionWin;
 pub use utxo_select_dlg::UtxoSelectDlg;
@@ -19,10 +19,14 @@ use gtk::prelude::*;
 use std::path::PathBuf;
 use std::rc::Rc;
 
+use crate::model::Document;
+
 static UI: &str = include_str!("../view/file_open.glade");
 
 pub struct OpenDlg {
     dialog: gtk::FileChooserDialog,
+    passphrase_box: gtk::Box,
+    passphrase_entry: gtk::Entry,
     open_btn: gtk::Button,
     cancel_btn: gtk::Button,
 }
@@ -31,12 +35,16 @@ impl OpenDlg {
     pub fn load_glade() -> Option<Rc<Self>> {
         let builder = gtk::Builder::from_string(UI);
 
+        let passphrase_box = builder.object("passphraseBox")?;
+        let passphrase_entry = builder.object("passphraseEntry")?;
         let open_btn = builder.object("open")?;
         let cancel_btn = builder.object("cancel")?;
         let dialog = builder.object("openDlg")?;
 
         Some(Rc::new(OpenDlg {
             dialog,
+            passphrase_box,
+            passphrase_entry,
             open_btn,
             cancel_btn,
         }))
@@ -44,11 +52,17 @@ impl OpenDlg {
 
     pub fn run(
         self: Rc<Self>,
-        on_open: impl Fn(PathBuf) + 'static,
+        on_open: impl Fn(PathBuf, Option<String>) + 'static,
         on_cancel: impl Fn() + 'static,
     ) {
         let me = self.clone();
 
+        me.dialog
+            .connect_selection_changed(clone!(@weak self as me => move |_| {
+                me.passphrase_box.set_visible(false);
+                me.passphrase_entry.set_text("");
+            }));
+
         me.cancel_btn
             .connect_clicked(clone!(@weak self as me => move |_| {
                 me.dialog.hide();
@@ -58,8 +72,18 @@ impl OpenDlg {
         me.open_btn
             .connect_clicked(clone!(@weak self as me => move |_| {
                 if let Some(path) = me.dialog.filename() {
+                    let encrypted =
+                        Document::is_encrypted_file(&path).unwrap_or_default();
+                    if encrypted && !me.passphrase_box.is_visible() {
+                        // Ask for the passphrase before opening the document
+                        me.passphrase_box.set_visible(true);
+                        me.passphrase_entry.grab_focus();
+                        return;
+                    }
+                    let passphrase = Some(me.passphrase_entry.text().to_string())
+                        .filter(|_| encrypted);
                     me.dialog.hide();
-                    on_open(path);
+                    on_open(path, passphrase);
                 }
             }));
 
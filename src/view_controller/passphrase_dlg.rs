@@ -0,0 +1,118 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::prelude::*;
+use std::rc::Rc;
+
+static UI: &str = include_str!("../view/passphrase.glade");
+
+pub struct PassphraseDlg {
+    dialog: gtk::Dialog,
+    info_label: gtk::Label,
+    msg_label: gtk::Label,
+    passphrase_entry: gtk::Entry,
+    confirm_entry: gtk::Entry,
+    apply_btn: gtk::Button,
+    cancel_btn: gtk::Button,
+}
+
+impl PassphraseDlg {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let info_label = builder.object("infoLabel")?;
+        let msg_label = builder.object("messageLabel")?;
+        let passphrase_entry = builder.object("passphraseEntry")?;
+        let confirm_entry = builder.object("confirmEntry")?;
+        let apply_btn = builder.object("apply")?;
+        let cancel_btn = builder.object("cancel")?;
+
+        let me = Rc::new(Self {
+            dialog: glade_load!(builder, "passphraseDlg").ok()?,
+            info_label,
+            msg_label,
+            passphrase_entry,
+            confirm_entry,
+            apply_btn,
+            cancel_btn,
+        });
+
+        for ctl in &[&me.passphrase_entry, &me.confirm_entry] {
+            ctl.connect_changed(clone!(@weak me => move |_| {
+                me.update_ui()
+            }));
+        }
+
+        Some(me)
+    }
+}
+
+impl PassphraseDlg {
+    /// Runs the dialog; `on_apply` receives the new passphrase or `None` if
+    /// the encryption must be removed
+    pub fn run(
+        self: Rc<Self>,
+        is_encrypted: bool,
+        on_apply: impl Fn(Option<String>) + 'static,
+        on_cancel: impl Fn() + 'static,
+    ) {
+        let me = self.clone();
+
+        if is_encrypted {
+            me.info_label.set_text(
+                "The document is encrypted. Enter a new passphrase to \
+                 change it, or leave both fields empty to remove the \
+                 encryption.",
+            );
+        }
+
+        me.cancel_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                me.dialog.hide();
+                on_cancel()
+            }));
+
+        me.apply_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                if let Some(passphrase) = me.passphrase() {
+                    me.dialog.hide();
+                    on_apply(Some(passphrase).filter(|p| !p.is_empty()));
+                }
+            }));
+
+        me.update_ui();
+        me.dialog.run();
+        me.dialog.hide();
+    }
+
+    /// Returns entered passphrase if both entries match
+    pub fn passphrase(&self) -> Option<String> {
+        let passphrase = self.passphrase_entry.text().to_string();
+        if passphrase == self.confirm_entry.text().as_str() {
+            Some(passphrase)
+        } else {
+            None
+        }
+    }
+
+    pub fn update_ui(&self) {
+        let matches = self.passphrase().is_some();
+        self.msg_label.set_text("Passphrases do not match");
+        self.msg_label.set_visible(!matches);
+        self.apply_btn.set_sensitive(matches);
+    }
+}
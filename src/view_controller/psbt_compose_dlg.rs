@@ -0,0 +1,432 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::prelude::*;
+use gtk::ResponseType;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use bitcoin::{Address, Network, OutPoint, TxOut, Txid};
+
+use crate::controller::coin_select::{self, Selection, SelectionMode};
+use crate::controller::psbt_compose::{self, Composition, PsbtCompose};
+use crate::model::{self, DescriptorAccount, Document, ResolverError};
+use crate::view_controller::fill_store;
+
+static UI: &str = include_str!("../view/psbt_compose.glade");
+
+#[derive(Debug, Display, From, Error)]
+#[display(doc_comments)]
+/// Errors from composing PSBT
+pub enum Error {
+    /// {0}
+    #[from]
+    Compose(psbt_compose::Error),
+
+    /// {0}
+    #[from]
+    CoinSelect(coin_select::Error),
+
+    /// Resolver configuration error: {0}
+    #[from]
+    Resolver(ResolverError),
+
+    /// {0}
+    #[from]
+    Document(model::Error),
+
+    /// Invalid payment address `{0}`
+    InvalidAddress(String),
+
+    /// Address {0} does not belong to the document network
+    WrongNetwork(Address),
+}
+
+pub struct PsbtComposeDlg {
+    dialog: gtk::Dialog,
+    utxo_store: gtk::ListStore,
+    utxo_selection: gtk::TreeSelection,
+    select_combo: gtk::ComboBoxText,
+    same_descriptor_chk: gtk::CheckButton,
+    select_btn: gtk::Button,
+    waste_label: gtk::Label,
+    output_store: gtk::ListStore,
+    output_selection: gtk::TreeSelection,
+    address_cell: gtk::CellRendererText,
+    amount_cell: gtk::CellRendererText,
+    output_add_btn: gtk::ToolButton,
+    output_remove_btn: gtk::ToolButton,
+    fee_rate_adj: gtk::Adjustment,
+    change_combo: gtk::ComboBoxText,
+    total_label: gtk::Label,
+    msg_box: gtk::Box,
+    msg_image: gtk::Image,
+    msg_label: gtk::Label,
+    compose_btn: gtk::Button,
+    cancel_btn: gtk::Button,
+    /// Automatic selection matching the currently selected outputs
+    selection: RefCell<Option<Selection>>,
+}
+
+impl PsbtComposeDlg {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let utxo_store = builder.object("utxoStore")?;
+        let utxo_selection = builder.object("utxoSelection")?;
+        let select_combo = builder.object("selectCombo")?;
+        let same_descriptor_chk = builder.object("sameDescriptorCheck")?;
+        let select_btn = builder.object("selectBtn")?;
+        let waste_label = builder.object("wasteLabel")?;
+        let output_store = builder.object("outputStore")?;
+        let output_selection = builder.object("outputSelection")?;
+        let address_cell = builder.object("addressCell")?;
+        let amount_cell = builder.object("amountCell")?;
+        let output_add_btn = builder.object("outputAdd")?;
+        let output_remove_btn = builder.object("outputRemove")?;
+        let fee_rate_adj = builder.object("feeRateAdj")?;
+        let change_combo = builder.object("changeCombo")?;
+        let total_label = builder.object("totalLabel")?;
+
+        let msg_box = builder.object("messageBox")?;
+        let msg_image = builder.object("messageImage")?;
+        let msg_label = builder.object("messageLabel")?;
+
+        let compose_btn = builder.object("compose")?;
+        let cancel_btn = builder.object("cancel")?;
+
+        let me = Rc::new(Self {
+            dialog: glade_load!(builder, "composeDlg").ok()?,
+            utxo_store,
+            utxo_selection,
+            select_combo,
+            same_descriptor_chk,
+            select_btn,
+            waste_label,
+            output_store,
+            output_selection,
+            address_cell,
+            amount_cell,
+            output_add_btn,
+            output_remove_btn,
+            fee_rate_adj,
+            change_combo,
+            total_label,
+            msg_box,
+            msg_image,
+            msg_label,
+            compose_btn,
+            cancel_btn,
+            selection: none!(),
+        });
+
+        Some(me)
+    }
+}
+
+impl PsbtComposeDlg {
+    pub fn run(
+        self: Rc<Self>,
+        doc: Rc<RefCell<Document>>,
+        on_compose: impl Fn(Composition) + 'static,
+        on_cancel: impl Fn() + 'static,
+    ) {
+        fill_store(&self.utxo_store, &doc.borrow().utxo_rows(None));
+        for (index, account) in doc.borrow().descriptors().iter().enumerate() {
+            self.change_combo
+                .append(Some(&index.to_string()), &account.name());
+        }
+
+        self.utxo_selection.connect_changed(
+            clone!(@weak self as me => move |_| {
+                me.update_ui();
+            }),
+        );
+
+        self.select_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let result = me.auto_select(&doc.borrow());
+                match result {
+                    Ok(selection) => me.apply_selection(selection),
+                    Err(err) => me.display_error(err),
+                }
+            }),
+        );
+
+        self.output_selection.connect_changed(
+            clone!(@weak self as me => move |_| {
+                me.update_ui();
+            }),
+        );
+
+        self.address_cell.connect_edited(
+            clone!(@weak self as me => move |_, path, text| {
+                if let Some(iter) = me.output_store.iter(&path) {
+                    me.output_store.set_value(
+                        &iter,
+                        0,
+                        &text.trim().to_value(),
+                    );
+                }
+                me.update_ui();
+            }),
+        );
+
+        self.amount_cell.connect_edited(
+            clone!(@weak self as me => move |_, path, text| {
+                if let (Some(iter), Ok(amount)) =
+                    (me.output_store.iter(&path), text.trim().parse::<u64>())
+                {
+                    me.output_store.set_value(&iter, 1, &amount.to_value());
+                }
+                me.update_ui();
+            }),
+        );
+
+        self.output_add_btn.connect_clicked(
+            clone!(@weak self as me => move |_| {
+                let iter = me
+                    .output_store
+                    .insert_with_values(None, &[(0, &""), (1, &0u64)]);
+                me.output_selection.select_iter(&iter);
+            }),
+        );
+
+        self.output_remove_btn.connect_clicked(
+            clone!(@weak self as me => move |_| {
+                if let Some((_, iter)) = me.output_selection.selected() {
+                    me.output_store.remove(&iter);
+                }
+                me.update_ui();
+            }),
+        );
+
+        self.cancel_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                me.dialog.response(ResponseType::Cancel);
+                on_cancel();
+            }));
+
+        self.compose_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let result = me.compose(&doc.borrow());
+                match result {
+                    Ok(composition) => {
+                        me.dialog.response(ResponseType::Ok);
+                        on_compose(composition);
+                    }
+                    Err(err) => me.display_error(err),
+                }
+            }),
+        );
+
+        self.update_ui();
+
+        self.dialog.run();
+        self.dialog.hide();
+    }
+
+    pub fn compose(&self, doc: &Document) -> Result<Composition, Error> {
+        let utxos = self
+            .selected_outpoints()
+            .into_iter()
+            .filter_map(|outpoint| doc.utxo_by_outpoint(outpoint))
+            .collect::<Vec<_>>();
+        let change = match *self.selection.borrow() {
+            // Changeless selection leaves the excess to the fee
+            Some(Selection { change: None, .. }) => None,
+            _ => self.change_account(doc)?,
+        };
+        Ok(self.compose_psbt(
+            &*doc.resolver()?,
+            doc.descriptors(),
+            utxos,
+            self.outputs(doc.network())?,
+            self.fee_rate_adj.value() as f32,
+            change.as_ref().map(|(account, index)| (account, *index)),
+            |txid| doc.transaction(txid).cloned(),
+        )?)
+    }
+
+    pub fn auto_select(&self, doc: &Document) -> Result<Selection, Error> {
+        let params = psbt_compose::selection_params(
+            &self.outputs(doc.network())?,
+            self.fee_rate_adj.value() as f32,
+            self.change_account(doc)?
+                .as_ref()
+                .map(|(account, index)| (account, *index)),
+        )?;
+        let mode = self
+            .select_combo
+            .active_id()
+            .and_then(|id| SelectionMode::from_str(&id).ok())
+            .unwrap_or(SelectionMode::BranchAndBound);
+        Ok(coin_select::select(
+            mode,
+            &doc.utxos(None),
+            &params,
+            self.same_descriptor_chk.is_active(),
+        )?)
+    }
+
+    /// Selects outputs chosen by the coin selection in the list
+    pub fn apply_selection(&self, selection: Selection) {
+        self.utxo_selection.unselect_all();
+        let store = &self.utxo_store;
+        if let Some(iter) = store.iter_first() {
+            loop {
+                let outpoint = Self::outpoint_at(store.upcast_ref(), &iter);
+                if selection
+                    .utxos
+                    .iter()
+                    .any(|utxo| Some(utxo.outpoint) == outpoint)
+                {
+                    self.utxo_selection.select_iter(&iter);
+                }
+                if !store.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        self.waste_label.set_text(&format!(
+            "Fee: {} sat, waste: {} sat",
+            selection.fee, selection.waste
+        ));
+        *self.selection.borrow_mut() = Some(selection);
+    }
+
+    pub fn display_error(&self, msg: impl std::error::Error) {
+        self.msg_label.set_text(&msg.to_string());
+        self.msg_image.set_from_icon_name(
+            Some("dialog-error"),
+            gtk::IconSize::SmallToolbar,
+        );
+        self.msg_box.set_visible(true);
+    }
+
+    pub fn update_ui(&self) {
+        let selected = self
+            .utxo_selection
+            .selected_rows()
+            .0
+            .iter()
+            .filter_map(|path| self.utxo_store.iter(path))
+            .map(|iter| {
+                self.utxo_store
+                    .value(&iter, 2)
+                    .get::<u64>()
+                    .unwrap_or_default()
+            })
+            .sum::<u64>();
+        let paid = self
+            .payments()
+            .iter()
+            .map(|(_, amount)| amount)
+            .sum::<u64>();
+        self.total_label
+            .set_text(&format!("{} sat selected, {} sat paid", selected, paid));
+
+        self.msg_box.set_visible(false);
+        self.waste_label.set_text("");
+        *self.selection.borrow_mut() = None;
+        self.output_remove_btn
+            .set_sensitive(self.output_selection.selected().is_some());
+        self.compose_btn
+            .set_sensitive(self.utxo_selection.count_selected_rows() > 0);
+    }
+
+    pub fn selected_outpoints(&self) -> Vec<OutPoint> {
+        let (paths, model) = self.utxo_selection.selected_rows();
+        paths
+            .iter()
+            .filter_map(|path| model.iter(path))
+            .filter_map(|iter| Self::outpoint_at(&model, &iter))
+            .collect()
+    }
+
+    fn outpoint_at(
+        model: &gtk::TreeModel,
+        iter: &gtk::TreeIter,
+    ) -> Option<OutPoint> {
+        let txid = model
+            .value(iter, 0)
+            .get::<String>()
+            .ok()
+            .and_then(|txid| Txid::from_str(&txid).ok())?;
+        let vout = model.value(iter, 1).get::<u32>().ok()?;
+        Some(OutPoint { txid, vout })
+    }
+
+    /// Selected change account with the derivation index of its next
+    /// unallocated address
+    fn change_account(
+        &self,
+        doc: &Document,
+    ) -> Result<Option<(DescriptorAccount, u32)>, Error> {
+        self.change_combo
+            .active_id()
+            .and_then(|id| usize::from_str(&id).ok())
+            .and_then(|index| doc.descriptor_at(index))
+            .map(|account| {
+                let index = doc.next_address_index(&account)?;
+                Ok((account, index))
+            })
+            .transpose()
+    }
+
+    fn payments(&self) -> Vec<(String, u64)> {
+        let store = &self.output_store;
+        let mut payments = vec![];
+        if let Some(iter) = store.iter_first() {
+            loop {
+                payments.push((
+                    store.value(&iter, 0).get::<String>().unwrap_or_default(),
+                    store.value(&iter, 1).get::<u64>().unwrap_or_default(),
+                ));
+                if !store.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        payments
+    }
+
+    pub fn outputs(
+        &self,
+        network: Option<Network>,
+    ) -> Result<Vec<TxOut>, Error> {
+        self.payments()
+            .into_iter()
+            .map(|(address, value)| {
+                let address = Address::from_str(&address)
+                    .map_err(|_| Error::InvalidAddress(address))?;
+                match network {
+                    Some(network) if !address.is_valid_for_network(network) => {
+                        Err(Error::WrongNetwork(address))
+                    }
+                    _ => Ok(TxOut {
+                        value,
+                        script_pubkey: address.script_pubkey(),
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+impl PsbtCompose for PsbtComposeDlg {}
@@ -0,0 +1,1187 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::prelude::*;
+use gtk::{gdk, FileChooserAction, ResponseType};
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use bitcoin::consensus::{deserialize, encode::serialize_hex};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::util::psbt::{Input, Output, PsbtSighashType};
+use bitcoin::{Network, OutPoint, Script, Transaction, TxOut, Txid};
+use wallet::psbt::Psbt;
+
+use crate::controller::psbt_finalize;
+use crate::model::Document;
+use crate::util::psbt::{
+    self, KeyOriginRow, MapError, PreimageRow, ProprietaryRow, SigRow,
+    UnknownRow,
+};
+use crate::view_controller::{
+    fill_store, read_store, StoreRow, TransactionWin, UtxoSelectDlg,
+};
+
+static UI: &str = include_str!("../view/psbt.glade");
+
+/// Part of the PSBT which is re-read from the stores once the user edits
+/// one of its cells
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Section {
+    Global,
+    Inputs,
+    Input,
+    Outputs,
+    Output,
+}
+
+/// Editor for PSBTs stored in the document or loaded from files and
+/// clipboard. All edits are made to a working copy, which is put into the
+/// document only with the "Save" button.
+pub struct PsbtWin {
+    window: gtk::ApplicationWindow,
+    header_bar: gtk::HeaderBar,
+    open_btn: gtk::Button,
+    paste_btn: gtk::Button,
+    finalize_btn: gtk::Button,
+    extract_btn: gtk::Button,
+    save_btn: gtk::Button,
+    export_btn: gtk::Button,
+    copy_btn: gtk::Button,
+    global_notebook: gtk::Notebook,
+    global_add_btn: gtk::ToolButton,
+    xpub_tree: gtk::TreeView,
+    xpub_store: gtk::ListStore,
+    global_vendor_tree: gtk::TreeView,
+    global_vendor_store: gtk::ListStore,
+    global_unknown_tree: gtk::TreeView,
+    global_unknown_store: gtk::ListStore,
+    input_tree: gtk::TreeView,
+    input_store: gtk::ListStore,
+    input_add_btn: gtk::ToolButton,
+    input_up_btn: gtk::ToolButton,
+    input_down_btn: gtk::ToolButton,
+    input_remove_btn: gtk::ToolButton,
+    input_notebook: gtk::Notebook,
+    input_map_add_btn: gtk::ToolButton,
+    sig_tree: gtk::TreeView,
+    sig_store: gtk::ListStore,
+    preimage_tree: gtk::TreeView,
+    preimage_store: gtk::ListStore,
+    hd_input_tree: gtk::TreeView,
+    hd_input_store: gtk::ListStore,
+    input_vendor_tree: gtk::TreeView,
+    input_vendor_store: gtk::ListStore,
+    input_unknown_tree: gtk::TreeView,
+    input_unknown_store: gtk::ListStore,
+    input_tx_entry: gtk::Entry,
+    input_tx_refresh_btn: gtk::Button,
+    sighash_combo: gtk::ComboBoxText,
+    anyone_can_pay_toggle: gtk::ToggleButton,
+    script_text: gtk::TextView,
+    script_buffer: gtk::TextBuffer,
+    output_tree: gtk::TreeView,
+    output_store: gtk::ListStore,
+    output_add_btn: gtk::ToolButton,
+    output_up_btn: gtk::ToolButton,
+    output_down_btn: gtk::ToolButton,
+    output_remove_btn: gtk::ToolButton,
+    output_notebook: gtk::Notebook,
+    output_map_add_btn: gtk::ToolButton,
+    hd_output_tree: gtk::TreeView,
+    hd_output_store: gtk::ListStore,
+    output_vendor_tree: gtk::TreeView,
+    output_vendor_store: gtk::ListStore,
+    output_unknown_tree: gtk::TreeView,
+    output_unknown_store: gtk::ListStore,
+    psbt: RefCell<Psbt>,
+    /// Position of the edited PSBT in the document; `None` for PSBTs which
+    /// were not saved yet
+    position: Cell<Option<usize>>,
+    network: Cell<Option<Network>>,
+    /// Set while widgets are filled with PSBT data, so their change
+    /// handlers do not write the data back
+    updating: Cell<bool>,
+}
+
+impl PsbtWin {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let header_bar = builder.object("headerBar")?;
+        let open_btn = builder.object("openBtn")?;
+        let paste_btn = builder.object("pasteBtn")?;
+        let finalize_btn = builder.object("finalizeBtn")?;
+        let extract_btn = builder.object("extractBtn")?;
+        let save_btn = builder.object("saveBtn")?;
+        let export_btn = builder.object("exportBtn")?;
+        let copy_btn = builder.object("copyBtn")?;
+
+        let global_notebook = builder.object("globalNotebook")?;
+        let global_add_btn = builder.object("globalAdd")?;
+        let xpub_tree = builder.object("xpubTree1")?;
+        let xpub_store = builder.object("xpubStore")?;
+        let global_vendor_tree = builder.object("globalVendorTree")?;
+        let global_vendor_store = builder.object("globalVendorStore")?;
+        let global_unknown_tree = builder.object("globalUnknownTree")?;
+        let global_unknown_store = builder.object("globalUnknownStore")?;
+
+        let input_tree = builder.object("inputTree1")?;
+        let input_store = builder.object("inputStore")?;
+        let input_add_btn = builder.object("inputAdd1")?;
+        let input_up_btn = builder.object("inputUp1")?;
+        let input_down_btn = builder.object("inputDown1")?;
+        let input_remove_btn = builder.object("inputRemove1")?;
+        let input_notebook = builder.object("inputNotebook")?;
+        let input_map_add_btn = builder.object("inputMapAdd")?;
+        let sig_tree = builder.object("sigTree1")?;
+        let sig_store = builder.object("sigStore")?;
+        let preimage_tree = builder.object("preimageTree1")?;
+        let preimage_store = builder.object("preimageStore")?;
+        let hd_input_tree = builder.object("hdInputTree1")?;
+        let hd_input_store = builder.object("hdInputStore")?;
+        let input_vendor_tree = builder.object("inputVendorKey")?;
+        let input_vendor_store = builder.object("inputVendorStore")?;
+        let input_unknown_tree = builder.object("inputUnknownTree")?;
+        let input_unknown_store = builder.object("inputUnknownStore")?;
+        let input_tx_entry = builder.object("inputTxEntry")?;
+        let input_tx_refresh_btn = builder.object("inputTxRefresh")?;
+        let sighash_combo = builder.object("sighashCombo")?;
+        let anyone_can_pay_toggle = builder.object("anyoneCanPayToggle")?;
+        let script_text = builder.object("scriptText")?;
+        let script_buffer = builder.object("scriptBuffer")?;
+
+        let output_tree = builder.object("outputTree1")?;
+        let output_store = builder.object("outputStore")?;
+        let output_add_btn = builder.object("outputAdd1")?;
+        let output_up_btn = builder.object("outputUp1")?;
+        let output_down_btn = builder.object("outputDown1")?;
+        let output_remove_btn = builder.object("outputRemove1")?;
+        let output_notebook = builder.object("outputNotebook")?;
+        let output_map_add_btn = builder.object("outputMapAdd")?;
+        let hd_output_tree = builder.object("hdOutputTree")?;
+        let hd_output_store = builder.object("hdOutputStore")?;
+        let output_vendor_tree = builder.object("outputVendorTree")?;
+        let output_vendor_store = builder.object("outputVendorStore")?;
+        let output_unknown_tree = builder.object("outputUnknownTree")?;
+        let output_unknown_store = builder.object("outputUnknownStore")?;
+
+        let me = Rc::new(Self {
+            window: glade_load!(builder, "psbtWin").ok()?,
+            header_bar,
+            open_btn,
+            paste_btn,
+            finalize_btn,
+            extract_btn,
+            save_btn,
+            export_btn,
+            copy_btn,
+            global_notebook,
+            global_add_btn,
+            xpub_tree,
+            xpub_store,
+            global_vendor_tree,
+            global_vendor_store,
+            global_unknown_tree,
+            global_unknown_store,
+            input_tree,
+            input_store,
+            input_add_btn,
+            input_up_btn,
+            input_down_btn,
+            input_remove_btn,
+            input_notebook,
+            input_map_add_btn,
+            sig_tree,
+            sig_store,
+            preimage_tree,
+            preimage_store,
+            hd_input_tree,
+            hd_input_store,
+            input_vendor_tree,
+            input_vendor_store,
+            input_unknown_tree,
+            input_unknown_store,
+            input_tx_entry,
+            input_tx_refresh_btn,
+            sighash_combo,
+            anyone_can_pay_toggle,
+            script_text,
+            script_buffer,
+            output_tree,
+            output_store,
+            output_add_btn,
+            output_up_btn,
+            output_down_btn,
+            output_remove_btn,
+            output_notebook,
+            output_map_add_btn,
+            hd_output_tree,
+            hd_output_store,
+            output_vendor_tree,
+            output_vendor_store,
+            output_unknown_tree,
+            output_unknown_store,
+            psbt: RefCell::new(psbt::empty_psbt()),
+            position: Cell::new(None),
+            network: Cell::new(None),
+            updating: Cell::new(false),
+        });
+
+        Some(me)
+    }
+}
+
+impl PsbtWin {
+    /// Shows the window with the PSBT stored in the document at the given
+    /// position; if no position is provided, the window starts with an
+    /// empty PSBT
+    pub fn run(
+        self: Rc<Self>,
+        doc: Rc<RefCell<Document>>,
+        position: Option<usize>,
+    ) {
+        if let Some(psbt) =
+            position.and_then(|pos| doc.borrow().psbt_at(pos).cloned())
+        {
+            *self.psbt.borrow_mut() = psbt;
+            self.position.set(position);
+        }
+        self.network.set(doc.borrow().network());
+
+        self.connect_cells(&self.xpub_tree, 3, Section::Global, &doc);
+        self.connect_cells(&self.global_vendor_tree, 4, Section::Global, &doc);
+        self.connect_cells(&self.global_unknown_tree, 3, Section::Global, &doc);
+        self.connect_cells(&self.input_tree, 2, Section::Inputs, &doc);
+        self.connect_cells(&self.sig_tree, 2, Section::Input, &doc);
+        self.connect_cells(&self.preimage_tree, 2, Section::Input, &doc);
+        self.connect_cells(&self.hd_input_tree, 3, Section::Input, &doc);
+        self.connect_cells(&self.input_vendor_tree, 4, Section::Input, &doc);
+        self.connect_cells(&self.input_unknown_tree, 3, Section::Input, &doc);
+        self.connect_cells(&self.output_tree, 2, Section::Outputs, &doc);
+        self.connect_cells(&self.hd_output_tree, 3, Section::Output, &doc);
+        self.connect_cells(&self.output_vendor_tree, 4, Section::Output, &doc);
+        self.connect_cells(&self.output_unknown_tree, 3, Section::Output, &doc);
+
+        self.open_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                if let Some(path) = me.choose_file(FileChooserAction::Open) {
+                    match fs::read(path) {
+                        Ok(data) => me.load(&doc.borrow(), &data),
+                        Err(err) => me.show_error(&err.to_string()),
+                    }
+                }
+            }),
+        );
+
+        self.paste_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+                if let Some(text) = clipboard.wait_for_text() {
+                    me.load(&doc.borrow(), text.as_bytes());
+                }
+            }),
+        );
+
+        self.finalize_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let doc = doc.borrow();
+                let result = psbt_finalize::finalize_psbt(
+                    &mut me.psbt.borrow_mut(),
+                    doc.descriptors(),
+                    &doc.utxos(None),
+                );
+                match result {
+                    Ok(_) => me.display(&doc),
+                    Err(err) => me.show_error(&err.to_string()),
+                }
+            }),
+        );
+
+        self.extract_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let tx = match psbt_finalize::extract_tx(&me.psbt.borrow()) {
+                    Ok(tx) => tx,
+                    Err(err) => {
+                        me.show_error(&err.to_string());
+                        return;
+                    }
+                };
+                let result = doc
+                    .borrow_mut()
+                    .update_tx_history(vec![tx.clone()], none!());
+                match result {
+                    Ok(_) => {
+                        if let Some(win) = TransactionWin::load_glade() {
+                            win.run(doc.clone(), Some(tx));
+                        }
+                    }
+                    Err(err) => me.show_error(&err.to_string()),
+                }
+            }),
+        );
+
+        self.copy_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD)
+                    .set_text(&psbt::psbt_base64(&me.psbt.borrow()));
+            }));
+
+        self.export_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                if let Some(path) = me.choose_file(FileChooserAction::Save) {
+                    let data = psbt::psbt_binary(&me.psbt.borrow());
+                    if let Err(err) = fs::write(path, data) {
+                        me.show_error(&err.to_string());
+                    }
+                }
+            }));
+
+        self.save_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let psbt = me.psbt.borrow().clone();
+                let mut doc = doc.borrow_mut();
+                let result = match me.position.get() {
+                    Some(pos) => doc.update_psbt_at(pos, psbt),
+                    None => {
+                        me.position.set(Some(doc.psbts().len()));
+                        doc.add_psbt(psbt)
+                    }
+                };
+                if let Err(err) = result {
+                    me.show_error(&err.to_string());
+                }
+            }),
+        );
+
+        self.global_add_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                match me.global_notebook.current_page() {
+                    Some(0) => {
+                        KeyOriginRow::default().append_to(&me.xpub_store)
+                    }
+                    Some(1) => ProprietaryRow::default()
+                        .append_to(&me.global_vendor_store),
+                    Some(2) => UnknownRow::default()
+                        .append_to(&me.global_unknown_store),
+                    _ => {}
+                }
+            }));
+
+        self.input_map_add_btn.connect_clicked(
+            clone!(@weak self as me => move |_| {
+                match me.input_notebook.current_page() {
+                    Some(0) => SigRow::default().append_to(&me.sig_store),
+                    Some(1) => {
+                        PreimageRow::default().append_to(&me.preimage_store)
+                    }
+                    Some(2) => {
+                        KeyOriginRow::default().append_to(&me.hd_input_store)
+                    }
+                    Some(3) => ProprietaryRow::default()
+                        .append_to(&me.input_vendor_store),
+                    Some(4) => UnknownRow::default()
+                        .append_to(&me.input_unknown_store),
+                    _ => {}
+                }
+            }),
+        );
+
+        self.output_map_add_btn.connect_clicked(
+            clone!(@weak self as me => move |_| {
+                match me.output_notebook.current_page() {
+                    Some(0) => {
+                        KeyOriginRow::default().append_to(&me.hd_output_store)
+                    }
+                    Some(1) => ProprietaryRow::default()
+                        .append_to(&me.output_vendor_store),
+                    Some(2) => UnknownRow::default()
+                        .append_to(&me.output_unknown_store),
+                    _ => {}
+                }
+            }),
+        );
+
+        self.input_add_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let utxo_dlg = UtxoSelectDlg::load_glade().expect("Must load");
+                utxo_dlg.run(
+                    doc.clone(),
+                    clone!(@weak me, @strong doc => move |utxo| {
+                        let outpoint = utxo.outpoint;
+                        let mut input = Input::default();
+                        if let Ok(tx) =
+                            TransactionWin::fetch(&doc.borrow(), outpoint.txid)
+                        {
+                            set_spent_tx(&mut input, tx, outpoint.vout);
+                        }
+                        let vin = {
+                            let mut psbt = me.psbt.borrow_mut();
+                            psbt::add_input(&mut psbt, outpoint, input);
+                            psbt.inputs.len() - 1
+                        };
+                        me.display_inputs(&doc.borrow(), Some(vin));
+                    }),
+                    || {},
+                );
+            }),
+        );
+
+        self.input_up_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                if let Some(vin) = me.input_selection().filter(|vin| *vin > 0) {
+                    psbt::swap_inputs(&mut me.psbt.borrow_mut(), vin, vin - 1);
+                    me.display_inputs(&doc.borrow(), Some(vin - 1));
+                }
+            }),
+        );
+
+        self.input_down_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                if let Some(vin) = me.input_selection() {
+                    let mut psbt = me.psbt.borrow_mut();
+                    let swapped = psbt::swap_inputs(&mut psbt, vin, vin + 1);
+                    drop(psbt);
+                    if swapped {
+                        me.display_inputs(&doc.borrow(), Some(vin + 1));
+                    }
+                }
+            }),
+        );
+
+        self.input_remove_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                if let Some(vin) = me.input_selection() {
+                    psbt::remove_input(&mut me.psbt.borrow_mut(), vin);
+                    me.display_inputs(&doc.borrow(), None);
+                }
+            }),
+        );
+
+        self.output_add_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let vout = {
+                    let mut psbt = me.psbt.borrow_mut();
+                    psbt::add_output(&mut psbt, TxOut {
+                        value: 0,
+                        script_pubkey: Script::new(),
+                    });
+                    psbt.outputs.len() - 1
+                };
+                me.display_outputs(&doc.borrow(), Some(vout));
+            }),
+        );
+
+        self.output_up_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let vout = me.output_selection().filter(|vout| *vout > 0);
+                if let Some(vout) = vout {
+                    let mut psbt = me.psbt.borrow_mut();
+                    psbt::swap_outputs(&mut psbt, vout, vout - 1);
+                    drop(psbt);
+                    me.display_outputs(&doc.borrow(), Some(vout - 1));
+                }
+            }),
+        );
+
+        self.output_down_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                if let Some(vout) = me.output_selection() {
+                    let mut psbt = me.psbt.borrow_mut();
+                    let swapped = psbt::swap_outputs(&mut psbt, vout, vout + 1);
+                    drop(psbt);
+                    if swapped {
+                        me.display_outputs(&doc.borrow(), Some(vout + 1));
+                    }
+                }
+            }),
+        );
+
+        self.output_remove_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                if let Some(vout) = me.output_selection() {
+                    psbt::remove_output(&mut me.psbt.borrow_mut(), vout);
+                    me.display_outputs(&doc.borrow(), None);
+                }
+            }),
+        );
+
+        self.input_tree.selection().connect_changed(
+            clone!(@weak self as me, @strong doc => move |_| {
+                me.display_input(&doc.borrow());
+                me.update_ui();
+            }),
+        );
+
+        self.output_tree.selection().connect_changed(
+            clone!(@weak self as me, @strong doc => move |_| {
+                me.display_output(&doc.borrow());
+                me.update_ui();
+            }),
+        );
+
+        self.input_tx_entry.connect_activate(
+            clone!(@weak self as me, @strong doc => move |entry| {
+                let text = entry.text();
+                let text = text.trim();
+                if text.is_empty() {
+                    if let Some(vin) = me.input_selection() {
+                        let mut psbt = me.psbt.borrow_mut();
+                        psbt.inputs[vin].non_witness_utxo = None;
+                        drop(psbt);
+                        me.display_inputs(&doc.borrow(), Some(vin));
+                    }
+                    return;
+                }
+                match Vec::<u8>::from_hex(text)
+                    .ok()
+                    .and_then(|data| deserialize(&data).ok())
+                {
+                    Some(tx) => me.set_spent_tx(&doc.borrow(), tx),
+                    None => me.show_error("Not a valid transaction hex"),
+                }
+            }),
+        );
+
+        self.input_tx_refresh_btn.connect_clicked(
+            clone!(@weak self as me, @strong doc => move |_| {
+                let txid = match me.input_outpoint() {
+                    Some(outpoint) => outpoint.txid,
+                    None => return,
+                };
+                match TransactionWin::fetch(&doc.borrow(), txid) {
+                    Ok(tx) => me.set_spent_tx(&doc.borrow(), tx),
+                    Err(err) => me.show_error(&err),
+                }
+            }),
+        );
+
+        self.sighash_combo.connect_changed(
+            clone!(@weak self as me => move |_| {
+                me.update_sighash();
+                me.update_ui();
+            }),
+        );
+
+        self.anyone_can_pay_toggle.connect_toggled(
+            clone!(@weak self as me => move |_| {
+                me.update_sighash();
+            }),
+        );
+
+        self.script_buffer.connect_changed(
+            clone!(@weak self as me => move |_| {
+                me.update_script();
+            }),
+        );
+
+        // The window owns the controller until it is closed; widgets being
+        // destroyed must not write into the PSBT
+        self.window.connect_delete_event(
+            clone!(@strong self as me => move |_, _| {
+                me.updating.set(true);
+                gtk::Inhibit(false)
+            }),
+        );
+
+        self.display(&doc.borrow());
+        self.window.show();
+    }
+
+    /// Makes first `columns` text cells of the tree editable, writing edited
+    /// values back into the PSBT section
+    fn connect_cells(
+        self: &Rc<Self>,
+        tree: &gtk::TreeView,
+        columns: usize,
+        section: Section,
+        doc: &Rc<RefCell<Document>>,
+    ) {
+        let store = match tree
+            .model()
+            .and_then(|model| model.downcast::<gtk::ListStore>().ok())
+        {
+            Some(store) => store,
+            None => return,
+        };
+        let me = Rc::clone(self);
+        for (no, column) in tree.columns().into_iter().enumerate().take(columns)
+        {
+            for cell in column.cells() {
+                let cell = match cell.downcast::<gtk::CellRendererText>() {
+                    Ok(cell) => cell,
+                    Err(_) => continue,
+                };
+                cell.set_editable(true);
+                cell.connect_edited(
+                    clone!(@weak me, @strong store, @strong doc =>
+                        move |_, path, text| {
+                            me.edit_cell(
+                                &doc.borrow(),
+                                &store,
+                                &path,
+                                no as u32,
+                                text,
+                                section,
+                            );
+                        }
+                    ),
+                );
+            }
+        }
+    }
+
+    fn edit_cell(
+        &self,
+        doc: &Document,
+        store: &gtk::ListStore,
+        path: &gtk::TreePath,
+        column: u32,
+        text: &str,
+        section: Section,
+    ) {
+        let iter = match store.iter(path) {
+            Some(iter) => iter,
+            None => return,
+        };
+        let value = match store.column_type(column as i32) {
+            glib::Type::U32 => {
+                // Output numbers are the only `u32` values; other numeric
+                // columns are byte-sized key types
+                let max = if section == Section::Inputs {
+                    u32::MAX
+                } else {
+                    u8::MAX as u32
+                };
+                match text.trim().parse::<u32>().ok().filter(|v| *v <= max) {
+                    Some(value) => value.to_value(),
+                    None => {
+                        self.show_error(&format!("Invalid number `{}`", text));
+                        return;
+                    }
+                }
+            }
+            glib::Type::U64 => match text.trim().parse::<u64>() {
+                Ok(value) => value.to_value(),
+                Err(_) => {
+                    self.show_error(&format!("Invalid amount `{}`", text));
+                    return;
+                }
+            },
+            _ => text.to_value(),
+        };
+        store.set_value(&iter, column, &value);
+        self.apply(doc, section);
+    }
+
+    /// Reads the section back from the stores into the PSBT; on failure
+    /// reports the error and restores the section from the PSBT
+    fn apply(&self, doc: &Document, section: Section) {
+        let mut psbt = self.psbt.borrow().clone();
+        let result = match section {
+            Section::Global => self.read_global(&mut psbt),
+            Section::Inputs => self.read_inputs(&mut psbt),
+            Section::Outputs => self.read_outputs(&mut psbt),
+            Section::Input => match self
+                .input_selection()
+                .and_then(|vin| psbt.inputs.get_mut(vin))
+            {
+                Some(input) => self.read_input(input),
+                None => Ok(()),
+            },
+            Section::Output => match self
+                .output_selection()
+                .and_then(|vout| psbt.outputs.get_mut(vout))
+            {
+                Some(output) => self.read_output(output),
+                None => Ok(()),
+            },
+        };
+        match result {
+            Ok(()) => *self.psbt.borrow_mut() = psbt,
+            Err(err) => self.show_error(&err.to_string()),
+        }
+        match section {
+            Section::Global => self.display_global(doc),
+            Section::Inputs => self.display_inputs(doc, self.input_selection()),
+            Section::Input => self.display_input(doc),
+            Section::Outputs => {
+                self.display_outputs(doc, self.output_selection())
+            }
+            Section::Output => self.display_output(doc),
+        }
+        self.update_ui();
+    }
+
+    fn read_global(&self, psbt: &mut Psbt) -> Result<(), MapError> {
+        psbt.xpub = psbt::key_origin_map(&read_store(&self.xpub_store))?;
+        psbt.proprietary =
+            psbt::proprietary_map(&read_store(&self.global_vendor_store))?;
+        psbt.unknown =
+            psbt::unknown_map(&read_store(&self.global_unknown_store))?;
+        Ok(())
+    }
+
+    fn read_inputs(&self, psbt: &mut Psbt) -> Result<(), MapError> {
+        for (vin, txin) in psbt.unsigned_tx.input.iter_mut().enumerate() {
+            let iter = match self.input_store.iter_nth_child(None, vin as i32) {
+                Some(iter) => iter,
+                None => break,
+            };
+            let txid = self
+                .input_store
+                .value(&iter, 0)
+                .get::<String>()
+                .unwrap_or_default();
+            let vout = self
+                .input_store
+                .value(&iter, 1)
+                .get::<u32>()
+                .unwrap_or_default();
+            txin.previous_output = OutPoint::new(
+                Txid::from_str(txid.trim()).map_err(|_| MapError::Hex(txid))?,
+                vout,
+            );
+        }
+        Ok(())
+    }
+
+    fn read_outputs(&self, psbt: &mut Psbt) -> Result<(), MapError> {
+        for (vout, txout) in psbt.unsigned_tx.output.iter_mut().enumerate() {
+            let iter = match self.output_store.iter_nth_child(None, vout as i32)
+            {
+                Some(iter) => iter,
+                None => break,
+            };
+            txout.value = self
+                .output_store
+                .value(&iter, 0)
+                .get::<u64>()
+                .unwrap_or_default();
+            txout.script_pubkey = psbt::parse_script(
+                &self
+                    .output_store
+                    .value(&iter, 1)
+                    .get::<String>()
+                    .unwrap_or_default(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn read_input(&self, input: &mut Input) -> Result<(), MapError> {
+        psbt::set_sigs(input, &read_store(&self.sig_store))?;
+        psbt::set_preimages(input, &read_store(&self.preimage_store))?;
+        input.bip32_derivation =
+            psbt::key_origin_map(&read_store(&self.hd_input_store))?;
+        input.proprietary =
+            psbt::proprietary_map(&read_store(&self.input_vendor_store))?;
+        input.unknown =
+            psbt::unknown_map(&read_store(&self.input_unknown_store))?;
+        Ok(())
+    }
+
+    fn read_output(&self, output: &mut Output) -> Result<(), MapError> {
+        output.bip32_derivation =
+            psbt::key_origin_map(&read_store(&self.hd_output_store))?;
+        output.proprietary =
+            psbt::proprietary_map(&read_store(&self.output_vendor_store))?;
+        output.unknown =
+            psbt::unknown_map(&read_store(&self.output_unknown_store))?;
+        Ok(())
+    }
+
+    /// Loads PSBT from the data. PSBT for the edited transaction (like the
+    /// one returned by a cosigner) is merged into the edited PSBT; otherwise
+    /// the edited PSBT is replaced and will be saved into the document as a
+    /// new PSBT.
+    fn load(&self, doc: &Document, data: &[u8]) {
+        let psbt = match psbt::parse_psbt(data) {
+            Ok(psbt) => psbt,
+            Err(err) => {
+                self.show_error(&err.to_string());
+                return;
+            }
+        };
+        if psbt.unsigned_tx.txid() == self.psbt.borrow().unsigned_tx.txid() {
+            let result =
+                psbt_finalize::merge_psbt(&mut self.psbt.borrow_mut(), psbt);
+            if let Err(err) = result {
+                self.show_error(&err.to_string());
+            }
+        } else {
+            self.position.set(None);
+            *self.psbt.borrow_mut() = psbt;
+        }
+        self.display(doc);
+    }
+
+    fn choose_file(&self, action: FileChooserAction) -> Option<PathBuf> {
+        let (title, accept) = match action {
+            FileChooserAction::Save => ("Export PSBT", "_Save"),
+            _ => ("Load PSBT", "_Open"),
+        };
+        let chooser = gtk::FileChooserNative::new(
+            Some(title),
+            Some(&self.window),
+            action,
+            Some(accept),
+            Some("_Cancel"),
+        );
+        if action == FileChooserAction::Save {
+            chooser.set_do_overwrite_confirmation(true);
+            chooser.set_current_name("transaction.psbt");
+        }
+        match chooser.run() {
+            ResponseType::Accept => chooser.filename(),
+            _ => None,
+        }
+    }
+
+    /// Sets the transaction spent by the selected input
+    fn set_spent_tx(&self, doc: &Document, tx: Transaction) {
+        let (vin, outpoint) =
+            match self.input_selection().zip(self.input_outpoint()) {
+                Some(selection) => selection,
+                None => return,
+            };
+        if tx.txid() != outpoint.txid {
+            self.show_error(&format!(
+                "Transaction {} is not spent by the input",
+                tx.txid()
+            ));
+            return;
+        }
+        set_spent_tx(
+            &mut self.psbt.borrow_mut().inputs[vin],
+            tx,
+            outpoint.vout,
+        );
+        self.display_inputs(doc, Some(vin));
+    }
+
+    fn update_sighash(&self) {
+        if self.updating.get() {
+            return;
+        }
+        let vin = match self.input_selection() {
+            Some(vin) => vin,
+            None => return,
+        };
+        let sighash = match self.sighash_combo.active_id().as_deref() {
+            Some("SIGHASH_ALL") => Some(0x01),
+            Some("SIGHASH_NONE") => Some(0x02),
+            Some("SIGHASH_SINGLE") => Some(0x03),
+            _ => None,
+        }
+        .map(|sighash| match self.anyone_can_pay_toggle.is_active() {
+            true => sighash | 0x80,
+            false => sighash,
+        });
+        self.psbt.borrow_mut().inputs[vin].sighash_type =
+            sighash.map(PsbtSighashType::from_u32);
+    }
+
+    /// Updates witness script of the selected input spending segwit output
+    /// or redeem script of other inputs. The script is kept unchanged while
+    /// the entered text is not a valid hex.
+    fn update_script(&self) {
+        if self.updating.get() {
+            return;
+        }
+        let vin = match self.input_selection() {
+            Some(vin) => vin,
+            None => return,
+        };
+        let (start, end) = self.script_buffer.bounds();
+        let text = self
+            .script_buffer
+            .text(&start, &end, false)
+            .map(|text| text.to_string())
+            .unwrap_or_default();
+        let script = match Vec::<u8>::from_hex(text.trim()) {
+            Ok(data) if data.is_empty() => None,
+            Ok(data) => Some(Script::from(data)),
+            Err(_) => return,
+        };
+        let mut psbt = self.psbt.borrow_mut();
+        let input = &mut psbt.inputs[vin];
+        match input.witness_utxo {
+            Some(_) => input.witness_script = script,
+            None => input.redeem_script = script,
+        }
+    }
+
+    fn display(&self, doc: &Document) {
+        self.display_global(doc);
+        self.display_inputs(doc, None);
+        self.display_outputs(doc, None);
+        self.update_ui();
+    }
+
+    fn display_global(&self, doc: &Document) {
+        let psbt = self.psbt.borrow();
+        fill_store(
+            &self.xpub_store,
+            &with_accounts(doc, psbt::key_origin_rows(&psbt.xpub)),
+        );
+        fill_store(
+            &self.global_vendor_store,
+            &psbt::proprietary_rows(&psbt.proprietary),
+        );
+        fill_store(
+            &self.global_unknown_store,
+            &psbt::unknown_rows(&psbt.unknown),
+        );
+    }
+
+    fn display_inputs(&self, doc: &Document, select: Option<usize>) {
+        fill_store(&self.input_store, &psbt::input_rows(&self.psbt.borrow()));
+        if let Some(vin) = select {
+            self.input_tree
+                .selection()
+                .select_path(&gtk::TreePath::from_indicesv(&[vin as i32]));
+        }
+        self.display_input(doc);
+        self.update_ui();
+    }
+
+    fn display_input(&self, doc: &Document) {
+        let updating = self.updating.replace(true);
+        let psbt = self.psbt.borrow();
+        let input = self.input_selection().and_then(|vin| {
+            Some((psbt.inputs.get(vin)?, psbt.unsigned_tx.input.get(vin)?))
+        });
+
+        match input {
+            None => {
+                self.sig_store.clear();
+                self.preimage_store.clear();
+                self.hd_input_store.clear();
+                self.input_vendor_store.clear();
+                self.input_unknown_store.clear();
+                self.input_tx_entry.set_text("");
+                self.input_tx_entry.set_secondary_icon_name(None);
+                self.sighash_combo.set_active_id(Some("SIGHASH_UNSET"));
+                self.anyone_can_pay_toggle.set_active(false);
+                self.script_buffer.set_text("");
+            }
+            Some((input, txin)) => {
+                fill_store(&self.sig_store, &psbt::sig_rows(input));
+                fill_store(&self.preimage_store, &psbt::preimage_rows(input));
+                fill_store(
+                    &self.hd_input_store,
+                    &with_accounts(
+                        doc,
+                        psbt::key_origin_rows(&input.bip32_derivation),
+                    ),
+                );
+                fill_store(
+                    &self.input_vendor_store,
+                    &psbt::proprietary_rows(&input.proprietary),
+                );
+                fill_store(
+                    &self.input_unknown_store,
+                    &psbt::unknown_rows(&input.unknown),
+                );
+
+                let spent_tx = input.non_witness_utxo.as_ref();
+                self.input_tx_entry
+                    .set_text(&spent_tx.map(serialize_hex).unwrap_or_default());
+                self.input_tx_entry.set_secondary_icon_name(spent_tx.map(
+                    |tx| match tx.txid() == txin.previous_output.txid {
+                        true => "dialog-ok",
+                        false => "dialog-error",
+                    },
+                ));
+
+                let sighash = input.sighash_type.map(PsbtSighashType::to_u32);
+                self.sighash_combo.set_active_id(Some(
+                    match sighash.map(|sighash| sighash & 0x1f) {
+                        None => "SIGHASH_UNSET",
+                        Some(0x02) => "SIGHASH_NONE",
+                        Some(0x03) => "SIGHASH_SINGLE",
+                        Some(_) => "SIGHASH_ALL",
+                    },
+                ));
+                self.anyone_can_pay_toggle.set_active(
+                    sighash.map(|sighash| sighash & 0x80 != 0).unwrap_or(false),
+                );
+
+                let script = match input.witness_utxo {
+                    Some(_) => input.witness_script.as_ref(),
+                    None => input.redeem_script.as_ref(),
+                };
+                self.script_buffer.set_text(
+                    &script
+                        .map(|script| script.as_bytes().to_hex())
+                        .unwrap_or_default(),
+                );
+            }
+        }
+
+        self.updating.set(updating);
+    }
+
+    fn display_outputs(&self, doc: &Document, select: Option<usize>) {
+        fill_store(
+            &self.output_store,
+            &psbt::output_rows(&self.psbt.borrow(), self.network.get()),
+        );
+        if let Some(vout) = select {
+            self.output_tree
+                .selection()
+                .select_path(&gtk::TreePath::from_indicesv(&[vout as i32]));
+        }
+        self.display_output(doc);
+        self.update_ui();
+    }
+
+    fn display_output(&self, doc: &Document) {
+        let psbt = self.psbt.borrow();
+        match self
+            .output_selection()
+            .and_then(|vout| psbt.outputs.get(vout))
+        {
+            None => {
+                self.hd_output_store.clear();
+                self.output_vendor_store.clear();
+                self.output_unknown_store.clear();
+            }
+            Some(output) => {
+                fill_store(
+                    &self.hd_output_store,
+                    &with_accounts(
+                        doc,
+                        psbt::key_origin_rows(&output.bip32_derivation),
+                    ),
+                );
+                fill_store(
+                    &self.output_vendor_store,
+                    &psbt::proprietary_rows(&output.proprietary),
+                );
+                fill_store(
+                    &self.output_unknown_store,
+                    &psbt::unknown_rows(&output.unknown),
+                );
+            }
+        }
+    }
+
+    pub fn update_ui(&self) {
+        let psbt = self.psbt.borrow();
+        let signed = psbt.inputs.iter().filter(|i| psbt::is_signed(i)).count();
+        self.header_bar.set_subtitle(Some(&format!(
+            "{}, {} of {} inputs signed",
+            psbt.unsigned_tx.txid(),
+            signed,
+            psbt.inputs.len()
+        )));
+
+        let vin = self.input_selection();
+        let inputs = psbt.inputs.len();
+        self.input_up_btn.set_sensitive(vin.unwrap_or_default() > 0);
+        self.input_down_btn
+            .set_sensitive(vin.map(|vin| vin + 1 < inputs).unwrap_or(false));
+        self.input_remove_btn.set_sensitive(vin.is_some());
+        self.input_map_add_btn.set_sensitive(vin.is_some());
+        self.input_tx_entry.set_sensitive(vin.is_some());
+        self.input_tx_refresh_btn.set_sensitive(vin.is_some());
+        self.sighash_combo.set_sensitive(vin.is_some());
+        self.anyone_can_pay_toggle.set_sensitive(
+            vin.is_some()
+                && self.sighash_combo.active_id().as_deref()
+                    != Some("SIGHASH_UNSET"),
+        );
+        self.script_text.set_sensitive(vin.is_some());
+
+        let vout = self.output_selection();
+        let outputs = psbt.outputs.len();
+        self.output_up_btn
+            .set_sensitive(vout.unwrap_or_default() > 0);
+        self.output_down_btn.set_sensitive(
+            vout.map(|vout| vout + 1 < outputs).unwrap_or(false),
+        );
+        self.output_remove_btn.set_sensitive(vout.is_some());
+        self.output_map_add_btn.set_sensitive(vout.is_some());
+    }
+
+    pub fn input_selection(&self) -> Option<usize> {
+        tree_selection(&self.input_tree)
+    }
+
+    pub fn output_selection(&self) -> Option<usize> {
+        tree_selection(&self.output_tree)
+    }
+
+    fn input_outpoint(&self) -> Option<OutPoint> {
+        let vin = self.input_selection()?;
+        self.psbt
+            .borrow()
+            .unsigned_tx
+            .input
+            .get(vin)
+            .map(|txin| txin.previous_output)
+    }
+
+    fn show_error(&self, msg: &str) {
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Close,
+            msg,
+        );
+        dlg.run();
+        dlg.hide();
+    }
+}
+
+/// Index of the selected row in a plain list
+fn tree_selection(tree: &gtk::TreeView) -> Option<usize> {
+    tree.selection()
+        .selected()
+        .and_then(|(model, iter)| model.path(&iter))
+        .and_then(|path| path.indices().first().copied())
+        .map(|index| index as usize)
+}
+
+/// Names tracking accounts owning the keys
+fn with_accounts(doc: &Document, rows: Vec<KeyOriginRow>) -> Vec<KeyOriginRow> {
+    rows.into_iter()
+        .map(|row| KeyOriginRow {
+            account: doc
+                .key_account(&row.key, &row.fingerprint)
+                .unwrap_or_default(),
+            ..row
+        })
+        .collect()
+}
+
+/// Puts the transaction spent by the input into its non-witness UTXO and,
+/// for segwit outputs, the spent output into its witness UTXO
+fn set_spent_tx(input: &mut Input, tx: Transaction, vout: u32) {
+    if let Some(txout) = tx.output.get(vout as usize) {
+        if txout.script_pubkey.is_witness_program() {
+            input.witness_utxo = Some(txout.clone());
+        }
+    }
+    input.non_witness_utxo = Some(tx);
+}
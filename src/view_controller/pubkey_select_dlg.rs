@@ -22,6 +22,7 @@ use std::rc::Rc;
 use gtk::ResponseType;
 
 use crate::model::{Document, TrackingAccount};
+use crate::view_controller::fill_store;
 
 static UI: &str = include_str!("../view/pubkey_select.glade");
 
@@ -70,7 +71,7 @@ impl PubkeySelectDlg {
         on_select: impl Fn(TrackingAccount) + 'static,
         on_cancel: impl Fn() + 'static,
     ) {
-        doc.borrow().fill_tracking_store(&self.pubkey_store);
+        fill_store(&self.pubkey_store, &doc.borrow().tracking_rows());
 
         self.pubkey_selection.connect_changed(
             clone!(@weak self as me => move |_| {
@@ -0,0 +1,368 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Presentation of document model rows inside GTK list stores
+
+use gtk::prelude::*;
+
+use crate::model::{
+    AddressRow, AssetRow, DescriptorRow, HistoryRow, LogRow, PsbtRow,
+    TrackingRow, TransactionRow, TxInputRow, TxOutputRow, UtxoRow,
+};
+use crate::util::psbt::{
+    KeyOriginRow, PreimageRow, ProprietaryRow, PsbtInputRow, PsbtOutputRow,
+    SigRow, UnknownRow,
+};
+
+pub trait StoreRow {
+    fn append_to(&self, store: &gtk::ListStore);
+}
+
+/// Rows which can be edited by the user and read back from the store
+pub trait EditableRow: StoreRow + Sized {
+    fn read_from(store: &gtk::ListStore, iter: &gtk::TreeIter) -> Self;
+}
+
+pub fn fill_store<'row, R>(
+    store: &gtk::ListStore,
+    rows: impl IntoIterator<Item = &'row R>,
+) where
+    R: StoreRow + 'row,
+{
+    store.clear();
+    rows.into_iter().for_each(|row| row.append_to(store));
+}
+
+pub fn read_store<R>(store: &gtk::ListStore) -> Vec<R>
+where
+    R: EditableRow,
+{
+    let mut rows = vec![];
+    if let Some(iter) = store.iter_first() {
+        loop {
+            rows.push(R::read_from(store, &iter));
+            if !store.iter_next(&iter) {
+                break;
+            }
+        }
+    }
+    rows
+}
+
+fn text_at(
+    store: &gtk::ListStore,
+    iter: &gtk::TreeIter,
+    column: i32,
+) -> String {
+    store
+        .value(iter, column)
+        .get::<String>()
+        .unwrap_or_default()
+}
+
+fn byte_at(store: &gtk::ListStore, iter: &gtk::TreeIter, column: i32) -> u8 {
+    store.value(iter, column).get::<u32>().unwrap_or_default() as u8
+}
+
+impl StoreRow for TrackingRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[(0, &self.name), (1, &self.details), (2, &self.count)],
+        );
+    }
+}
+
+impl StoreRow for DescriptorRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[(0, &self.name), (1, &self.type_name), (2, &self.descriptor)],
+        );
+    }
+}
+
+impl StoreRow for UtxoRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.outpoint.txid.to_string()),
+                (1, &self.outpoint.vout),
+                (2, &self.amount),
+                (3, &self.height),
+            ],
+        );
+    }
+}
+
+impl StoreRow for AddressRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.index),
+                (1, &self.address_string()),
+                (2, &self.label),
+                (3, &self.note),
+                (4, &self.issued.clone().unwrap_or_default()),
+                (5, &self.used),
+            ],
+        );
+    }
+}
+
+impl StoreRow for HistoryRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.txid.to_string()),
+                (1, &self.height),
+                (2, &self.received),
+                (3, &self.spent),
+                (4, &self.net()),
+            ],
+        );
+    }
+}
+
+impl StoreRow for TransactionRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.txid.to_string()),
+                (
+                    1,
+                    &self
+                        .height
+                        .as_ref()
+                        .map(u32::to_string)
+                        .unwrap_or(s!("?")),
+                ),
+                (2, &(self.inputs as u32)),
+                (3, &(self.outputs as u32)),
+            ],
+        );
+    }
+}
+
+impl StoreRow for TxInputRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.no),
+                (1, &self.previous_output.txid.to_string()),
+                (2, &self.previous_output.vout),
+                (3, &self.sequence),
+                (4, &self.descriptor.clone().unwrap_or_default()),
+            ],
+        );
+    }
+}
+
+impl StoreRow for TxOutputRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.no),
+                (1, &self.value),
+                (2, &self.script_pubkey.asm()),
+                (3, &self.spent),
+                (4, &self.descriptor.clone().unwrap_or_default()),
+            ],
+        );
+    }
+}
+
+impl StoreRow for AssetRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.ticker),
+                (1, &self.name),
+                (2, &self.known_value),
+                (
+                    3,
+                    &self
+                        .circulating
+                        .as_ref()
+                        .map(u64::to_string)
+                        .unwrap_or(s!("?")),
+                ),
+                (4, &1),
+                (5, &self.inflatable),
+                (6, &0),
+                (7, &self.contract_id.to_string()),
+            ],
+        );
+    }
+}
+
+impl StoreRow for PsbtRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.txid.to_string()),
+                (1, &(self.inputs as u32)),
+                (2, &(self.outputs as u32)),
+                (3, &(self.signed as u32)),
+            ],
+        );
+    }
+}
+
+impl StoreRow for LogRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[(0, &self.time), (1, &self.operation), (2, &self.details)],
+        );
+    }
+}
+
+impl StoreRow for PsbtInputRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.previous_output.txid.to_string()),
+                (1, &self.previous_output.vout),
+                (2, &self.utxo_known),
+                (3, &self.signatures),
+            ],
+        );
+    }
+}
+
+impl StoreRow for PsbtOutputRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(None, &[(0, &self.value), (1, &self.script)]);
+    }
+}
+
+impl StoreRow for KeyOriginRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.key),
+                (1, &self.fingerprint),
+                (2, &self.derivation),
+                (3, &self.account),
+            ],
+        );
+    }
+}
+
+impl EditableRow for KeyOriginRow {
+    fn read_from(store: &gtk::ListStore, iter: &gtk::TreeIter) -> Self {
+        KeyOriginRow {
+            key: text_at(store, iter, 0),
+            fingerprint: text_at(store, iter, 1),
+            derivation: text_at(store, iter, 2),
+            account: text_at(store, iter, 3),
+        }
+    }
+}
+
+impl StoreRow for ProprietaryRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &self.prefix),
+                (1, &(self.subtype as u32)),
+                (2, &self.key),
+                (3, &self.value),
+            ],
+        );
+    }
+}
+
+impl EditableRow for ProprietaryRow {
+    fn read_from(store: &gtk::ListStore, iter: &gtk::TreeIter) -> Self {
+        ProprietaryRow {
+            prefix: text_at(store, iter, 0),
+            subtype: byte_at(store, iter, 1),
+            key: text_at(store, iter, 2),
+            value: text_at(store, iter, 3),
+        }
+    }
+}
+
+impl StoreRow for UnknownRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[
+                (0, &(self.type_value as u32)),
+                (1, &self.key),
+                (2, &self.value),
+            ],
+        );
+    }
+}
+
+impl EditableRow for UnknownRow {
+    fn read_from(store: &gtk::ListStore, iter: &gtk::TreeIter) -> Self {
+        UnknownRow {
+            type_value: byte_at(store, iter, 0),
+            key: text_at(store, iter, 1),
+            value: text_at(store, iter, 2),
+        }
+    }
+}
+
+impl StoreRow for SigRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(
+            None,
+            &[(0, &self.pubkey), (1, &self.signature), (2, &self.origin)],
+        );
+    }
+}
+
+impl EditableRow for SigRow {
+    fn read_from(store: &gtk::ListStore, iter: &gtk::TreeIter) -> Self {
+        SigRow {
+            pubkey: text_at(store, iter, 0),
+            signature: text_at(store, iter, 1),
+            origin: text_at(store, iter, 2),
+        }
+    }
+}
+
+impl StoreRow for PreimageRow {
+    fn append_to(&self, store: &gtk::ListStore) {
+        store.insert_with_values(None, &[(0, &self.hash), (1, &self.preimage)]);
+    }
+}
+
+impl EditableRow for PreimageRow {
+    fn read_from(store: &gtk::ListStore, iter: &gtk::TreeIter) -> Self {
+        PreimageRow {
+            hash: text_at(store, iter, 0),
+            preimage: text_at(store, iter, 1),
+        }
+    }
+}
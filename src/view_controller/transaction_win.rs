@@ -0,0 +1,576 @@
+// Bitcoin Pro: Professional bitcoin accounts & assets management
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoraprime.ch>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use gtk::gdk;
+use gtk::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use bitcoin::consensus::{deserialize, encode::serialize_hex};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{Network, OutPoint, Transaction, Txid};
+
+use crate::controller::broadcast::TxBroadcast;
+use crate::model::Document;
+use crate::util::script::{self, ScriptFormat};
+use crate::view_controller::{fill_store, FeeBumpDlg, PsbtWin};
+
+static UI: &str = include_str!("../view/transaction.glade");
+
+/// Viewer for the transactions from the document cache, blockchain or
+/// pasted in hex form
+pub struct TransactionWin {
+    window: gtk::ApplicationWindow,
+    header_bar: gtk::HeaderBar,
+    search: gtk::SearchEntry,
+    back_btn: gtk::Button,
+    forward_btn: gtk::Button,
+    export_btn: gtk::Button,
+    broadcast_btn: gtk::Button,
+    bump_fee_btn: gtk::Button,
+    txid_display: gtk::Entry,
+    wtxid_display: gtk::Entry,
+    version_display: gtk::Entry,
+    locktime_display: gtk::Entry,
+    witness_check: gtk::CheckButton,
+    size_display: gtk::Entry,
+    vsize_display: gtk::Entry,
+    weight_display: gtk::Entry,
+    fee_display: gtk::Entry,
+    fee_rate_display: gtk::Entry,
+    input_tree: gtk::TreeView,
+    input_store: gtk::ListStore,
+    input_goto_btn: gtk::ToolButton,
+    input_format_combo: gtk::ComboBoxText,
+    input_script_combo: gtk::ComboBoxText,
+    input_script_display: gtk::Entry,
+    output_tree: gtk::TreeView,
+    output_store: gtk::ListStore,
+    output_goto_btn: gtk::ToolButton,
+    output_refresh_btn: gtk::ToolButton,
+    output_format_combo: gtk::ComboBoxText,
+    output_script_display: gtk::Entry,
+    network_label: gtk::Label,
+    block_label: gtk::Label,
+    height_label: gtk::Label,
+    time_label: gtk::Label,
+    confirmations_label: gtk::Label,
+    network: Cell<Option<Network>>,
+    /// Transactions opened in this window, for back and forward navigation
+    history: RefCell<Vec<Transaction>>,
+    position: Cell<usize>,
+}
+
+impl TransactionWin {
+    pub fn load_glade() -> Option<Rc<Self>> {
+        let builder = gtk::Builder::from_string(UI);
+
+        let header_bar = builder.object("headerBar")?;
+        let search = builder.object("search")?;
+        let back_btn = builder.object("backBtn")?;
+        let forward_btn = builder.object("forwardBtn")?;
+        let export_btn = builder.object("exportBtn")?;
+        let broadcast_btn = builder.object("broadcastBtn")?;
+        let bump_fee_btn = builder.object("bumpFeeBtn")?;
+
+        let txid_display = builder.object("txidDisplay")?;
+        let wtxid_display = builder.object("wtxidDisplay")?;
+        let version_display = builder.object("versionDisplay")?;
+        let locktime_display = builder.object("locktimeDisplay")?;
+        let witness_check = builder.object("witnessCheck")?;
+        let size_display = builder.object("sizeDisplay")?;
+        let vsize_display = builder.object("vsizeDisplay")?;
+        let weight_display = builder.object("weightDisplay")?;
+        let fee_display = builder.object("feeDisplay")?;
+        let fee_rate_display = builder.object("feeRateDisplay")?;
+
+        let input_tree = builder.object("inputTree")?;
+        let input_store = builder.object("inputStore")?;
+        let input_goto_btn = builder.object("outputGoto1")?;
+        let input_format_combo = builder.object("inputFormatCombo")?;
+        let input_script_combo = builder.object("inputScriptCombo")?;
+        let input_script_display = builder.object("inputScriptDisplay")?;
+
+        let output_tree = builder.object("outputTree")?;
+        let output_store = builder.object("outputStore")?;
+        let output_goto_btn = builder.object("outputGoto")?;
+        let output_refresh_btn = builder.object("outputRefresh")?;
+        let output_format_combo = builder.object("outputFormatCombo")?;
+        let output_script_display = builder.object("outputScriptDisplay")?;
+
+        let network_label = builder.object("networkLabel")?;
+        let block_label = builder.object("blockLabel")?;
+        let height_label = builder.object("heightLabel")?;
+        let time_label = builder.object("timeLabel")?;
+        let confirmations_label = builder.object("confirmationsLabel")?;
+
+        let me = Rc::new(Self {
+            window: glade_load!(builder, "transactionWin").ok()?,
+            header_bar,
+            search,
+            back_btn,
+            forward_btn,
+            export_btn,
+            broadcast_btn,
+            bump_fee_btn,
+            txid_display,
+            wtxid_display,
+            version_display,
+            locktime_display,
+            witness_check,
+            size_display,
+            vsize_display,
+            weight_display,
+            fee_display,
+            fee_rate_display,
+            input_tree,
+            input_store,
+            input_goto_btn,
+            input_format_combo,
+            input_script_combo,
+            input_script_display,
+            output_tree,
+            output_store,
+            output_goto_btn,
+            output_refresh_btn,
+            output_format_combo,
+            output_script_display,
+            network_label,
+            block_label,
+            height_label,
+            time_label,
+            confirmations_label,
+            network: Cell::new(None),
+            history: empty!(),
+            position: Cell::new(0),
+        });
+
+        Some(me)
+    }
+}
+
+impl TransactionWin {
+    /// Shows the window with the given transaction; if no transaction is
+    /// provided, the window waits for a transaction id or hex to be entered
+    /// into the search field
+    pub fn run(
+        self: Rc<Self>,
+        doc: Rc<RefCell<Document>>,
+        tx: Option<Transaction>,
+    ) {
+        self.header_bar.set_subtitle(Some(&doc.borrow().name()));
+        self.network_label.set_text(&doc.borrow().chain().to_string());
+        self.network.set(doc.borrow().network());
+
+        self.search.connect_activate(
+            clone!(@weak self as me, @strong doc => move |entry| {
+                let query = entry.text();
+                let query = query.trim();
+                let tx = match Txid::from_hex(query) {
+                    Ok(txid) => Self::fetch(&doc.borrow(), txid),
+                    Err(_) => Vec::<u8>::from_hex(query)
+                        .ok()
+                        .and_then(|data| deserialize(&data).ok())
+                        .ok_or_else(|| s!("Not a transaction id or hex")),
+                };
+                match tx {
+                    Ok(tx) => me.open(&doc.borrow(), tx),
+                    Err(err) => me.show_error(&err),
+                }
+            }),
+        );
+
+        self.back_btn
+            .connect_clicked(clone!(@weak self as me, @strong doc => move |_| {
+                me.navigate(&doc.borrow(), -1);
+            }));
+
+        self.forward_btn
+            .connect_clicked(clone!(@weak self as me, @strong doc => move |_| {
+                me.navigate(&doc.borrow(), 1);
+            }));
+
+        self.export_btn
+            .connect_clicked(clone!(@weak self as me => move |_| {
+                if let Some(tx) = me.current() {
+                    gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD)
+                        .set_text(&serialize_hex(&tx));
+                }
+            }));
+
+        self.broadcast_btn
+            .connect_clicked(clone!(@weak self as me, @strong doc => move |_| {
+                me.broadcast(&doc);
+            }));
+
+        self.bump_fee_btn
+            .connect_clicked(clone!(@weak self as me, @strong doc => move |_| {
+                let tx = match me.current() {
+                    Some(tx) => tx,
+                    None => return,
+                };
+                let bump_dlg = FeeBumpDlg::load_glade().expect("Must load");
+                bump_dlg.run(
+                    doc.clone(),
+                    tx,
+                    clone!(@strong doc => move |composition| {
+                        let pos = doc.borrow().psbts().len();
+                        let result = doc.borrow_mut().add_composed_psbt(
+                            composition.psbt,
+                            composition.change_link.as_ref(),
+                        );
+                        if result.is_ok() {
+                            if let Some(win) = PsbtWin::load_glade() {
+                                win.run(doc.clone(), Some(pos));
+                            }
+                        }
+                    }),
+                    || {},
+                );
+            }));
+
+        self.input_tree.selection().connect_changed(
+            clone!(@weak self as me => move |_| {
+                me.update_input_script();
+                me.update_ui();
+            }),
+        );
+        self.input_format_combo.connect_changed(
+            clone!(@weak self as me => move |_| me.update_input_script()),
+        );
+        self.input_script_combo.connect_changed(
+            clone!(@weak self as me => move |_| me.update_input_script()),
+        );
+
+        self.output_tree.selection().connect_changed(
+            clone!(@weak self as me => move |_| {
+                me.update_output_script();
+                me.update_ui();
+            }),
+        );
+        self.output_format_combo.connect_changed(
+            clone!(@weak self as me => move |_| me.update_output_script()),
+        );
+
+        self.input_goto_btn
+            .connect_clicked(clone!(@weak self as me, @strong doc => move |_| {
+                let outpoint = match me.input_selection()
+                    .and_then(|vin| me.current()?.input.get(vin).cloned()) {
+                    Some(txin) => txin.previous_output,
+                    None => return,
+                };
+                match Self::fetch(&doc.borrow(), outpoint.txid) {
+                    Ok(tx) => me.open(&doc.borrow(), tx),
+                    Err(err) => me.show_error(&err),
+                }
+            }));
+
+        self.output_goto_btn
+            .connect_clicked(clone!(@weak self as me, @strong doc => move |_| {
+                let doc = doc.borrow();
+                let spending_tx = me
+                    .current()
+                    .zip(me.output_selection())
+                    .and_then(|(tx, vout)| {
+                        doc.spending_txid(OutPoint::new(tx.txid(), vout))
+                    })
+                    .and_then(|txid| doc.transaction(txid).cloned());
+                if let Some(tx) = spending_tx {
+                    me.open(&doc, tx);
+                }
+            }));
+
+        self.output_refresh_btn
+            .connect_clicked(clone!(@weak self as me, @strong doc => move |_| {
+                me.display(&doc.borrow());
+            }));
+
+        // The window owns the controller until it is closed
+        self.window.connect_delete_event(
+            clone!(@strong self as me => move |_, _| {
+                me.history.borrow_mut().clear();
+                gtk::Inhibit(false)
+            }),
+        );
+
+        match tx {
+            Some(tx) => self.open(&doc.borrow(), tx),
+            None => self.display(&doc.borrow()),
+        }
+
+        self.window.show();
+    }
+
+    /// Transaction from the document cache or, if it is not known, from the
+    /// configured blockchain resolver
+    pub fn fetch(doc: &Document, txid: Txid) -> Result<Transaction, String> {
+        if let Some(tx) = doc.transaction(txid) {
+            return Ok(tx.clone());
+        }
+        doc.resolver()
+            .and_then(|resolver| resolver.transaction(txid))
+            .map_err(|err| err.to_string())
+    }
+
+    /// Broadcasts the current transaction with the document resolver and
+    /// records it into the document history. If pre-flight checks fail, the
+    /// user may still broadcast the transaction.
+    fn broadcast(&self, doc: &Rc<RefCell<Document>>) {
+        let tx = match self.current() {
+            Some(tx) => tx,
+            None => return,
+        };
+        let resolver = match doc.borrow().resolver() {
+            Ok(resolver) => resolver,
+            Err(err) => {
+                self.show_error(&err.to_string());
+                return;
+            }
+        };
+        let cached = |txid: Txid| doc.borrow().transaction(txid).cloned();
+        if let Err(err) = self.preflight(&*resolver, &tx, cached) {
+            if !self.confirm(&format!("{}. Broadcast it anyway?", err)) {
+                return;
+            }
+        }
+        let result = self
+            .broadcast_tx(&*resolver, &tx, false, cached)
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                doc.borrow_mut()
+                    .record_broadcast(tx)
+                    .map_err(|err| err.to_string())
+            });
+        match result {
+            Ok(_) => self.display(&doc.borrow()),
+            Err(err) => self.show_error(&err),
+        }
+    }
+
+    /// Opens new transaction, dropping forward navigation history
+    fn open(&self, doc: &Document, tx: Transaction) {
+        {
+            let mut history = self.history.borrow_mut();
+            if !history.is_empty() {
+                history.truncate(self.position.get() + 1);
+            }
+            history.push(tx);
+            self.position.set(history.len() - 1);
+        }
+        self.display(doc);
+    }
+
+    fn navigate(&self, doc: &Document, step: isize) {
+        let position = self.position.get() as isize + step;
+        if position < 0 || position as usize >= self.history.borrow().len() {
+            return;
+        }
+        self.position.set(position as usize);
+        self.display(doc);
+    }
+
+    fn current(&self) -> Option<Transaction> {
+        self.history.borrow().get(self.position.get()).cloned()
+    }
+
+    fn display(&self, doc: &Document) {
+        let tx = match self.current() {
+            Some(tx) => tx,
+            None => {
+                self.input_store.clear();
+                self.output_store.clear();
+                self.update_ui();
+                return;
+            }
+        };
+        let txid = tx.txid();
+
+        self.txid_display.set_text(&txid.to_string());
+        self.wtxid_display.set_text(&tx.wtxid().to_string());
+        self.version_display.set_text(&tx.version.to_string());
+        self.locktime_display.set_text(&tx.lock_time.to_string());
+        self.witness_check
+            .set_active(tx.input.iter().any(|txin| !txin.witness.is_empty()));
+        self.size_display.set_text(&tx.size().to_string());
+        self.vsize_display.set_text(&tx.vsize().to_string());
+        self.weight_display.set_text(&tx.weight().to_string());
+        match doc.tx_fee(&tx) {
+            Some(fee) => {
+                self.fee_display.set_text(&fee.to_string());
+                self.fee_rate_display.set_text(&format!(
+                    "{:.1}",
+                    fee as f64 / tx.vsize() as f64
+                ));
+            }
+            None => {
+                self.fee_display.set_text("?");
+                self.fee_rate_display.set_text("?");
+            }
+        }
+
+        // Block hash and time are not tracked by the document
+        self.block_label.set_text("-");
+        self.time_label.set_text("-");
+        self.confirmations_label.set_text("-");
+        self.height_label.set_text(&match doc.transaction_height(txid) {
+            None => s!("-"),
+            Some(0) => s!("mempool"),
+            Some(height) => height.to_string(),
+        });
+
+        fill_store(&self.input_store, &doc.tx_input_rows(&tx));
+        fill_store(&self.output_store, &doc.tx_output_rows(&tx));
+
+        self.update_input_script();
+        self.update_output_script();
+        self.update_ui();
+    }
+
+    fn update_input_script(&self) {
+        let txin = self
+            .input_selection()
+            .and_then(|vin| self.current()?.input.get(vin).cloned());
+        let txin = match txin {
+            Some(txin) => txin,
+            None => {
+                self.input_script_display.set_text("");
+                return;
+            }
+        };
+        let format = self
+            .input_format_combo
+            .active_id()
+            .and_then(|id| id.parse().ok())
+            .unwrap_or(ScriptFormat::Hex);
+        let text = match self.input_script_combo.active_id().as_deref() {
+            Some("redeemScript") => {
+                script::redeem_script(&txin).and_then(|script| {
+                    script::format_script(&script, format, false, None)
+                })
+            }
+            Some("witnessScript") => {
+                script::witness_script(&txin).and_then(|script| {
+                    script::format_script(&script, format, true, None)
+                })
+            }
+            Some("witness") => Some(script::witness_stack(&txin)),
+            _ => script::format_script(&txin.script_sig, format, false, None),
+        };
+        show_script(&self.input_script_display, text);
+    }
+
+    fn update_output_script(&self) {
+        let txout = self.output_selection().and_then(|vout| {
+            self.current()?.output.get(vout as usize).cloned()
+        });
+        let txout = match txout {
+            Some(txout) => txout,
+            None => {
+                self.output_script_display.set_text("");
+                return;
+            }
+        };
+        let format = self
+            .output_format_combo
+            .active_id()
+            .and_then(|id| id.parse().ok())
+            .unwrap_or(ScriptFormat::Hex);
+        show_script(
+            &self.output_script_display,
+            script::format_script(
+                &txout.script_pubkey,
+                format,
+                false,
+                self.network.get(),
+            ),
+        );
+    }
+
+    pub fn update_ui(&self) {
+        let position = self.position.get();
+        let len = self.history.borrow().len();
+        self.back_btn.set_sensitive(position > 0);
+        self.forward_btn.set_sensitive(position + 1 < len);
+        self.export_btn.set_sensitive(len > 0);
+        self.broadcast_btn.set_sensitive(len > 0);
+        self.bump_fee_btn.set_sensitive(len > 0);
+
+        let is_coinbase = self
+            .input_selection()
+            .and_then(|vin| self.current()?.input.get(vin).cloned())
+            .map(|txin| txin.previous_output.is_null());
+        self.input_goto_btn.set_sensitive(is_coinbase == Some(false));
+
+        let is_spent = self
+            .output_tree
+            .selection()
+            .selected()
+            .and_then(|(model, iter)| {
+                model.value(&iter, 3).get::<bool>().ok()
+            });
+        self.output_goto_btn.set_sensitive(is_spent == Some(true));
+    }
+
+    pub fn input_selection(&self) -> Option<usize> {
+        self.input_tree
+            .selection()
+            .selected()
+            .and_then(|(model, iter)| model.value(&iter, 0).get::<u32>().ok())
+            .map(|no| no as usize)
+    }
+
+    pub fn output_selection(&self) -> Option<u32> {
+        self.output_tree
+            .selection()
+            .selected()
+            .and_then(|(model, iter)| model.value(&iter, 0).get::<u32>().ok())
+    }
+
+    fn show_error(&self, msg: &str) {
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Close,
+            msg,
+        );
+        dlg.run();
+        dlg.hide();
+    }
+
+    fn confirm(&self, msg: &str) -> bool {
+        let dlg = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::YesNo,
+            msg,
+        );
+        let response = dlg.run();
+        dlg.hide();
+        response == gtk::ResponseType::Yes
+    }
+}
+
+impl TxBroadcast for TransactionWin {}
+
+/// Displays the script, or a hint if it can't be represented in the selected
+/// format
+fn show_script(entry: &gtk::Entry, text: Option<String>) {
+    entry.set_text(&text.unwrap_or_default());
+    entry.set_placeholder_text(Some("Not available in this format"));
+}
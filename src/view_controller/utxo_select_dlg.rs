@@ -24,6 +24,7 @@ use std::str::FromStr;
 use bitcoin::{OutPoint, Txid};
 
 use crate::model::{Document, UtxoEntry};
+use crate::view_controller::fill_store;
 
 static UI: &str = include_str!("../view/utxo_select.glade");
 
@@ -78,7 +79,7 @@ impl UtxoSelectDlg {
         on_select: impl Fn(UtxoEntry) + 'static,
         on_cancel: impl Fn() + 'static,
     ) {
-        doc.borrow().fill_descriptor_store(&self.descriptor_store);
+        fill_store(&self.descriptor_store, &doc.borrow().descriptor_rows());
 
         self.descriptor_selection.connect_changed(
             clone!(@weak self as me, @strong doc => move |_| {
@@ -88,7 +89,7 @@ impl UtxoSelectDlg {
                         doc.borrow().descriptor_by_generator(&generator)
                     })
                 {
-                    doc.borrow().fill_utxo_store(&me.utxo_store, Some(&descriptor_generator));
+                    fill_store(&me.utxo_store, &doc.borrow().utxo_rows(Some(&descriptor_generator)));
                 }
                 me.update_ui();
             }),